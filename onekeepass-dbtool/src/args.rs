@@ -0,0 +1,321 @@
+use std::path::Path;
+
+// The password and the key file of a db. The password is read from a file, from an environment
+// variable or asked in the terminal so that it does not show up in the process list
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Credentials {
+    pub(crate) password: Option<String>,
+    pub(crate) key_file: Option<String>,
+}
+
+impl Credentials {
+    pub(crate) fn password(&self) -> Option<&str> {
+        self.password.as_deref()
+    }
+
+    pub(crate) fn key_file(&self) -> Option<&str> {
+        self.key_file.as_deref()
+    }
+}
+
+// The credential options as given in the args
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct CredentialArgs {
+    password_file: Option<String>,
+    password_env: Option<String>,
+    no_password: bool,
+    key_file: Option<String>,
+}
+
+impl CredentialArgs {
+    // 'prefix' is "" for the db given first, "source-" for the source db of a merge and "new-"
+    // for a new master key
+    fn take(args: &mut Vec<String>, prefix: &str) -> Result<Self, String> {
+        Ok(CredentialArgs {
+            password_file: take_option(args, &format!("--{}password-file", prefix))?,
+            password_env: take_option(args, &format!("--{}password-env", prefix))?,
+            no_password: take_flag(args, &format!("--{}no-password", prefix)),
+            key_file: take_option(args, &format!("--{}key-file", prefix))?,
+        })
+    }
+
+    fn is_given(&self) -> bool {
+        *self != CredentialArgs::default()
+    }
+
+    // True when the password is asked in the terminal
+    pub(crate) fn prompts(&self) -> bool {
+        !self.no_password && self.password_file.is_none() && self.password_env.is_none()
+    }
+
+    // 'label' is used in the terminal prompt
+    pub(crate) fn read(&self, label: &str) -> Result<Credentials, String> {
+        let password = if self.no_password {
+            None
+        } else if let Some(file) = &self.password_file {
+            let data = std::fs::read_to_string(file)
+                .map_err(|e| format!("Reading the password file {} failed: {}", file, e))?;
+            // Only the line end added by an editor or 'echo' is removed
+            Some(data.trim_end_matches(['\r', '\n']).to_string())
+        } else if let Some(var) = &self.password_env {
+            Some(
+                std::env::var(var)
+                    .map_err(|_| format!("The environment variable {} is not set", var))?,
+            )
+        } else {
+            let p = rpassword::prompt_password(format!("{}: ", label)).map_err(|e| {
+                format!(
+                    "The password can not be read from the terminal ({}). Use --password-file or --password-env",
+                    e
+                )
+            })?;
+            Some(p)
+        };
+
+        if let Some(key_file) = &self.key_file {
+            if !Path::new(key_file).exists() {
+                return Err(format!("The key file {} is not found", key_file));
+            }
+        }
+
+        Ok(Credentials {
+            password,
+            key_file: self.key_file.clone(),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Command {
+    Verify {
+        db: String,
+        credentials: CredentialArgs,
+    },
+    ChangeKey {
+        db: String,
+        credentials: CredentialArgs,
+        new_credentials: CredentialArgs,
+        backup: Option<String>,
+    },
+    Merge {
+        target: String,
+        source: String,
+        credentials: CredentialArgs,
+        // The target's credentials are used when none is given for the source
+        source_credentials: Option<CredentialArgs>,
+        backup: Option<String>,
+    },
+    ExportXml {
+        db: String,
+        out: String,
+        credentials: CredentialArgs,
+    },
+    ExportCsv {
+        db: String,
+        out: String,
+        credentials: CredentialArgs,
+    },
+    ImportCsv {
+        db: String,
+        csv: String,
+        credentials: CredentialArgs,
+        custom_fields: bool,
+        backup: Option<String>,
+    },
+    Report {
+        db: String,
+        credentials: CredentialArgs,
+        json: bool,
+    },
+}
+
+pub(crate) const USAGE: &str = "Usage: okp-dbtool <command> [options]
+
+Commands:
+  verify <db.kdbx>                      Opens the database and verifies its integrity
+  change-key <db.kdbx>                  Changes the master key (password and/or key file)
+  merge <target.kdbx> <source.kdbx>     Merges the source database into the target database
+  export-xml <db.kdbx> <out.xml>        Exports the whole database as KeePass XML (not encrypted)
+  export-csv <db.kdbx> <out.csv>        Exports the entries as CSV (not encrypted)
+  import-csv <db.kdbx> <in.csv>         Imports the entries of a CSV file into the database
+  report <db.kdbx>                      Prints a password health report
+
+Options for the database:
+  --password-file <file>  --password-env <var>  --no-password  --key-file <file>
+  The password is asked in the terminal when none of the password options is given.
+
+Options of change-key (the new master key that replaces the current one):
+  --new-password-file <file>  --new-password-env <var>  --new-no-password  --new-key-file <file>
+  The new password is asked in the terminal when none of the password options is given.
+  The new master key uses a key file only when --new-key-file is given.
+
+Options of merge (the source database, the target's credentials are used when not given):
+  --source-password-file <file>  --source-password-env <var>  --source-no-password  --source-key-file <file>
+
+Other options:
+  --backup <file>     (change-key, merge, import-csv) Copies the database file here before saving
+  --custom-fields     (import-csv) Imports the columns that are not mapped as custom fields
+  --json              (report) Prints the report as json";
+
+// Removes '<name> <value>' from the args and returns the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    let Some(pos) = args.iter().position(|a| a == name) else {
+        return Ok(None);
+    };
+    if pos + 1 >= args.len() {
+        return Err(format!("{} needs a value", name));
+    }
+    let value = args.remove(pos + 1);
+    args.remove(pos);
+    Ok(Some(value))
+}
+
+// Removes the flag from the args and returns true when it was given
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let len = args.len();
+    args.retain(|a| a != name);
+    args.len() != len
+}
+
+// The args without the program name
+pub(crate) fn parse(mut args: Vec<String>) -> Result<Command, String> {
+    let new_credentials = CredentialArgs::take(&mut args, "new-")?;
+    let source_credentials = CredentialArgs::take(&mut args, "source-")?;
+    let credentials = CredentialArgs::take(&mut args, "")?;
+    let backup = take_option(&mut args, "--backup")?;
+    let custom_fields = take_flag(&mut args, "--custom-fields");
+    let json = take_flag(&mut args, "--json");
+
+    if let Some(unknown) = args.iter().find(|a| a.starts_with("--")) {
+        return Err(format!("Unknown option {}\n\n{}", unknown, USAGE));
+    }
+
+    let positional = |n: usize| -> Result<Vec<String>, String> {
+        if args.len() == n + 1 {
+            Ok(args[1..].to_vec())
+        } else {
+            Err(USAGE.to_string())
+        }
+    };
+
+    let command = match args.first().map(|a| a.as_str()) {
+        Some("verify") => {
+            let [db] = <[String; 1]>::try_from(positional(1)?).unwrap();
+            Command::Verify { db, credentials }
+        }
+        Some("change-key") => {
+            let [db] = <[String; 1]>::try_from(positional(1)?).unwrap();
+            Command::ChangeKey {
+                db,
+                credentials,
+                new_credentials,
+                backup,
+            }
+        }
+        Some("merge") => {
+            let [target, source] = <[String; 2]>::try_from(positional(2)?).unwrap();
+            Command::Merge {
+                target,
+                source,
+                credentials,
+                source_credentials: source_credentials.is_given().then_some(source_credentials),
+                backup,
+            }
+        }
+        Some("export-xml") => {
+            let [db, out] = <[String; 2]>::try_from(positional(2)?).unwrap();
+            Command::ExportXml {
+                db,
+                out,
+                credentials,
+            }
+        }
+        Some("export-csv") => {
+            let [db, out] = <[String; 2]>::try_from(positional(2)?).unwrap();
+            Command::ExportCsv {
+                db,
+                out,
+                credentials,
+            }
+        }
+        Some("import-csv") => {
+            let [db, csv] = <[String; 2]>::try_from(positional(2)?).unwrap();
+            Command::ImportCsv {
+                db,
+                csv,
+                credentials,
+                custom_fields,
+                backup,
+            }
+        }
+        Some("report") => {
+            let [db] = <[String; 1]>::try_from(positional(1)?).unwrap();
+            Command::Report {
+                db,
+                credentials,
+                json,
+            }
+        }
+        _ => return Err(USAGE.to_string()),
+    };
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parse_commands() {
+        let c = parse(args("verify db.kdbx --key-file k.keyx --password-env PW")).unwrap();
+        assert_eq!(
+            c,
+            Command::Verify {
+                db: "db.kdbx".into(),
+                credentials: CredentialArgs {
+                    password_env: Some("PW".into()),
+                    key_file: Some("k.keyx".into()),
+                    ..Default::default()
+                },
+            }
+        );
+
+        let c = parse(args(
+            "merge a.kdbx b.kdbx --password-file p --source-no-password",
+        ))
+        .unwrap();
+        assert!(matches!(
+            c,
+            Command::Merge {
+                source_credentials: Some(CredentialArgs {
+                    no_password: true,
+                    ..
+                }),
+                ..
+            }
+        ));
+
+        let c = parse(args("merge a.kdbx b.kdbx --password-file p")).unwrap();
+        assert!(matches!(
+            c,
+            Command::Merge {
+                source_credentials: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse(args("change-key db.kdbx --new-key-file")).is_err());
+        assert!(parse(args("verify")).is_err());
+        assert!(parse(args("verify a.kdbx b.kdbx")).is_err());
+        assert!(parse(args("verify a.kdbx --password-file")).is_err());
+        assert!(parse(args("verify a.kdbx --unknown")).is_err());
+        assert!(parse(args("delete a.kdbx")).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use std::{fs::File, io::Write};
+
+use serde_json::json;
+use uuid::Uuid;
+
+use onekeepass_core::db_service as kp_service;
+
+// The standard entry fields that are written in an export and that the headers of an imported
+// file are mapped to. These are the same names as used in the app's csv import dialog
+const GROUP: &str = "Group";
+const TITLE: &str = "Title";
+const USER_NAME: &str = "UserName";
+const PASSWORD: &str = "Password";
+const URL: &str = "URL";
+const NOTES: &str = "Notes";
+const OTP: &str = "otp";
+const TAGS: &str = "Tags";
+
+const EXPORT_COLUMNS: [&str; 6] = [TITLE, USER_NAME, PASSWORD, URL, NOTES, OTP];
+
+const IMPORT_FIELDS: [&str; 8] = [GROUP, TITLE, USER_NAME, PASSWORD, URL, NOTES, OTP, TAGS];
+
+// Quotes a value as in RFC 4180 when it has a separator, a quote or a line break
+fn quoted(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_line(values: &[&str]) -> String {
+    let values: Vec<String> = values.iter().map(|v| quoted(v)).collect();
+    format!("{}\r\n", values.join(","))
+}
+
+#[cfg(unix)]
+fn create_private_file(path: &str) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private_file(path: &str) -> std::io::Result<File> {
+    File::create(path)
+}
+
+// Writes the entries (except the ones in the recycle bin) with their passwords in plain text.
+// Returns the number of entries written
+pub(crate) fn export(db_key: &str, out: &str) -> Result<usize, String> {
+    let entries = kp_service::entry_summary_data(db_key, kp_service::EntryCategory::AllEntries)
+        .map_err(|e| e.to_string())?;
+
+    let mut file =
+        create_private_file(out).map_err(|e| format!("Creating {} failed: {}", out, e))?;
+    let mut data = csv_line(&EXPORT_COLUMNS);
+    let mut count = 0;
+    for summary in entries {
+        let Ok(entry_uuid) = Uuid::parse_str(&summary.uuid) else {
+            continue;
+        };
+        let fields =
+            kp_service::entry_key_value_fields(db_key, &entry_uuid).map_err(|e| e.to_string())?;
+        let values: Vec<&str> = EXPORT_COLUMNS
+            .iter()
+            .map(|c| fields.get(*c).map(|v| v.as_str()).unwrap_or_default())
+            .collect();
+        data.push_str(&csv_line(&values));
+        count += 1;
+    }
+    file.write_all(data.as_bytes())
+        .map_err(|e| format!("Writing {} failed: {}", out, e))?;
+    Ok(count)
+}
+
+// Maps the headers of a csv file to the standard fields by their names ignoring the case. A
+// header that is not mapped is imported as a custom field when 'custom_fields' is true.
+// Returns the mapping in the form expected by 'CsvImportMapping'
+fn mapping_json(headers: &[String], custom_fields: bool) -> Result<serde_json::Value, String> {
+    let mut mapped_fields = vec![];
+    let mut mapped_headers = vec![];
+    for field in IMPORT_FIELDS {
+        if let Some(header) = headers
+            .iter()
+            .find(|h| h.trim().eq_ignore_ascii_case(field))
+        {
+            mapped_fields.push(json!({"field_name": field, "mapped_name": header}));
+            mapped_headers.push(header.clone());
+        }
+    }
+
+    if !mapped_headers
+        .iter()
+        .any(|h| h.trim().eq_ignore_ascii_case(TITLE))
+    {
+        return Err(format!(
+            "The csv file has no '{}' column. The columns are: {}",
+            TITLE,
+            headers.join(", ")
+        ));
+    }
+
+    let not_mapped_headers: Vec<&String> = headers
+        .iter()
+        .filter(|h| !mapped_headers.contains(h))
+        .collect();
+
+    Ok(json!({
+        "headers": headers,
+        "mapped_fields": mapped_fields,
+        "not_mapped_headers": not_mapped_headers,
+        "unmapped_custom_field": custom_fields,
+    }))
+}
+
+// Imports the entries of the csv file into the opened db. The caller saves the db
+pub(crate) fn import(db_key: &str, csv: &str, custom_fields: bool) -> Result<(), String> {
+    let header_info =
+        kp_service::CsvImport::read_from_path(csv, None).map_err(|e| e.to_string())?;
+
+    let result = mapping_json(&header_info.headers, custom_fields).and_then(|m| {
+        let mapping: kp_service::CsvImportMapping =
+            serde_json::from_value(m).map_err(|e| e.to_string())?;
+        mapping.import_into_db(db_key).map_err(|e| e.to_string())
+    });
+
+    // The records read are kept in the core till cleared
+    kp_service::CsvImport::clear_stored_records();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_quoting() {
+        assert_eq!(
+            csv_line(&["a", "b,c", "say \"hi\"", "x\ny"]),
+            "a,\"b,c\",\"say \"\"hi\"\"\",\"x\ny\"\r\n"
+        );
+    }
+
+    #[test]
+    fn verify_mapping() {
+        let headers: Vec<String> = ["title", "Login", "PASSWORD", "url"]
+            .iter()
+            .map(|h| h.to_string())
+            .collect();
+        let m = mapping_json(&headers, true).unwrap();
+        assert_eq!(m["mapped_fields"].as_array().unwrap().len(), 3);
+        assert_eq!(m["mapped_fields"][0]["field_name"], TITLE);
+        assert_eq!(m["mapped_fields"][0]["mapped_name"], "title");
+        assert_eq!(m["not_mapped_headers"], json!(["Login"]));
+
+        let headers = vec!["Name".to_string(), "Password".to_string()];
+        assert!(mapping_json(&headers, false).is_err());
+    }
+}
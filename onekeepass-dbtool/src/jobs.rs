@@ -0,0 +1,172 @@
+use onekeepass_core::db_service as kp_service;
+
+use crate::args::{CredentialArgs, Credentials};
+use crate::{csv, report};
+
+// An opened db that is closed when dropped so that the keys are removed from the key store
+struct OpenedDb {
+    db_key: String,
+}
+
+impl OpenedDb {
+    fn open(db: &str, credentials: &CredentialArgs) -> Result<Self, String> {
+        let credentials = credentials.read(&format!("Password of {}", db))?;
+        kp_service::load_kdbx(db, credentials.password(), credentials.key_file())
+            .map_err(|e| format!("Opening {} failed: {}", db, e))?;
+        Ok(OpenedDb {
+            db_key: db.to_string(),
+        })
+    }
+
+    fn save(&self, backup: Option<&str>) -> Result<(), String> {
+        kp_service::save_kdbx_with_backup(&self.db_key, backup, false)
+            .map_err(|e| format!("Saving {} failed: {}", self.db_key, e))?;
+        Ok(())
+    }
+}
+
+impl Drop for OpenedDb {
+    fn drop(&mut self) {
+        let _ = kp_service::close_kdbx(&self.db_key);
+    }
+}
+
+fn entry_count(db_key: &str) -> Result<usize, String> {
+    Ok(
+        kp_service::entry_summary_data(db_key, kp_service::EntryCategory::AllEntries)
+            .map_err(|e| e.to_string())?
+            .len(),
+    )
+}
+
+pub(crate) fn verify(db: &str, credentials: &CredentialArgs) -> Result<(), String> {
+    let opened = OpenedDb::open(db, credentials)?;
+    // Reads the file again and checks its header and block hashes against the opened content
+    kp_service::read_and_verify_db_file(&opened.db_key)
+        .map_err(|e| format!("Verifying {} failed: {}", db, e))?;
+    println!(
+        "{} is verified ({} entries)",
+        db,
+        entry_count(&opened.db_key)?
+    );
+    Ok(())
+}
+
+// The new master key replaces the current one. A key file is used only when one is given
+// in the new master key options
+pub(crate) fn change_key(
+    db: &str,
+    credentials: &CredentialArgs,
+    new_credentials: &CredentialArgs,
+    backup: Option<&str>,
+) -> Result<(), String> {
+    let opened = OpenedDb::open(db, credentials)?;
+
+    let new = new_credentials.read("New password")?;
+    if new_credentials.prompts() {
+        let repeated = new_credentials.read("Repeat the new password")?;
+        if repeated.password != new.password {
+            return Err("The new passwords do not match".into());
+        }
+    }
+    let Credentials { password, key_file } = new;
+    if password.is_none() && key_file.is_none() {
+        return Err("The new master key needs a password or a key file".into());
+    }
+
+    let settings = kp_service::get_db_settings(&opened.db_key).map_err(|e| e.to_string())?;
+    let mut settings = serde_json::to_value(settings).map_err(|e| e.to_string())?;
+    settings["password_used"] = password.is_some().into();
+    settings["password"] = password.into();
+    settings["password_changed"] = true.into();
+    settings["key_file_used"] = key_file.is_some().into();
+    settings["key_file_name"] = key_file.into();
+    settings["key_file_changed"] = true.into();
+    let settings = serde_json::from_value(settings).map_err(|e| e.to_string())?;
+
+    kp_service::set_db_settings(&opened.db_key, settings).map_err(|e| e.to_string())?;
+    opened.save(backup)?;
+    println!("The master key of {} is changed", db);
+    Ok(())
+}
+
+pub(crate) fn merge(
+    target: &str,
+    source: &str,
+    credentials: &CredentialArgs,
+    source_credentials: Option<&CredentialArgs>,
+    backup: Option<&str>,
+) -> Result<(), String> {
+    let opened = OpenedDb::open(target, credentials)?;
+
+    let source_credentials = source_credentials
+        .unwrap_or(credentials)
+        .read(&format!("Password of {}", source))?;
+    let result = kp_service::merge_databases(
+        &opened.db_key,
+        source,
+        source_credentials.password(),
+        source_credentials.key_file(),
+    )
+    .map_err(|e| format!("Merging {} failed: {}", source, e))?;
+
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&result).unwrap_or_default()
+    );
+    if result.merge_done {
+        opened.save(backup)?;
+        println!("{} is merged into {}", source, target);
+    } else {
+        println!("Nothing to merge from {}", source);
+    }
+    Ok(())
+}
+
+pub(crate) fn export_xml(db: &str, out: &str, credentials: &CredentialArgs) -> Result<(), String> {
+    let opened = OpenedDb::open(db, credentials)?;
+    kp_service::export_as_xml(&opened.db_key, out)
+        .map_err(|e| format!("Exporting to {} failed: {}", out, e))?;
+    println!("{} is exported to {}. The file is not encrypted", db, out);
+    Ok(())
+}
+
+pub(crate) fn export_csv(db: &str, out: &str, credentials: &CredentialArgs) -> Result<(), String> {
+    let opened = OpenedDb::open(db, credentials)?;
+    let count = csv::export(&opened.db_key, out)?;
+    println!(
+        "{} entries of {} are exported to {}. The file is not encrypted",
+        count, db, out
+    );
+    Ok(())
+}
+
+pub(crate) fn import_csv(
+    db: &str,
+    csv_file: &str,
+    credentials: &CredentialArgs,
+    custom_fields: bool,
+    backup: Option<&str>,
+) -> Result<(), String> {
+    let opened = OpenedDb::open(db, credentials)?;
+    let before = entry_count(&opened.db_key)?;
+    csv::import(&opened.db_key, csv_file, custom_fields)?;
+    opened.save(backup)?;
+    println!(
+        "{} entries are imported into {}",
+        entry_count(&opened.db_key)?.saturating_sub(before),
+        db
+    );
+    Ok(())
+}
+
+pub(crate) fn report(db: &str, credentials: &CredentialArgs, json: bool) -> Result<(), String> {
+    let opened = OpenedDb::open(db, credentials)?;
+    let report = report::health_report(&opened.db_key)?;
+    if json {
+        println!("{}", report.to_json());
+    } else {
+        println!("{}", report.to_text());
+    }
+    Ok(())
+}
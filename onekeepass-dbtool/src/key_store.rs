@@ -0,0 +1,44 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use secstr::SecVec;
+
+use onekeepass_core::db_service::{self as kp_service, KeyStoreOperation, KeyStoreService};
+
+// The core keeps the encryption key of an opened db's session keys in this store. As in the
+// app on Windows (see src-tauri/src/key_secure/other.rs), it is kept only in memory. The tool
+// exits after a job and so nothing is left behind
+#[derive(Default)]
+struct MemoryKeyStore {
+    store: HashMap<String, SecVec<u8>>,
+}
+
+impl KeyStoreService for MemoryKeyStore {
+    fn store_key(&mut self, db_key: &str, data: Vec<u8>) -> kp_service::Result<()> {
+        self.store.insert(db_key.into(), SecVec::new(data));
+        Ok(())
+    }
+
+    fn get_key(&self, db_key: &str) -> Option<Vec<u8>> {
+        self.store.get(db_key).map(|v| Vec::from(v.unsecure()))
+    }
+
+    fn delete_key(&mut self, db_key: &str) -> kp_service::Result<()> {
+        self.store.remove(db_key);
+        Ok(())
+    }
+
+    fn copy_key(&mut self, source_db_key: &str, target_db_key: &str) -> kp_service::Result<()> {
+        if let Some(key) = self.store.get(source_db_key).cloned() {
+            self.store.insert(target_db_key.into(), key);
+        }
+        Ok(())
+    }
+}
+
+// Should be called before any db is opened
+pub(crate) fn init() {
+    KeyStoreOperation::init(Arc::new(Mutex::new(MemoryKeyStore::default())));
+}
@@ -0,0 +1,90 @@
+// okp-dbtool runs maintenance jobs on KDBX files without the app. It uses the same
+// onekeepass-core calls as the app's commands and can be used in scripts and on a headless
+// server. A db is opened with its password and/or key file, the job is done and the db is
+// closed again. Nothing is kept once the tool exits
+
+mod args;
+mod csv;
+mod jobs;
+mod key_store;
+mod report;
+
+use std::process::ExitCode;
+
+use args::Command;
+
+const EXIT_ERROR: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+
+fn run(command: Command) -> Result<(), String> {
+    match command {
+        Command::Verify { db, credentials } => jobs::verify(&db, &credentials),
+        Command::ChangeKey {
+            db,
+            credentials,
+            new_credentials,
+            backup,
+        } => jobs::change_key(&db, &credentials, &new_credentials, backup.as_deref()),
+        Command::Merge {
+            target,
+            source,
+            credentials,
+            source_credentials,
+            backup,
+        } => jobs::merge(
+            &target,
+            &source,
+            &credentials,
+            source_credentials.as_ref(),
+            backup.as_deref(),
+        ),
+        Command::ExportXml {
+            db,
+            out,
+            credentials,
+        } => jobs::export_xml(&db, &out, &credentials),
+        Command::ExportCsv {
+            db,
+            out,
+            credentials,
+        } => jobs::export_csv(&db, &out, &credentials),
+        Command::ImportCsv {
+            db,
+            csv,
+            credentials,
+            custom_fields,
+            backup,
+        } => jobs::import_csv(&db, &csv, &credentials, custom_fields, backup.as_deref()),
+        Command::Report {
+            db,
+            credentials,
+            json,
+        } => jobs::report(&db, &credentials, json),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{}", args::USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    let command = match args::parse(args) {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    key_store::init();
+
+    match run(command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
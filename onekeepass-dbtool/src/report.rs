@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use onekeepass_core::db_service as kp_service;
+
+// The score names (see kp_service::PasswordScore) that are reported as weak
+const WEAK_SCORES: [&str; 4] = ["VeryDangerous", "Dangerous", "VeryWeak", "Weak"];
+
+#[derive(Serialize, Debug, Default)]
+struct WeakEntry {
+    title: String,
+    score: String,
+}
+
+// The passwords themselves are never part of the report
+#[derive(Serialize, Debug, Default)]
+pub(crate) struct HealthReport {
+    entries: usize,
+    without_password: usize,
+    // Number of entries for each score name
+    scores: BTreeMap<String, usize>,
+    weak: Vec<WeakEntry>,
+    // The titles of the entries that share a password. One list for each password used more
+    // than once
+    reused: Vec<Vec<String>>,
+}
+
+fn score_name(password: &str) -> String {
+    let score: kp_service::PasswordScore = password.into();
+    serde_json::to_value(score)
+        .ok()
+        .and_then(|v| {
+            v.get("name")
+                .and_then(|n| n.as_str())
+                .map(|n| n.to_string())
+        })
+        .unwrap_or_else(|| "Unknown".to_string())
+}
+
+pub(crate) fn health_report(db_key: &str) -> Result<HealthReport, String> {
+    let entries = kp_service::entry_summary_data(db_key, kp_service::EntryCategory::AllEntries)
+        .map_err(|e| e.to_string())?;
+
+    let mut report = HealthReport::default();
+    let mut by_password: HashMap<String, Vec<String>> = HashMap::new();
+
+    for summary in entries {
+        let Ok(entry_uuid) = Uuid::parse_str(&summary.uuid) else {
+            continue;
+        };
+        report.entries += 1;
+        let mut fields =
+            kp_service::entry_key_value_fields(db_key, &entry_uuid).map_err(|e| e.to_string())?;
+        let password = fields
+            .remove(kp_service::entry_keyvalue_key::PASSWORD)
+            .unwrap_or_default();
+        if password.is_empty() {
+            report.without_password += 1;
+            continue;
+        }
+
+        let score = score_name(&password);
+        *report.scores.entry(score.clone()).or_default() += 1;
+        if WEAK_SCORES.contains(&score.as_str()) {
+            report.weak.push(WeakEntry {
+                title: summary.title.clone(),
+                score,
+            });
+        }
+        by_password.entry(password).or_default().push(summary.title);
+    }
+
+    report.reused = by_password.into_values().filter(|t| t.len() > 1).collect();
+    report.reused.sort();
+    report.weak.sort_by(|a, b| a.title.cmp(&b.title));
+
+    Ok(report)
+}
+
+impl HealthReport {
+    pub(crate) fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+
+    pub(crate) fn to_text(&self) -> String {
+        let mut lines = vec![
+            format!("Entries: {}", self.entries),
+            format!("Entries without a password: {}", self.without_password),
+            String::new(),
+            "Password scores:".to_string(),
+        ];
+        for (name, count) in &self.scores {
+            lines.push(format!("  {:<14} {}", name, count));
+        }
+
+        lines.push(String::new());
+        lines.push(format!("Weak passwords: {}", self.weak.len()));
+        for w in &self.weak {
+            lines.push(format!("  {} ({})", w.title, w.score));
+        }
+
+        lines.push(String::new());
+        lines.push(format!("Reused passwords: {}", self.reused.len()));
+        for titles in &self.reused {
+            lines.push(format!(
+                "  Used by {} entries: {}",
+                titles.len(),
+                titles.join(", ")
+            ));
+        }
+        lines.join("\n")
+    }
+}
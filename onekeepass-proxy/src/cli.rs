@@ -0,0 +1,321 @@
+use std::{io::Write, path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tipsy::{Connection, Endpoint};
+use tokio::io::{split, ReadHalf, WriteHalf};
+
+use crate::proxy_client::{read_framed_message, write_framed_to_app};
+use crate::sandbox;
+
+// The 'okp' command line client. Either
+//   onekeepass-proxy cli <command> ...
+// or, with this binary copied/linked as 'okp' in the PATH,
+//   okp <command> ...
+//
+// It connects to the app's own command line endpoint (see cli_service in the main app) and not
+// to the endpoint of the browser proxies. On the first use the app asks the user to allow this
+// client. The client id and token then given by the app are kept in the user's config dir and
+// the client can be revoked in the app settings.
+
+pub(crate) const CLI_BINARY_NAME: &str = "okp";
+pub(crate) const CLI_ARG: &str = "cli";
+
+// IMPORTANT: Same path should be used in the cli_service of the main app
+cfg_if::cfg_if! {
+    if #[cfg(debug_assertions)] {
+        const CLI_CONNECTION_NAME: &str = "okp_cli_ipc_dev";
+        const CREDENTIALS_FILE_NAME: &str = "okp-cli-dev.json";
+    } else {
+        const CLI_CONNECTION_NAME: &str = "okp_cli_ipc";
+        const CREDENTIALS_FILE_NAME: &str = "okp-cli.json";
+    }
+}
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+// Longer than the time the app waits for the user's approval
+const APPROVAL_REPLY_TIMEOUT: Duration = Duration::from_secs(90);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+const USAGE: &str = "Usage: okp [--json] <command>
+
+Commands:
+  list-dbs                          Lists the opened and the locked databases
+  search <term> [--db <name>]       Lists the entries matching the term
+  get <field> <entry> [--db <name>] Prints a field (password, username, url, title, notes or a custom field)
+  totp <entry> [--db <name>]        Prints the current one-time password
+  generate [--length <n>] [--no-symbols] [--no-numbers] [--no-uppercase] [--no-lowercase]
+                                    Prints a generated password
+  lock [<db name>]                  Locks the given database or all opened databases
+
+An entry is given by its uuid or by a search term that matches only one entry.
+The command line access needs to be enabled in OneKeePass settings.";
+
+#[derive(Serialize, Deserialize, Default)]
+struct ClientCredentials {
+    client_id: String,
+    token: String,
+}
+
+struct CliArgs {
+    json: bool,
+    request: Value,
+}
+
+// Returns the command line args when the proxy is run as the okp client
+pub(crate) fn cli_args(args: &[String]) -> Option<Vec<String>> {
+    let invoked_as_okp = args
+        .first()
+        .and_then(|a| std::path::Path::new(a).file_stem().map(|s| s.to_string_lossy().to_string()))
+        .is_some_and(|name| name == CLI_BINARY_NAME);
+
+    if invoked_as_okp {
+        Some(args[1..].to_vec())
+    } else if args.get(1).map(|a| a.as_str()) == Some(CLI_ARG) {
+        Some(args[2..].to_vec())
+    } else {
+        None
+    }
+}
+
+// Removes '--<name> <value>' from the args and returns the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    let Some(pos) = args.iter().position(|a| a == name) else {
+        return Ok(None);
+    };
+    if pos + 1 >= args.len() {
+        return Err(format!("{} needs a value", name));
+    }
+    let value = args.remove(pos + 1);
+    args.remove(pos);
+    Ok(Some(value))
+}
+
+// Removes the flag from the args and returns true when it was given
+fn take_flag(args: &mut Vec<String>, name: &str) -> bool {
+    let len = args.len();
+    args.retain(|a| a != name);
+    args.len() != len
+}
+
+fn parse_args(mut args: Vec<String>) -> Result<CliArgs, String> {
+    let json = take_flag(&mut args, "--json");
+    let db = take_option(&mut args, "--db")?;
+
+    let request = match args.first().map(|a| a.as_str()) {
+        Some("list-dbs") if args.len() == 1 => json!({ "action": "ListDbs" }),
+        Some("search") if args.len() == 2 => json!({ "action": "Search", "term": args[1], "db": db }),
+        Some("get") if args.len() == 3 => json!({ "action": "Get", "field": args[1], "entry": args[2], "db": db }),
+        Some("totp") if args.len() == 2 => json!({ "action": "Totp", "entry": args[1], "db": db }),
+        Some("generate") => {
+            let length = match take_option(&mut args, "--length")? {
+                Some(l) => l.parse::<usize>().map_err(|_| format!("Invalid length {}", l))?,
+                None => 20,
+            };
+            let numbers = !take_flag(&mut args, "--no-numbers");
+            let symbols = !take_flag(&mut args, "--no-symbols");
+            let uppercase_letters = !take_flag(&mut args, "--no-uppercase");
+            let lowercase_letters = !take_flag(&mut args, "--no-lowercase");
+            if args.len() != 1 {
+                return Err(USAGE.to_string());
+            }
+            // All fields of PasswordGenerationOptions of onekeepass-core
+            json!({ "action": "Generate", "options": {
+                "length": length,
+                "numbers": numbers,
+                "lowercase_letters": lowercase_letters,
+                "uppercase_letters": uppercase_letters,
+                "symbols": symbols,
+                "spaces": false,
+                "exclude_similar_characters": true,
+                "strict": true,
+            }})
+        }
+        Some("lock") if args.len() <= 2 => json!({ "action": "Lock", "db": args.get(1).cloned().or(db) }),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    Ok(CliArgs { json, request })
+}
+
+fn config_dir() -> Option<PathBuf> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "windows")] {
+            std::env::var_os("APPDATA").map(PathBuf::from)
+        } else if #[cfg(target_os = "macos")] {
+            std::env::var_os("HOME").map(|h| PathBuf::from(h).join("Library").join("Application Support"))
+        } else {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .filter(|p| p.is_absolute())
+                .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        }
+    }
+}
+
+fn credentials_file() -> Option<PathBuf> {
+    config_dir().map(|d| d.join("onekeepass").join(CREDENTIALS_FILE_NAME))
+}
+
+fn read_credentials() -> Option<ClientCredentials> {
+    let data = std::fs::read(credentials_file()?).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+// The file is readable only by the user as the token gives access to the opened databases
+fn write_credentials(credentials: &ClientCredentials) -> Result<(), String> {
+    let path = credentials_file().ok_or("No config dir is found to keep the client token")?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let data = serde_json::to_vec_pretty(credentials).map_err(|e| e.to_string())?;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&path).map_err(|e| format!("Writing {} failed: {}", path.display(), e))?;
+    file.write_all(&data).map_err(|e| e.to_string())
+}
+
+fn client_name() -> String {
+    std::env::var("OKP_CLIENT_NAME").unwrap_or_else(|_| {
+        let user = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_default();
+        format!("okp ({})", user)
+    })
+}
+
+// Sends one request and returns the value of a successful reply
+async fn call(reader: &mut ReadHalf<Connection>, writer: &mut WriteHalf<Connection>, request: &Value, timeout: Duration) -> Result<Value, String> {
+    write_framed_to_app(writer, request.to_string().as_bytes())
+        .await
+        .map_err(|e| format!("Sending the request to OneKeePass failed: {}", e))?;
+
+    let reply = match tokio::time::timeout(timeout, read_framed_message(reader)).await {
+        Ok(Ok(bytes)) => bytes,
+        Ok(Err(e)) => return Err(format!("Reading the reply from OneKeePass failed: {}", e)),
+        Err(_) => return Err("No reply from OneKeePass".to_string()),
+    };
+    let mut json: Value = serde_json::from_slice(&reply).map_err(|e| format!("The reply is not valid json: {}", e))?;
+
+    match json.get("error").and_then(|e| e.as_str()) {
+        Some(e) => Err(e.to_string()),
+        None => Ok(json["ok"].take()),
+    }
+}
+
+// Authenticates with the kept credentials or asks the user in the app to allow this client
+async fn hello(reader: &mut ReadHalf<Connection>, writer: &mut WriteHalf<Connection>) -> Result<(), String> {
+    let credentials = read_credentials();
+    if credentials.is_none() {
+        eprintln!("Waiting for this client to be allowed in OneKeePass...");
+    }
+    let (client_id, token) = credentials.map(|c| (Some(c.client_id), Some(c.token))).unwrap_or_default();
+    let request = json!({
+        "action": "Hello",
+        "client_name": client_name(),
+        "client_id": client_id,
+        "token": token,
+    });
+
+    let value = call(reader, writer, &request, APPROVAL_REPLY_TIMEOUT).await?;
+    // Credentials are returned only when this client is newly allowed
+    if !value.is_null() {
+        let credentials: ClientCredentials = serde_json::from_value(value).map_err(|e| format!("Invalid reply: {}", e))?;
+        write_credentials(&credentials)?;
+    }
+    Ok(())
+}
+
+fn print_output(command: &str, value: &Value, as_json: bool) {
+    let mut out = String::new();
+    if as_json {
+        out = format!("{}\n", serde_json::to_string_pretty(value).unwrap_or_default());
+    } else {
+        match command {
+            "ListDbs" => {
+                for db in value.as_array().into_iter().flatten() {
+                    let locked = if db["locked"].as_bool() == Some(true) { " (locked)" } else { "" };
+                    out.push_str(&format!(
+                        "{}{}\t{}\n",
+                        db["name"].as_str().unwrap_or_default(),
+                        locked,
+                        db["db_key"].as_str().unwrap_or_default()
+                    ));
+                }
+            }
+            "Search" => {
+                for e in value.as_array().into_iter().flatten() {
+                    out.push_str(&format!(
+                        "{}\t{}\t{}\t{}\n",
+                        e["uuid"].as_str().unwrap_or_default(),
+                        e["title"].as_str().unwrap_or_default(),
+                        e["username"].as_str().unwrap_or_default(),
+                        e["db_name"].as_str().unwrap_or_default()
+                    ));
+                }
+            }
+            "Get" => out = format!("{}\n", value.as_str().unwrap_or_default()),
+            "Totp" => out = format!("{}\n", value["token"].as_str().unwrap_or_default()),
+            "Generate" => out = format!("{}\n", value["password"].as_str().unwrap_or_default()),
+            "Lock" => {
+                for name in value.as_array().into_iter().flatten() {
+                    out.push_str(&format!("Locked {}\n", name.as_str().unwrap_or_default()));
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(out.as_bytes());
+    let _ = stdout.flush();
+}
+
+// Returns the process exit code
+pub(crate) async fn run(args: Vec<String>) -> i32 {
+    let cli_args = match parse_args(args) {
+        Ok(a) => a,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+
+    let server_id = crate::endpoint_server_id(CLI_CONNECTION_NAME, sandbox::group_container_path());
+    let connection = match tokio::time::timeout(CONNECT_TIMEOUT, Endpoint::connect(server_id)).await {
+        Ok(Ok(c)) => c,
+        Ok(Err(e)) => {
+            eprintln!(
+                "okp: OneKeePass is not reachable ({}). Is it running with the command line access enabled?",
+                e
+            );
+            return 1;
+        }
+        Err(_) => {
+            eprintln!("okp: Connecting to OneKeePass timed out");
+            return 1;
+        }
+    };
+    let (mut reader, mut writer) = split(connection);
+
+    if let Err(e) = hello(&mut reader, &mut writer).await {
+        log::error!("okp: hello failed: {}", e);
+        eprintln!("okp: {}", e);
+        return 1;
+    }
+
+    let command = cli_args.request["action"].as_str().unwrap_or_default().to_string();
+    match call(&mut reader, &mut writer, &cli_args.request, REPLY_TIMEOUT).await {
+        Ok(value) => {
+            print_output(&command, &value, cli_args.json);
+            0
+        }
+        Err(e) => {
+            eprintln!("okp: {}", e);
+            1
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use std::{path::PathBuf, time::Duration};
+
+use serde::Serialize;
+use tipsy::Endpoint;
+use tokio::io::split;
+
+use crate::proxy_client::{read_framed_message, write_framed_to_app};
+
+// Run as 'onekeepass-proxy --diagnose [--json]' from a terminal. This checks the pieces that the
+// browser extension depends on - the native messaging manifest files, the proxy binary they point to
+// and the main app's IPC endpoint - and prints a report. Nothing here is written to stdout in the
+// native messaging framing, so this mode must never be launched by a browser.
+
+cfg_if::cfg_if! {
+    if #[cfg(debug_assertions)] {
+        const OKP_NATIVE_APP_NAME: &str = "org.onekeepass.onekeepass_browser_dev";
+    } else {
+        const OKP_NATIVE_APP_NAME: &str = "org.onekeepass.onekeepass_browser";
+    }
+}
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize)]
+struct ManifestCheck {
+    browser: String,
+    packaging: String,
+    manifest_path: String,
+    found: bool,
+    // Any problem found in the manifest content or the proxy path it refers
+    problems: Vec<String>,
+    proxy_path: Option<String>,
+    // True when the manifest points to this proxy executable
+    points_to_this_proxy: bool,
+}
+
+#[derive(Serialize)]
+struct ConnectionCheck {
+    endpoint: String,
+    connected: bool,
+    ping_ok: bool,
+    app_version: Option<String>,
+    round_trip_ms: Option<u128>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DiagnoseReport {
+    proxy_version: String,
+    proxy_path: String,
+    manifests: Vec<ManifestCheck>,
+    connection: ConnectionCheck,
+}
+
+struct ManifestLocation {
+    browser: &'static str,
+    packaging: &'static str,
+    dir: PathBuf,
+    // Windows only. The registry key that points to the manifest file
+    #[allow(dead_code)]
+    reg_key: Option<String>,
+}
+
+// Mirrors the manifest locations used in the main app's browser_service/native_messaging_config.rs
+#[cfg(target_os = "linux")]
+fn manifest_locations() -> Vec<ManifestLocation> {
+    let Some(home) = std::env::var_os("HOME").map(PathBuf::from) else {
+        return vec![];
+    };
+
+    let locations: [(&str, &str, &str); 14] = [
+        ("Firefox", "native", ".mozilla/native-messaging-hosts"),
+        ("Firefox", "flatpak", ".var/app/org.mozilla.firefox/.mozilla/native-messaging-hosts"),
+        ("LibreWolf", "native", ".librewolf/native-messaging-hosts"),
+        (
+            "LibreWolf",
+            "flatpak",
+            ".var/app/io.gitlab.librewolf-community/.librewolf/native-messaging-hosts",
+        ),
+        ("Chrome", "native", ".config/google-chrome/NativeMessagingHosts"),
+        (
+            "Chrome",
+            "flatpak",
+            ".var/app/com.google.Chrome/config/google-chrome/NativeMessagingHosts",
+        ),
+        ("Brave", "native", ".config/BraveSoftware/Brave-Browser/NativeMessagingHosts"),
+        (
+            "Brave",
+            "flatpak",
+            ".var/app/com.brave.Browser/config/BraveSoftware/Brave-Browser/NativeMessagingHosts",
+        ),
+        ("Chromium", "native", ".config/chromium/NativeMessagingHosts"),
+        (
+            "Chromium",
+            "flatpak",
+            ".var/app/org.chromium.Chromium/config/chromium/NativeMessagingHosts",
+        ),
+        ("Chromium", "snap", "snap/chromium/common/chromium/NativeMessagingHosts"),
+        ("Edge", "native", ".config/microsoft-edge/NativeMessagingHosts"),
+        (
+            "Edge",
+            "flatpak",
+            ".var/app/com.microsoft.Edge/config/microsoft-edge/NativeMessagingHosts",
+        ),
+        ("Vivaldi", "native", ".config/vivaldi/NativeMessagingHosts"),
+    ];
+
+    locations
+        .iter()
+        .map(|&(browser, packaging, dir)| ManifestLocation {
+            browser,
+            packaging,
+            dir: home.join(dir),
+            reg_key: None,
+        })
+        .collect()
+}
+
+#[cfg(target_os = "macos")]
+fn manifest_locations() -> Vec<ManifestLocation> {
+    let home = match std::env::var("HOME") {
+        Ok(h) => match h.find("/Library/Containers/") {
+            Some(idx) => PathBuf::from(&h[..idx]),
+            None => PathBuf::from(h),
+        },
+        Err(_) => return vec![],
+    };
+
+    [
+        ("Firefox", "Library/Application Support/Mozilla/NativeMessagingHosts"),
+        ("Chrome", "Library/Application Support/Google/Chrome/NativeMessagingHosts"),
+        ("Brave", "Library/Application Support/BraveSoftware/Brave-Browser/NativeMessagingHosts"),
+    ]
+    .iter()
+    .map(|&(browser, dir)| ManifestLocation {
+        browser,
+        packaging: "native",
+        dir: home.join(dir),
+        reg_key: None,
+    })
+    .collect()
+}
+
+#[cfg(target_os = "windows")]
+fn manifest_locations() -> Vec<ManifestLocation> {
+    let Some(local_app_data) = std::env::var_os("LOCALAPPDATA").map(PathBuf::from) else {
+        return vec![];
+    };
+
+    [
+        ("Firefox", "Software\\Mozilla\\NativeMessagingHosts"),
+        ("Chrome", "Software\\Google\\Chrome\\NativeMessagingHosts"),
+        ("Brave", "Software\\Google\\Chrome\\NativeMessagingHosts"),
+    ]
+    .iter()
+    .map(|&(browser, reg_path)| ManifestLocation {
+        browser,
+        packaging: "native",
+        dir: local_app_data.join("OneKeePass").join(browser),
+        reg_key: Some(format!("HKCU\\{}\\{}", reg_path, OKP_NATIVE_APP_NAME)),
+    })
+    .collect()
+}
+
+// Reads the default value of the registry key using 'reg query' so that the proxy does not need
+// a registry crate only for this diagnostic
+#[cfg(target_os = "windows")]
+fn registry_manifest_path(reg_key: &str) -> Option<String> {
+    let output = std::process::Command::new("reg").args(["query", reg_key, "/ve"]).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find(|l| l.contains("REG_SZ"))
+        .and_then(|l| l.split("REG_SZ").nth(1))
+        .map(|v| v.trim().to_string())
+}
+
+fn check_manifest(location: &ManifestLocation, this_proxy: &str) -> ManifestCheck {
+    let mut check = ManifestCheck {
+        browser: location.browser.to_string(),
+        packaging: location.packaging.to_string(),
+        manifest_path: location.dir.join(format!("{}.json", OKP_NATIVE_APP_NAME)).to_string_lossy().to_string(),
+        found: false,
+        problems: vec![],
+        proxy_path: None,
+        points_to_this_proxy: false,
+    };
+
+    #[cfg(target_os = "windows")]
+    if let Some(reg_key) = &location.reg_key {
+        match registry_manifest_path(reg_key) {
+            Some(p) => check.manifest_path = p,
+            None => check.problems.push(format!("Registry key {} is not found", reg_key)),
+        }
+    }
+
+    let Ok(content) = std::fs::read_to_string(&check.manifest_path) else {
+        return check;
+    };
+    check.found = true;
+
+    let json: serde_json::Value = match serde_json::from_str(&content) {
+        Ok(v) => v,
+        Err(e) => {
+            check.problems.push(format!("Manifest is not valid json: {}", e));
+            return check;
+        }
+    };
+
+    if json["name"].as_str() != Some(OKP_NATIVE_APP_NAME) {
+        check.problems.push(format!("Manifest 'name' is not {}", OKP_NATIVE_APP_NAME));
+    }
+
+    if json["type"].as_str() != Some("stdio") {
+        check.problems.push("Manifest 'type' is not stdio".to_string());
+    }
+
+    let allowed = if json.get("allowed_extensions").is_some() {
+        &json["allowed_extensions"]
+    } else {
+        &json["allowed_origins"]
+    };
+    if allowed.as_array().map(|a| a.is_empty()).unwrap_or(true) {
+        check.problems.push("Manifest has no allowed_extensions or allowed_origins".to_string());
+    }
+
+    match json["path"].as_str() {
+        Some(path) => {
+            let proxy = PathBuf::from(path);
+            if !proxy.exists() {
+                check.problems.push(format!("Proxy binary {} does not exist", path));
+            } else if !is_executable(&proxy) {
+                check.problems.push(format!("Proxy binary {} is not executable", path));
+            }
+            check.points_to_this_proxy = same_path(path, this_proxy);
+            check.proxy_path = Some(path.to_string());
+        }
+        None => check.problems.push("Manifest has no 'path'".to_string()),
+    }
+
+    check
+}
+
+#[cfg(unix)]
+fn is_executable(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).map(|m| m.permissions().mode() & 0o111 != 0).unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &std::path::Path) -> bool {
+    path.is_file()
+}
+
+#[cfg(target_os = "windows")]
+fn same_path(left: &str, right: &str) -> bool {
+    left.eq_ignore_ascii_case(right)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn same_path(left: &str, right: &str) -> bool {
+    left == right
+}
+
+// Connects to the main app's endpoint and does one framed Ping round trip
+async fn check_connection() -> ConnectionCheck {
+    let server_id = crate::server_id();
+    let mut check = ConnectionCheck {
+        endpoint: format!("{:?}", &server_id),
+        connected: false,
+        ping_ok: false,
+        app_version: None,
+        round_trip_ms: None,
+        error: None,
+    };
+
+    let connection = match tokio::time::timeout(CONNECT_TIMEOUT, Endpoint::connect(server_id)).await {
+        Ok(Ok(c)) => c,
+        Ok(Err(e)) => {
+            check.error = Some(format!(
+                "Connecting to the app failed: {}. Is OneKeePass running with browser integration enabled?",
+                e
+            ));
+            return check;
+        }
+        Err(_) => {
+            check.error = Some("Connecting to the app timed out".to_string());
+            return check;
+        }
+    };
+    check.connected = true;
+
+    let (mut reader, mut writer) = split(connection);
+
+    let started = std::time::Instant::now();
+    let ping = r#"{"action":"Ping","request_id":"diagnose"}"#;
+    if let Err(e) = write_framed_to_app(&mut writer, ping.as_bytes()).await {
+        check.error = Some(format!("Sending ping to the app failed: {}", e));
+        return check;
+    }
+
+    let reply = match tokio::time::timeout(PING_TIMEOUT, read_framed_message(&mut reader)).await {
+        Ok(Ok(bytes)) => bytes,
+        Ok(Err(e)) => {
+            check.error = Some(format!("Reading ping reply failed: {}", e));
+            return check;
+        }
+        Err(_) => {
+            check.error = Some("No ping reply from the app. The app may be an older version without Ping support".to_string());
+            return check;
+        }
+    };
+    check.round_trip_ms = Some(started.elapsed().as_millis());
+
+    match serde_json::from_slice::<serde_json::Value>(&reply) {
+        Ok(json) if json["ok"]["action"].as_str() == Some("Ping") => {
+            check.ping_ok = true;
+            check.app_version = json["ok"]["app_version"].as_str().map(|s| s.to_string());
+        }
+        Ok(json) => {
+            check.error = Some(format!("Unexpected ping reply {}", json));
+        }
+        Err(e) => {
+            check.error = Some(format!("Ping reply is not valid json: {}", e));
+        }
+    }
+
+    check
+}
+
+fn print_text_report(report: &DiagnoseReport) {
+    println!("OneKeePass proxy diagnostics");
+    println!("  Proxy version : {}", report.proxy_version);
+    println!("  Proxy path    : {}", report.proxy_path);
+    println!();
+    println!("Native messaging manifests");
+    for m in &report.manifests {
+        let state = if !m.found {
+            "not found"
+        } else if !m.problems.is_empty() {
+            "PROBLEM"
+        } else if m.points_to_this_proxy {
+            "ok"
+        } else {
+            "ok (points to another proxy)"
+        };
+        println!("  {} ({}) : {}", m.browser, m.packaging, state);
+        println!("      {}", m.manifest_path);
+        if let Some(p) = &m.proxy_path {
+            println!("      proxy: {}", p);
+        }
+        for problem in &m.problems {
+            println!("      - {}", problem);
+        }
+    }
+    println!();
+    let c = &report.connection;
+    println!("App connection");
+    println!("  Endpoint  : {}", c.endpoint);
+    println!("  Connected : {}", c.connected);
+    println!("  Ping      : {}", if c.ping_ok { "ok" } else { "failed" });
+    if let Some(v) = &c.app_version {
+        println!("  App version : {}", v);
+    }
+    if let Some(ms) = c.round_trip_ms {
+        println!("  Round trip  : {} ms", ms);
+    }
+    if let Some(e) = &c.error {
+        println!("  Error     : {}", e);
+    }
+}
+
+// Returns the process exit code. Non zero when the app could not be reached or no usable manifest is found
+pub(crate) async fn run(as_json: bool) -> i32 {
+    let this_proxy = std::env::current_exe().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+
+    let manifests: Vec<ManifestCheck> = manifest_locations().iter().map(|l| check_manifest(l, &this_proxy)).collect();
+    let connection = check_connection().await;
+
+    let report = DiagnoseReport {
+        proxy_version: env!("CARGO_PKG_VERSION").to_string(),
+        proxy_path: this_proxy,
+        manifests,
+        connection,
+    };
+
+    if as_json {
+        match serde_json::to_string_pretty(&report) {
+            Ok(s) => println!("{}", s),
+            Err(e) => eprintln!("Report serialization failed with error {}", e),
+        }
+    } else {
+        print_text_report(&report);
+    }
+
+    let usable_manifest = report.manifests.iter().any(|m| m.found && m.problems.is_empty());
+    if report.connection.ping_ok && usable_manifest {
+        0
+    } else {
+        1
+    }
+}
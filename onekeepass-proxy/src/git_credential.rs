@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    time::Duration,
+};
+
+use tipsy::Endpoint;
+use tokio::io::split;
+
+use crate::proxy_client::{read_framed_message, write_framed_to_app};
+
+// Run by git as a credential helper. Either
+//   git config --global credential.helper "/path/to/onekeepass-proxy git-credential"
+// or, with this binary copied/linked as 'git-credential-onekeepass' in the PATH,
+//   git config --global credential.helper onekeepass
+//
+// The remote given by git is sent to the main app over the same endpoint that the browser
+// proxies use and the app asks the user for each request:
+//   get:   The user picks one of the matching entries and only then the user name and password
+//          are returned.
+//   store: Git sends the credential that worked. The app keeps it in an entry the user picks or
+//          in a new entry. Nothing is asked when an entry already has it.
+//   erase: Git sends the credential that was rejected. The app moves the entry the user picks
+//          to the recycle bin.
+//
+// Nothing is written to stdout when no credential is available so that git asks the next helper
+// or prompts in the terminal. Like the diagnose mode, stdout is not in the native messaging framing.
+
+pub(crate) const GIT_CREDENTIAL_BINARY_NAME: &str = "git-credential-onekeepass";
+pub(crate) const GIT_CREDENTIAL_ARG: &str = "git-credential";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+// Longer than the time the app waits for the user before denying
+const REPLY_TIMEOUT: Duration = Duration::from_secs(90);
+
+// Returns the git operation (get/store/erase) when the proxy is run as a git credential helper
+pub(crate) fn git_operation(args: &[String]) -> Option<String> {
+    let invoked_as_helper = args
+        .first()
+        .and_then(|a| std::path::Path::new(a).file_stem().map(|s| s.to_string_lossy().to_string()))
+        .is_some_and(|name| name == GIT_CREDENTIAL_BINARY_NAME);
+
+    if invoked_as_helper {
+        Some(args.get(1).cloned().unwrap_or_default())
+    } else if args.get(1).map(|a| a.as_str()) == Some(GIT_CREDENTIAL_ARG) {
+        Some(args.get(2).cloned().unwrap_or_default())
+    } else {
+        None
+    }
+}
+
+// Reads the 'key=value' lines git writes to stdin till an empty line or the end of the input
+fn read_git_input() -> HashMap<String, String> {
+    let mut input = HashMap::new();
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            input.insert(key.to_string(), value.to_string());
+        }
+    }
+    input
+}
+
+// A value with a new line or NUL would break git's input format
+fn is_valid_value(value: &str) -> bool {
+    !value.contains(['\n', '\0'])
+}
+
+// Sends the remote and, for 'store' and 'erase', the credential to the app and returns the app's
+// reply
+async fn send_request(operation: &str, input: &HashMap<String, String>) -> Result<Option<serde_json::Value>, String> {
+    let (Some(protocol), Some(host)) = (input.get("protocol"), input.get("host")) else {
+        return Ok(None);
+    };
+
+    let request = serde_json::json!({
+        "action": "GitCredential",
+        "request_id": format!("git-{}", std::process::id()),
+        "operation": operation,
+        "protocol": protocol,
+        "host": host,
+        "path": input.get("path"),
+        "username": input.get("username"),
+        // Only in the input of 'store' and 'erase'
+        "password": input.get("password"),
+    })
+    .to_string();
+
+    let connection = match tokio::time::timeout(CONNECT_TIMEOUT, Endpoint::connect(crate::server_id())).await {
+        Ok(Ok(c)) => c,
+        Ok(Err(e)) => {
+            return Err(format!(
+                "OneKeePass is not reachable ({}). Is it running with browser integration enabled?",
+                e
+            ))
+        }
+        Err(_) => return Err("Connecting to OneKeePass timed out".to_string()),
+    };
+    let (mut reader, mut writer) = split(connection);
+
+    write_framed_to_app(&mut writer, request.as_bytes())
+        .await
+        .map_err(|e| format!("Sending the request to OneKeePass failed: {}", e))?;
+
+    let reply = match tokio::time::timeout(REPLY_TIMEOUT, read_framed_message(&mut reader)).await {
+        Ok(Ok(bytes)) => bytes,
+        Ok(Err(e)) => return Err(format!("Reading the reply from OneKeePass failed: {}", e)),
+        Err(_) => return Err("No reply from OneKeePass".to_string()),
+    };
+
+    serde_json::from_slice(&reply)
+        .map(Some)
+        .map_err(|e| format!("The reply is not valid json: {}", e))
+}
+
+async fn request_credential(input: &HashMap<String, String>) -> Result<Option<(Option<String>, String)>, String> {
+    let Some(json) = send_request("get", input).await? else {
+        return Ok(None);
+    };
+
+    if json["ok"]["action"].as_str() != Some("GitCredential") {
+        // Not found or denied by the user. An older app version replies with JsonParseError
+        log::info!("No git credential from the app: {}", json["error"]["error_message"]);
+        return Ok(None);
+    }
+
+    let Some(password) = json["ok"]["password"].as_str() else {
+        return Ok(None);
+    };
+    let username = json["ok"]["username"].as_str().map(|s| s.to_string());
+    Ok(Some((username, password.to_string())))
+}
+
+// For 'store' and 'erase'. Git does not read any output of these operations
+async fn update_credential(operation: &str, input: &HashMap<String, String>) -> Result<(), String> {
+    if !input.contains_key("password") {
+        return Ok(());
+    }
+    let Some(json) = send_request(operation, input).await? else {
+        return Ok(());
+    };
+
+    if json["ok"]["action"].as_str() == Some("GitCredentialUpdated") {
+        log::info!("Git credential {}: updated {}", operation, json["ok"]["updated"]);
+    } else {
+        log::info!("Git credential {} not done by the app: {}", operation, json["error"]["error_message"]);
+    }
+    Ok(())
+}
+
+// Returns the process exit code
+pub(crate) async fn run(operation: &str) -> i32 {
+    if operation.is_empty() {
+        eprintln!("Usage: {} <get|store|erase>", GIT_CREDENTIAL_BINARY_NAME);
+        return 1;
+    }
+
+    let input = read_git_input();
+
+    // As asked by git, any operation that is not supported is silently ignored
+    if operation == "store" || operation == "erase" {
+        if let Err(e) = update_credential(operation, &input).await {
+            log::error!("Git credential {} failed: {}", operation, e);
+            eprintln!("onekeepass: {}", e);
+        }
+        return 0;
+    }
+    if operation != "get" {
+        return 0;
+    }
+
+    match request_credential(&input).await {
+        Ok(Some((username, password))) => {
+            let mut out = String::new();
+            if let Some(username) = username.filter(|u| is_valid_value(u)) {
+                out.push_str(&format!("username={}\n", username));
+            }
+            if !is_valid_value(&password) {
+                log::error!("The password of the picked entry can not be passed to git");
+                return 0;
+            }
+            out.push_str(&format!("password={}\n", password));
+
+            let mut stdout = std::io::stdout().lock();
+            let _ = stdout.write_all(out.as_bytes());
+            let _ = stdout.flush();
+            0
+        }
+        Ok(None) => 0,
+        Err(e) => {
+            log::error!("Git credential request failed: {}", e);
+            eprintln!("onekeepass: {}", e);
+            0
+        }
+    }
+}
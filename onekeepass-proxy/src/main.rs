@@ -1,3 +1,6 @@
+mod cli;
+mod diagnose;
+mod git_credential;
 mod proxy_client;
 mod sandbox;
 
@@ -164,9 +167,61 @@ cfg_if::cfg_if! {
     }
 }
 
+// Under sandbox the parent app binds inside the App Group container; we must
+// connect to the same path. parent_folder() is a no-op on Windows (named pipes
+// ignore parent dir).
+//
+// Defensively ensure the group container dir exists before connecting.
+// The proxy is browser-spawned and may start before the parent app has
+// had a chance to create it. An entitled process can mkdir inside the
+// container directly without needing the NSFileManager API.
+//
+// A proxy copy launched by a Flatpak/Snap browser on Linux connects through the
+// runtime dir shared with the sandbox instead.
+pub(crate) fn server_id() -> ServerId<&'static str> {
+    endpoint_server_id(
+        NATIVE_MESSAGE_CONNECTION_NAME,
+        sandbox::group_container_path().or_else(sandbox::sandbox_ipc_dir),
+    )
+}
+
+// The endpoint 'name' of the parent app in the folder 'parent' or in tipsy's default location
+pub(crate) fn endpoint_server_id(name: &'static str, parent: Option<PathBuf>) -> ServerId<&'static str> {
+    match parent {
+        Some(parent) => {
+            let _ = std::fs::create_dir_all(&parent);
+            ServerId::new(name).parent_folder(parent)
+        }
+        None => ServerId::new(name),
+    }
+}
+
 //#[tokio::main(flavor = "multi_thread", worker_threads = 10)]
 #[tokio::main]
 async fn main() {
+    // Diagnostic mode is run by the user from a terminal. Browsers launch the proxy with
+    // the manifest path/extension origin as arguments and never with this flag
+    let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|a| a == "--diagnose") {
+        let _ = init_log();
+        let code = diagnose::run(args.iter().any(|a| a == "--json")).await;
+        std::process::exit(code);
+    }
+
+    // Git credential helper mode. Browsers never launch the proxy with this name or argument
+    if let Some(operation) = git_credential::git_operation(&args) {
+        let _ = init_log();
+        let code = git_credential::run(&operation).await;
+        std::process::exit(code);
+    }
+
+    // The okp command line client mode. Browsers never launch the proxy with this name or argument
+    if let Some(cli_args) = cli::cli_args(&args) {
+        let _ = init_log();
+        let code = cli::run(cli_args).await;
+        std::process::exit(code);
+    }
+
     if let Err(e) = init_log() {
         // eprintln!("Logging initialization failed with error {}", e);
         // send_proxy_error_message(format!("Logging initialization failed with error {}", e).as_ref());
@@ -180,21 +235,7 @@ async fn main() {
         Err(e) => log::warn!("Proxy launched ({} build); could not determine executable path: {}", build_mode, e),
     }
 
-    // Under sandbox the parent app binds inside the App Group container; we must
-    // connect to the same path. parent_folder() is a no-op on Windows (named pipes
-    // ignore parent dir).
-    //
-    // Defensively ensure the group container dir exists before connecting.
-    // The proxy is browser-spawned and may start before the parent app has
-    // had a chance to create it. An entitled process can mkdir inside the
-    // container directly without needing the NSFileManager API.
-    let server_id = match sandbox::group_container_path() {
-        Some(parent) => {
-            let _ = std::fs::create_dir_all(&parent);
-            ServerId::new(NATIVE_MESSAGE_CONNECTION_NAME).parent_folder(parent)
-        }
-        None => ServerId::new(NATIVE_MESSAGE_CONNECTION_NAME),
-    };
+    let server_id = server_id();
 
     log::info!("Proxy side end point connection pat (server_id) is {:?}", &server_id);
 
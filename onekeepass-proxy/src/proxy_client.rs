@@ -21,7 +21,9 @@ cfg_if::cfg_if! {
     }
 }
 
-async fn read_framed_message(reader: &mut ReadHalf<Connection>) -> std::io::Result<Vec<u8>> {
+pub(crate) async fn read_framed_message(
+    reader: &mut ReadHalf<Connection>,
+) -> std::io::Result<Vec<u8>> {
     let mut length_bytes = [0; 4];
     reader.read_exact(&mut length_bytes).await?;
 
@@ -41,7 +43,7 @@ async fn read_framed_message(reader: &mut ReadHalf<Connection>) -> std::io::Resu
     Ok(message_bytes)
 }
 
-async fn write_framed_to_app(
+pub(crate) async fn write_framed_to_app(
     writer: &mut WriteHalf<Connection>,
     body: &[u8],
 ) -> std::io::Result<()> {
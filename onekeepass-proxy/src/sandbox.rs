@@ -51,3 +51,23 @@ pub fn group_container_path() -> Option<PathBuf> {
 pub fn group_container_path() -> Option<PathBuf> {
     None
 }
+
+// When this proxy is a copy launched by a Flatpak or Snap packaged browser, the default
+// tipsy location (/tmp) is private to that sandbox. The parent app listens on an additional
+// endpoint in the runtime dir that is shared with the sandbox.
+// Must match 'sandbox_ipc_dir' in the parent app's browser_service/native_messaging_config.rs
+#[cfg(target_os = "linux")]
+pub fn sandbox_ipc_dir() -> Option<PathBuf> {
+    let runtime_dir = PathBuf::from(std::env::var_os("XDG_RUNTIME_DIR")?);
+    if let Some(app_id) = std::env::var_os("FLATPAK_ID") {
+        Some(runtime_dir.join("app").join(app_id))
+    } else {
+        let snap_name = std::env::var("SNAP_NAME").ok()?;
+        Some(runtime_dir.join(format!("snap.{}", snap_name)))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn sandbox_ipc_dir() -> Option<PathBuf> {
+    None
+}
@@ -185,6 +185,11 @@ pub(crate) fn app_backup_dir() -> PathBuf {
     app_home_dir().join("backups")
 }
 
+// Local copies of the remote dbs. See remote_storage::offline_cache
+pub(crate) fn app_remote_cache_dir() -> PathBuf {
+    app_home_dir().join("remote_cache")
+}
+
 pub(crate) fn wordlists_dir() -> PathBuf {
     app_home_dir().join("wordlists")
 }
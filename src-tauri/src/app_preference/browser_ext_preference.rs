@@ -4,11 +4,17 @@ use serde::{Deserialize, Serialize};
 
 use onekeepass_core::error::{self, Result};
 
+use crate::browser_service::{self, start_proxy_handler};
+
+#[cfg(any(target_os = "macos", target_os = "windows"))]
 use crate::browser_service::{
-    self, start_proxy_handler, BraveNativeMessagingConfig, ChromeNativeMessagingConfig,
-    FirefoxNativeMessagingConfig, BRAVE, CHROME, FIREFOX,
+    BraveNativeMessagingConfig, ChromeNativeMessagingConfig, FirefoxNativeMessagingConfig, BRAVE,
+    CHROME, FIREFOX,
 };
 
+#[cfg(target_os = "linux")]
+use crate::browser_service::{LinuxNativeMessagingConfig, SUPPORTED_BROWSERS};
+
 #[cfg(any(target_os = "macos", target_os = "windows"))]
 fn has_chromium_browser(browsers: &Vec<String>) -> bool {
     browsers.contains(&CHROME.to_string()) || browsers.contains(&BRAVE.to_string())
@@ -61,6 +67,20 @@ impl BrowserExtSupport {
     // folder via NSOpenPanel and the bookmark has already been stored.
     // Also (re)starts the proxy handler on success.
     pub(crate) fn write_browser_manifest_for(&mut self, browser_id: &str) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            if !SUPPORTED_BROWSERS.contains(&browser_id) {
+                return Err(error::Error::UnexpectedError(format!(
+                    "Unknown browser id '{}'",
+                    browser_id
+                )));
+            }
+            self.write_manifest_with_scope(browser_id, || {
+                LinuxNativeMessagingConfig::write(browser_id)
+            })?;
+        }
+
+        #[cfg(any(target_os = "macos", target_os = "windows"))]
         match browser_id {
             FIREFOX => {
                 self.write_manifest_with_scope(FIREFOX, FirefoxNativeMessagingConfig::write)?;
@@ -162,53 +182,41 @@ impl BrowserExtSupport {
 
         let result = if self.extension_use_enabled {
             // We call browser specific config file writing/removal first
+            #[cfg(any(target_os = "macos", target_os = "windows"))]
             let r = self
                 .firefox_ext_add_or_remove(&allowed_browsers, &other.allowed_browsers)
                 .and_then(|_| {
-                    #[cfg(any(target_os = "macos", target_os = "windows"))]
-                    {
-                        self.chromium_ext_add_or_remove(&allowed_browsers, &other.allowed_browsers)
-                    }
-
-                    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
-                    {
-                        self.chrome_ext_add_or_remove(&allowed_browsers, &other.allowed_browsers)
-                    }
-                })
-                .and_then(|_| {
-                    #[cfg(any(target_os = "macos", target_os = "windows"))]
-                    {
-                        Ok(())
-                    }
+                    self.chromium_ext_add_or_remove(&allowed_browsers, &other.allowed_browsers)
+                });
 
-                    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+            #[cfg(target_os = "linux")]
+            let r = SUPPORTED_BROWSERS.iter().try_for_each(|browser_id| {
+                self.linux_ext_add_or_remove(browser_id, &allowed_browsers, &other.allowed_browsers)
+            });
+
+            let r = r.and_then(|_| {
+                #[cfg(any(target_os = "macos", target_os = "windows"))]
+                {
+                    if other
+                        .reconnect_browsers
+                        .iter()
+                        .any(|browser_id| browser_id == CHROME || browser_id == BRAVE)
+                        && has_chromium_browser(&other.allowed_browsers)
                     {
-                        self.brave_ext_add_or_remove(&allowed_browsers, &other.allowed_browsers)
-                    }
-                })
-                .and_then(|_| {
-                    #[cfg(any(target_os = "macos", target_os = "windows"))]
-                    {
-                        if other
-                            .reconnect_browsers
-                            .iter()
-                            .any(|browser_id| browser_id == CHROME || browser_id == BRAVE)
-                            && has_chromium_browser(&other.allowed_browsers)
-                        {
-                            self.write_browser_manifest_for(CHROME)?;
-                        }
+                        self.write_browser_manifest_for(CHROME)?;
                     }
+                }
 
-                    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
-                    {
-                        for browser_id in &other.reconnect_browsers {
-                            if other.allowed_browsers.contains(browser_id) {
-                                self.write_browser_manifest_for(browser_id)?;
-                            }
+                #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+                {
+                    for browser_id in &other.reconnect_browsers {
+                        if other.allowed_browsers.contains(browser_id) {
+                            self.write_browser_manifest_for(browser_id)?;
                         }
                     }
-                    Ok(())
-                });
+                }
+                Ok(())
+            });
 
             if r.is_ok() {
                 // Finally update the allowed browsers list. Done only when all writes succeeded.
@@ -221,11 +229,19 @@ impl BrowserExtSupport {
 
             // Remove all existing browser native messaging config files as app level
             // extension use is disabled. Bookmark scope is used if sandboxed.
-            let _ = self.write_manifest_with_scope(FIREFOX, FirefoxNativeMessagingConfig::remove);
-            let _ = self.write_manifest_with_scope(CHROME, ChromeNativeMessagingConfig::remove);
+            #[cfg(any(target_os = "macos", target_os = "windows"))]
+            {
+                let _ =
+                    self.write_manifest_with_scope(FIREFOX, FirefoxNativeMessagingConfig::remove);
+                let _ = self.write_manifest_with_scope(CHROME, ChromeNativeMessagingConfig::remove);
+            }
 
-            #[cfg(not(any(target_os = "macos", target_os = "windows")))]
-            let _ = self.write_manifest_with_scope(BRAVE, BraveNativeMessagingConfig::remove);
+            #[cfg(target_os = "linux")]
+            for browser_id in SUPPORTED_BROWSERS {
+                let _ = self.write_manifest_with_scope(browser_id, || {
+                    LinuxNativeMessagingConfig::remove(browser_id)
+                });
+            }
 
             // Clear the stored bookmark files when integration is fully disabled so that
             // the next enable re-prompts via NSOpenPanel (fresh grant).
@@ -252,6 +268,7 @@ impl BrowserExtSupport {
         }
     }
 
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
     fn firefox_ext_add_or_remove(
         &mut self,
         existing_allowed_browsers: &Vec<String>,
@@ -332,79 +349,46 @@ impl BrowserExtSupport {
         Ok(())
     }
 
-    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
-    fn chrome_ext_add_or_remove(
+    // On Linux each supported browser has its own manifest locations (native, Flatpak and Snap)
+    #[cfg(target_os = "linux")]
+    fn linux_ext_add_or_remove(
         &mut self,
+        browser_id: &str,
         existing_allowed_browsers: &Vec<String>,
         new_allowed_browsers: &Vec<String>,
     ) -> Result<()> {
-        // We call the config file writing/removal only if there is a change in allowed_browsers
-        if !existing_allowed_browsers.contains(&CHROME.to_string())
-            && new_allowed_browsers.contains(&CHROME.to_string())
-        {
-            // Extension is enabled at the app level and browser Chrome is ext support enabled
-
-            log::debug!("Writing the chrome config....");
-
-            self.write_manifest_with_scope(CHROME, ChromeNativeMessagingConfig::write)?;
-
-            // As app level ext is enabled, we need to start the app side Endpoint to listen messages from proxy
-            start_proxy_handler();
-        } else if existing_allowed_browsers.contains(&CHROME.to_string())
-            && !new_allowed_browsers.contains(&CHROME.to_string())
-        {
-            // This means browser Chrome ext support is disabled and the allowed list does not have Chrome
-            // Then we remove the any previous config written so that next time
-            // if user enables Chrome in the allowed list, the config is written again
-
-            // Need to remove any previous user confirmations
-            self.user_confirmed_browsers.remove(CHROME);
-
-            log::debug!("Removing the chrome config...");
-            let r = self.write_manifest_with_scope(CHROME, ChromeNativeMessagingConfig::remove);
-            crate::mas::bookmarks::remove_browser_dir(CHROME);
-            log::debug!(
-                "After remove call for chrome native messaging config with result {:?}",
-                &r
-            );
-        }
+        let browser = browser_id.to_string();
 
-        Ok(())
-    }
-
-    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
-    fn brave_ext_add_or_remove(
-        &mut self,
-        existing_allowed_browsers: &Vec<String>,
-        new_allowed_browsers: &Vec<String>,
-    ) -> Result<()> {
         // We call the config file writing/removal only if there is a change in allowed_browsers
-        if !existing_allowed_browsers.contains(&BRAVE.to_string())
-            && new_allowed_browsers.contains(&BRAVE.to_string())
+        if !existing_allowed_browsers.contains(&browser) && new_allowed_browsers.contains(&browser)
         {
-            // Extension is enabled at the app level and browser Brave is ext support enabled
+            // Extension is enabled at the app level and this browser is ext support enabled
 
-            log::debug!("Writing the brave config....");
+            log::debug!("Writing the {} config....", browser_id);
 
-            self.write_manifest_with_scope(BRAVE, BraveNativeMessagingConfig::write)?;
+            self.write_manifest_with_scope(browser_id, || {
+                LinuxNativeMessagingConfig::write(browser_id)
+            })?;
 
             // As app level ext is enabled, we need to start the app side Endpoint to listen messages from proxy
             start_proxy_handler();
-        } else if existing_allowed_browsers.contains(&BRAVE.to_string())
-            && !new_allowed_browsers.contains(&BRAVE.to_string())
+        } else if existing_allowed_browsers.contains(&browser)
+            && !new_allowed_browsers.contains(&browser)
         {
-            // This means browser Brave ext support is disabled and the allowed list does not have Brave
-            // Then we remove the any previous config written so that next time
-            // if user enables Brave in the allowed list, the config is written again
+            // This browser's ext support is disabled and then we remove any previous config written
+            // so that next time if user enables it in the allowed list, the config is written again
 
             // Need to remove any previous user confirmations
-            self.user_confirmed_browsers.remove(BRAVE);
+            self.user_confirmed_browsers.remove(browser_id);
 
-            log::debug!("Removing the brave config...");
-            let r = self.write_manifest_with_scope(BRAVE, BraveNativeMessagingConfig::remove);
-            crate::mas::bookmarks::remove_browser_dir(BRAVE);
+            log::debug!("Removing the {} config...", browser_id);
+            let r = self.write_manifest_with_scope(browser_id, || {
+                LinuxNativeMessagingConfig::remove(browser_id)
+            });
+            crate::mas::bookmarks::remove_browser_dir(browser_id);
             log::debug!(
-                "After remove call for brave native messaging config with result {:?}",
+                "After remove call for {} native messaging config with result {:?}",
+                browser_id,
                 &r
             );
         }
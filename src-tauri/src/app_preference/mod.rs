@@ -14,6 +14,11 @@ pub(crate) use crate::app_preference::browser_ext_preference::BrowserExtSupportD
 pub(crate) struct BackupPreference {
     pub(crate) enabled: bool,
     pub(crate) dir: Option<String>,
+    // Older preference files do not have this and get the default (disabled) policy
+    #[serde(default)]
+    pub(crate) retention: BackupRetention,
+    #[serde(default)]
+    pub(crate) verify: BackupVerifyMode,
 }
 
 impl Default for BackupPreference {
@@ -21,10 +26,163 @@ impl Default for BackupPreference {
         Self {
             enabled: false,
             dir: None,
+            retention: BackupRetention::default(),
+            verify: BackupVerifyMode::default(),
         }
     }
 }
 
+// How a backup file is checked right after it is written
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum BackupVerifyMode {
+    #[default]
+    Off,
+    // KDBX header and the block stream framing are checked. No key is required
+    Header,
+    // In addition to the header checks, the saved database file is fully decrypted with
+    // the composite key of the opened database and the backup must have the same bytes.
+    // The backup of a remote database gets the header checks only
+    Full,
+}
+
+// Retention policy for the timestamped backups written to the custom backup dir.
+// Applied per database after each successful save. A backup is kept if any of the
+// 'keep_*' rules selects it; the newest backup is always kept.
+// A zero value turns that rule off
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub(crate) struct BackupRetention {
+    #[serde(default)]
+    pub(crate) enabled: bool,
+    // Number of the most recent backups to keep
+    #[serde(default)]
+    pub(crate) keep_last: u32,
+    // Newest backup of each of the last N days/weeks/months having a backup
+    #[serde(default)]
+    pub(crate) keep_daily: u32,
+    #[serde(default)]
+    pub(crate) keep_weekly: u32,
+    #[serde(default)]
+    pub(crate) keep_monthly: u32,
+    // Upper limit for the total size of the kept backups of a database
+    #[serde(default)]
+    pub(crate) max_total_size_mb: u64,
+}
+
+// Background checks of the opened remote (SFTP/WebDAV) dbs. See remote_storage::poller
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub(crate) struct RemoteSyncPreference {
+    // Seconds between the checks. Zero turns the background checks off
+    pub(crate) poll_interval_secs: u32,
+    // A remote change is merged without asking when the db has no unsaved changes
+    pub(crate) auto_merge: bool,
+    // Previous versions of the remote file kept on the server on each save. Zero turns
+    // it off. See remote_storage::history
+    pub(crate) history_copies: u8,
+}
+
+impl Default for RemoteSyncPreference {
+    fn default() -> Self {
+        Self {
+            poll_interval_secs: 60,
+            auto_merge: true,
+            history_copies: 0,
+        }
+    }
+}
+
+// Quick unlock of a locked db with a PIN. See quick_unlock.rs
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub(crate) struct QuickUnlockPreference {
+    // Minutes after which a PIN set for a db is removed and the full credentials are
+    // required again
+    pub(crate) pin_expiry_minutes: u32,
+}
+
+impl Default for QuickUnlockPreference {
+    fn default() -> Self {
+        Self {
+            pin_expiry_minutes: 60,
+        }
+    }
+}
+
+// Which session events lock all opened dbs (Linux). See session_lock.rs
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub(crate) struct SessionLockPreference {
+    // The system is about to suspend or hibernate
+    pub(crate) lock_on_sleep: bool,
+    // The desktop session is locked or the user switches to another session
+    pub(crate) lock_on_session_lock: bool,
+    // The screen saver is activated
+    pub(crate) lock_on_screen_saver: bool,
+}
+
+impl Default for SessionLockPreference {
+    fn default() -> Self {
+        Self {
+            lock_on_sleep: true,
+            lock_on_session_lock: true,
+            lock_on_screen_saver: true,
+        }
+    }
+}
+
+// Global enable flag of the Secret Service provider (Linux). See secret_service/mod.rs
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub(crate) struct SecretServiceSupport {
+    pub(crate) enabled: bool,
+}
+
+// A group of a db served as a Secret Service collection. The label is the group name when
+// it was chosen and is shown while the db is not opened
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub(crate) struct SecretServiceCollection {
+    pub(crate) db_key: String,
+    pub(crate) group_uuid: uuid::Uuid,
+    pub(crate) label: String,
+}
+
+// Global enable flag of the okp command line client access. See cli_service/mod.rs
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub(crate) struct CliSupport {
+    pub(crate) enabled: bool,
+}
+
+// The system tray icon. See tray.rs
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub(crate) struct TrayPreference {
+    pub(crate) show_icon: bool,
+    // The main window is hidden instead of quitting the app when it is closed. Used only
+    // when the icon is shown
+    pub(crate) close_to_tray: bool,
+}
+
+impl Default for TrayPreference {
+    fn default() -> Self {
+        Self {
+            show_icon: true,
+            close_to_tray: false,
+        }
+    }
+}
+
+// An okp client approved by the user on its first connect. Only the hash of the token
+// given to the client is kept
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub(crate) struct CliClient {
+    pub(crate) client_id: String,
+    pub(crate) name: String,
+    pub(crate) token_hash: String,
+    pub(crate) approved_at: chrono::NaiveDateTime,
+}
+
 // One entry in the recent-files list. Replaces the previous `Vec<String>`.
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub(crate) struct RecentFile {
@@ -52,5 +210,14 @@ pub(crate) struct PreferenceData {
     // it on every save; Preference::update merges it and AppState::update_preference
     // starts/stops the listener when it changes.
     ssh_agent_support: Option<SshAgentSupport>,
+    remote_sync: Option<RemoteSyncPreference>,
+    quick_unlock: Option<QuickUnlockPreference>,
+    session_lock: Option<SessionLockPreference>,
+    // Like ssh_agent_support, AppState::update_preference starts/stops the provider when
+    // the flag changes. The collections are set from the group menu instead
+    secret_service_support: Option<SecretServiceSupport>,
+    // The approved clients are removed through the 'revoke_cli_client' command
+    cli_support: Option<CliSupport>,
+    tray: Option<TrayPreference>,
     // browser_ext_supported_databases:Option<Vec<DatabaseBrowserExtSupport>>,
 }
@@ -1,8 +1,13 @@
+use std::collections::HashMap;
 use std::fs;
 
 use crate::app_preference::password_gen_preference::PasswordGeneratorPreference;
 
-use crate::app_preference::{BackupPreference, PreferenceData, RecentFile};
+use crate::app_preference::{
+    BackupPreference, CliClient, CliSupport, PreferenceData, QuickUnlockPreference, RecentFile,
+    RemoteSyncPreference, SecretServiceCollection, SecretServiceSupport, SessionLockPreference,
+    TrayPreference,
+};
 
 use crate::app_preference::browser_ext_preference::{BrowserExtSupport, BrowserExtSupportData};
 
@@ -119,6 +124,25 @@ pub(crate) struct SshAgentSupport {
     pub(crate) client_transport: SshAgentClientTransport,
 }
 
+// What to do when the file watcher finds that an opened database file was changed
+// by another program (e.g. a sync tool). Set per database
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ExternalChangePolicy {
+    // Ask the user to merge, reload or ignore
+    Prompt,
+    // Reload when there are no unsaved changes. Otherwise merge and save
+    AutoReload,
+    // Merge when there are no unsaved changes. Otherwise merge and save
+    AutoMerge,
+}
+
+impl Default for ExternalChangePolicy {
+    fn default() -> Self {
+        Self::Prompt
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub(crate) struct Preference {
     version: String,
@@ -155,6 +179,36 @@ pub(crate) struct Preference {
     // Global enable flag for the desktop SSH agent service. Disabled by default.
     #[serde(default)]
     ssh_agent_support: SshAgentSupport,
+
+    // Databases (keyed by db_key) with a policy other than the default 'Prompt'
+    #[serde(default)]
+    external_change_policies: HashMap<String, ExternalChangePolicy>,
+
+    #[serde(default)]
+    pub(crate) remote_sync: RemoteSyncPreference,
+
+    #[serde(default)]
+    pub(crate) quick_unlock: QuickUnlockPreference,
+
+    #[serde(default)]
+    pub(crate) session_lock: SessionLockPreference,
+
+    // Disabled by default like the SSH agent
+    #[serde(default)]
+    secret_service_support: SecretServiceSupport,
+
+    #[serde(default)]
+    secret_service_collections: Vec<SecretServiceCollection>,
+
+    // Disabled by default. The clients are added when the user approves them
+    #[serde(default)]
+    cli_support: CliSupport,
+
+    #[serde(default)]
+    cli_clients: Vec<CliClient>,
+
+    #[serde(default)]
+    pub(crate) tray: TrayPreference,
     // For now this feature is not used in the UI
     // This will be used in future to allow user to select which database to use with browser extension
     // Typically user will enable browser ext support for one or more databases in the database settings
@@ -181,6 +235,15 @@ impl Default for Preference {
 
             browser_ext_support: BrowserExtSupport::default(),
             ssh_agent_support: SshAgentSupport::default(),
+            external_change_policies: HashMap::default(),
+            remote_sync: RemoteSyncPreference::default(),
+            quick_unlock: QuickUnlockPreference::default(),
+            session_lock: SessionLockPreference::default(),
+            secret_service_support: SecretServiceSupport::default(),
+            secret_service_collections: vec![],
+            cli_support: CliSupport::default(),
+            cli_clients: vec![],
+            tray: TrayPreference::default(),
             // browser_ext_supported_databases: vec![],
         }
     }
@@ -420,6 +483,42 @@ impl Preference {
             updated = true;
         }
 
+        // The running background checks pick up the new interval on their next round
+        if let Some(v) = preference_data.remote_sync {
+            self.remote_sync = v;
+            updated = true;
+        }
+
+        // A PIN already set keeps the expiry it got when it was set
+        if let Some(v) = preference_data.quick_unlock {
+            self.quick_unlock = v;
+            updated = true;
+        }
+
+        // The session event watchers read this when an event is received
+        if let Some(v) = preference_data.session_lock {
+            self.session_lock = v;
+            updated = true;
+        }
+
+        // The provider is started/stopped by AppState::update_preference as done for the
+        // SSH agent
+        if let Some(v) = preference_data.secret_service_support {
+            self.secret_service_support = v;
+            updated = true;
+        }
+
+        if let Some(v) = preference_data.cli_support {
+            self.cli_support = v;
+            updated = true;
+        }
+
+        // The icon is shown/hidden by AppState::update_preference
+        if let Some(v) = preference_data.tray {
+            self.tray = v;
+            updated = true;
+        }
+
         // For now this feature is not used in the UI
         // This will be used in future to allow user to select which database to use with browser extension
 
@@ -485,6 +584,10 @@ impl Preference {
         self.recent_files.iter().any(|r| r.path == file_name)
     }
 
+    pub(crate) fn recent_file_paths(&self) -> Vec<String> {
+        self.recent_files.iter().map(|r| r.path.clone()).collect()
+    }
+
     pub(crate) fn clear_recent_files(&mut self) -> &mut Self {
         self.recent_files.clear();
         // MAS: Clearing recents should not revoke saved DB file permissions.
@@ -519,6 +622,83 @@ impl Preference {
         self.write_toml();
     }
 
+    #[cfg(target_os = "linux")]
+    pub(crate) fn is_secret_service_enabled(&self) -> bool {
+        self.secret_service_support.enabled
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn set_secret_service_enabled(&mut self, enabled: bool) {
+        self.secret_service_support.enabled = enabled;
+        self.write_toml();
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn secret_service_collections(&self) -> &[SecretServiceCollection] {
+        &self.secret_service_collections
+    }
+
+    // Adds or removes a group as a Secret Service collection
+    #[cfg(target_os = "linux")]
+    pub(crate) fn set_secret_service_collection(
+        &mut self,
+        db_key: &str,
+        group_uuid: &uuid::Uuid,
+        label: &str,
+        exposed: bool,
+    ) {
+        self.secret_service_collections
+            .retain(|c| !(c.db_key == db_key && &c.group_uuid == group_uuid));
+        if exposed {
+            self.secret_service_collections
+                .push(SecretServiceCollection {
+                    db_key: db_key.to_string(),
+                    group_uuid: *group_uuid,
+                    label: label.to_string(),
+                });
+        }
+        self.write_toml();
+    }
+
+    pub(crate) fn is_cli_enabled(&self) -> bool {
+        self.cli_support.enabled
+    }
+
+    pub(crate) fn cli_clients(&self) -> &[CliClient] {
+        &self.cli_clients
+    }
+
+    pub(crate) fn add_cli_client(&mut self, client: CliClient) {
+        self.cli_clients.push(client);
+        self.write_toml();
+    }
+
+    pub(crate) fn remove_cli_client(&mut self, client_id: &str) {
+        self.cli_clients.retain(|c| c.client_id != client_id);
+        self.write_toml();
+    }
+
+    pub(crate) fn external_change_policy(&self, db_key: &str) -> ExternalChangePolicy {
+        self.external_change_policies
+            .get(db_key)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn set_external_change_policy(
+        &mut self,
+        db_key: &str,
+        policy: ExternalChangePolicy,
+    ) {
+        if policy == ExternalChangePolicy::Prompt {
+            self.external_change_policies.remove(db_key);
+        } else {
+            self.external_change_policies
+                .insert(db_key.to_string(), policy);
+        }
+        self.write_toml();
+    }
+
     // Writes the native-messaging manifest for `browser_id` using the stored
     // security-scoped folder bookmark from the App Group bookmark store.
     pub(crate) fn write_browser_manifest(
@@ -17,7 +17,10 @@ use tauri::{AppHandle, State};
 
 use tauri::{path::BaseDirectory, App, Manager, Runtime};
 
-use crate::app_preference::{BrowserExtSupportData, Preference, PreferenceData};
+use crate::app_preference::{
+    BackupVerifyMode, BrowserExtSupportData, ExternalChangePolicy, Preference, PreferenceData,
+    QuickUnlockPreference, RemoteSyncPreference,
+};
 use crate::biometric;
 use crate::constants::standard_file_names::APP_PREFERENCE_FILE;
 use crate::key_secure;
@@ -121,6 +124,17 @@ pub(crate) fn init_app(app: &App) {
     // Start the SSH agent service if the user enabled it on a prior run
     state.start_ssh_agent_if_enabled();
 
+    // Start the Secret Service provider if the user enabled it on a prior run
+    #[cfg(target_os = "linux")]
+    state.start_secret_service_if_enabled();
+
+    // The okp command line endpoint. Requests are rejected while it is disabled
+    state.start_cli_service_if_enabled();
+
+    // Locks the opened dbs on screen lock, suspend and user switch as per the preference
+    #[cfg(target_os = "linux")]
+    crate::session_lock::start();
+
     info!("{}", "Intit app is done");
 }
 
@@ -190,6 +204,8 @@ pub(crate) struct AppState {
     timers_init_completed: Mutex<bool>,
     resource_dir_path: Mutex<Option<PathBuf>>,
     pub(crate) db_file_watcher: crate::db_file_watcher::DbFileWatcherState,
+    // Background checks of the opened remote dbs
+    pub(crate) remote_poller: crate::remote_storage::poller::RemotePollerState,
     // macOS App Sandbox security-scoped bookmark handles currently held open.
     // Empty on non-MAS builds. See `crate::mas`.
     scoped_access: Mutex<HashMap<mas::ScopedAccessKey, mas::BookmarkHandle>>,
@@ -211,6 +227,7 @@ impl AppState {
             timers_init_completed: Mutex::new(false),
             resource_dir_path: Mutex::new(None),
             db_file_watcher: crate::db_file_watcher::DbFileWatcherState::new(),
+            remote_poller: crate::remote_storage::poller::RemotePollerState::new(),
             scoped_access: Mutex::new(HashMap::new()),
             remote_mtimes: Mutex::new(HashMap::new()),
             active_db_key: Mutex::new(None),
@@ -335,6 +352,57 @@ impl AppState {
             file_util::generate_backup_file_name(backup_dir_path, db_file_name)
         }
     }
+
+    // Gets the custom backup dir set in the preference where the timestamped backups are kept
+    pub(crate) fn custom_backup_dir(&self) -> Option<PathBuf> {
+        let store_pref = self.preference.lock().unwrap();
+        let backup_dir = store_pref.backup.dir.as_ref()?.trim();
+        if backup_dir.is_empty() || !mas::can_use_backup_dir(backup_dir) {
+            return None;
+        }
+        Some(PathBuf::from(backup_dir))
+    }
+
+    pub(crate) fn backup_verify_mode(&self) -> BackupVerifyMode {
+        self.preference.lock().unwrap().backup.verify
+    }
+
+    pub(crate) fn remote_sync_preference(&self) -> RemoteSyncPreference {
+        self.preference.lock().unwrap().remote_sync.clone()
+    }
+
+    pub(crate) fn quick_unlock_preference(&self) -> QuickUnlockPreference {
+        self.preference.lock().unwrap().quick_unlock.clone()
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn session_lock_preference(&self) -> crate::app_preference::SessionLockPreference {
+        self.preference.lock().unwrap().session_lock.clone()
+    }
+
+    // Removes the older timestamped backups of the database for which 'backup_file_name'
+    // was just written as per the backup retention preference. Should be called only
+    // after a successful save so that the backup just written is among the kept ones
+    pub(crate) fn prune_backups(&self, backup_file_name: Option<&str>) {
+        let Some(backup_file_name) = backup_file_name else {
+            return;
+        };
+
+        let retention = {
+            let store_pref = self.preference.lock().unwrap();
+            // Only the custom dir backups are timestamped and retained as generations
+            if !store_pref.backup.enabled || !store_pref.backup.retention.enabled {
+                return;
+            }
+            store_pref.backup.retention.clone()
+        };
+
+        match file_util::prune_timestamped_backups(backup_file_name, &retention) {
+            Ok(0) => {}
+            Ok(n) => debug!("Pruned {} old backup files as per retention policy", n),
+            Err(e) => log::error!("Pruning of old backup files failed with error {}", e),
+        }
+    }
 }
 
 // All preference access fns are grouped under this impl
@@ -362,6 +430,13 @@ impl AppState {
             )
         };
 
+        let prior_cli_enabled = self.preference.lock().unwrap().is_cli_enabled();
+        let prior_tray_shown = self.preference.lock().unwrap().tray.show_icon;
+
+        #[cfg(target_os = "linux")]
+        let prior_secret_service_enabled =
+            self.preference.lock().unwrap().is_secret_service_enabled();
+
         let result = {
             let mut store_pref = self.preference.lock().unwrap();
             store_pref.update(preference_data)
@@ -389,6 +464,29 @@ impl AppState {
             crate::ssh_agent::start();
         }
 
+        // The listener is kept running once started and checks the flag on each request
+        if !prior_cli_enabled && self.preference.lock().unwrap().is_cli_enabled() {
+            crate::cli_service::start();
+        }
+
+        let current_tray_shown = self.preference.lock().unwrap().tray.show_icon;
+        if prior_tray_shown != current_tray_shown {
+            crate::tray::set_shown(current_tray_shown);
+        }
+
+        #[cfg(target_os = "linux")]
+        {
+            let current_secret_service_enabled =
+                self.preference.lock().unwrap().is_secret_service_enabled();
+            if prior_secret_service_enabled != current_secret_service_enabled {
+                if current_secret_service_enabled {
+                    crate::secret_service::start();
+                } else {
+                    crate::secret_service::stop();
+                }
+            }
+        }
+
         // If the backup dir actually changed, rotate the scoped-access handle
         // so the new dir's bookmark backs file writes for the rest of the
         // session. No-op outside macOS sandbox.
@@ -541,9 +639,10 @@ impl AppState {
             .start_proxy_handling_service();
     }
 
-    // Called once at app boot. Binds the SSH agent socket only if the user left
-    // the feature enabled on a prior run; otherwise stays disabled-by-default.
-    fn start_ssh_agent_if_enabled(&self) {
+    // Called at app boot and after a session lock stopped the agent (see session_lock.rs).
+    // Binds the SSH agent socket only if the user left the feature enabled on a prior
+    // run; otherwise stays disabled-by-default.
+    pub(crate) fn start_ssh_agent_if_enabled(&self) {
         let enabled = self.preference.lock().unwrap().is_ssh_agent_enabled();
         if enabled {
             crate::ssh_agent::start();
@@ -557,6 +656,75 @@ impl AppState {
         store_pref.set_ssh_agent_enabled(enabled);
     }
 
+    fn start_cli_service_if_enabled(&self) {
+        if self.is_cli_enabled() {
+            crate::cli_service::start();
+        }
+    }
+
+    pub(crate) fn is_cli_enabled(&self) -> bool {
+        self.preference.lock().unwrap().is_cli_enabled()
+    }
+
+    pub(crate) fn cli_clients(&self) -> Vec<crate::app_preference::CliClient> {
+        let store_pref = self.preference.lock().unwrap();
+        store_pref.cli_clients().to_vec()
+    }
+
+    pub(crate) fn add_cli_client(&self, client: crate::app_preference::CliClient) {
+        let mut store_pref = self.preference.lock().unwrap();
+        store_pref.add_cli_client(client);
+    }
+
+    pub(crate) fn remove_cli_client(&self, client_id: &str) {
+        let mut store_pref = self.preference.lock().unwrap();
+        store_pref.remove_cli_client(client_id);
+    }
+
+    #[cfg(target_os = "linux")]
+    fn start_secret_service_if_enabled(&self) {
+        let enabled = self.preference.lock().unwrap().is_secret_service_enabled();
+        if enabled {
+            crate::secret_service::start();
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn set_secret_service_enabled(&self, enabled: bool) {
+        let mut store_pref = self.preference.lock().unwrap();
+        store_pref.set_secret_service_enabled(enabled);
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn secret_service_collections(
+        &self,
+    ) -> Vec<crate::app_preference::SecretServiceCollection> {
+        let store_pref = self.preference.lock().unwrap();
+        store_pref.secret_service_collections().to_vec()
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn set_secret_service_collection(
+        &self,
+        db_key: &str,
+        group_uuid: &uuid::Uuid,
+        label: &str,
+        exposed: bool,
+    ) {
+        let mut store_pref = self.preference.lock().unwrap();
+        store_pref.set_secret_service_collection(db_key, group_uuid, label, exposed);
+    }
+
+    pub(crate) fn external_change_policy(&self, db_key: &str) -> ExternalChangePolicy {
+        let store_pref = self.preference.lock().unwrap();
+        store_pref.external_change_policy(db_key)
+    }
+
+    pub(crate) fn set_external_change_policy(&self, db_key: &str, policy: ExternalChangePolicy) {
+        let mut store_pref = self.preference.lock().unwrap();
+        store_pref.set_external_change_policy(db_key, policy);
+    }
+
     pub(crate) fn clear_recent_files(&self) {
         let mut store_pref = self.preference.lock().unwrap();
         store_pref.clear_recent_files();
@@ -567,6 +735,15 @@ impl AppState {
         store_pref.remove_recent_file(file_name);
     }
 
+    pub(crate) fn recent_file_paths(&self) -> Vec<String> {
+        let store_pref = self.preference.lock().unwrap();
+        store_pref.recent_file_paths()
+    }
+
+    pub(crate) fn tray_preference(&self) -> crate::app_preference::TrayPreference {
+        self.preference.lock().unwrap().tray.clone()
+    }
+
     pub(crate) fn app_version(&self) -> String {
         let store_pref = self.preference.lock().unwrap();
         store_pref.version().to_string()
@@ -13,7 +13,7 @@ use onekeepass_core::error::Result;
 use serde::Serialize;
 use tauri::State;
 
-use crate::{app_state, mas};
+use crate::{app_state, db_lock, mas};
 
 #[derive(Default, Serialize)]
 pub(crate) struct AutoOpenDbsInfo {
@@ -132,8 +132,9 @@ fn inner_open_all_auto_open_dbs(
         let kf_path = ao_resolved.key_file_path.as_deref();
 
         if let Some(db_key_to_open) = ao_resolved.url_field_value {
-            // Condider only the dbs that are not yet opened
-            if !kp_service::is_db_opened(&db_key_to_open) {
+            // Condider only the dbs that are not yet opened. A locked db is still opened
+            // in the app and is unlocked by the user
+            if !kp_service::is_db_opened(&db_key_to_open) && !db_lock::is_locked(&db_key_to_open) {
                 let mut scoped_access = mas::prepare_auto_open_db(&db_key_to_open, kf_path);
 
                 // Note: load_kdbx will add this db_key to 'all_kdbx_cache'
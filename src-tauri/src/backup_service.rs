@@ -0,0 +1,691 @@
+// Backup browser. Lists the backups written for an opened database (the single
+// backup file kept in the app's backup dir and the timestamped ones kept in the
+// custom backup dir), opens a chosen backup read-only with the credentials entered by
+// the user, compares it with the opened database and restores either the whole file
+// or the selected entries from it. The opened backups of a database are closed when
+// that database is locked or closed.
+//
+// Also verifies each backup right after it is written (as per the backup preference)
+// before the older backups are pruned.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufReader, Write};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use chrono::{DateTime, Local, NaiveDateTime};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tauri::{Emitter, Manager};
+use uuid::Uuid;
+
+use onekeepass_core::db_service as kp_service;
+use onekeepass_core::error::{self, Result};
+
+use crate::app_preference::BackupVerifyMode;
+use crate::app_state::AppState;
+use crate::constants::event_names::BACKUP_VERIFICATION_FAILED_EVENT;
+use crate::constants::window_labels::MAIN_WINDOW_LABEL;
+use crate::{app_paths, file_util, mas, ssh_agent};
+
+// A backup opened read-only is kept in core's cache with this prefix added to the
+// backup's full file name so that it never clashes with the same file opened as a
+// regular database
+const BACKUP_DB_KEY_PREFIX: &str = "Backup-";
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BackupKind {
+    // The single backup file kept in the app's backup dir
+    AppHome,
+    // One of the timestamped backups kept in the custom backup dir
+    Timestamped,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct BackupFileInfo {
+    pub(crate) file_name: String,
+    pub(crate) kind: BackupKind,
+    // Local time when this backup was written
+    pub(crate) backup_time: NaiveDateTime,
+    pub(crate) size: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct BackupEntryInfo {
+    pub(crate) entry_uuid: Uuid,
+    pub(crate) title: String,
+}
+
+// Differences of the opened database compared to a backup
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct BackupDiffSummary {
+    // Entries found only in the opened database
+    pub(crate) added: Vec<BackupEntryInfo>,
+    // Entries found only in the backup
+    pub(crate) removed: Vec<BackupEntryInfo>,
+    // Entries found in both but modified since the backup was written
+    pub(crate) changed: Vec<BackupEntryInfo>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct FailedEntryRestore {
+    pub(crate) entry_uuid: Uuid,
+    pub(crate) error: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct BackupEntriesRestored {
+    pub(crate) restored: Vec<Uuid>,
+    pub(crate) failed: Vec<FailedEntryRestore>,
+}
+
+pub(crate) fn is_backup_db_key(db_key: &str) -> bool {
+    db_key.starts_with(BACKUP_DB_KEY_PREFIX)
+}
+
+// The db_keys of the opened databases. An opened backup is also in core's cache and is
+// left out so that it is never used as a regular database
+pub(crate) fn opened_db_keys() -> Result<Vec<String>> {
+    Ok(kp_service::all_kdbx_cache_keys()?
+        .into_iter()
+        .filter(|k| !is_backup_db_key(k))
+        .collect())
+}
+
+fn backup_db_key(backup_file_name: &str) -> String {
+    format!("{BACKUP_DB_KEY_PREFIX}{backup_file_name}")
+}
+
+// The db_key of the database each opened backup belongs to, keyed by the backup's db_key
+fn opened_backups() -> &'static Mutex<HashMap<String, String>> {
+    static OPENED_BACKUPS: OnceLock<Mutex<HashMap<String, String>>> = OnceLock::new();
+    OPENED_BACKUPS.get_or_init(Default::default)
+}
+
+fn close_backup_db(backup_key: &str) -> Result<()> {
+    opened_backups().lock().unwrap().remove(backup_key);
+    if kp_service::is_db_opened(backup_key) {
+        kp_service::close_kdbx(backup_key)?;
+    }
+    Ok(())
+}
+
+// Closes the backups opened for the database 'db_key' so that their decrypted content is
+// not left in core's cache. Called when that database is locked or closed
+pub(crate) fn close_backups_of(db_key: &str) {
+    let backup_keys: Vec<String> = opened_backups()
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, parent)| parent.as_str() == db_key)
+        .map(|(backup_key, _)| backup_key.clone())
+        .collect();
+    for backup_key in backup_keys {
+        if let Err(e) = close_backup_db(&backup_key) {
+            log::error!("Closing the backup {} failed: {}", backup_key, e);
+        }
+    }
+}
+
+// Closes every backup found in core's cache. Called when all databases are locked
+pub(crate) fn close_all_backups() {
+    for backup_key in kp_service::all_kdbx_cache_keys()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|k| is_backup_db_key(k))
+    {
+        if let Err(e) = close_backup_db(&backup_key) {
+            log::error!("Closing the backup {} failed: {}", backup_key, e);
+        }
+    }
+    opened_backups().lock().unwrap().clear();
+}
+
+fn local_time_of(time: std::time::SystemTime) -> NaiveDateTime {
+    DateTime::<Local>::from(time).naive_local()
+}
+
+// Lists all backups of the database 'db_key' (newest first)
+pub(crate) fn list_backups(db_key: &str) -> Result<Vec<BackupFileInfo>> {
+    let mut backups = vec![];
+
+    if let Some(file_name) =
+        file_util::generate_backup_file_name(app_paths::app_backup_dir(), db_key)
+    {
+        if let Ok(metadata) = fs::metadata(&file_name) {
+            backups.push(BackupFileInfo {
+                file_name,
+                kind: BackupKind::AppHome,
+                backup_time: metadata.modified().map(local_time_of).unwrap_or_default(),
+                size: metadata.len(),
+            });
+        }
+    }
+
+    if let Some(backup_dir) = AppState::state_instance().custom_backup_dir() {
+        for b in file_util::list_timestamped_backups(&backup_dir, db_key)? {
+            backups.push(BackupFileInfo {
+                file_name: b.path.to_string_lossy().to_string(),
+                kind: BackupKind::Timestamped,
+                backup_time: b.timestamp,
+                size: b.size,
+            });
+        }
+    }
+
+    backups.sort_by(|a, b| b.backup_time.cmp(&a.backup_time));
+    Ok(backups)
+}
+
+// Only the backups that belong to the database 'db_key' can be opened or restored
+fn ensure_backup_of(db_key: &str, backup_file_name: &str) -> Result<()> {
+    if list_backups(db_key)?
+        .iter()
+        .any(|b| b.file_name == backup_file_name)
+    {
+        Ok(())
+    } else {
+        Err(error::Error::DataError(
+            "The file is not a backup of the opened database",
+        ))
+    }
+}
+
+// Opens the backup of the database 'db_key' read-only with the credentials the user
+// entered for it. A backup written before a credentials change needs the old ones.
+// The returned 'KdbxLoaded' has the db_key to use for reading the backup's content
+pub(crate) fn open_backup(
+    db_key: &str,
+    backup_file_name: &str,
+    password: Option<&str>,
+    key_file_name: Option<&str>,
+) -> Result<kp_service::KdbxLoaded> {
+    ensure_backup_of(db_key, backup_file_name)?;
+
+    let backup_key = backup_db_key(backup_file_name);
+    // Reopening always reads the current file content
+    close_backup_db(&backup_key)?;
+
+    let file_name = Path::new(backup_file_name)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string());
+
+    let mut reader = BufReader::new(fs::File::open(backup_file_name)?);
+    let kdbx_loaded = kp_service::read_kdbx(
+        &mut reader,
+        &backup_key,
+        password,
+        key_file_name,
+        file_name.as_deref(),
+    )?;
+    opened_backups()
+        .lock()
+        .unwrap()
+        .insert(backup_key, db_key.to_string());
+
+    Ok(kdbx_loaded)
+}
+
+pub(crate) fn close_backup(backup_file_name: &str) -> Result<()> {
+    close_backup_db(&backup_db_key(backup_file_name))
+}
+
+// Returns the db_key of the backup opened for the database 'db_key'. The backup needs
+// to be opened first with its credentials
+fn opened_backup_db_key(db_key: &str, backup_file_name: &str) -> Result<String> {
+    let backup_key = backup_db_key(backup_file_name);
+    let opened_for_db = opened_backups()
+        .lock()
+        .unwrap()
+        .get(&backup_key)
+        .is_some_and(|parent| parent == db_key);
+    if opened_for_db && kp_service::is_db_opened(&backup_key) {
+        Ok(backup_key)
+    } else {
+        Err(error::Error::DataError("The backup is not opened"))
+    }
+}
+
+fn entry_titles(db_key: &str) -> Result<HashMap<Uuid, String>> {
+    let entries = kp_service::entry_summary_data(db_key, kp_service::EntryCategory::AllEntries)?;
+    Ok(entries
+        .into_iter()
+        .filter_map(|e| Uuid::parse_str(&e.uuid).ok().map(|u| (u, e.title)))
+        .collect())
+}
+
+// Compares the opened database 'db_key' with its backup
+pub(crate) fn compare_with_backup(
+    db_key: &str,
+    backup_file_name: &str,
+) -> Result<BackupDiffSummary> {
+    let backup_key = opened_backup_db_key(db_key, backup_file_name)?;
+
+    let current = entry_titles(db_key)?;
+    let in_backup = entry_titles(&backup_key)?;
+
+    let mut summary = BackupDiffSummary::default();
+
+    for (entry_uuid, title) in &current {
+        if !in_backup.contains_key(entry_uuid) {
+            summary.added.push(BackupEntryInfo {
+                entry_uuid: *entry_uuid,
+                title: title.clone(),
+            });
+            continue;
+        }
+
+        let current_form = kp_service::get_entry_form_data_by_id(db_key, entry_uuid)?;
+        let backup_form = kp_service::get_entry_form_data_by_id(&backup_key, entry_uuid)?;
+        if current_form.last_modification_time != backup_form.last_modification_time {
+            summary.changed.push(BackupEntryInfo {
+                entry_uuid: *entry_uuid,
+                title: title.clone(),
+            });
+        }
+    }
+
+    for (entry_uuid, title) in &in_backup {
+        if !current.contains_key(entry_uuid) {
+            summary.removed.push(BackupEntryInfo {
+                entry_uuid: *entry_uuid,
+                title: title.clone(),
+            });
+        }
+    }
+
+    for v in [
+        &mut summary.added,
+        &mut summary.removed,
+        &mut summary.changed,
+    ] {
+        v.sort_by(|a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase()));
+    }
+
+    Ok(summary)
+}
+
+fn restore_entry(db_key: &str, backup_key: &str, entry_uuid: &Uuid) -> Result<()> {
+    let form_data = kp_service::get_entry_form_data_by_id(backup_key, entry_uuid)?;
+
+    if kp_service::get_entry_form_data_by_id(db_key, entry_uuid).is_ok() {
+        // The current values are kept in the entry's history by the update
+        kp_service::update_entry_from_form_data(db_key, form_data)?;
+    } else {
+        if kp_service::get_group_by_id(db_key, &form_data.group_uuid).is_err() {
+            return Err(error::Error::DataError(
+                "The group of this entry is not found in the opened database",
+            ));
+        }
+        kp_service::insert_entry_from_form_data(db_key, form_data)?;
+    }
+    Ok(())
+}
+
+// Copies the selected entries from the backup into the opened database. The opened
+// database is not saved here and the user saves it as usual
+pub(crate) fn restore_entries_from_backup(
+    db_key: &str,
+    backup_file_name: &str,
+    entry_uuids: &[Uuid],
+) -> Result<BackupEntriesRestored> {
+    let backup_key = opened_backup_db_key(db_key, backup_file_name)?;
+
+    let mut result = BackupEntriesRestored::default();
+    for entry_uuid in entry_uuids {
+        match restore_entry(db_key, &backup_key, entry_uuid) {
+            Ok(()) => result.restored.push(*entry_uuid),
+            Err(e) => result.failed.push(FailedEntryRestore {
+                entry_uuid: *entry_uuid,
+                error: format!("{}", e),
+            }),
+        }
+    }
+
+    if !result.restored.is_empty() {
+        ssh_agent::reload_keys_for_db(db_key);
+    }
+
+    Ok(result)
+}
+
+// Writes the data to a temp file in the same dir, flushes it to the disk and then renames
+// it over 'file_name' so that the file is never left partly written
+fn write_file_atomically(file_name: &str, data: &[u8]) -> Result<()> {
+    let path = Path::new(file_name);
+    let tmp_name = format!(
+        ".{}.okp-tmp",
+        path.file_name()
+            .map(|n| n.to_string_lossy())
+            .unwrap_or_default()
+    );
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let written = fs::File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|_| fs::rename(&tmp_path, path)) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+// Replaces the database file with the backup and reloads the opened database.
+// The current file is first copied as a backup so that the restore can be undone
+pub(crate) fn restore_backup_file(
+    db_key: &str,
+    backup_file_name: &str,
+) -> Result<kp_service::KdbxLoaded> {
+    if crate::remote_storage::is_remote_db_key(db_key) {
+        return Err(error::Error::DataError(
+            "Restoring the whole file is not supported for remote databases. Restore the entries instead",
+        ));
+    }
+
+    // The backup must have been opened, which confirms that it is a readable KDBX file,
+    // before the database file is touched. The reload below fails and the current file is
+    // written back when the backup uses other credentials than the opened database
+    ensure_backup_of(db_key, backup_file_name)?;
+    opened_backup_db_key(db_key, backup_file_name)?;
+    close_backup(backup_file_name)?;
+
+    // The backup may be the same file as the one the current content is copied to
+    let backup_bytes = fs::read(backup_file_name)?;
+
+    let app_state = AppState::state_instance();
+    let _db_file_access = mas::db_file_access(db_key);
+    let current_bytes = fs::read(db_key)?;
+
+    if let Some(copy_file_name) = app_state.get_backup_file(db_key) {
+        fs::write(&copy_file_name, &current_bytes)?;
+        backup_written(db_key, Some(&copy_file_name));
+    }
+
+    // Our own write should not be reported as an external change
+    app_state.db_file_watcher.stop_watching(db_key);
+    let reloaded =
+        write_file_atomically(db_key, &backup_bytes).and_then(|_| kp_service::reload_kdbx(db_key));
+
+    let result = match reloaded {
+        Ok(kdbx_loaded) => {
+            ssh_agent::reload_keys_for_db(db_key);
+            Ok(kdbx_loaded)
+        }
+        Err(e) => {
+            log::error!("Restoring backup {} failed: {}", backup_file_name, e);
+            if let Err(write_err) = write_file_atomically(db_key, &current_bytes) {
+                log::error!("Writing back the database file failed: {}", write_err);
+            }
+            Err(e)
+        }
+    };
+    app_state.db_file_watcher.start_watching(db_key);
+
+    result
+}
+
+// ---- Backup verification ----
+
+const KDBX_SIGNATURE1: u32 = 0x9AA2_D903;
+const KDBX_SIGNATURE2: u32 = 0xB54B_FB67;
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct BackupVerification {
+    pub(crate) backup_file_name: String,
+    pub(crate) mode: BackupVerifyMode,
+    pub(crate) verified_time: NaiveDateTime,
+    // None when the backup passed the verification
+    pub(crate) error: Option<String>,
+}
+
+// The result of the last verification done for each database
+static LAST_VERIFICATIONS: OnceLock<Mutex<HashMap<String, BackupVerification>>> = OnceLock::new();
+
+fn last_verifications() -> &'static Mutex<HashMap<String, BackupVerification>> {
+    LAST_VERIFICATIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+pub(crate) fn last_verification(db_key: &str) -> Option<BackupVerification> {
+    last_verifications().lock().unwrap().get(db_key).cloned()
+}
+
+#[derive(Clone, Serialize)]
+struct BackupVerificationFailedPayload {
+    db_key: String,
+    backup_file_name: String,
+    error: String,
+}
+
+fn read_u32_le(data: &[u8], pos: usize) -> Option<u32> {
+    data.get(pos..pos.checked_add(4)?)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+// Checks the KDBX header and, for KDBX 4, the header checksum and the framing of the
+// HMAC block stream till the final empty block. The HMAC values themselves can only
+// be checked with the key and that is done by the full verification
+fn verify_kdbx_structure(data: &[u8]) -> std::result::Result<(), &'static str> {
+    const TRUNCATED_HEADER: &str = "The KDBX header is truncated";
+    const TRUNCATED_BLOCKS: &str = "The KDBX block stream is truncated";
+
+    if read_u32_le(data, 0) != Some(KDBX_SIGNATURE1)
+        || read_u32_le(data, 4) != Some(KDBX_SIGNATURE2)
+    {
+        return Err("The file is not a KDBX file");
+    }
+
+    let major_version = read_u32_le(data, 8).ok_or(TRUNCATED_HEADER)? >> 16;
+    // The header field size is u16 in KDBX 3 and u32 in KDBX 4
+    let size_len = match major_version {
+        3 => 2,
+        4 => 4,
+        _ => return Err("Unsupported KDBX version"),
+    };
+
+    let mut pos = 12;
+    loop {
+        let field_type = *data.get(pos).ok_or(TRUNCATED_HEADER)?;
+        pos += 1;
+        let size = if size_len == 2 {
+            data.get(pos..pos + 2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+                .ok_or(TRUNCATED_HEADER)?
+        } else {
+            read_u32_le(data, pos).ok_or(TRUNCATED_HEADER)? as usize
+        };
+        pos = (pos + size_len)
+            .checked_add(size)
+            .filter(|p| *p <= data.len())
+            .ok_or(TRUNCATED_HEADER)?;
+        // End of header field
+        if field_type == 0 {
+            break;
+        }
+    }
+
+    // The KDBX 3 content can only be checked by decrypting it
+    if major_version == 3 {
+        return Ok(());
+    }
+
+    let header_hash = data.get(pos..pos + 32).ok_or(TRUNCATED_HEADER)?;
+    if Sha256::digest(&data[..pos]).as_slice() != header_hash {
+        return Err("The KDBX header checksum does not match");
+    }
+    // Header's SHA-256 and HMAC-SHA-256
+    pos += 64;
+
+    // Each block is a 32 bytes HMAC, the u32 block size and the block data.
+    // The last block is an empty one
+    loop {
+        let size = read_u32_le(data, pos + 32).ok_or(TRUNCATED_BLOCKS)? as usize;
+        pos = (pos + 36)
+            .checked_add(size)
+            .filter(|p| *p <= data.len())
+            .ok_or(TRUNCATED_BLOCKS)?;
+        if size == 0 {
+            break;
+        }
+    }
+
+    if pos != data.len() {
+        return Err("Unexpected data found after the KDBX block stream");
+    }
+
+    Ok(())
+}
+
+// The backup is the content just saved to the database file. The database file is
+// decrypted with the composite key of the opened database 'db_key' and the backup must
+// have the same bytes
+fn verify_by_decrypting(db_key: &str, data: &[u8]) -> Result<()> {
+    let _db_file_access = mas::db_file_access(db_key);
+    if fs::read(db_key)? != data {
+        return Err(error::Error::DataError(
+            "The backup differs from the saved database file",
+        ));
+    }
+    kp_service::read_and_verify_db_file(db_key)
+}
+
+// The full verification needs the local database file. A remote database's backup gets
+// the header and blocks check only
+fn effective_verify_mode(db_key: &str, mode: BackupVerifyMode) -> BackupVerifyMode {
+    if mode == BackupVerifyMode::Full && crate::remote_storage::is_remote_db_key(db_key) {
+        BackupVerifyMode::Header
+    } else {
+        mode
+    }
+}
+
+fn verify_backup(
+    db_key: &str,
+    backup_file_name: &str,
+    mode: BackupVerifyMode,
+) -> std::result::Result<(), String> {
+    let data = fs::read(backup_file_name).map_err(|e| e.to_string())?;
+    verify_kdbx_structure(&data).map_err(|e| e.to_string())?;
+    if mode == BackupVerifyMode::Full {
+        verify_by_decrypting(db_key, &data).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn emit_verification_failed(payload: BackupVerificationFailedPayload) {
+    if let Some(win) = AppState::global_app_handle().get_webview_window(MAIN_WINDOW_LABEL) {
+        if let Err(e) = win.emit(BACKUP_VERIFICATION_FAILED_EVENT, payload) {
+            log::error!("Failed to emit BACKUP_VERIFICATION_FAILED_EVENT: {}", e);
+        }
+    }
+}
+
+// Called after each successful save. The backup just written is verified first (when
+// enabled in the preference) and the older backups are pruned only when it is good so
+// that a broken backup never causes a good one to be removed
+pub(crate) fn backup_written(db_key: &str, backup_file_name: Option<&str>) {
+    let Some(backup_file_name) = backup_file_name else {
+        return;
+    };
+    let app_state = AppState::state_instance();
+
+    let mode = effective_verify_mode(db_key, app_state.backup_verify_mode());
+    if mode != BackupVerifyMode::Off {
+        let outcome = verify_backup(db_key, backup_file_name, mode);
+
+        last_verifications().lock().unwrap().insert(
+            db_key.to_string(),
+            BackupVerification {
+                backup_file_name: backup_file_name.to_string(),
+                mode,
+                verified_time: Local::now().naive_local(),
+                error: outcome.as_ref().err().cloned(),
+            },
+        );
+
+        if let Err(e) = outcome {
+            log::error!("Backup {} failed verification: {}", backup_file_name, &e);
+            emit_verification_failed(BackupVerificationFailedPayload {
+                db_key: db_key.to_string(),
+                backup_file_name: backup_file_name.to_string(),
+                error: e,
+            });
+            return;
+        }
+    }
+
+    app_state.prune_backups(Some(backup_file_name));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_kdbx_structure, KDBX_SIGNATURE1, KDBX_SIGNATURE2};
+    use sha2::{Digest, Sha256};
+
+    // A KDBX 4 file with one header field and one data block
+    fn kdbx4_bytes() -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&KDBX_SIGNATURE1.to_le_bytes());
+        data.extend_from_slice(&KDBX_SIGNATURE2.to_le_bytes());
+        data.extend_from_slice(&0x0004_0001u32.to_le_bytes());
+        // Cipher id field
+        data.push(2);
+        data.extend_from_slice(&16u32.to_le_bytes());
+        data.extend_from_slice(&[7u8; 16]);
+        // End of header
+        data.push(0);
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(b"\r\n\r\n");
+
+        let header_hash = Sha256::digest(&data);
+        data.extend_from_slice(&header_hash);
+        data.extend_from_slice(&[0u8; 32]);
+
+        data.extend_from_slice(&[1u8; 32]);
+        data.extend_from_slice(&10u32.to_le_bytes());
+        data.extend_from_slice(&[9u8; 10]);
+        data.extend_from_slice(&[2u8; 32]);
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn valid_kdbx4_structure_is_accepted() {
+        assert_eq!(verify_kdbx_structure(&kdbx4_bytes()), Ok(()));
+    }
+
+    #[test]
+    fn truncated_backup_is_rejected() {
+        let data = kdbx4_bytes();
+        assert!(verify_kdbx_structure(&data[..data.len() - 5]).is_err());
+        assert!(verify_kdbx_structure(&data[..20]).is_err());
+        assert!(verify_kdbx_structure(&[]).is_err());
+    }
+
+    #[test]
+    fn corrupted_header_is_rejected() {
+        let mut data = kdbx4_bytes();
+        data[20] ^= 0xff;
+        assert_eq!(
+            verify_kdbx_structure(&data),
+            Err("The KDBX header checksum does not match")
+        );
+
+        let mut data = kdbx4_bytes();
+        data[0] = 0;
+        assert_eq!(
+            verify_kdbx_structure(&data),
+            Err("The file is not a KDBX file")
+        );
+    }
+
+    #[test]
+    fn trailing_data_is_rejected() {
+        let mut data = kdbx4_bytes();
+        data.push(0);
+        assert!(verify_kdbx_structure(&data).is_err());
+    }
+}
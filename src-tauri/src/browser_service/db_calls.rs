@@ -11,7 +11,7 @@ use crate::browser_service::{passkey_db, passkey_db::OpenedDbInfo};
 // Returns a generic error if the key is not recognised to avoid revealing
 // which databases exist (enumeration protection).
 pub(crate) fn validate_db_key(db_key: &str) -> Result<()> {
-    let open_keys = kp_service::all_kdbx_cache_keys()?;
+    let open_keys = crate::backup_service::opened_db_keys()?;
     if open_keys.iter().any(|k| k == db_key) {
         Ok(())
     } else {
@@ -31,7 +31,7 @@ pub(crate) struct AllMatchedEntries {
 
 pub(crate) fn find_matching_in_enabled_db_entries(input_url: &str) -> Result<AllMatchedEntries> {
     // TODO: Need to get only the browser enabaled databases
-    let enabled_db_keys = kp_service::all_kdbx_cache_keys()?;
+    let enabled_db_keys = crate::backup_service::opened_db_keys()?;
 
     // log::debug!("In find_matching_in_enabled_db_entries enabled_db_keys are {:?}", &enabled_db_keys);
 
@@ -109,7 +109,7 @@ pub(crate) fn find_matching_passkeys(
     rp_id: &str,
     allow_credential_ids: Vec<String>,
 ) -> Result<PasskeyListResult> {
-    let db_keys = kp_service::all_kdbx_cache_keys()?;
+    let db_keys = crate::backup_service::opened_db_keys()?;
     let browser_enabled_db_available = !db_keys.is_empty();
     let passkey_list = passkey_db::find_matching_passkeys(rp_id, allow_credential_ids)?;
     Ok(PasskeyListResult {
@@ -125,6 +125,7 @@ pub(crate) fn sign_passkey_assertion(
     entry_uuid: &Uuid,
     options_json: &str,
     origin: &str,
+    user_verified: bool,
 ) -> Result<String> {
-    passkey_db::sign_passkey_assertion(db_key, entry_uuid, options_json, origin)
+    passkey_db::sign_passkey_assertion(db_key, entry_uuid, options_json, origin, user_verified)
 }
@@ -0,0 +1,513 @@
+// Git credential helper support. The 'git-credential-onekeepass' mode of onekeepass-proxy
+// connects to the same endpoint as the browser proxies and sends a 'GitCredential' request
+// for each git 'get', 'store' and 'erase' (see message.rs).
+//
+// get: The entries of the opened dbs whose URL field matches the remote are offered to the
+// user in a dialog. Only the entry the user picks there is returned and nothing is returned
+// without the user's answer.
+//
+// store: Sent by git after the credential worked. Nothing is asked when a matching entry
+// already has this password, which is the case for a credential returned by 'get'. Otherwise
+// the user picks a matching entry to update or a db to add a new Login entry to.
+//
+// erase: Sent by git after the credential was rejected. The user picks which of the matching
+// entries with this password is moved to the recycle bin.
+//
+// A locked db is not searched or changed as its content is closed in the backend
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tauri::Emitter;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use onekeepass_core::db_service as kp_service;
+use onekeepass_core::error::{self, Result};
+
+use crate::app_state::AppState;
+use crate::browser_service::passkey_db;
+use crate::constants::event_names::{
+    GIT_CREDENTIAL_DATA_CHANGED_EVENT, GIT_CREDENTIAL_REQUEST_EVENT,
+};
+use crate::entry_form_util;
+
+// Git waits for the helper without any timeout of its own. This keeps a 'git push' from
+// hanging when the dialog is ignored
+const CONFIRM_TIMEOUT_SECS: u64 = 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GitOperation {
+    #[default]
+    Get,
+    Store,
+    Erase,
+}
+
+// The remote as described by git in the helper input
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct GitRemote {
+    pub(crate) protocol: String,
+    // May include the port (e.g. "example.com:8443")
+    pub(crate) host: String,
+    // Sent by git only when 'credential.useHttpPath' is set
+    pub(crate) path: Option<String>,
+    pub(crate) username: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub(crate) struct GitCredentialMatch {
+    db_key: String,
+    db_name: String,
+    entry_uuid: Uuid,
+    title: String,
+    username: Option<String>,
+}
+
+// An opened db where a new entry can be added for a 'store'
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub(crate) struct GitCredentialDb {
+    db_key: String,
+    db_name: String,
+}
+
+pub(crate) struct GitCredential {
+    pub(crate) username: Option<String>,
+    pub(crate) password: String,
+}
+
+// The user's answer. The entry uuid is None when a new entry is to be added to the db
+type Selected = Option<(String, Option<Uuid>)>;
+
+struct PendingRequest {
+    matches: Vec<GitCredentialMatch>,
+    new_entry_dbs: Vec<GitCredentialDb>,
+    tx: oneshot::Sender<Selected>,
+}
+
+// In-flight requests keyed by a generated request id. The answer command removes the entry
+fn pending_requests() -> &'static Mutex<HashMap<String, PendingRequest>> {
+    static PENDING_REQUESTS: OnceLock<Mutex<HashMap<String, PendingRequest>>> = OnceLock::new();
+    PENDING_REQUESTS.get_or_init(Default::default)
+}
+
+// Payload sent to the UI to raise the entry pick dialog. No secret is sent
+#[derive(Serialize, Clone, Debug)]
+struct GitCredentialRequestPayload {
+    request_id: String,
+    operation: GitOperation,
+    protocol: String,
+    host: String,
+    path: Option<String>,
+    matches: Vec<GitCredentialMatch>,
+    // Only for a 'store'
+    new_entry_dbs: Vec<GitCredentialDb>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct DbKeyPayload {
+    db_key: String,
+}
+
+// The parts of an URL field value that are compared with the remote. A value without a
+// scheme (e.g. "github.com/org") has no protocol
+#[derive(Debug, PartialEq)]
+struct UrlParts {
+    protocol: Option<String>,
+    host: String,
+    path: String,
+}
+
+fn normalized_path(path: &str) -> String {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let path = path.trim_matches('/');
+    path.strip_suffix(".git").unwrap_or(path).to_string()
+}
+
+fn url_parts(url: &str) -> Option<UrlParts> {
+    let url = url.trim();
+    let (protocol, rest) = match url.split_once("://") {
+        Some((p, r)) => (Some(p.to_lowercase()), r),
+        None => (None, url),
+    };
+    let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+    // Any user info is dropped
+    let host = authority
+        .rsplit('@')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    if host.is_empty() {
+        return None;
+    }
+    Some(UrlParts {
+        protocol,
+        host,
+        path: normalized_path(path),
+    })
+}
+
+// True when an entry with this URL field value and user name can be offered for the remote.
+// The url path, when given in the entry, needs to be a prefix of the remote path. It is
+// ignored when git does not send the path
+fn matches_remote(url: &str, entry_username: Option<&str>, remote: &GitRemote) -> bool {
+    let Some(parts) = url_parts(url) else {
+        return false;
+    };
+    if parts.host != remote.host.to_lowercase() {
+        return false;
+    }
+    if parts
+        .protocol
+        .as_deref()
+        .is_some_and(|p| p != remote.protocol.to_lowercase())
+    {
+        return false;
+    }
+    if let Some(remote_path) = remote.path.as_deref() {
+        let remote_path = normalized_path(remote_path);
+        let prefix_matched =
+            remote_path == parts.path || remote_path.starts_with(&format!("{}/", parts.path));
+        if !parts.path.is_empty() && !prefix_matched {
+            return false;
+        }
+    }
+    if let Some(username) = remote.username.as_deref() {
+        if entry_username != Some(username) {
+            return false;
+        }
+    }
+    true
+}
+
+// The matching entries of all opened dbs. Entries in the recycle bin are not included
+pub(crate) fn find_matches(remote: &GitRemote) -> Result<Vec<GitCredentialMatch>> {
+    let db_keys = crate::backup_service::opened_db_keys()?;
+    let mut result = vec![];
+
+    for db_key in &db_keys {
+        // The db may have been closed or locked in the meantime
+        let Ok(entries) =
+            kp_service::entry_summary_data(db_key, kp_service::EntryCategory::AllEntries)
+        else {
+            continue;
+        };
+        let db_name =
+            kp_service::browser_extension::get_db_name(db_key).unwrap_or_else(|_| db_key.clone());
+
+        for summary in entries {
+            let Ok(entry_uuid) = Uuid::parse_str(&summary.uuid) else {
+                continue;
+            };
+            let Ok(kvs) = kp_service::entry_key_value_fields(db_key, &entry_uuid) else {
+                continue;
+            };
+            let Some(url) = kvs.get(kp_service::entry_keyvalue_key::URL) else {
+                continue;
+            };
+            let username = kvs
+                .get(kp_service::entry_keyvalue_key::USER_NAME)
+                .filter(|u| !u.is_empty())
+                .cloned();
+            if matches_remote(url, username.as_deref(), remote) {
+                result.push(GitCredentialMatch {
+                    db_key: db_key.clone(),
+                    db_name: db_name.clone(),
+                    entry_uuid,
+                    title: summary.title,
+                    username,
+                });
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn credential(db_key: &str, entry_uuid: &Uuid) -> Result<GitCredential> {
+    let kvs = kp_service::entry_key_value_fields(db_key, entry_uuid)?;
+    let password = kvs
+        .get(kp_service::entry_keyvalue_key::PASSWORD)
+        .filter(|p| !p.is_empty())
+        .cloned()
+        .ok_or(error::Error::DataError(
+            "The entry does not have a password",
+        ))?;
+    Ok(GitCredential {
+        username: kvs
+            .get(kp_service::entry_keyvalue_key::USER_NAME)
+            .filter(|u| !u.is_empty())
+            .cloned(),
+        password,
+    })
+}
+
+// The opened dbs that are not locked
+fn opened_dbs() -> Result<Vec<GitCredentialDb>> {
+    Ok(crate::backup_service::opened_db_keys()?
+        .into_iter()
+        .filter(|db_key| !crate::db_lock::is_locked(db_key))
+        .map(|db_key| GitCredentialDb {
+            db_name: kp_service::browser_extension::get_db_name(&db_key)
+                .unwrap_or_else(|_| db_key.clone()),
+            db_key,
+        })
+        .collect())
+}
+
+// The URL kept in a new entry for the remote
+fn remote_url(remote: &GitRemote) -> String {
+    match remote.path.as_deref() {
+        Some(path) => format!("{}://{}/{}", remote.protocol, remote.host, path),
+        None => format!("{}://{}", remote.protocol, remote.host),
+    }
+}
+
+// Raises the dialog and waits for the user's pick. Returns None when the user denied or the
+// request timed out
+async fn ask_user(
+    operation: GitOperation,
+    remote: &GitRemote,
+    matches: Vec<GitCredentialMatch>,
+    new_entry_dbs: Vec<GitCredentialDb>,
+) -> Selected {
+    let request_id = Uuid::new_v4().to_string();
+    let (tx, rx) = oneshot::channel::<Selected>();
+
+    pending_requests().lock().unwrap().insert(
+        request_id.clone(),
+        PendingRequest {
+            matches: matches.clone(),
+            new_entry_dbs: new_entry_dbs.clone(),
+            tx,
+        },
+    );
+
+    let payload = GitCredentialRequestPayload {
+        request_id: request_id.clone(),
+        operation,
+        protocol: remote.protocol.clone(),
+        host: remote.host.clone(),
+        path: remote.path.clone(),
+        matches,
+        new_entry_dbs,
+    };
+
+    if let Err(e) = AppState::global_app_handle().emit(GIT_CREDENTIAL_REQUEST_EVENT, payload) {
+        log::error!("Git credential: failed to emit request event: {}", e);
+        pending_requests().lock().unwrap().remove(&request_id);
+        return None;
+    }
+
+    let outcome = tokio::time::timeout(Duration::from_secs(CONFIRM_TIMEOUT_SECS), rx).await;
+    pending_requests().lock().unwrap().remove(&request_id);
+
+    match outcome {
+        Ok(Ok(selected)) => selected,
+        _ => {
+            log::info!("Git credential: request timed out or was abandoned; denying");
+            None
+        }
+    }
+}
+
+// Saves the changed db and informs the UI
+fn save_changed_db(db_key: &str) -> Result<()> {
+    passkey_db::save_db_with_backup(db_key)?;
+    let payload = DbKeyPayload {
+        db_key: db_key.to_string(),
+    };
+    if let Err(e) = AppState::global_app_handle().emit(GIT_CREDENTIAL_DATA_CHANGED_EVENT, payload) {
+        log::error!("Git credential: failed to emit data changed event: {}", e);
+    }
+    Ok(())
+}
+
+// Finds the matching entries, raises the dialog and waits for the user's pick. Returns
+// None when nothing matched, the user denied or the request timed out
+pub(crate) async fn request_credential(remote: GitRemote) -> Result<Option<GitCredential>> {
+    let matches = find_matches(&remote)?;
+    // Git then asks the next helper or the user in the terminal. The dialog is not
+    // shown as there is nothing to pick
+    if matches.is_empty() {
+        return Ok(None);
+    }
+
+    match ask_user(GitOperation::Get, &remote, matches, vec![]).await {
+        Some((db_key, Some(entry_uuid))) => credential(&db_key, &entry_uuid).map(Some),
+        _ => Ok(None),
+    }
+}
+
+// Keeps the credential that worked for the remote. Returns true when a db was changed
+pub(crate) async fn store_credential(remote: GitRemote, password: String) -> Result<bool> {
+    let matches = find_matches(&remote)?;
+    if matches
+        .iter()
+        .any(|m| credential(&m.db_key, &m.entry_uuid).is_ok_and(|c| c.password == password))
+    {
+        return Ok(false);
+    }
+    let new_entry_dbs = opened_dbs()?;
+    if new_entry_dbs.is_empty() {
+        return Ok(false);
+    }
+
+    let Some((db_key, entry_uuid)) =
+        ask_user(GitOperation::Store, &remote, matches, new_entry_dbs).await
+    else {
+        return Ok(false);
+    };
+
+    let mut form_data = match entry_uuid {
+        Some(entry_uuid) => kp_service::get_entry_form_data_by_id(&db_key, &entry_uuid)?,
+        None => {
+            let mut form_data = entry_form_util::new_login_entry_form_data(&db_key, None)?;
+            form_data.title = remote.host.clone();
+            entry_form_util::set_field_value(
+                &mut form_data,
+                kp_service::entry_keyvalue_key::URL,
+                &remote_url(&remote),
+            );
+            if let Some(username) = remote.username.as_deref() {
+                entry_form_util::set_field_value(
+                    &mut form_data,
+                    kp_service::entry_keyvalue_key::USER_NAME,
+                    username,
+                );
+            }
+            form_data
+        }
+    };
+    if !entry_form_util::set_field_value(
+        &mut form_data,
+        kp_service::entry_keyvalue_key::PASSWORD,
+        &password,
+    ) {
+        return Err(error::Error::DataError(
+            "The entry does not have a password field",
+        ));
+    }
+
+    if entry_uuid.is_some() {
+        kp_service::update_entry_from_form_data(&db_key, form_data)?;
+    } else {
+        kp_service::insert_entry_from_form_data(&db_key, form_data)?;
+    }
+    save_changed_db(&db_key)?;
+    log::info!("Git credential: stored for {}", remote.host);
+    Ok(true)
+}
+
+// Removes the credential that the remote rejected. Only the entries with this password are
+// offered and the one picked is moved to the recycle bin. Returns true when a db was changed
+pub(crate) async fn erase_credential(remote: GitRemote, password: String) -> Result<bool> {
+    let matches: Vec<_> = find_matches(&remote)?
+        .into_iter()
+        .filter(|m| credential(&m.db_key, &m.entry_uuid).is_ok_and(|c| c.password == password))
+        .collect();
+    if matches.is_empty() {
+        return Ok(false);
+    }
+
+    let Some((db_key, Some(entry_uuid))) =
+        ask_user(GitOperation::Erase, &remote, matches, vec![]).await
+    else {
+        return Ok(false);
+    };
+
+    kp_service::move_entry_to_recycle_bin(&db_key, entry_uuid)?;
+    save_changed_db(&db_key)?;
+    log::info!("Git credential: erased for {}", remote.host);
+    Ok(true)
+}
+
+// Delivers the user's answer (or None when denied). Called from the
+// `git_credential_confirm_result` command. Only one of the offered entries or, for a
+// 'store', one of the offered dbs without an entry is accepted
+pub(crate) fn submit_confirmation(request_id: &str, selected: Selected) {
+    let Some(pending) = pending_requests().lock().unwrap().remove(request_id) else {
+        log::warn!(
+            "Git credential: answer for unknown/expired request {}",
+            request_id
+        );
+        return;
+    };
+    let selected = selected.filter(|(db_key, entry_uuid)| match entry_uuid {
+        Some(entry_uuid) => pending
+            .matches
+            .iter()
+            .any(|m| &m.db_key == db_key && &m.entry_uuid == entry_uuid),
+        None => pending.new_entry_dbs.iter().any(|d| &d.db_key == db_key),
+    });
+    let _ = pending.tx.send(selected);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(host: &str, path: Option<&str>, username: Option<&str>) -> GitRemote {
+        GitRemote {
+            protocol: "https".into(),
+            host: host.into(),
+            path: path.map(String::from),
+            username: username.map(String::from),
+        }
+    }
+
+    #[test]
+    fn verify_url_parts() {
+        assert_eq!(
+            url_parts("https://octo@GitHub.com/org/repo.git?x=1"),
+            Some(UrlParts {
+                protocol: Some("https".into()),
+                host: "github.com".into(),
+                path: "org/repo".into(),
+            })
+        );
+        assert_eq!(
+            url_parts("gitlab.example.com:8443"),
+            Some(UrlParts {
+                protocol: None,
+                host: "gitlab.example.com:8443".into(),
+                path: "".into(),
+            })
+        );
+        assert_eq!(url_parts("https:///path"), None);
+        assert_eq!(url_parts(""), None);
+    }
+
+    #[test]
+    fn verify_matches_remote() {
+        let r = remote("github.com", None, None);
+        assert!(matches_remote("https://github.com", None, &r));
+        assert!(matches_remote("github.com/org", None, &r));
+        assert!(!matches_remote("http://github.com", None, &r));
+        assert!(!matches_remote("https://gitlab.com", None, &r));
+        assert!(!matches_remote("https://github.com.evil.com", None, &r));
+
+        let r = remote("github.com", Some("org/repo.git"), None);
+        assert!(matches_remote("https://github.com", None, &r));
+        assert!(matches_remote("https://github.com/org", None, &r));
+        assert!(matches_remote("https://github.com/org/repo", None, &r));
+        assert!(!matches_remote("https://github.com/org/other", None, &r));
+        assert!(!matches_remote("https://github.com/or", None, &r));
+
+        let r = remote("github.com", None, Some("octo"));
+        assert!(matches_remote("https://github.com", Some("octo"), &r));
+        assert!(!matches_remote("https://github.com", Some("other"), &r));
+        assert!(!matches_remote("https://github.com", None, &r));
+    }
+
+    #[test]
+    fn verify_remote_url() {
+        assert_eq!(remote_url(&remote("github.com", None, None)), "https://github.com");
+        assert_eq!(
+            remote_url(&remote("github.com", Some("org/repo.git"), None)),
+            "https://github.com/org/repo.git"
+        );
+    }
+}
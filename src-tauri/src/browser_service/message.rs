@@ -6,14 +6,41 @@ use uuid::Uuid;
 use crate::app_state;
 
 use crate::browser_service::{
-    db_calls,
+    db_calls, git_credential,
     key_share::{BrowserServiceTx, SessionStore},
-    native_messaging_config, passkey_db, verifier, SUPPORTED_BROWSERS,
+    native_messaging_config, passkey_db,
+    user_verification::{self, Ceremony},
+    verifier, SUPPORTED_BROWSERS,
 };
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "action")]
 pub enum Request {
+    // Sent by 'onekeepass-proxy --diagnose' to check that the app side endpoint is reachable.
+    // No association or session is required and the reply has no secret data
+    Ping {
+        #[serde(default)]
+        request_id: Option<String>,
+    },
+
+    // Sent by 'onekeepass-proxy git-credential <operation>' for git's credential helper
+    // 'get', 'store' and 'erase'. As for 'Ping', there is no association or session. The user
+    // confirms each request in the app (see git_credential.rs). The password is sent only
+    // for 'store' and 'erase'
+    GitCredential {
+        request_id: String,
+        #[serde(default)]
+        operation: git_credential::GitOperation,
+        protocol: String,
+        host: String,
+        #[serde(default)]
+        path: Option<String>,
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<String>,
+    },
+
     // Called first time when the extension app is about to use the OneKeePass app
     Associate {
         client_id: String,
@@ -137,6 +164,12 @@ fn is_known_extension_id(browser_id: &str, extension_id: &str) -> bool {
         "Firefox" => extension_id == native_messaging_config::FIREFOX_EXTENSION_ID,
         "Chrome" => native_messaging_config::CHROME_EXTENSION_IDS.contains(&extension_id),
         "Brave" => native_messaging_config::BRAVE_EXTENSION_IDS.contains(&extension_id),
+        // LibreWolf installs the extension from the Firefox add-ons store and the other
+        // Chromium based browsers from the Chrome Web Store
+        "LibreWolf" => extension_id == native_messaging_config::FIREFOX_EXTENSION_ID,
+        "Chromium" | "Edge" | "Vivaldi" => {
+            native_messaging_config::CHROME_EXTENSION_IDS.contains(&extension_id)
+        }
         _ => false,
     }
 }
@@ -212,6 +245,30 @@ fn validate_origin_matches_tab_url(
     Ok(())
 }
 
+// Asks the user to verify (db password or biometric) when the site requires user
+// verification. Returns whether the user was verified, which becomes the UV flag of the
+// authenticator data, or an error when the required verification failed
+async fn verify_user_if_required(
+    db_key: &str,
+    options_json: &str,
+    origin: &str,
+    ceremony: Ceremony,
+) -> onekeepass_core::error::Result<bool> {
+    if !passkey_db::user_verification_required(options_json) {
+        return Ok(false);
+    }
+    let rp_id = passkey_db::rp_id_from_options(options_json, origin);
+    if user_verification::request_user_verification(db_key, &rp_id, ceremony).await {
+        Ok(true)
+    } else {
+        Err(user_verification_failed())
+    }
+}
+
+fn user_verification_failed() -> onekeepass_core::error::Error {
+    onekeepass_core::error::Error::UnexpectedError("USER_VERIFICATION_FAILED".to_string())
+}
+
 // Rejects oversized string fields before they reach the database or crypto layer.
 // Returns a generic error to avoid leaking field details to the caller.
 fn check_field_len(
@@ -235,6 +292,28 @@ fn check_field_len(
 }
 
 impl Request {
+    // Whether the request may wait for the user's answer in the app. The proxy handler
+    // handles these in their own task (see proxy_handler.rs)
+    pub(crate) fn waits_for_user(input_message: &str) -> bool {
+        #[derive(Deserialize)]
+        struct RequestAction {
+            action: String,
+            #[serde(default)]
+            options_json: Option<String>,
+        }
+
+        let Ok(request) = serde_json::from_str::<RequestAction>(input_message) else {
+            return false;
+        };
+        match request.action.as_str() {
+            "GitCredential" => true,
+            "CreatePasskey" | "CompletePasskeyAssertion" => request
+                .options_json
+                .is_some_and(|o| passkey_db::user_verification_required(&o)),
+            _ => false,
+        }
+    }
+
     // Called when the app side proxy handler receives a native message json string from browser extension through okp proxy stdio app
     pub(crate) async fn handle_input_message(input_message: String, sender: Arc<BrowserServiceTx>) {
         // log::debug!("In handle_input_message ...");
@@ -243,6 +322,33 @@ impl Request {
         // Currently there is no request that has any sensitive data. So the extension side no encryption is done
         // In the future we pass any sensitive data as "message_data", then we need to decrypt and then convert that json to rust struct
         match serde_json::from_str(&input_message) {
+            Ok(Request::Ping { request_id }) => {
+                let resp = ResponseResult::with_ok(Response::Ping {
+                    app_version: app_state::AppState::state_instance().app_version(),
+                    request_id,
+                });
+                // Replied directly as there is no session for this request
+                let _r = sender.send(resp.json_str()).await;
+            }
+
+            Ok(Request::GitCredential {
+                request_id,
+                operation,
+                protocol,
+                host,
+                path,
+                username,
+                password,
+            }) => {
+                let remote = git_credential::GitRemote {
+                    protocol,
+                    host,
+                    path,
+                    username,
+                };
+                Self::git_credential(operation, remote, password, &request_id, sender).await;
+            }
+
             Ok(Request::Associate {
                 client_id,
                 extension_id,
@@ -411,6 +517,86 @@ impl Request {
         }
     }
 
+    // The credential is sent to the git helper unencrypted over the local endpoint like the
+    // secret of an ssh key through the ssh agent socket. Replied directly as there is no session
+    async fn git_credential(
+        operation: git_credential::GitOperation,
+        remote: git_credential::GitRemote,
+        password: Option<String>,
+        request_id: &str,
+        sender: Arc<BrowserServiceTx>,
+    ) {
+        let checked = check_field_len("protocol", &remote.protocol, 64)
+            .and_then(|_| check_field_len("host", &remote.host, 1024))
+            .and_then(|_| check_field_len("path", remote.path.as_deref().unwrap_or_default(), 2048))
+            .and_then(|_| {
+                check_field_len(
+                    "username",
+                    remote.username.as_deref().unwrap_or_default(),
+                    1024,
+                )
+            })
+            .and_then(|_| {
+                check_field_len("password", password.as_deref().unwrap_or_default(), 4096)
+            });
+
+        let resp = match checked {
+            Ok(()) => match operation {
+                git_credential::GitOperation::Get => {
+                    Self::git_credential_get(remote, request_id).await
+                }
+                git_credential::GitOperation::Store | git_credential::GitOperation::Erase => {
+                    Self::git_credential_update(operation, remote, password, request_id).await
+                }
+            },
+            Err(e) => ResponseActionName::GitCredential.from_error(e, request_id),
+        };
+        let _r = sender.send(resp.json_str()).await;
+    }
+
+    async fn git_credential_get(
+        remote: git_credential::GitRemote,
+        request_id: &str,
+    ) -> ResponseResult {
+        match git_credential::request_credential(remote).await {
+            Ok(Some(credential)) => ResponseResult::with_ok(Response::GitCredential {
+                request_id: request_id.to_string(),
+                username: credential.username,
+                password: credential.password,
+            }),
+            Ok(None) => ResponseResult::from_error(
+                ResponseActionName::GitCredential,
+                "GIT_CREDENTIAL_NOT_AVAILABLE",
+                request_id,
+            ),
+            Err(e) => ResponseActionName::GitCredential.from_error(e, request_id),
+        }
+    }
+
+    // For 'store' and 'erase'. The reply only tells whether a db was changed
+    async fn git_credential_update(
+        operation: git_credential::GitOperation,
+        remote: git_credential::GitRemote,
+        password: Option<String>,
+        request_id: &str,
+    ) -> ResponseResult {
+        // Git sends the password in both cases. Nothing can be matched without it
+        let updated = match password.filter(|p| !p.is_empty()) {
+            None => Ok(false),
+            Some(password) if operation == git_credential::GitOperation::Store => {
+                git_credential::store_credential(remote, password).await
+            }
+            Some(password) => git_credential::erase_credential(remote, password).await,
+        };
+        match updated {
+            Ok(updated) => ResponseResult::with_ok(Response::GitCredentialUpdated {
+                request_id: request_id.to_string(),
+                updated,
+            }),
+            Err(e) => ResponseActionName::GitCredential.from_error(e, request_id),
+        }
+    }
+
     // Called first time when the extension sends the associate message
     // Need to check that either user has already enabled the browser extension use and if not we need to ask user
     // confirm the extension use
@@ -762,17 +948,25 @@ impl Request {
                 )
             })
             .and_then(|_| db_calls::validate_db_key(db_key))
-            .and_then(|_| {
-                passkey_db::create_and_store_passkey(
-                    db_key,
-                    options_json,
-                    origin,
-                    existing_entry_uuid,
-                    new_entry_name,
-                    group_uuid,
-                    new_group_name,
-                )
-            });
+            .and_then(|_| passkey_db::check_exclude_credentials(options_json, origin));
+
+        let credential_result = match credential_result {
+            Ok(()) => verify_user_if_required(db_key, options_json, origin, Ceremony::Create)
+                .await
+                .and_then(|user_verified| {
+                    passkey_db::create_and_store_passkey(
+                        db_key,
+                        options_json,
+                        origin,
+                        existing_entry_uuid,
+                        new_entry_name,
+                        group_uuid,
+                        new_group_name,
+                        user_verified,
+                    )
+                }),
+            Err(e) => Err(e),
+        };
 
         let resp = match credential_result {
             Ok(ref credential_json) => {
@@ -875,10 +1069,22 @@ impl Request {
             .and_then(|_| check_field_len("origin", origin, 512))
             .and_then(|_| validate_https_origin(origin))
             .and_then(|_| validate_origin_matches_tab_url(origin, tab_url))
-            .and_then(|_| db_calls::validate_db_key(db_key))
-            .and_then(|_| {
-                db_calls::sign_passkey_assertion(db_key, entry_uuid, options_json, origin)
-            });
+            .and_then(|_| db_calls::validate_db_key(db_key));
+
+        let result = match result {
+            Ok(()) => verify_user_if_required(db_key, options_json, origin, Ceremony::Get)
+                .await
+                .and_then(|user_verified| {
+                    db_calls::sign_passkey_assertion(
+                        db_key,
+                        entry_uuid,
+                        options_json,
+                        origin,
+                        user_verified,
+                    )
+                }),
+            Err(e) => Err(e),
+        };
 
         let resp = match result {
             Ok(ref json) => match SessionStore::encrypt(association_id, json).await {
@@ -938,6 +1144,26 @@ impl Request {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "action")]
 pub enum Response {
+    // Reply to the diagnostic 'Ping' request
+    Ping {
+        app_version: String,
+        request_id: Option<String>,
+    },
+
+    // Reply to the 'GitCredential' request with the entry picked by the user
+    GitCredential {
+        request_id: String,
+        username: Option<String>,
+        password: String,
+    },
+
+    // Reply to the 'GitCredential' request for a 'store' or 'erase'. 'updated' is false when
+    // nothing was changed as the user denied or the credential was already kept
+    GitCredentialUpdated {
+        request_id: String,
+        updated: bool,
+    },
+
     // Responds with association_id from app
     Associate {
         client_id: String,
@@ -1034,6 +1260,7 @@ impl Response {
 }
 
 enum ResponseActionName {
+    GitCredential,
     Associate,
     InitSessionKey,
     EnabledDatabaseMatchedEntryList,
@@ -1054,6 +1281,7 @@ impl ResponseActionName {
     fn name(&self) -> &str {
         use ResponseActionName::*;
         match self {
+            GitCredential => "GitCredential",
             Associate => "Associate",
             InitSessionKey => "InitSessionKey",
             EnabledDatabaseMatchedEntryList => "EnabledDatabaseMatchedEntryList",
@@ -1146,6 +1374,7 @@ Error trailing characters at line 1 column 146 in deserializing to json of recei
 
 #[cfg(test)]
 mod tests {
+    use crate::browser_service::git_credential::GitOperation;
     use crate::browser_service::message::Request;
 
     #[test]
@@ -1176,6 +1405,82 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_ping_request() {
+        let json = r#"{"action":"Ping","request_id":"diagnose"}"#;
+        match serde_json::from_str(json).unwrap() {
+            Request::Ping { request_id } => {
+                assert_eq!(request_id.as_deref(), Some("diagnose"));
+            }
+            other => panic!("Unexpected variant: {:?}", other),
+        }
+
+        // request_id is optional
+        let json = r#"{"action":"Ping"}"#;
+        assert!(matches!(
+            serde_json::from_str(json).unwrap(),
+            Request::Ping { request_id: None }
+        ));
+    }
+
+    #[test]
+    fn parse_git_credential_request() {
+        let json = r#"{"action":"GitCredential","request_id":"git-1","protocol":"https","host":"github.com","path":"org/repo.git"}"#;
+        match serde_json::from_str(json).unwrap() {
+            Request::GitCredential {
+                request_id,
+                operation,
+                protocol,
+                host,
+                path,
+                username,
+                password,
+            } => {
+                assert_eq!(request_id, "git-1");
+                assert_eq!(operation, GitOperation::Get);
+                assert!(password.is_none());
+                assert_eq!(protocol, "https");
+                assert_eq!(host, "github.com");
+                assert_eq!(path.as_deref(), Some("org/repo.git"));
+                assert!(username.is_none());
+            }
+            other => panic!("Unexpected variant: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_git_credential_store_request() {
+        let json = r#"{"action":"GitCredential","request_id":"git-2","operation":"store","protocol":"https","host":"github.com","username":"me","password":"secret"}"#;
+        match serde_json::from_str(json).unwrap() {
+            Request::GitCredential {
+                operation,
+                username,
+                password,
+                ..
+            } => {
+                assert_eq!(operation, GitOperation::Store);
+                assert_eq!(username.as_deref(), Some("me"));
+                assert_eq!(password.as_deref(), Some("secret"));
+            }
+            other => panic!("Unexpected variant: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn verify_waits_for_user() {
+        assert!(Request::waits_for_user(
+            r#"{"action":"GitCredential","request_id":"git-1","protocol":"https","host":"github.com"}"#
+        ));
+        assert!(Request::waits_for_user(
+            r#"{"action":"CompletePasskeyAssertion","options_json":"{\"userVerification\":\"required\"}"}"#
+        ));
+        assert!(!Request::waits_for_user(
+            r#"{"action":"CompletePasskeyAssertion","options_json":"{\"userVerification\":\"preferred\"}"}"#
+        ));
+        assert!(!Request::waits_for_user(r#"{"action":"Ping"}"#));
+        assert!(!Request::waits_for_user("not json"));
+    }
+
     // ── Passkey Request deserialization ───────────────────────────────────────
 
     #[test]
@@ -6,19 +6,38 @@ mod verifier;
 
 mod native_messaging_config;
 
+pub(crate) mod git_credential;
 pub(crate) mod passkey_crypto;
 pub(crate) mod passkey_db;
+pub(crate) mod passkey_exchange;
+pub(crate) mod user_verification;
 
 pub(crate) use native_messaging_config::*;
-pub(crate) use proxy_handler::start_proxy_handler;
+pub(crate) use proxy_handler::{read_framed_message, start_proxy_handler, write_framed_message};
 pub(crate) use verifier::run_verifier;
 
 pub(crate) const FIREFOX: &str = "Firefox";
 pub(crate) const CHROME: &str = "Chrome";
 pub(crate) const BRAVE: &str = "Brave";
-//pub(crate) const EDGE: &str = "Edge";
 
-const SUPPORTED_BROWSERS: [&str; 3] = [FIREFOX, CHROME, BRAVE];
+// Additional browsers that are supported only on Linux for now. Each one has its own
+// native messaging manifest location (see LinuxNativeMessagingConfig)
+#[cfg(target_os = "linux")]
+pub(crate) const CHROMIUM: &str = "Chromium";
+#[cfg(target_os = "linux")]
+pub(crate) const EDGE: &str = "Edge";
+#[cfg(target_os = "linux")]
+pub(crate) const VIVALDI: &str = "Vivaldi";
+#[cfg(target_os = "linux")]
+pub(crate) const LIBREWOLF: &str = "LibreWolf";
+
+cfg_if::cfg_if! {
+    if #[cfg(target_os = "linux")] {
+        pub(crate) const SUPPORTED_BROWSERS: [&str; 7] = [FIREFOX, CHROME, BRAVE, CHROMIUM, EDGE, VIVALDI, LIBREWOLF];
+    } else {
+        pub(crate) const SUPPORTED_BROWSERS: [&str; 3] = [FIREFOX, CHROME, BRAVE];
+    }
+}
 
 // This also works and supported_browsers().contains(&client_id.as_str()) can be used
 // fn supported_browsers() -> Vec<&'static str> {
@@ -118,12 +118,31 @@ fn same_proxy_path(left: &str, right: &str) -> bool {
 #[derive(Serialize)]
 pub(crate) struct NativeMessagingManifestStatus {
     browser_id: String,
+    // One of "native", "flatpak" or "snap". Only Linux has more than one manifest
+    // location for the same browser
+    packaging: String,
     manifest_path: String,
     expected_proxy_path: String,
     installed_proxy_path: Option<String>,
     owner: String,
 }
 
+// Reads the 'path' field of an existing manifest file
+fn installed_proxy_path_in(manifest_path: &str) -> Option<String> {
+    std::fs::read_to_string(manifest_path)
+        .ok()
+        .and_then(|json| serde_json::from_str::<ExistingNativeMessagingConfig>(&json).ok())
+        .and_then(|config| config.path)
+}
+
+fn proxy_owner(installed_proxy_path: Option<&str>, expected_proxy_path: &str) -> &'static str {
+    match installed_proxy_path {
+        None => "missing",
+        Some(path) if same_proxy_path(path, expected_proxy_path) => "this-app",
+        Some(_) => "other-app",
+    }
+}
+
 fn manifest_status(
     browser_id: &str,
     manifest_path: String,
@@ -137,18 +156,13 @@ fn manifest_status(
 
     let installed_proxy_path = manifest_path_in_use
         .as_deref()
-        .and_then(|path| std::fs::read_to_string(path).ok())
-        .and_then(|json| serde_json::from_str::<ExistingNativeMessagingConfig>(&json).ok())
-        .and_then(|config| config.path);
+        .and_then(installed_proxy_path_in);
 
-    let owner = match installed_proxy_path.as_deref() {
-        None => "missing",
-        Some(path) if same_proxy_path(path, &expected_proxy_path) => "this-app",
-        Some(_) => "other-app",
-    };
+    let owner = proxy_owner(installed_proxy_path.as_deref(), &expected_proxy_path);
 
     Ok(NativeMessagingManifestStatus {
         browser_id: browser_id.to_string(),
+        packaging: "native".to_string(),
         manifest_path: manifest_path_in_use.unwrap_or_default(),
         expected_proxy_path,
         installed_proxy_path,
@@ -157,7 +171,8 @@ fn manifest_status(
 }
 
 pub(crate) fn native_messaging_manifest_statuses() -> Result<Vec<NativeMessagingManifestStatus>> {
-    Ok(vec![
+    #[allow(unused_mut)]
+    let mut statuses = vec![
         manifest_status(
             FIREFOX,
             FirefoxNativeMessagingConfig::firefox_native_messaging_config_full_name()?,
@@ -170,7 +185,12 @@ pub(crate) fn native_messaging_manifest_statuses() -> Result<Vec<NativeMessaging
             BRAVE,
             BraveNativeMessagingConfig::brave_native_messaging_config_full_name()?,
         )?,
-    ])
+    ];
+
+    #[cfg(target_os = "linux")]
+    statuses.extend(LinuxNativeMessagingConfig::statuses()?);
+
+    Ok(statuses)
 }
 
 #[derive(Serialize)]
@@ -660,3 +680,488 @@ impl<'a> BraveNativeMessagingConfig<'a> {
         Ok(())
     }
 }
+
+#[cfg(target_os = "linux")]
+pub(crate) use linux::LinuxNativeMessagingConfig;
+
+// On Linux, the same browser may be installed as a distro package, a Flatpak or a Snap and
+// each of these reads the native messaging manifest from a different dir. The additional
+// Chromium based browsers and LibreWolf are also handled here.
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+    use crate::browser_service::{CHROMIUM, EDGE, LIBREWOLF, SUPPORTED_BROWSERS, VIVALDI};
+
+    #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+    enum Packaging {
+        Native,
+        Flatpak,
+        Snap,
+    }
+
+    impl Packaging {
+        fn as_str(&self) -> &'static str {
+            match self {
+                Packaging::Native => "native",
+                Packaging::Flatpak => "flatpak",
+                Packaging::Snap => "snap",
+            }
+        }
+    }
+
+    struct ManifestLocation {
+        browser_id: &'static str,
+        packaging: Packaging,
+        // Flatpak application id or the snap name. Empty for a native install
+        app_id: &'static str,
+        // Manifest dir relative to the user's home dir
+        manifest_dir: &'static str,
+    }
+
+    // The native locations of Firefox, Chrome and Brave are not listed here as those are
+    // written by FirefoxNativeMessagingConfig, ChromeNativeMessagingConfig and BraveNativeMessagingConfig.
+    // The Firefox snap uses the WebExtensions portal which reads the native location and
+    // hence there is no snap entry for it.
+    // Refs:
+    // https://docs.flatpak.org/en/latest/conventions.html#xdg-base-directories
+    // https://chromium.googlesource.com/chromium/src/+/main/chrome/common/chrome_paths.cc
+    const MANIFEST_LOCATIONS: &[ManifestLocation] = &[
+        ManifestLocation {
+            browser_id: FIREFOX,
+            packaging: Packaging::Flatpak,
+            app_id: "org.mozilla.firefox",
+            manifest_dir: ".var/app/org.mozilla.firefox/.mozilla/native-messaging-hosts",
+        },
+        ManifestLocation {
+            browser_id: LIBREWOLF,
+            packaging: Packaging::Native,
+            app_id: "",
+            manifest_dir: ".librewolf/native-messaging-hosts",
+        },
+        ManifestLocation {
+            browser_id: LIBREWOLF,
+            packaging: Packaging::Flatpak,
+            app_id: "io.gitlab.librewolf-community",
+            manifest_dir:
+                ".var/app/io.gitlab.librewolf-community/.librewolf/native-messaging-hosts",
+        },
+        ManifestLocation {
+            browser_id: CHROME,
+            packaging: Packaging::Flatpak,
+            app_id: "com.google.Chrome",
+            manifest_dir: ".var/app/com.google.Chrome/config/google-chrome/NativeMessagingHosts",
+        },
+        ManifestLocation {
+            browser_id: BRAVE,
+            packaging: Packaging::Flatpak,
+            app_id: "com.brave.Browser",
+            manifest_dir:
+                ".var/app/com.brave.Browser/config/BraveSoftware/Brave-Browser/NativeMessagingHosts",
+        },
+        ManifestLocation {
+            browser_id: CHROMIUM,
+            packaging: Packaging::Native,
+            app_id: "",
+            manifest_dir: ".config/chromium/NativeMessagingHosts",
+        },
+        ManifestLocation {
+            browser_id: CHROMIUM,
+            packaging: Packaging::Flatpak,
+            app_id: "org.chromium.Chromium",
+            manifest_dir: ".var/app/org.chromium.Chromium/config/chromium/NativeMessagingHosts",
+        },
+        ManifestLocation {
+            browser_id: CHROMIUM,
+            packaging: Packaging::Snap,
+            app_id: "chromium",
+            manifest_dir: "snap/chromium/common/chromium/NativeMessagingHosts",
+        },
+        ManifestLocation {
+            browser_id: EDGE,
+            packaging: Packaging::Native,
+            app_id: "",
+            manifest_dir: ".config/microsoft-edge/NativeMessagingHosts",
+        },
+        ManifestLocation {
+            browser_id: EDGE,
+            packaging: Packaging::Flatpak,
+            app_id: "com.microsoft.Edge",
+            manifest_dir: ".var/app/com.microsoft.Edge/config/microsoft-edge/NativeMessagingHosts",
+        },
+        ManifestLocation {
+            browser_id: VIVALDI,
+            packaging: Packaging::Native,
+            app_id: "",
+            manifest_dir: ".config/vivaldi/NativeMessagingHosts",
+        },
+    ];
+
+    const PROXY_BINARY_NAME: &str = "onekeepass-proxy";
+
+    impl ManifestLocation {
+        fn is_firefox_family(&self) -> bool {
+            self.browser_id == FIREFOX || self.browser_id == LIBREWOLF
+        }
+
+        // The per user dir of a sandboxed package. The Flatpak/Snap runtime makes this dir
+        // visible at the same path inside the sandbox
+        fn package_root(&self, home: &Path) -> Option<PathBuf> {
+            match self.packaging {
+                Packaging::Native => None,
+                Packaging::Flatpak => Some(home.join(".var/app").join(self.app_id)),
+                Packaging::Snap => Some(home.join("snap").join(self.app_id)),
+            }
+        }
+
+        // A native location is always used. A sandboxed location is used only when
+        // that package has been run at least once by this user
+        fn is_available(&self, home: &Path) -> bool {
+            self.package_root(home)
+                .map(|root| root.exists())
+                .unwrap_or(true)
+        }
+
+        fn manifest_full_name(&self, home: &Path) -> PathBuf {
+            home.join(self.manifest_dir)
+                .join(OKP_NATIVE_MESSAING_CONFIG_FILE_NAME)
+        }
+
+        // A sandboxed browser can not launch our bundled proxy binary. Instead a copy of the
+        // proxy is placed in the package's own data dir and the manifest points to that copy
+        fn proxy_path(&self, home: &Path) -> Result<PathBuf> {
+            match (self.packaging, self.package_root(home)) {
+                (Packaging::Flatpak, Some(root)) => {
+                    Ok(root.join("data").join("onekeepass").join(PROXY_BINARY_NAME))
+                }
+                (Packaging::Snap, Some(root)) => Ok(root
+                    .join("common")
+                    .join("onekeepass")
+                    .join(PROXY_BINARY_NAME)),
+                _ => proxy_full_path(),
+            }
+        }
+
+        fn copy_proxy(&self, proxy_path: &Path) -> Result<()> {
+            if self.packaging == Packaging::Native {
+                return Ok(());
+            }
+            let source = proxy_full_path()?;
+            if is_same_file_content(&source, proxy_path) {
+                return Ok(());
+            }
+            if let Some(parent) = proxy_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            // The copy is renamed over the old one as a running proxy can not be overwritten.
+            // fs::copy also copies the permission bits and the copy remains executable
+            let tmp_path = proxy_path.with_extension("okp-tmp");
+            std::fs::copy(&source, &tmp_path)?;
+            if let Err(e) = std::fs::rename(&tmp_path, proxy_path) {
+                let _ = std::fs::remove_file(&tmp_path);
+                return Err(e.into());
+            }
+            log::debug!("Copied the proxy binary to {:?}", proxy_path);
+            Ok(())
+        }
+
+        fn manifest_json(&self, proxy_path: &str) -> Result<String> {
+            let description = "OneKeePass integration with native messaging support";
+            let json_str = if self.is_firefox_family() {
+                serde_json::to_string_pretty(&FirefoxNativeMessagingConfig {
+                    allowed_extensions: vec![FIREFOX_EXTENSION_ID],
+                    description,
+                    name: OKP_NATIVE_APP_NAME,
+                    path: proxy_path,
+                    type_of_app: "stdio",
+                })?
+            } else {
+                let cid1 = format!("chrome-extension://{}/", CHROME_EXTENSION_ID1);
+                let cid2 = format!("chrome-extension://{}/", CHROME_EXTENSION_ID2);
+                serde_json::to_string_pretty(&ChromeNativeMessagingConfig {
+                    allowed_origins: vec![&cid1, &cid2],
+                    description,
+                    name: OKP_NATIVE_APP_NAME,
+                    path: proxy_path,
+                    type_of_app: "stdio",
+                })?
+            };
+            Ok(json_str)
+        }
+
+        fn write(&self, home: &Path) -> Result<()> {
+            let proxy_path = self.proxy_path(home)?;
+            self.copy_proxy(&proxy_path)?;
+
+            let manifest_dir = home.join(self.manifest_dir);
+            if !manifest_dir.exists() {
+                let r = std::fs::create_dir_all(&manifest_dir);
+                log::debug!(
+                    "Created {} proxy location dir {:?} with result {:?}",
+                    self.browser_id,
+                    &manifest_dir,
+                    &r
+                );
+            }
+
+            let config_file_full_name = self.manifest_full_name(home);
+            let json_str = self.manifest_json(&proxy_path.to_string_lossy())?;
+
+            log::info!(
+                "Going to write {} ({}) native messaging the config file {:?} ",
+                self.browser_id,
+                self.packaging.as_str(),
+                &config_file_full_name
+            );
+
+            std::fs::write(&config_file_full_name, json_str)?;
+
+            Ok(())
+        }
+
+        // Missing files are not treated as errors as the location may never have been written
+        fn remove(&self, home: &Path) -> Result<()> {
+            let config_file_full_name = self.manifest_full_name(home);
+            if config_file_full_name.exists() {
+                std::fs::remove_file(&config_file_full_name)?;
+                log::debug!("Removed the config file {:?} ", &config_file_full_name);
+            }
+
+            if self.packaging != Packaging::Native {
+                let proxy_path = self.proxy_path(home)?;
+                if proxy_path.exists() {
+                    std::fs::remove_file(&proxy_path)?;
+                    log::debug!("Removed the proxy copy {:?} ", &proxy_path);
+                }
+            }
+
+            Ok(())
+        }
+
+        fn status(&self, home: &Path) -> Result<NativeMessagingManifestStatus> {
+            let manifest_path = self.manifest_full_name(home).to_string_lossy().to_string();
+            let expected_proxy_path = self.proxy_path(home)?.to_string_lossy().to_string();
+            let installed_proxy_path = installed_proxy_path_in(&manifest_path);
+            let owner = proxy_owner(installed_proxy_path.as_deref(), &expected_proxy_path);
+
+            Ok(NativeMessagingManifestStatus {
+                browser_id: self.browser_id.to_string(),
+                packaging: self.packaging.as_str().to_string(),
+                manifest_path,
+                expected_proxy_path,
+                installed_proxy_path,
+                owner: owner.to_string(),
+            })
+        }
+
+        // The IPC dir that the proxy copy inside this package's sandbox can reach.
+        // The host's $XDG_RUNTIME_DIR/app/<app-id> is shared with a Flatpak app and
+        // $XDG_RUNTIME_DIR/snap.<name> with a snap
+        fn sandbox_ipc_dir(&self, runtime_dir: &Path) -> Option<PathBuf> {
+            match self.packaging {
+                Packaging::Native => None,
+                Packaging::Flatpak => Some(runtime_dir.join("app").join(self.app_id)),
+                Packaging::Snap => Some(runtime_dir.join(format!("snap.{}", self.app_id))),
+            }
+        }
+    }
+
+    fn file_sha256(path: &Path) -> Option<Vec<u8>> {
+        use sha2::{Digest, Sha256};
+        let mut file = std::fs::File::open(path).ok()?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher).ok()?;
+        Some(hasher.finalize().to_vec())
+    }
+
+    // Compares the sizes first so that the hashes are computed only when the sizes match
+    fn is_same_file_content(left: &Path, right: &Path) -> bool {
+        match (std::fs::metadata(left), std::fs::metadata(right)) {
+            (Ok(l), Ok(r)) if l.len() == r.len() => {
+                let left_hash = file_sha256(left);
+                left_hash.is_some() && left_hash == file_sha256(right)
+            }
+            _ => false,
+        }
+    }
+
+    fn home_dir() -> Result<PathBuf> {
+        std::env::home_dir().ok_or_else(|| error::Error::DataError("No home dir is found"))
+    }
+
+    fn locations_for<'a>(
+        browser_id: &'a str,
+    ) -> impl Iterator<Item = &'static ManifestLocation> + 'a {
+        MANIFEST_LOCATIONS
+            .iter()
+            .filter(move |loc| loc.browser_id == browser_id)
+    }
+
+    pub(crate) struct LinuxNativeMessagingConfig;
+
+    impl LinuxNativeMessagingConfig {
+        // Writes the manifest to every location that the browser 'browser_id' may use
+        pub(crate) fn write(browser_id: &str) -> Result<()> {
+            match browser_id {
+                FIREFOX => FirefoxNativeMessagingConfig::write()?,
+                CHROME => ChromeNativeMessagingConfig::write()?,
+                BRAVE => BraveNativeMessagingConfig::write()?,
+                _ => {}
+            }
+
+            let home = home_dir()?;
+            for loc in locations_for(browser_id).filter(|loc| loc.is_available(&home)) {
+                loc.write(&home)?;
+            }
+
+            Ok(())
+        }
+
+        pub(crate) fn remove(browser_id: &str) -> Result<()> {
+            let home = home_dir()?;
+            for loc in locations_for(browser_id) {
+                let r = loc.remove(&home);
+                log::debug!(
+                    "Removing {} ({}) native messaging config returned {:?}",
+                    browser_id,
+                    loc.packaging.as_str(),
+                    &r
+                );
+            }
+
+            // The earlier browsers' remove fns also send the disconnect message
+            match browser_id {
+                FIREFOX => FirefoxNativeMessagingConfig::remove(),
+                CHROME => ChromeNativeMessagingConfig::remove(),
+                BRAVE => BraveNativeMessagingConfig::remove(),
+                _ => {
+                    // disconnect needs the &'static str browser id
+                    if let Some(id) = SUPPORTED_BROWSERS.iter().find(|id| **id == browser_id) {
+                        Response::disconnect(*id);
+                    }
+                    Ok(())
+                }
+            }
+        }
+
+        // Copies the bundled proxy again to the Flatpak/Snap locations that have a manifest
+        // when their copy differs, e.g after an app upgrade. Called when the proxy handler starts
+        pub(crate) fn refresh_proxy_copies() {
+            let Ok(home) = home_dir() else {
+                return;
+            };
+            for loc in MANIFEST_LOCATIONS.iter().filter(|loc| {
+                loc.packaging != Packaging::Native
+                    && loc.is_available(&home)
+                    && loc.manifest_full_name(&home).exists()
+            }) {
+                let r = loc.proxy_path(&home).and_then(|p| loc.copy_proxy(&p));
+                if let Err(e) = r {
+                    log::error!(
+                        "Refreshing the {} ({}) proxy copy failed: {}",
+                        loc.browser_id,
+                        loc.packaging.as_str(),
+                        e
+                    );
+                }
+            }
+        }
+
+        // Statuses of all additional locations that are relevant for this user
+        pub(crate) fn statuses() -> Result<Vec<NativeMessagingManifestStatus>> {
+            let home = home_dir()?;
+            MANIFEST_LOCATIONS
+                .iter()
+                .filter(|loc| loc.is_available(&home))
+                .map(|loc| loc.status(&home))
+                .collect()
+        }
+
+        // Dirs where the app side proxy handler needs to listen in addition to the default
+        // location so that the proxy copies inside Flatpak/Snap sandboxes can connect
+        pub(crate) fn sandbox_ipc_dirs() -> Vec<PathBuf> {
+            let (Ok(home), Some(runtime_dir)) = (
+                home_dir(),
+                std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from),
+            ) else {
+                return vec![];
+            };
+
+            MANIFEST_LOCATIONS
+                .iter()
+                .filter(|loc| loc.is_available(&home))
+                .filter_map(|loc| loc.sandbox_ipc_dir(&runtime_dir))
+                .collect()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // A dir of its own for each test so that parallel runs do not collide
+        fn test_home() -> PathBuf {
+            let home =
+                std::env::temp_dir().join(format!("okp_nm_linux_test_{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&home).unwrap();
+            home
+        }
+
+        #[test]
+        fn verify_sandboxed_locations_are_used_only_when_installed() {
+            let home = test_home();
+
+            let chromium: Vec<_> = locations_for(CHROMIUM).collect();
+            assert_eq!(chromium.len(), 3);
+
+            let available: Vec<_> = chromium.iter().filter(|l| l.is_available(&home)).collect();
+            assert_eq!(available.len(), 1);
+            assert_eq!(available[0].packaging, Packaging::Native);
+
+            std::fs::create_dir_all(home.join(".var/app/org.chromium.Chromium")).unwrap();
+            let available: Vec<_> = chromium.iter().filter(|l| l.is_available(&home)).collect();
+            assert_eq!(available.len(), 2);
+
+            let flatpak = available[1];
+            assert_eq!(
+                flatpak.proxy_path(&home).unwrap(),
+                home.join(".var/app/org.chromium.Chromium/data/onekeepass/onekeepass-proxy")
+            );
+            assert_eq!(
+                flatpak.sandbox_ipc_dir(Path::new("/run/user/1000")),
+                Some(PathBuf::from("/run/user/1000/app/org.chromium.Chromium"))
+            );
+
+            let _ = std::fs::remove_dir_all(&home);
+        }
+
+        #[test]
+        fn verify_same_file_content() {
+            let home = test_home();
+            let (a, b) = (home.join("a"), home.join("b"));
+            std::fs::write(&a, b"proxy v1").unwrap();
+            assert!(!is_same_file_content(&a, &b));
+            std::fs::write(&b, b"proxy v1").unwrap();
+            assert!(is_same_file_content(&a, &b));
+            // Same size, different content
+            std::fs::write(&b, b"proxy v2").unwrap();
+            assert!(!is_same_file_content(&a, &b));
+
+            let _ = std::fs::remove_dir_all(&home);
+        }
+
+        #[test]
+        fn verify_manifest_json_by_browser_family() {
+            let librewolf = locations_for(LIBREWOLF).next().unwrap();
+            let json = librewolf.manifest_json("/x/onekeepass-proxy").unwrap();
+            assert!(json.contains("allowed_extensions"));
+            assert!(json.contains(FIREFOX_EXTENSION_ID));
+
+            let edge = locations_for(EDGE).next().unwrap();
+            let json = edge.manifest_json("/x/onekeepass-proxy").unwrap();
+            assert!(json.contains("allowed_origins"));
+            assert!(json.contains(CHROME_EXTENSION_ID1));
+        }
+    }
+}
@@ -2,3 +2,190 @@
 // across desktop, iOS, and Android.  This module re-exports everything the
 // existing desktop callers (passkey_db.rs, etc.) reference.
 pub use onekeepass_core::passkey_crypto::{create_passkey, sign_assertion, PasskeyCreationResult};
+
+use ciborium::Value;
+use data_encoding::BASE64URL_NOPAD;
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+use p256::pkcs8::DecodePrivateKey;
+use sha2::{Digest, Sha256};
+
+use onekeepass_core::error::{self, Result};
+
+// The authenticator data starts with the 32 bytes rp id hash followed by the flags byte
+const FLAGS_OFFSET: usize = 32;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+
+// The keys of the attestation object map
+const AUTH_DATA_KEY: &str = "authData";
+const FMT_KEY: &str = "fmt";
+
+// Sets the UV flag in the authenticator data to the actual verification outcome.
+// Returns true when the flags byte was changed
+fn set_uv_flag(auth_data: &mut [u8], user_verified: bool) -> Result<bool> {
+    let flags = auth_data
+        .get_mut(FLAGS_OFFSET)
+        .ok_or(error::Error::DataError("Invalid authenticator data"))?;
+    let new_flags = if user_verified {
+        *flags | FLAG_USER_VERIFIED
+    } else {
+        *flags & !FLAG_USER_VERIFIED
+    };
+    let changed = *flags != new_flags;
+    *flags = new_flags;
+    Ok(changed)
+}
+
+fn decode_response_field(credential: &serde_json::Value, field: &str) -> Result<Option<Vec<u8>>> {
+    credential["response"][field]
+        .as_str()
+        .map(|s| {
+            BASE64URL_NOPAD
+                .decode(s.as_bytes())
+                .map_err(|_| error::Error::DataError("Invalid passkey response data"))
+        })
+        .transpose()
+}
+
+fn map_value<'a>(map: &'a [(Value, Value)], key: &str) -> Option<&'a Value> {
+    map.iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, v)| v)
+}
+
+// Sets the UV flag in the authenticator data of the CBOR encoded attestation object.
+// Returns the encoded attestation object with the new flag or None when it is not changed
+fn set_attestation_uv_flag(
+    attestation_object: &[u8],
+    user_verified: bool,
+) -> Result<Option<Vec<u8>>> {
+    let invalid = || error::Error::DataError("Invalid passkey attestation object");
+
+    let mut value: Value = ciborium::from_reader(attestation_object).map_err(|_| invalid())?;
+    let map = value.as_map_mut().ok_or_else(invalid)?;
+    // Only the "none" attestation is used and there is no attestation signature to redo
+    if map_value(map, FMT_KEY).and_then(Value::as_text) != Some("none") {
+        return Err(error::Error::DataError(
+            "Unsupported passkey attestation format",
+        ));
+    }
+    let auth_data = map
+        .iter_mut()
+        .find(|(k, _)| k.as_text() == Some(AUTH_DATA_KEY))
+        .and_then(|(_, v)| v.as_bytes_mut())
+        .ok_or_else(invalid)?;
+    if !set_uv_flag(auth_data, user_verified)? {
+        return Ok(None);
+    }
+
+    let mut encoded = vec![];
+    ciborium::into_writer(&value, &mut encoded)
+        .map_err(|e| error::Error::UnexpectedError(e.to_string()))?;
+    Ok(Some(encoded))
+}
+
+// Updates the UV flag of a registration response ('credential_json' from create_passkey)
+pub(crate) fn set_registration_user_verified(
+    credential_json: &str,
+    user_verified: bool,
+) -> Result<String> {
+    let mut credential: serde_json::Value = serde_json::from_str(credential_json)?;
+
+    let attestation_object = decode_response_field(&credential, "attestationObject")?.ok_or(
+        error::Error::DataError("The passkey response has no attestation object"),
+    )?;
+    let Some(attestation_object) = set_attestation_uv_flag(&attestation_object, user_verified)?
+    else {
+        return Ok(credential_json.to_string());
+    };
+    credential["response"]["attestationObject"] =
+        BASE64URL_NOPAD.encode(&attestation_object).into();
+
+    // The WebAuthn JSON serialization also carries the authenticator data on its own
+    if let Some(mut auth_data) = decode_response_field(&credential, "authenticatorData")? {
+        set_uv_flag(&mut auth_data, user_verified)?;
+        credential["response"]["authenticatorData"] = BASE64URL_NOPAD.encode(&auth_data).into();
+    }
+
+    Ok(serde_json::to_string(&credential)?)
+}
+
+// Updates the UV flag of an assertion response ('credential_json' from sign_assertion).
+// The signature covers the authenticator data and so it is made again with the passkey's key
+pub(crate) fn set_assertion_user_verified(
+    credential_json: &str,
+    private_key_pem: &str,
+    user_verified: bool,
+) -> Result<String> {
+    let mut credential: serde_json::Value = serde_json::from_str(credential_json)?;
+
+    let mut auth_data = decode_response_field(&credential, "authenticatorData")?.ok_or(
+        error::Error::DataError("The passkey response has no authenticator data"),
+    )?;
+    if !set_uv_flag(&mut auth_data, user_verified)? {
+        return Ok(credential_json.to_string());
+    }
+    let client_data_json = decode_response_field(&credential, "clientDataJSON")?.ok_or(
+        error::Error::DataError("The passkey response has no client data"),
+    )?;
+
+    let signing_key = SigningKey::from_pkcs8_pem(private_key_pem)
+        .map_err(|_| error::Error::DataError("Invalid passkey private key"))?;
+    let mut signed_data = auth_data.clone();
+    signed_data.extend_from_slice(&Sha256::digest(&client_data_json));
+    let signature: Signature = signing_key.sign(&signed_data);
+
+    credential["response"]["authenticatorData"] = BASE64URL_NOPAD.encode(&auth_data).into();
+    credential["response"]["signature"] =
+        BASE64URL_NOPAD.encode(signature.to_der().as_bytes()).into();
+
+    Ok(serde_json::to_string(&credential)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_uv_flag_is_set_and_cleared() {
+        let mut auth_data = vec![0u8; 37];
+        auth_data[FLAGS_OFFSET] = 0x45;
+        assert!(set_uv_flag(&mut auth_data, false).unwrap());
+        assert_eq!(auth_data[FLAGS_OFFSET], 0x41);
+        assert!(!set_uv_flag(&mut auth_data, false).unwrap());
+        assert!(set_uv_flag(&mut auth_data, true).unwrap());
+        assert_eq!(auth_data[FLAGS_OFFSET], 0x45);
+
+        assert!(set_uv_flag(&mut [0u8; 10], true).is_err());
+    }
+
+    fn attestation_object(fmt: &str, auth_data: Vec<u8>) -> Vec<u8> {
+        let value = Value::Map(vec![
+            (Value::Text(FMT_KEY.into()), Value::Text(fmt.into())),
+            (Value::Text("attStmt".into()), Value::Map(vec![])),
+            (Value::Text(AUTH_DATA_KEY.into()), Value::Bytes(auth_data)),
+        ]);
+        let mut encoded = vec![];
+        ciborium::into_writer(&value, &mut encoded).unwrap();
+        encoded
+    }
+
+    #[test]
+    fn verify_uv_flag_in_attestation_object() {
+        let mut auth_data = vec![0u8; 164];
+        auth_data[FLAGS_OFFSET] = 0x41;
+        let encoded = attestation_object("none", auth_data.clone());
+
+        // The encoded map is the same apart from the flag
+        auth_data[FLAGS_OFFSET] = 0x45;
+        assert_eq!(
+            set_attestation_uv_flag(&encoded, true).unwrap(),
+            Some(attestation_object("none", auth_data))
+        );
+        assert_eq!(set_attestation_uv_flag(&encoded, false).unwrap(), None);
+
+        assert!(
+            set_attestation_uv_flag(&attestation_object("packed", vec![0u8; 164]), true).is_err()
+        );
+        assert!(set_attestation_uv_flag(b"\xa1\x63fmt\x64none", true).is_err());
+    }
+}
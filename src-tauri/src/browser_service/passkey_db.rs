@@ -4,6 +4,7 @@
 // Each public function in this module corresponds to one message round-trip
 // between the browser extension and the desktop app.
 
+use chrono::NaiveDateTime;
 use serde::Serialize;
 use tauri::{Emitter, Manager};
 use uuid::Uuid;
@@ -12,7 +13,7 @@ use onekeepass_core::db_service as kp_service;
 use onekeepass_core::db_service::browser_extension::{
     PasskeyEntry, PasskeyStorageInfo, PasskeyStoreOutcome, PasskeySummary,
 };
-use onekeepass_core::error::Result;
+use onekeepass_core::error::{self, Result};
 
 use crate::app_state;
 use crate::browser_service::passkey_crypto::{self, PasskeyCreationResult};
@@ -53,7 +54,7 @@ pub struct OpenedDbInfo {
 // The extension presents this list to the user as the first step of the
 // passkey creation popup.
 pub(crate) fn get_opened_databases_for_passkey() -> Result<Vec<OpenedDbInfo>> {
-    let db_keys = kp_service::all_kdbx_cache_keys()?;
+    let db_keys = crate::backup_service::opened_db_keys()?;
     let active_db_key = app_state::AppState::state_instance().active_db_key();
     let mut result = Vec::with_capacity(db_keys.len());
 
@@ -73,12 +74,80 @@ pub(crate) fn get_opened_databases_for_passkey() -> Result<Vec<OpenedDbInfo>> {
     Ok(result)
 }
 
+// Returns the RP id from the creation ('rp.id') or request ('rpId') options.
+// When the site does not set one, WebAuthn uses the host of the origin
+pub(crate) fn rp_id_from_options(options_json: &str, origin: &str) -> String {
+    let opts: serde_json::Value = serde_json::from_str(options_json).unwrap_or_default();
+    opts["rp"]["id"]
+        .as_str()
+        .or_else(|| opts["rpId"].as_str())
+        .map(String::from)
+        .unwrap_or_else(|| {
+            let host_port = origin.strip_prefix("https://").unwrap_or(origin);
+            host_port
+                .rsplit_once(':')
+                .map_or(host_port, |(host, _port)| host)
+                .to_string()
+        })
+}
+
+// True when the site asks for `userVerification: "required"` either in the
+// creation options ('authenticatorSelection.userVerification') or in the
+// request options ('userVerification'). The WebAuthn default is "preferred"
+pub(crate) fn user_verification_required(options_json: &str) -> bool {
+    let opts: serde_json::Value = serde_json::from_str(options_json).unwrap_or_default();
+    let uv = opts["authenticatorSelection"]["userVerification"]
+        .as_str()
+        .or_else(|| opts["userVerification"].as_str());
+    uv == Some("required")
+}
+
+// Rejects the registration when any of the site's `excludeCredentials` is
+// already stored for this RP in one of the opened databases. Without this
+// check the same account ends up with duplicate passkeys
+pub(crate) fn check_exclude_credentials(options_json: &str, origin: &str) -> Result<()> {
+    let opts: serde_json::Value = serde_json::from_str(options_json)?;
+
+    let exclude_ids: Vec<String> = opts["excludeCredentials"]
+        .as_array()
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|v| v["id"].as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // An empty list would match every passkey of this RP
+    if exclude_ids.is_empty() {
+        return Ok(());
+    }
+
+    let rp_id = rp_id_from_options(options_json, origin);
+    let db_keys = crate::backup_service::opened_db_keys()?;
+    let existing =
+        kp_service::browser_extension::find_matching_passkeys(&db_keys, &rp_id, &exclude_ids)?;
+
+    if !existing.is_empty() {
+        log::info!(
+            "Passkey registration for rp id {} rejected as an excluded credential is already stored",
+            &rp_id
+        );
+        return Err(error::Error::UnexpectedError(
+            "CREDENTIAL_EXCLUDED".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
 // Generates a new P-256 key pair, builds all WebAuthn registration structures,
 // stores the passkey in KDBX, persists the database to disk, and returns the
 // credential JSON that the extension passes back to the website.
 //
 // `target` determines whether the passkey is added to an existing KDBX entry
-// or stored as a brand-new entry.
+// or stored as a brand-new entry. The UV flag of the returned authenticator data
+// is set only when `user_verified` is true.
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn create_and_store_passkey(
     db_key: &str,
     options_json: &str,
@@ -87,10 +156,15 @@ pub(crate) fn create_and_store_passkey(
     new_entry_name: Option<String>,
     group_uuid: Option<String>,
     new_group_name: Option<String>,
+    user_verified: bool,
 ) -> Result<String> {
     // 1. Crypto: generate key, build WebAuthn structures
     let creation_result: PasskeyCreationResult =
         passkey_crypto::create_passkey(options_json, origin)?;
+    let credential_json = passkey_crypto::set_registration_user_verified(
+        &creation_result.credential_json,
+        user_verified,
+    )?;
 
     // 2. Convert the optional UUIDs from strings
     let existing_entry_uuid_parsed = existing_entry_uuid
@@ -129,7 +203,17 @@ pub(crate) fn create_and_store_passkey(
         new_group_name,
     };
     // 4. Delegate entry create/update + save to core (with correct backup path)
-    let backup_file_name = app_state::AppState::state_instance().get_backup_file(db_key);
+    //    and notify the UI
+    store_passkey(db_key, storage_info)?;
+
+    Ok(credential_json)
+}
+
+// Stores the passkey through core (entry create/update + save with the correct
+// backup path) and informs the UI about the affected entry
+fn store_passkey(db_key: &str, storage_info: PasskeyStorageInfo) -> Result<()> {
+    let app_state = app_state::AppState::state_instance();
+    let backup_file_name = app_state.get_backup_file(db_key);
 
     log::debug!(
         "Passkey creation kdbx backup_file_name {:?}",
@@ -141,27 +225,29 @@ pub(crate) fn create_and_store_passkey(
         storage_info,
         backup_file_name.as_deref(),
     )?;
+    crate::backup_service::backup_written(db_key, backup_file_name.as_deref());
+
+    // Notify the main window so the UI reloads the entry list and can navigate
+    // to the affected entry
+    emit_passkey_data_changed(PasskeyChangedPayload {
+        db_key: db_key.to_string(),
+        entry_uuid: outcome.entry_uuid.to_string(),
+        group_uuid: outcome.group_uuid.to_string(),
+        entry_type_uuid: outcome.entry_type_uuid.to_string(),
+        entry_type_name: outcome.entry_type_name.clone(),
+        tags: outcome.tags.clone(),
+    });
+
+    Ok(())
+}
 
-    // 5. Notify the main window so the UI reloads the entry list and can navigate
-    //    to the affected entry
+fn emit_passkey_data_changed(payload: PasskeyChangedPayload) {
     if let Some(win) =
         app_state::AppState::global_app_handle().get_webview_window(MAIN_WINDOW_LABEL)
     {
         log::debug!("Emiting event PASSKEY_DATA_CHANGED_EVENT in db message to the UI layer");
-        let _ = win.emit(
-            PASSKEY_DATA_CHANGED_EVENT,
-            PasskeyChangedPayload {
-                db_key: db_key.to_string(),
-                entry_uuid: outcome.entry_uuid.to_string(),
-                group_uuid: outcome.group_uuid.to_string(),
-                entry_type_uuid: outcome.entry_type_uuid.to_string(),
-                entry_type_name: outcome.entry_type_name.clone(),
-                tags: outcome.tags.clone(),
-            },
-        );
+        let _ = win.emit(PASSKEY_DATA_CHANGED_EVENT, payload);
     }
-
-    Ok(creation_result.credential_json)
 }
 
 // ── Passkey authentication helpers ───────────────────────────────────────────
@@ -174,17 +260,18 @@ pub(crate) fn find_matching_passkeys(
     rp_id: &str,
     allow_credential_ids: Vec<String>,
 ) -> Result<Vec<PasskeySummary>> {
-    let db_keys = kp_service::all_kdbx_cache_keys()?;
+    let db_keys = crate::backup_service::opened_db_keys()?;
     kp_service::browser_extension::find_matching_passkeys(&db_keys, rp_id, &allow_credential_ids)
 }
 
 // Loads the passkey stored in `entry_uuid` (within `db_key`) and produces the
-// signed WebAuthn assertion JSON.
+// signed WebAuthn assertion JSON. The UV flag is set only when `user_verified` is true.
 pub(crate) fn sign_passkey_assertion(
     db_key: &str,
     entry_uuid: &Uuid,
     options_json: &str,
     origin: &str,
+    user_verified: bool,
 ) -> Result<String> {
     // 1. Retrieve the passkey data from KDBX
     let passkey: PasskeyEntry =
@@ -200,5 +287,310 @@ pub(crate) fn sign_passkey_assertion(
         origin,
     )?;
 
-    Ok(assertion.credential_json)
+    // 3. Set the UV flag to the actual verification outcome
+    passkey_crypto::set_assertion_user_verified(
+        &assertion.credential_json,
+        &passkey.private_key_pem,
+        user_verified,
+    )
+}
+
+// ── Passkey management helpers ───────────────────────────────────────────────
+//
+// These back the desktop side passkey management (list/rename/delete/move).
+// A passkey is a set of KeePassXC compatible 'KPEX_PASSKEY_*' fields in one
+// section of an entry, so all of the operations below work on the entry form
+// data and then persist the database through the same backup-aware save path
+// used for a normal save.
+
+const KPEX_PASSKEY_FIELD_PREFIX: &str = "KPEX_PASSKEY_";
+const KPEX_PASSKEY_CREDENTIAL_ID: &str = "KPEX_PASSKEY_CREDENTIAL_ID";
+const KPEX_PASSKEY_RELYING_PARTY: &str = "KPEX_PASSKEY_RELYING_PARTY";
+const KPEX_PASSKEY_USERNAME: &str = "KPEX_PASSKEY_USERNAME";
+const KPEX_PASSKEY_PRIVATE_KEY_PEM: &str = "KPEX_PASSKEY_PRIVATE_KEY_PEM";
+const KPEX_PASSKEY_USER_HANDLE: &str = "KPEX_PASSKEY_USER_HANDLE";
+
+// The section name used when a passkey is moved to an entry that has no passkey
+// section yet and the source section could not be determined
+const PASSKEY_SECTION_NAME: &str = "Passkey Details";
+
+#[derive(Debug, Serialize)]
+pub struct PasskeyInfo {
+    pub db_key: String,
+    pub db_name: String,
+    pub entry_uuid: Uuid,
+    pub entry_title: String,
+    pub group_uuid: Uuid,
+    pub credential_id_b64url: String,
+    pub rp_id: String,
+    pub username: Option<String>,
+    // The passkey fields do not carry their own timestamp. The creation time of
+    // the entry holding the passkey is used instead
+    pub creation_time: NaiveDateTime,
+}
+
+fn is_passkey_field(key: &str) -> bool {
+    key.starts_with(KPEX_PASSKEY_FIELD_PREFIX)
+}
+
+fn passkey_field_value(form_data: &kp_service::EntryFormData, key: &str) -> Option<String> {
+    form_data
+        .section_fields
+        .values()
+        .flatten()
+        .find(|kv| kv.key == key)
+        .and_then(|kv| kv.value.clone())
+}
+
+// Returns the name of the section that holds the passkey fields of this entry
+fn passkey_section_name(form_data: &kp_service::EntryFormData) -> Option<String> {
+    form_data
+        .section_fields
+        .iter()
+        .find(|(_, kvs)| kvs.iter().any(|kv| kv.key == KPEX_PASSKEY_CREDENTIAL_ID))
+        .map(|(name, _)| name.clone())
+}
+
+fn passkey_entry_form_data(db_key: &str, entry_uuid: &Uuid) -> Result<kp_service::EntryFormData> {
+    let form_data = kp_service::get_entry_form_data_by_id(db_key, entry_uuid)?;
+    if passkey_section_name(&form_data).is_none() {
+        return Err(error::Error::DataError("The entry does not have a passkey"));
+    }
+    Ok(form_data)
+}
+
+fn changed_payload(db_key: &str, form_data: &kp_service::EntryFormData) -> PasskeyChangedPayload {
+    PasskeyChangedPayload {
+        db_key: db_key.to_string(),
+        entry_uuid: form_data.uuid.to_string(),
+        group_uuid: form_data.group_uuid.to_string(),
+        entry_type_uuid: form_data.entry_type_uuid.to_string(),
+        entry_type_name: form_data.entry_type_name.clone(),
+        tags: form_data.tags.clone(),
+    }
+}
+
+// Saves the database after a passkey change. Remote dbs go through the remote
+// save path so that the mtime based conflict detection is still done. This is called
+// from async tasks and so the remote save is run where it may block
+pub(crate) fn save_db_with_backup(db_key: &str) -> Result<()> {
+    let app_state = app_state::AppState::state_instance();
+    let backup_file_name = app_state.get_backup_file(db_key);
+
+    if crate::remote_storage::is_remote_db_key(db_key) {
+        let recorded_mtime = app_state.remote_mtime(db_key);
+        let (_kdbx_saved, remote_mtime, queued) = crate::remote_storage::run_blocking(|| {
+            crate::remote_storage::rs_save_kdbx(
+                db_key,
+                false,
+                recorded_mtime,
+                backup_file_name.as_deref(),
+            )
+        })?;
+        app_state.set_remote_mtime(db_key, remote_mtime);
+        crate::remote_storage::emit_sync_status(db_key, queued);
+    } else {
+        let _db_file_access = crate::mas::db_file_access(db_key);
+        kp_service::save_kdbx_with_backup(db_key, backup_file_name.as_deref(), false)?;
+    }
+    crate::backup_service::backup_written(db_key, backup_file_name.as_deref());
+    Ok(())
+}
+
+// Updates the entry in memory, persists the db and informs the UI
+fn update_passkey_entry(db_key: &str, form_data: kp_service::EntryFormData) -> Result<()> {
+    let payload = changed_payload(db_key, &form_data);
+    kp_service::update_entry_from_form_data(db_key, form_data)?;
+    save_db_with_backup(db_key)?;
+    emit_passkey_data_changed(payload);
+    Ok(())
+}
+
+// Lists the passkeys found in all opened databases. Entries in the recycle bin
+// are not included
+pub(crate) fn list_passkeys() -> Result<Vec<PasskeyInfo>> {
+    let db_keys = crate::backup_service::opened_db_keys()?;
+    let mut result = vec![];
+
+    for db_key in &db_keys {
+        // The db may have been closed or locked in the meantime
+        let Ok(entries) =
+            kp_service::entry_summary_data(db_key, kp_service::EntryCategory::AllEntries)
+        else {
+            continue;
+        };
+        let db_name =
+            kp_service::browser_extension::get_db_name(db_key).unwrap_or_else(|_| db_key.clone());
+
+        for summary in entries {
+            let Ok(entry_uuid) = Uuid::parse_str(&summary.uuid) else {
+                continue;
+            };
+            // The key-value fields are enough to find out whether this entry has a
+            // passkey. Only then the full form data is loaded
+            let has_passkey = kp_service::entry_key_value_fields(db_key, &entry_uuid)
+                .map(|kvs| kvs.contains_key(KPEX_PASSKEY_CREDENTIAL_ID))
+                .unwrap_or(false);
+            if !has_passkey {
+                continue;
+            }
+
+            let Ok(form_data) = kp_service::get_entry_form_data_by_id(db_key, &entry_uuid) else {
+                continue;
+            };
+            let Some(credential_id_b64url) =
+                passkey_field_value(&form_data, KPEX_PASSKEY_CREDENTIAL_ID)
+            else {
+                continue;
+            };
+
+            result.push(PasskeyInfo {
+                db_key: db_key.clone(),
+                db_name: db_name.clone(),
+                entry_uuid,
+                entry_title: form_data.title.clone(),
+                group_uuid: form_data.group_uuid,
+                credential_id_b64url,
+                rp_id: passkey_field_value(&form_data, KPEX_PASSKEY_RELYING_PARTY)
+                    .unwrap_or_default(),
+                username: passkey_field_value(&form_data, KPEX_PASSKEY_USERNAME),
+                creation_time: form_data.creation_time,
+            });
+        }
+    }
+
+    Ok(result)
+}
+
+// Stores each of the passkeys in a new entry through core as done for a passkey created in
+// the browser. Core has no batch insert and so the db is saved once per passkey
+pub(crate) fn store_new_passkeys(db_key: &str, passkeys: Vec<PasskeyStorageInfo>) -> Result<()> {
+    for storage_info in passkeys {
+        store_passkey(db_key, storage_info)?;
+    }
+    Ok(())
+}
+
+// Changes the user name shown for this passkey in the browser's passkey picker.
+// The user handle is left as is and the site continues to recognize the passkey
+pub(crate) fn rename_passkey(db_key: &str, entry_uuid: &Uuid, username: &str) -> Result<()> {
+    let username = username.trim();
+    if username.is_empty() {
+        return Err(error::Error::DataError("Passkey user name cannot be empty"));
+    }
+
+    let mut form_data = passkey_entry_form_data(db_key, entry_uuid)?;
+    let mut found = false;
+    for kv in form_data.section_fields.values_mut().flatten() {
+        if kv.key == KPEX_PASSKEY_USERNAME {
+            kv.value = Some(username.to_string());
+            found = true;
+        }
+    }
+    if !found {
+        return Err(error::Error::DataError(
+            "The passkey does not have a user name field",
+        ));
+    }
+
+    update_passkey_entry(db_key, form_data)
+}
+
+// Removes all passkey fields from the entry. The entry itself and its other
+// fields are kept
+pub(crate) fn delete_passkey(db_key: &str, entry_uuid: &Uuid) -> Result<()> {
+    let mut form_data = passkey_entry_form_data(db_key, entry_uuid)?;
+    let _ = take_passkey_fields(&mut form_data);
+    update_passkey_entry(db_key, form_data)
+}
+
+// Removes the passkey fields from the form data and returns them along with the
+// name of the section they were found in. A section left with no fields is removed
+fn take_passkey_fields(
+    form_data: &mut kp_service::EntryFormData,
+) -> (String, Vec<kp_service::KeyValueData>) {
+    let section_name =
+        passkey_section_name(form_data).unwrap_or_else(|| PASSKEY_SECTION_NAME.to_string());
+
+    let mut taken = vec![];
+    for kvs in form_data.section_fields.values_mut() {
+        let (passkey_kvs, other_kvs): (Vec<_>, Vec<_>) = std::mem::take(kvs)
+            .into_iter()
+            .partition(|kv| is_passkey_field(&kv.key));
+        *kvs = other_kvs;
+        taken.extend(passkey_kvs);
+    }
+
+    form_data.section_fields.retain(|_, kvs| !kvs.is_empty());
+    let section_fields = &form_data.section_fields;
+    form_data
+        .section_names
+        .retain(|name| section_fields.contains_key(name));
+
+    (section_name, taken)
+}
+
+// Moves the passkey of one entry to another entry. The target entry may be in
+// the same or in any other opened database but must not have a passkey already.
+// The target db is saved first so that a failure there leaves the source untouched.
+// When the source can not be saved afterwards, the copy is removed from the target
+// again so that the passkey does not end up in both entries
+pub(crate) fn move_passkey(
+    source_db_key: &str,
+    source_entry_uuid: &Uuid,
+    target_db_key: &str,
+    target_entry_uuid: &Uuid,
+) -> Result<()> {
+    if source_db_key == target_db_key && source_entry_uuid == target_entry_uuid {
+        return Err(error::Error::DataError(
+            "The passkey is already in the selected entry",
+        ));
+    }
+
+    let mut source_form_data = passkey_entry_form_data(source_db_key, source_entry_uuid)?;
+    let mut target_form_data =
+        kp_service::get_entry_form_data_by_id(target_db_key, target_entry_uuid)?;
+    if passkey_section_name(&target_form_data).is_some() {
+        return Err(error::Error::DataError(
+            "The selected entry already has a passkey",
+        ));
+    }
+
+    // Unchanged copies used to undo the move when the source save fails
+    let original_source_form_data =
+        kp_service::get_entry_form_data_by_id(source_db_key, source_entry_uuid)?;
+    let original_target_form_data =
+        kp_service::get_entry_form_data_by_id(target_db_key, target_entry_uuid)?;
+
+    let (section_name, passkey_kvs) = take_passkey_fields(&mut source_form_data);
+
+    if !target_form_data.section_names.contains(&section_name) {
+        target_form_data.section_names.push(section_name.clone());
+    }
+    target_form_data
+        .section_fields
+        .entry(section_name)
+        .or_default()
+        .extend(passkey_kvs);
+
+    update_passkey_entry(target_db_key, target_form_data)?;
+
+    let Err(source_error) = update_passkey_entry(source_db_key, source_form_data) else {
+        return Ok(());
+    };
+    log::error!(
+        "Saving the source db of the moved passkey failed: {}",
+        source_error
+    );
+
+    // The source entry may have been updated in memory before its save failed
+    let _ = kp_service::update_entry_from_form_data(source_db_key, original_source_form_data);
+    match update_passkey_entry(target_db_key, original_target_form_data) {
+        Ok(()) => Err(source_error),
+        Err(e) => Err(error::Error::UnexpectedError(format!(
+            "The passkey was copied to the selected entry but could not be removed from the \
+            source entry ({}). Removing the copy also failed ({}). Delete one of the two passkeys",
+            source_error, e
+        ))),
+    }
 }
@@ -1,3 +1,4 @@
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use futures_util::StreamExt as _;
@@ -29,7 +30,8 @@ const BUFFER_SIZE: usize = 1024 * 1024;
 // before users actually trip the browser-side limit.
 const SIZE_WARN_BYTES: usize = 800 * 1024;
 
-async fn read_framed_message(
+// Also used by the okp CLI endpoint (see cli_service)
+pub(crate) async fn read_framed_message(
     reader: &mut ReadHalf<Connection>,
 ) -> std::io::Result<Vec<u8>> {
     let mut length_bytes = [0u8; 4];
@@ -52,7 +54,10 @@ async fn read_framed_message(
 }
 
 // Reads the bytes from the proxy app and sends to the request handler (Request::handle_input_message).
-// The request handler after processing sends the response to a channel 'sender'
+// The request handler after processing sends the response to a channel 'sender'.
+// The messages are handled one after the other in the order received. Only the requests that
+// wait for the user (passkey user verification, git credential confirmation) are handled in
+// their own task so that they do not hold up the other requests
 fn handle_input(mut reader: ReadHalf<Connection>, sender: Arc<BrowserServiceTx>) {
     tauri::async_runtime::spawn(async move {
         loop {
@@ -62,6 +67,12 @@ fn handle_input(mut reader: ReadHalf<Connection>, sender: Arc<BrowserServiceTx>)
                     break;
                 }
                 Ok(body) => match String::from_utf8(body) {
+                    Ok(input_message) if Request::waits_for_user(&input_message) => {
+                        tauri::async_runtime::spawn(Request::handle_input_message(
+                            input_message,
+                            sender.clone(),
+                        ));
+                    }
                     Ok(input_message) => {
                         Request::handle_input_message(input_message, sender.clone()).await;
                     }
@@ -78,7 +89,7 @@ fn handle_input(mut reader: ReadHalf<Connection>, sender: Arc<BrowserServiceTx>)
     });
 }
 
-async fn write_framed_message(
+pub(crate) async fn write_framed_message(
     writer: &mut WriteHalf<Connection>,
     message: &str,
 ) -> std::io::Result<()> {
@@ -154,7 +165,7 @@ fn is_endpoint_server_running() -> bool {
 
 // Called to connect to the browser native message proxy app endpoint and provides listeners to receive
 // messages( or send) messages from (or to) the native message proxy app for all browser extensions
-async fn run_server(path: String) {
+async fn run_server(path: String, parent_folder: Option<PathBuf>) {
     log::info!(
         "Proxy listener - Run server is called with path {}, ",
         &path
@@ -164,7 +175,9 @@ async fn run_server(path: String) {
     // ($TMPDIR) is per-process and unreachable from the browser-spawned proxy.
     // Bind the socket inside the shared App Group container instead. Both ends
     // must hold the application-groups entitlement for this path to be writable.
-    let server_id = match sandbox::group_container_path() {
+    // On Linux, 'parent_folder' is passed for a Flatpak/Snap browser's shared runtime dir
+    let is_sandbox_endpoint = parent_folder.is_some();
+    let server_id = match parent_folder.or_else(sandbox::group_container_path) {
         Some(parent) => ServerId::new(path).parent_folder(parent),
         None => ServerId::new(path),
     };
@@ -189,8 +202,11 @@ async fn run_server(path: String) {
 
     log::info!("Listener to the Browser native message proxy is started ...");
 
-    // Set the flag saying that endpoint server is started
-    endpoint_server_started();
+    // Set the flag saying that endpoint server is started. The additional sandbox
+    // endpoints are tracked separately in SANDBOX_ENDPOINTS_RUNNING
+    if !is_sandbox_endpoint {
+        endpoint_server_started();
+    }
 
     // When each browser's native message proxy app is launched (Ext -> Native message -> Lauches the proxy),
     // the 'next' call returns with the connection to that proxy
@@ -248,11 +264,51 @@ pub(crate) fn start_proxy_handler() {
                 "Starting the app side proxy listening service with IPC name {} ",
                 NATIVE_MESSAGE_CONNECTION_NAME
             );
-            run_server(NATIVE_MESSAGE_CONNECTION_NAME.to_string()).await;
+            run_server(NATIVE_MESSAGE_CONNECTION_NAME.to_string(), None).await;
         });
     } else {
         log::debug!("Endpoint server for proxy connetion listener is already running");
     }
+
+    #[cfg(target_os = "linux")]
+    {
+        crate::browser_service::LinuxNativeMessagingConfig::refresh_proxy_copies();
+        start_sandbox_endpoints();
+    }
+}
+
+// Proxy copies launched by Flatpak/Snap browsers have their own private /tmp and can not
+// reach the default endpoint. For each such browser installed, one more endpoint is
+// started in the runtime dir that is shared with that sandbox. The proxy uses the same
+// dir when it finds itself running inside a sandbox (see onekeepass-proxy/src/sandbox.rs)
+#[cfg(target_os = "linux")]
+static SANDBOX_ENDPOINTS_RUNNING: std::sync::Mutex<Vec<PathBuf>> =
+    std::sync::Mutex::new(Vec::new());
+
+#[cfg(target_os = "linux")]
+fn start_sandbox_endpoints() {
+    let mut running = SANDBOX_ENDPOINTS_RUNNING.lock().unwrap();
+    for dir in crate::browser_service::LinuxNativeMessagingConfig::sandbox_ipc_dirs() {
+        if running.contains(&dir) {
+            continue;
+        }
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            log::error!(
+                "Creating the sandbox ipc dir {:?} failed with error {}",
+                &dir,
+                e
+            );
+            continue;
+        }
+        log::debug!(
+            "Starting the proxy listening service in the sandbox ipc dir {:?}",
+            &dir
+        );
+        running.push(dir.clone());
+        tauri::async_runtime::spawn(async move {
+            run_server(NATIVE_MESSAGE_CONNECTION_NAME.to_string(), Some(dir)).await;
+        });
+    }
 }
 
 /*
@@ -0,0 +1,162 @@
+// User verification (the WebAuthn 'UV' step) for passkey registration and
+// assertion requests where the site asks for `userVerification: "required"`.
+//
+// The browser request is parked here while the UI asks the user to re-enter the
+// database password (or to use biometric where available). The UI's answer is
+// verified against the database credentials before the parked request continues.
+// If the user is not verified, the request fails and no authenticator data is
+// produced. onekeepass-core's passkey_crypto always sets the UV flag, so the flag is
+// set again from the actual outcome (see passkey_crypto::set_*_user_verified) and
+// it is cleared whenever no verification was done.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use serde::Serialize;
+use tauri::Emitter;
+use tokio::sync::oneshot;
+
+use onekeepass_core::db_service as kp_service;
+use onekeepass_core::error::{self, Result};
+
+use crate::app_state::AppState;
+use crate::biometric;
+use crate::constants::event_names::PASSKEY_USER_VERIFICATION_REQUEST_EVENT;
+
+// Should be well within the WebAuthn ceremony timeout the browser applies
+const VERIFICATION_TIMEOUT_SECS: u64 = 120;
+
+struct PendingVerification {
+    db_key: String,
+    tx: oneshot::Sender<bool>,
+}
+
+// In-flight verification requests keyed by a generated request id
+static PENDING_VERIFICATIONS: OnceLock<Mutex<HashMap<String, PendingVerification>>> =
+    OnceLock::new();
+
+fn pending_verifications() -> &'static Mutex<HashMap<String, PendingVerification>> {
+    PENDING_VERIFICATIONS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Ceremony {
+    Create,
+    Get,
+}
+
+// Payload sent to the UI to raise the verification dialog
+#[derive(Serialize, Clone, Debug)]
+struct UserVerificationRequestPayload {
+    request_id: String,
+    db_key: String,
+    db_name: String,
+    rp_id: String,
+    ceremony: Ceremony,
+    biometric_type: String,
+}
+
+// Raises the verification dialog for the database 'db_key' and waits for the
+// user (auto-failing after a timeout). Returns true only when the user was
+// verified against that database's credentials
+pub(crate) async fn request_user_verification(
+    db_key: &str,
+    rp_id: &str,
+    ceremony: Ceremony,
+) -> bool {
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let (tx, rx) = oneshot::channel::<bool>();
+
+    pending_verifications().lock().unwrap().insert(
+        request_id.clone(),
+        PendingVerification {
+            db_key: db_key.to_string(),
+            tx,
+        },
+    );
+
+    let payload = UserVerificationRequestPayload {
+        request_id: request_id.clone(),
+        db_key: db_key.to_string(),
+        db_name: kp_service::browser_extension::get_db_name(db_key)
+            .unwrap_or_else(|_| db_key.to_string()),
+        rp_id: rp_id.to_string(),
+        ceremony,
+        biometric_type: biometric::supported_biometric_type(),
+    };
+
+    log::debug!(
+        "Emitting passkey user verification request {} to the UI",
+        &request_id
+    );
+
+    if let Err(e) =
+        AppState::global_app_handle().emit(PASSKEY_USER_VERIFICATION_REQUEST_EVENT, payload)
+    {
+        log::error!("Failed to emit the passkey user verification event: {}", e);
+        pending_verifications().lock().unwrap().remove(&request_id);
+        return false;
+    }
+
+    let outcome = tokio::time::timeout(Duration::from_secs(VERIFICATION_TIMEOUT_SECS), rx).await;
+
+    pending_verifications().lock().unwrap().remove(&request_id);
+
+    match outcome {
+        Ok(Ok(verified)) => verified,
+        _ => {
+            log::info!("Passkey user verification timed out or was abandoned");
+            false
+        }
+    }
+}
+
+// Verifies the credentials entered in the dialog and, on success, releases the
+// parked request. A wrong password returns an error and leaves the request
+// pending so that the user can try again
+pub(crate) fn verify_user(
+    request_id: &str,
+    password: Option<&str>,
+    key_file_name: Option<&str>,
+    use_biometric: bool,
+) -> Result<()> {
+    let db_key = pending_verifications()
+        .lock()
+        .unwrap()
+        .get(request_id)
+        .map(|p| p.db_key.clone())
+        .ok_or(error::Error::DataError(
+            "The passkey verification request has expired",
+        ))?;
+
+    if use_biometric {
+        if !biometric::authenticate_with_biometric(&db_key) {
+            return Err(error::Error::DataError("Biometric authentication failed"));
+        }
+    } else {
+        // The credentials are checked the same way as when a locked database is
+        // unlocked. The opened database content is not changed by this call
+        kp_service::unlock_kdbx(&db_key, password, key_file_name)?;
+    }
+
+    submit(request_id, true);
+    Ok(())
+}
+
+// Called when the user dismisses the dialog
+pub(crate) fn cancel_verification(request_id: &str) {
+    submit(request_id, false);
+}
+
+fn submit(request_id: &str, verified: bool) {
+    if let Some(p) = pending_verifications().lock().unwrap().remove(request_id) {
+        let _ = p.tx.send(verified);
+    } else {
+        log::warn!(
+            "Passkey user verification answer for unknown/expired request {}",
+            request_id
+        );
+    }
+}
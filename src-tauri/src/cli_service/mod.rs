@@ -0,0 +1,262 @@
+// The local endpoint used by the 'okp' command line client (the okp mode of onekeepass-proxy).
+//
+// The endpoint is separate from the one of the browser proxies and uses the same framing
+// (see browser_service::read_framed_message). A client first sends a 'Hello'. A new client
+// is shown to the user in a dialog and, when allowed, is given a client id and a token that
+// it keeps in its config file. Only the hash of the token is stored in the preference and
+// the user can revoke a client in the settings any time.
+//
+// The listener is started when the access is enabled and is then kept running till the app
+// exits. The enable flag and the approved clients are checked on each request so that a
+// disable or a revoke takes effect on an already connected client too
+
+mod request;
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use futures_util::StreamExt as _;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tauri::Emitter;
+use tipsy::{Connection, Endpoint, OnConflict, ServerId};
+use tokio::io::{split, ReadHalf, WriteHalf};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::app_preference::CliClient;
+use crate::app_state::AppState;
+use crate::browser_service::{read_framed_message, write_framed_message};
+use crate::constants::event_names::CLI_CLIENT_APPROVAL_REQUEST_EVENT;
+use crate::sandbox;
+
+use request::{Reply, Request};
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "onekeepass-dev")] {
+        const CLI_CONNECTION_NAME: &str = "okp_cli_ipc_dev";
+    } else {
+        const CLI_CONNECTION_NAME: &str = "okp_cli_ipc";
+    }
+}
+
+// The okp client waits a little longer than this before giving up
+const APPROVAL_TIMEOUT_SECS: u64 = 60;
+
+fn server_started() -> &'static Mutex<bool> {
+    static SERVER_STARTED: OnceLock<Mutex<bool>> = OnceLock::new();
+    SERVER_STARTED.get_or_init(Default::default)
+}
+
+// In-flight approval requests keyed by a generated request id
+fn pending_approvals() -> &'static Mutex<HashMap<String, oneshot::Sender<bool>>> {
+    static PENDING_APPROVALS: OnceLock<Mutex<HashMap<String, oneshot::Sender<bool>>>> =
+        OnceLock::new();
+    PENDING_APPROVALS.get_or_init(Default::default)
+}
+
+// Payload sent to the UI to raise the approval dialog
+#[derive(Serialize, Clone, Debug)]
+struct CliClientApprovalPayload {
+    request_id: String,
+    client_name: String,
+}
+
+// The client id and the token returned to a newly approved client
+#[derive(Serialize, Debug)]
+struct ClientCredentials {
+    client_id: String,
+    token: String,
+}
+
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn is_approved_client(client_id: &str) -> bool {
+    AppState::state_instance()
+        .cli_clients()
+        .iter()
+        .any(|c| c.client_id == client_id)
+}
+
+fn verify_credentials(client_id: &str, token: &str) -> bool {
+    let hash = token_hash(token);
+    AppState::state_instance()
+        .cli_clients()
+        .iter()
+        .any(|c| c.client_id == client_id && c.token_hash == hash)
+}
+
+// Raises the approval dialog and waits for the user's answer. Denied on a time out
+async fn request_approval(client_name: &str) -> bool {
+    let request_id = Uuid::new_v4().to_string();
+    let (tx, rx) = oneshot::channel::<bool>();
+    pending_approvals()
+        .lock()
+        .unwrap()
+        .insert(request_id.clone(), tx);
+
+    let payload = CliClientApprovalPayload {
+        request_id: request_id.clone(),
+        client_name: client_name.to_string(),
+    };
+    if let Err(e) = AppState::global_app_handle().emit(CLI_CLIENT_APPROVAL_REQUEST_EVENT, payload) {
+        log::error!("okp: failed to emit approval request event: {}", e);
+        pending_approvals().lock().unwrap().remove(&request_id);
+        return false;
+    }
+
+    let outcome = tokio::time::timeout(Duration::from_secs(APPROVAL_TIMEOUT_SECS), rx).await;
+    pending_approvals().lock().unwrap().remove(&request_id);
+    matches!(outcome, Ok(Ok(true)))
+}
+
+// Delivers the user's answer. Called from the `cli_client_approval_result` command
+pub(crate) fn submit_approval(request_id: &str, allow: bool) {
+    match pending_approvals().lock().unwrap().remove(request_id) {
+        Some(tx) => {
+            let _ = tx.send(allow);
+        }
+        None => log::warn!("okp: answer for unknown/expired request {}", request_id),
+    }
+}
+
+// Returns the client id when the client is known or has just been approved. A new client
+// is given its credentials in the reply
+async fn hello(
+    client_name: String,
+    client_id: Option<String>,
+    token: Option<String>,
+) -> (Option<String>, Reply) {
+    if let (Some(client_id), Some(token)) = (&client_id, &token) {
+        if verify_credentials(client_id, token) {
+            return (Some(client_id.clone()), Reply::ok(serde_json::Value::Null));
+        }
+    }
+
+    if !request_approval(&client_name).await {
+        return (None, Reply::error("The access was denied in OneKeePass"));
+    }
+
+    let credentials = ClientCredentials {
+        client_id: Uuid::new_v4().to_string(),
+        token: format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple()),
+    };
+    AppState::state_instance().add_cli_client(CliClient {
+        client_id: credentials.client_id.clone(),
+        name: client_name,
+        token_hash: token_hash(&credentials.token),
+        approved_at: chrono::Utc::now().naive_utc(),
+    });
+    log::info!("okp: client {} is approved", &credentials.client_id);
+
+    (Some(credentials.client_id.clone()), Reply::ok(credentials))
+}
+
+async fn handle_connection(mut reader: ReadHalf<Connection>, mut writer: WriteHalf<Connection>) {
+    // Set on a successful 'Hello'
+    let mut client_id: Option<String> = None;
+
+    loop {
+        let body = match read_framed_message(&mut reader).await {
+            Ok(body) if body.is_empty() => break,
+            Ok(body) => body,
+            Err(e) => {
+                log::debug!("okp: connection closed: {}", e);
+                break;
+            }
+        };
+
+        let reply = if !AppState::state_instance().is_cli_enabled() {
+            Reply::error("The command line access is disabled in OneKeePass settings")
+        } else {
+            match serde_json::from_slice::<Request>(&body) {
+                Ok(Request::Hello {
+                    client_name,
+                    client_id: id,
+                    token,
+                }) => {
+                    let (id, reply) = hello(client_name, id, token).await;
+                    client_id = id;
+                    reply
+                }
+                Ok(request) => match &client_id {
+                    Some(id) if is_approved_client(id) => request.handle(),
+                    Some(_) => {
+                        client_id = None;
+                        Reply::error("This client was revoked in OneKeePass")
+                    }
+                    None => Reply::error("The client is not approved"),
+                },
+                Err(e) => Reply::error(format!("Invalid request: {}", e)),
+            }
+        };
+
+        if let Err(e) = write_framed_message(&mut writer, &reply.to_json()).await {
+            log::error!("okp: writing the reply failed: {}", e);
+            break;
+        }
+    }
+}
+
+async fn run_server() {
+    let server_id = match sandbox::group_container_path() {
+        Some(parent) => ServerId::new(CLI_CONNECTION_NAME).parent_folder(parent),
+        None => ServerId::new(CLI_CONNECTION_NAME),
+    };
+
+    let endpoint = match Endpoint::new(server_id, OnConflict::Overwrite) {
+        Ok(e) => e,
+        Err(e) => {
+            log::error!("okp: creating the endpoint failed: {}", e);
+            return;
+        }
+    };
+    let incoming = match endpoint.incoming() {
+        Ok(incoming) => incoming,
+        Err(e) => {
+            log::error!("okp: failed to open the socket: {}", e);
+            return;
+        }
+    };
+    futures_util::pin_mut!(incoming);
+
+    log::info!("okp command line listener is started");
+
+    while let Some(result) = incoming.next().await {
+        match result {
+            Ok(stream) => {
+                let (reader, writer) = split(stream);
+                tauri::async_runtime::spawn(handle_connection(reader, writer));
+            }
+            Err(e) => log::error!("okp: error in making incoming connection {}", e),
+        }
+    }
+}
+
+// Starts the listener once. Called when the access is enabled at the start up or in the settings
+pub(crate) fn start() {
+    let mut started = server_started().lock().unwrap();
+    if *started {
+        return;
+    }
+    *started = true;
+    tauri::async_runtime::spawn(run_server());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_token_hash() {
+        let hash = token_hash("abc");
+        assert_eq!(
+            hash,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_ne!(token_hash("abd"), hash);
+    }
+}
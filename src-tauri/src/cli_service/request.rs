@@ -0,0 +1,342 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use onekeepass_core::db_service as kp_service;
+use onekeepass_core::error::{self, Result};
+
+use crate::app_state::AppState;
+use crate::{backup_service, db_lock, ssh_agent};
+
+// The requests of the okp client. A db is given by its name or its db key. An entry is given
+// by its uuid or by a search term that matches only one entry
+#[derive(Deserialize, Debug)]
+#[serde(tag = "action")]
+pub(crate) enum Request {
+    Hello {
+        client_name: String,
+        client_id: Option<String>,
+        token: Option<String>,
+    },
+    ListDbs,
+    Search {
+        term: String,
+        db: Option<String>,
+    },
+    Get {
+        field: String,
+        entry: String,
+        db: Option<String>,
+    },
+    Totp {
+        entry: String,
+        db: Option<String>,
+    },
+    Generate {
+        options: kp_service::PasswordGenerationOptions,
+    },
+    Lock {
+        db: Option<String>,
+    },
+}
+
+// Sent as {"ok": <value>} or {"error": <message>}
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Reply {
+    Ok(serde_json::Value),
+    Error(String),
+}
+
+impl Reply {
+    pub(crate) fn ok<T: Serialize>(value: T) -> Self {
+        match serde_json::to_value(value) {
+            Ok(v) => Reply::Ok(v),
+            Err(e) => Reply::Error(e.to_string()),
+        }
+    }
+
+    pub(crate) fn error(message: impl Into<String>) -> Self {
+        Reply::Error(message.into())
+    }
+
+    pub(crate) fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct DbInfo {
+    db_key: String,
+    name: String,
+    locked: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct EntryInfo {
+    db_name: String,
+    uuid: Uuid,
+    title: String,
+    username: Option<String>,
+}
+
+// The entry field names that can be used in lower case in 'okp get'. Any other name is
+// taken as the name of a custom field
+fn field_key(field: &str) -> String {
+    match field.to_lowercase().as_str() {
+        "password" => kp_service::entry_keyvalue_key::PASSWORD.to_string(),
+        "username" | "user" => kp_service::entry_keyvalue_key::USER_NAME.to_string(),
+        "url" => kp_service::entry_keyvalue_key::URL.to_string(),
+        "title" => "Title".to_string(),
+        "notes" => "Notes".to_string(),
+        _ => field.to_string(),
+    }
+}
+
+fn db_name(db_key: &str) -> String {
+    kp_service::browser_extension::get_db_name(db_key).unwrap_or_else(|_| locked_db_name(db_key))
+}
+
+// The name of a locked db is not known in the backend. The file name is used instead
+fn locked_db_name(db_key: &str) -> String {
+    std::path::Path::new(db_key)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| db_key.to_string())
+}
+
+// The opened dbs except a backup opened to compare with a db
+fn opened_db_keys() -> Vec<String> {
+    backup_service::opened_db_keys().unwrap_or_default()
+}
+
+// All opened dbs or only the given one
+fn resolve_dbs(db: Option<&str>) -> Result<Vec<String>> {
+    let db_keys = opened_db_keys();
+    let Some(db) = db else {
+        if db_keys.is_empty() {
+            return Err(error::Error::UnexpectedError(
+                "No database is opened in OneKeePass".into(),
+            ));
+        }
+        return Ok(db_keys);
+    };
+
+    if let Some(db_key) = db_keys.iter().find(|k| *k == db || db_name(k) == db) {
+        return Ok(vec![db_key.clone()]);
+    }
+    let locked = db_lock::locked_db_keys()
+        .iter()
+        .any(|k| k == db || locked_db_name(k) == db);
+    if locked {
+        Err(error::Error::UnexpectedError(format!(
+            "The database '{}' is locked",
+            db
+        )))
+    } else {
+        Err(error::Error::UnexpectedError(format!(
+            "No opened database '{}'",
+            db
+        )))
+    }
+}
+
+fn username(db_key: &str, entry_uuid: &Uuid) -> Option<String> {
+    kp_service::entry_key_value_fields(db_key, entry_uuid)
+        .ok()?
+        .get(kp_service::entry_keyvalue_key::USER_NAME)
+        .filter(|u| !u.is_empty())
+        .cloned()
+}
+
+fn search(term: &str, db: Option<&str>) -> Result<Vec<EntryInfo>> {
+    let mut found = vec![];
+    for db_key in resolve_dbs(db)? {
+        let name = db_name(&db_key);
+        for summary in kp_service::search_term(&db_key, term)?.entry_items {
+            let Ok(uuid) = Uuid::parse_str(&summary.uuid) else {
+                continue;
+            };
+            found.push(EntryInfo {
+                db_name: name.clone(),
+                uuid,
+                username: username(&db_key, &uuid),
+                title: summary.title,
+            });
+        }
+    }
+    Ok(found)
+}
+
+// Finds the db key and the uuid of the entry given in a request
+fn resolve_entry(entry: &str, db: Option<&str>) -> Result<(String, Uuid)> {
+    if let Ok(entry_uuid) = Uuid::parse_str(entry) {
+        return resolve_dbs(db)?
+            .into_iter()
+            .find(|k| kp_service::entry_key_value_fields(k, &entry_uuid).is_ok())
+            .map(|k| (k, entry_uuid))
+            .ok_or_else(|| {
+                error::Error::UnexpectedError(format!("No entry with the uuid {}", entry))
+            });
+    }
+
+    let mut matched = vec![];
+    for db_key in resolve_dbs(db)? {
+        for summary in kp_service::search_term(&db_key, entry)?.entry_items {
+            if let Ok(uuid) = Uuid::parse_str(&summary.uuid) {
+                matched.push((db_key.clone(), uuid, summary.title));
+            }
+        }
+    }
+
+    // An exact title match is taken when the term matches other entries too
+    if matched.len() > 1 {
+        let exact: Vec<_> = matched
+            .iter()
+            .filter(|(_, _, title)| title.eq_ignore_ascii_case(entry))
+            .cloned()
+            .collect();
+        if exact.len() == 1 {
+            matched = exact;
+        }
+    }
+
+    match matched.len() {
+        0 => Err(error::Error::UnexpectedError(format!(
+            "No entry matches '{}'",
+            entry
+        ))),
+        1 => {
+            let (db_key, uuid, _) = matched.remove(0);
+            Ok((db_key, uuid))
+        }
+        n => Err(error::Error::UnexpectedError(format!(
+            "'{}' matches {} entries. Use 'okp search' and give the entry uuid",
+            entry, n
+        ))),
+    }
+}
+
+fn get_field(field: &str, entry: &str, db: Option<&str>) -> Result<String> {
+    let (db_key, entry_uuid) = resolve_entry(entry, db)?;
+    let key = field_key(field);
+    kp_service::entry_key_value_fields(&db_key, &entry_uuid)?
+        .remove(&key)
+        .ok_or_else(|| error::Error::UnexpectedError(format!("The entry has no field '{}'", field)))
+}
+
+fn totp(entry: &str, db: Option<&str>) -> Result<kp_service::CurrentOtpTokenData> {
+    let (db_key, entry_uuid) = resolve_entry(entry, db)?;
+    Ok(kp_service::entry_form_current_otp(
+        &db_key,
+        &entry_uuid,
+        "otp",
+    )?)
+}
+
+fn list_dbs() -> Vec<DbInfo> {
+    let mut dbs: Vec<DbInfo> = opened_db_keys()
+        .into_iter()
+        .map(|db_key| DbInfo {
+            name: db_name(&db_key),
+            db_key,
+            locked: false,
+        })
+        .collect();
+    dbs.extend(db_lock::locked_db_keys().into_iter().map(|db_key| DbInfo {
+        name: locked_db_name(&db_key),
+        db_key,
+        locked: true,
+    }));
+    dbs
+}
+
+// Locks the dbs the same way as a session event does (see session_lock.rs). The UI then
+// locks each one again with its save pending state
+fn lock(db: Option<&str>) -> Result<Vec<String>> {
+    let app_state = AppState::state_instance();
+    let mut db_keys = vec![];
+    for db_key in resolve_dbs(db)? {
+        ssh_agent::clear_keys_for_db(&db_key);
+        app_state.remote_poller.stop_polling(&db_key);
+        db_lock::lock(&db_key, true)?;
+        db_keys.push(db_key);
+    }
+    let names = db_keys.iter().map(|k| locked_db_name(k)).collect();
+    db_lock::notify_locked_in_backend(db_keys);
+    Ok(names)
+}
+
+fn reply<T: Serialize>(result: Result<T>) -> Reply {
+    match result {
+        Ok(v) => Reply::ok(v),
+        Err(e) => Reply::error(e.to_string()),
+    }
+}
+
+impl Request {
+    // Handles a request of an approved client. 'Hello' is handled in mod.rs
+    pub(crate) fn handle(self) -> Reply {
+        log::debug!("okp: handling {:?}", &self);
+        match self {
+            Request::Hello { .. } => Reply::error("Unexpected Hello"),
+            Request::ListDbs => Reply::ok(list_dbs()),
+            Request::Search { term, db } => reply(search(&term, db.as_deref())),
+            Request::Get { field, entry, db } => reply(get_field(&field, &entry, db.as_deref())),
+            Request::Totp { entry, db } => reply(totp(&entry, db.as_deref())),
+            Request::Generate { options } => reply(options.analyzed_password()),
+            Request::Lock { db } => reply(lock(db.as_deref())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_requests() {
+        let r: Request = serde_json::from_str(
+            r#"{"action":"Hello","client_name":"okp (user)","client_id":null,"token":null}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            r,
+            Request::Hello {
+                client_id: None,
+                ..
+            }
+        ));
+
+        let r: Request =
+            serde_json::from_str(r#"{"action":"Get","field":"password","entry":"GitHub"}"#)
+                .unwrap();
+        assert!(
+            matches!(r, Request::Get { field, entry, db: None } if field == "password" && entry == "GitHub")
+        );
+
+        let r: Request = serde_json::from_str(r#"{"action":"Lock","db":"Personal"}"#).unwrap();
+        assert!(matches!(r, Request::Lock { db: Some(d) } if d == "Personal"));
+
+        assert!(serde_json::from_str::<Request>(r#"{"action":"Delete"}"#).is_err());
+    }
+
+    #[test]
+    fn verify_field_key() {
+        assert_eq!(
+            field_key("password"),
+            kp_service::entry_keyvalue_key::PASSWORD
+        );
+        assert_eq!(
+            field_key("UserName"),
+            kp_service::entry_keyvalue_key::USER_NAME
+        );
+        assert_eq!(field_key("notes"), "Notes");
+        assert_eq!(field_key("Recovery Code"), "Recovery Code");
+    }
+
+    #[test]
+    fn verify_reply_json() {
+        assert_eq!(Reply::ok("abc").to_json(), r#"{"ok":"abc"}"#);
+        assert_eq!(Reply::error("failed").to_json(), r#"{"error":"failed"}"#);
+    }
+}
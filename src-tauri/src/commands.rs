@@ -15,14 +15,19 @@ use crate::app_state::SystemInfoWithPreference;
 use crate::auto_open::{self, AutoOpenProperties, AutoOpenPropertiesResolved};
 #[cfg(not(feature = "mas-build"))]
 use crate::auto_type;
+use crate::backup_service;
 use crate::browser_service;
+use crate::cli_service;
+use crate::db_file_watcher;
+use crate::launch_request::{self, LaunchRequest};
 use crate::menu::MenuActionRequest;
 use crate::ssh_agent;
+use crate::tray::{self, QuickSearchEntry};
 use crate::{app_preference, app_state};
 use crate::{biometric, OTP_TOKEN_UPDATE_EVENT};
 #[cfg(not(feature = "mas-build"))]
 use crate::updater;
-use crate::{mas, menu, pass_phrase, translation};
+use crate::{db_lock, mas, menu, pass_phrase, quick_unlock, translation};
 use onekeepass_core::async_service as kp_async_service;
 use onekeepass_core::db_service as kp_service;
 use onekeepass_core::remote_storage::storage_service::{
@@ -136,6 +141,153 @@ pub(crate) async fn set_active_db_key(
     Ok(())
 }
 
+// Passkey management. Each change is saved right away (with backup) and the
+// PASSKEY_DATA_CHANGED_EVENT is emitted for the affected entry
+
+#[tauri::command]
+pub(crate) async fn list_passkeys() -> Result<Vec<browser_service::passkey_db::PasskeyInfo>> {
+    Ok(browser_service::passkey_db::list_passkeys()?)
+}
+
+#[tauri::command]
+pub(crate) async fn rename_passkey(db_key: &str, entry_uuid: Uuid, username: &str) -> Result<()> {
+    Ok(browser_service::passkey_db::rename_passkey(
+        db_key,
+        &entry_uuid,
+        username,
+    )?)
+}
+
+#[tauri::command]
+pub(crate) async fn delete_passkey(db_key: &str, entry_uuid: Uuid) -> Result<()> {
+    Ok(browser_service::passkey_db::delete_passkey(
+        db_key,
+        &entry_uuid,
+    )?)
+}
+
+#[tauri::command]
+pub(crate) async fn move_passkey(
+    source_db_key: &str,
+    source_entry_uuid: Uuid,
+    target_db_key: &str,
+    target_entry_uuid: Uuid,
+) -> Result<()> {
+    Ok(browser_service::passkey_db::move_passkey(
+        source_db_key,
+        &source_entry_uuid,
+        target_db_key,
+        &target_entry_uuid,
+    )?)
+}
+
+// Exports the passkeys of the given databases. See 'passkey_exchange' for the
+// meaning of 'path' for each format
+#[tauri::command]
+pub(crate) async fn export_passkeys(
+    db_keys: Vec<String>,
+    format: browser_service::passkey_exchange::PasskeyExchangeFormat,
+    path: &str,
+) -> Result<usize> {
+    Ok(browser_service::passkey_exchange::export_passkeys(
+        &db_keys, format, path,
+    )?)
+}
+
+// Imports passkeys from a KeePassXC or CXF export file. With 'dry_run' true
+// nothing is stored and only the report with the conflicts is returned
+#[tauri::command]
+pub(crate) async fn import_passkeys(
+    file_name: &str,
+    db_key: &str,
+    group_uuid: Option<Uuid>,
+    dry_run: bool,
+) -> Result<browser_service::passkey_exchange::PasskeyImportReport> {
+    Ok(browser_service::passkey_exchange::import_passkeys(
+        file_name, db_key, group_uuid, dry_run,
+    )?)
+}
+
+// Answers a pending passkey user verification request raised through the
+// PASSKEY_USER_VERIFICATION_REQUEST_EVENT. Returns an error when the entered
+// credentials do not match so that the dialog can stay open for a retry
+#[command]
+pub(crate) async fn verify_passkey_user(
+    request_id: &str,
+    password: Option<&str>,
+    key_file_name: Option<&str>,
+    use_biometric: bool,
+) -> Result<()> {
+    Ok(browser_service::user_verification::verify_user(
+        request_id,
+        password,
+        key_file_name,
+        use_biometric,
+    )?)
+}
+
+#[command]
+pub(crate) async fn cancel_passkey_user_verification(request_id: &str) -> Result<()> {
+    browser_service::user_verification::cancel_verification(request_id);
+    Ok(())
+}
+
+// Answers a pending git credential request raised through the GIT_CREDENTIAL_REQUEST_EVENT.
+// Both 'db_key' and 'entry_uuid' are None when the user denied the request. Only 'entry_uuid'
+// is None when a new entry is to be added to the db for a 'store'
+#[command]
+pub(crate) async fn git_credential_confirm_result(
+    request_id: &str,
+    db_key: Option<String>,
+    entry_uuid: Option<Uuid>,
+) -> Result<()> {
+    browser_service::git_credential::submit_confirmation(
+        request_id,
+        db_key.map(|k| (k, entry_uuid)),
+    );
+    Ok(())
+}
+
+// Answers a pending okp client approval raised through the CLI_CLIENT_APPROVAL_REQUEST_EVENT
+#[command]
+pub(crate) async fn cli_client_approval_result(request_id: &str, allow: bool) -> Result<()> {
+    cli_service::submit_approval(request_id, allow);
+    Ok(())
+}
+
+// Returns the dbs and entries to open that were given in the command line, by a second
+// instance or by a link. Called at the start up and on a LAUNCH_REQUEST_EVENT
+#[command]
+pub(crate) async fn pending_launch_requests() -> Result<Vec<LaunchRequest>> {
+    Ok(launch_request::take_pending_requests())
+}
+
+// Searches the entries of all opened dbs for the quick search dialog raised from the tray menu
+#[command]
+pub(crate) async fn quick_search(term: &str) -> Result<Vec<QuickSearchEntry>> {
+    Ok(tray::quick_search(term)?)
+}
+
+// Returns the username, password or the current TOTP token of a quick search result to copy
+#[command]
+pub(crate) async fn quick_search_field_value(
+    db_key: &str,
+    entry_uuid: Uuid,
+    field: &str,
+) -> Result<String> {
+    Ok(tray::quick_search_field_value(db_key, &entry_uuid, field)?)
+}
+
+// Removes an approved okp client. Its next request is rejected
+#[command]
+pub(crate) async fn revoke_cli_client(
+    client_id: &str,
+    app_state: State<'_, app_state::AppState>,
+) -> Result<()> {
+    app_state.remove_cli_client(client_id);
+    Ok(())
+}
+
 // #[tauri::command]
 // pub(crate) async fn tokio_runtime_start() -> Result<()> {
 //   kp_async_service::start_runtime();
@@ -783,6 +935,10 @@ pub(crate) async fn save_kdbx(
     overwrite: bool,
     app_state: State<'_, app_state::AppState>,
 ) -> Result<kp_service::KdbxSaved> {
+    if backup_service::is_backup_db_key(db_key) {
+        return Err("A backup is opened read-only and cannot be saved".into());
+    }
+
     // Remote dbs (Sftp-/Webdav- prefixed db_keys) cannot go through the local
     // backup-then-rename path: there is no local file to write to, the
     // backup-file resolver would produce a nonsense path containing slashes
@@ -792,30 +948,32 @@ pub(crate) async fn save_kdbx(
     if crate::remote_storage::is_remote_db_key(db_key) {
         let recorded_mtime = app_state.remote_mtime(db_key);
         let backup_file_name = app_state.get_backup_file(db_key);
+        let backup_file_for_task = backup_file_name.clone();
         let db_key_owned = db_key.to_string();
         let db_key_for_cache = db_key_owned.clone();
-        let (kdbx_saved, remote_mtime) = tokio::task::spawn_blocking(move || {
+        let (kdbx_saved, remote_mtime, queued) = tokio::task::spawn_blocking(move || {
             crate::remote_storage::rs_save_kdbx(
                 &db_key_owned,
                 overwrite,
                 recorded_mtime,
-                backup_file_name.as_deref(),
+                backup_file_for_task.as_deref(),
             )
         })
         .await
         .map_err(spawn_blocking_join_err)??;
         app_state.set_remote_mtime(&db_key_for_cache, remote_mtime);
+        backup_service::backup_written(db_key, backup_file_name.as_deref());
+        crate::remote_storage::emit_sync_status(db_key, queued);
         return Ok(kdbx_saved);
     }
 
     // db_key is the full database file name and backup file name is derived from that
     let backup_file_name = app_state.get_backup_file(db_key);
     let _db_file_access = mas::db_file_access(db_key);
-    Ok(kp_service::save_kdbx_with_backup(
-        db_key,
-        backup_file_name.as_deref(),
-        overwrite,
-    )?)
+    let kdbx_saved =
+        kp_service::save_kdbx_with_backup(db_key, backup_file_name.as_deref(), overwrite)?;
+    backup_service::backup_written(db_key, backup_file_name.as_deref());
+    Ok(kdbx_saved)
 }
 
 #[command]
@@ -846,11 +1004,22 @@ pub(crate) async fn save_all_modified_dbs(
         .map(|s| (s.clone(), app_state.get_backup_file(s)))
         .collect();
 
-    let mut results = kp_service::save_all_modified_dbs_with_backups(dbs_with_backups)?;
+    let mut results = kp_service::save_all_modified_dbs_with_backups(dbs_with_backups.clone())?;
+
+    // Backups are verified and pruned only for the dbs that were saved successfully
+    for (db_key, backup_file_name) in &dbs_with_backups {
+        let saved = results.iter().any(|r| {
+            &r.db_key == db_key && matches!(r.save_status, kp_service::SaveStatus::Success)
+        });
+        if saved {
+            backup_service::backup_written(db_key, backup_file_name.as_deref());
+        }
+    }
 
     for db_key in remote_keys {
         let recorded_mtime = app_state.remote_mtime(&db_key);
         let backup_file_name = app_state.get_backup_file(&db_key);
+        let backup_file_for_task = backup_file_name.clone();
         let db_key_for_cache = db_key.clone();
         let db_key_for_task = db_key.clone();
         let response = match tokio::task::spawn_blocking(move || {
@@ -858,14 +1027,16 @@ pub(crate) async fn save_all_modified_dbs(
                 &db_key_for_task,
                 false,
                 recorded_mtime,
-                backup_file_name.as_deref(),
+                backup_file_for_task.as_deref(),
             )
         })
         .await
         .map_err(spawn_blocking_join_err)?
         {
-            Ok((_kdbx_saved, remote_mtime)) => {
+            Ok((_kdbx_saved, remote_mtime, queued)) => {
                 app_state.set_remote_mtime(&db_key_for_cache, remote_mtime);
+                backup_service::backup_written(&db_key_for_cache, backup_file_name.as_deref());
+                crate::remote_storage::emit_sync_status(&db_key_for_cache, queued);
                 kp_service::SaveAllResponse {
                     db_key,
                     save_status: kp_service::SaveStatus::Success,
@@ -882,21 +1053,98 @@ pub(crate) async fn save_all_modified_dbs(
     Ok(results)
 }
 
+// Backup browser. A backup is opened read-only with the credentials entered for it and
+// it is kept opened till 'close_db_backup' is called or its database is locked or closed
+
+#[command]
+pub(crate) async fn list_db_backups(db_key: &str) -> Result<Vec<backup_service::BackupFileInfo>> {
+    Ok(backup_service::list_backups(db_key)?)
+}
+
+#[command]
+pub(crate) async fn open_db_backup(
+    db_key: &str,
+    backup_file_name: &str,
+    password: Option<&str>,
+    key_file_name: Option<&str>,
+) -> Result<kp_service::KdbxLoaded> {
+    Ok(backup_service::open_backup(
+        db_key,
+        backup_file_name,
+        password,
+        key_file_name,
+    )?)
+}
+
+#[command]
+pub(crate) async fn close_db_backup(backup_file_name: &str) -> Result<()> {
+    Ok(backup_service::close_backup(backup_file_name)?)
+}
+
+#[command]
+pub(crate) async fn compare_db_with_backup(
+    db_key: &str,
+    backup_file_name: &str,
+) -> Result<backup_service::BackupDiffSummary> {
+    Ok(backup_service::compare_with_backup(
+        db_key,
+        backup_file_name,
+    )?)
+}
+
+#[command]
+pub(crate) async fn restore_db_backup(
+    db_key: &str,
+    backup_file_name: &str,
+) -> Result<kp_service::KdbxLoaded> {
+    Ok(backup_service::restore_backup_file(
+        db_key,
+        backup_file_name,
+    )?)
+}
+
+#[command]
+pub(crate) async fn restore_entries_from_db_backup(
+    db_key: &str,
+    backup_file_name: &str,
+    entry_uuids: Vec<Uuid>,
+) -> Result<backup_service::BackupEntriesRestored> {
+    Ok(backup_service::restore_entries_from_backup(
+        db_key,
+        backup_file_name,
+        &entry_uuids,
+    )?)
+}
+
+// The result of the last verification of a backup written for the database
+#[command]
+pub(crate) async fn last_backup_verification(
+    db_key: &str,
+) -> Result<Option<backup_service::BackupVerification>> {
+    Ok(backup_service::last_verification(db_key))
+}
+
 #[command]
 pub(crate) async fn close_kdbx(
     db_key: &str,
     app_state: State<'_, app_state::AppState>,
 ) -> Result<()> {
     app_state.db_file_watcher.stop_watching(db_key);
+    app_state.remote_poller.stop_polling(db_key);
     // Release the scoped-access handle paired with this DB's load_kdbx (if any).
     // Safe to call on non-macOS / non-sandboxed paths — it's a HashMap remove.
     app_state.release_scoped_access(&mas::ScopedAccessKey::Db(db_key.to_string()));
-    kp_service::close_kdbx(db_key)?;
+    // A locked db is already closed in the core
+    if !db_lock::discard(db_key) {
+        kp_service::close_kdbx(db_key)?;
+    }
+    backup_service::close_backups_of(db_key);
     app_state.remove_app_home_backup_file(db_key);
     // Drop any in-memory connection config cached while this remote db was open.
     crate::remote_storage::clear_cached_connection_config(db_key);
     // Remove this db's SSH keys from the agent (no-op if the agent isn't running).
     ssh_agent::clear_keys_for_db(db_key);
+    quick_unlock::remove_for_db(db_key);
     Ok(())
 }
 
@@ -932,6 +1180,54 @@ pub(crate) async fn ssh_agent_sign_confirm_result(request_id: String, allow: boo
     Ok(())
 }
 
+// ---- Secret Service provider commands (Linux) ----
+
+#[cfg(target_os = "linux")]
+#[command]
+pub(crate) async fn start_secret_service(
+    app_state: State<'_, app_state::AppState>,
+) -> Result<crate::secret_service::SecretServiceStatus> {
+    app_state.set_secret_service_enabled(true);
+    Ok(crate::secret_service::start())
+}
+
+#[cfg(target_os = "linux")]
+#[command]
+pub(crate) async fn stop_secret_service(
+    app_state: State<'_, app_state::AppState>,
+) -> Result<crate::secret_service::SecretServiceStatus> {
+    app_state.set_secret_service_enabled(false);
+    Ok(crate::secret_service::stop())
+}
+
+#[cfg(target_os = "linux")]
+#[command]
+pub(crate) async fn secret_service_status() -> Result<crate::secret_service::SecretServiceStatus> {
+    Ok(crate::secret_service::status())
+}
+
+// Delivers the user's allow/deny answer for a pending item access of a client app
+#[cfg(target_os = "linux")]
+#[command]
+pub(crate) async fn secret_service_confirm_result(request_id: String, allow: bool) -> Result<()> {
+    crate::secret_service::submit_confirmation(&request_id, allow);
+    Ok(())
+}
+
+// Serves or stops serving a group as a Secret Service collection
+#[cfg(target_os = "linux")]
+#[command]
+pub(crate) async fn set_secret_service_collection(
+    db_key: &str,
+    group_uuid: Uuid,
+    exposed: bool,
+    app_state: State<'_, app_state::AppState>,
+) -> Result<()> {
+    let group = kp_service::get_group_by_id(db_key, &group_uuid)?;
+    app_state.set_secret_service_collection(db_key, &group_uuid, &group.name, exposed);
+    Ok(())
+}
+
 #[command]
 pub(crate) async fn merge_kdbx_with_disk_version(
     db_key: &str,
@@ -942,6 +1238,47 @@ pub(crate) async fn merge_kdbx_with_disk_version(
     Ok(result)
 }
 
+// Merges a sync tool's conflict copy of the db file. See DB_CONFLICT_COPY_DETECTED_EVENT
+#[command]
+pub(crate) async fn merge_db_conflict_copy(
+    db_key: &str,
+    conflict_copy: &str,
+) -> Result<kp_service::MergeResult> {
+    Ok(db_file_watcher::merge_conflict_copy(db_key, conflict_copy)?)
+}
+
+// Used when the db's external change policy is not 'Prompt'. See DbFileChangedPayload.
+// Runs off the async worker threads as a remote db's change is merged with blocking calls
+#[command]
+pub(crate) async fn apply_external_db_change(
+    db_key: String,
+    save_pending: bool,
+) -> Result<db_file_watcher::ExternalChangeApplied> {
+    Ok(tokio::task::spawn_blocking(move || {
+        db_file_watcher::apply_external_change(&db_key, save_pending)
+    })
+    .await
+    .map_err(spawn_blocking_join_err)??)
+}
+
+#[command]
+pub(crate) async fn external_change_policy(
+    db_key: &str,
+    app_state: State<'_, app_state::AppState>,
+) -> Result<app_preference::ExternalChangePolicy> {
+    Ok(app_state.external_change_policy(db_key))
+}
+
+#[command]
+pub(crate) async fn set_external_change_policy(
+    db_key: &str,
+    policy: app_preference::ExternalChangePolicy,
+    app_state: State<'_, app_state::AppState>,
+) -> Result<()> {
+    app_state.set_external_change_policy(db_key, policy);
+    Ok(())
+}
+
 #[command]
 pub(crate) async fn acknowledge_db_file_change(
     db_key: &str,
@@ -951,27 +1288,42 @@ pub(crate) async fn acknowledge_db_file_change(
     Ok(())
 }
 
+// The db is closed in the core and only what is needed to unlock is kept (see db_lock.rs).
+// 'save_pending' is true when the db has changes not yet saved
 #[command]
-pub(crate) async fn lock_kdbx(_db_key: &str) -> Result<()> {
-    //TODO:
-    // Need to remove the session encryption key from memory in 'key_secure' module
-    // This key need to be retreived during 'unlock_kdbx' call
-
-    // Drop this db's decrypted SSH keys from the agent on lock. This is correct
-    // regardless of the lock_kdbx stub above: a locked database must not keep
-    // serving its keys, so the agent's in-memory copy is wiped here.
-    ssh_agent::clear_keys_for_db(_db_key);
+pub(crate) async fn lock_kdbx(
+    db_key: &str,
+    save_pending: bool,
+    app_state: State<'_, app_state::AppState>,
+) -> Result<()> {
+    // Drop this db's decrypted SSH keys from the agent on lock: a locked database
+    // must not keep serving its keys, so the agent's in-memory copy is wiped here.
+    ssh_agent::clear_keys_for_db(db_key);
+    // The background checks of a remote db need the opened db
+    app_state.remote_poller.stop_polling(db_key);
 
+    db_lock::lock(db_key, save_pending)?;
     Ok(())
 }
 
+// Common steps after a db is unlocked in any of the ways
+fn db_unlocked(db_key: &str, app_state: &app_state::AppState) {
+    // Re-add this db's SSH keys now that it is unlocked.
+    ssh_agent::reload_keys_for_db(db_key);
+    #[cfg(target_os = "linux")]
+    crate::secret_service::db_unlocked(db_key);
+    if crate::remote_storage::is_remote_db_key(db_key) {
+        app_state.remote_poller.start_polling(db_key);
+    }
+}
+
 #[command]
 pub(crate) async fn unlock_kdbx_on_biometric_authentication(
     db_key: &str,
+    app_state: State<'_, app_state::AppState>,
 ) -> Result<kp_service::KdbxLoaded> {
-    let r = kp_service::unlock_kdbx_on_biometric_authentication(db_key)?;
-    // Re-add this db's SSH keys now that it is unlocked.
-    ssh_agent::reload_keys_for_db(db_key);
+    let r = quick_unlock::unlock_on_biometric(db_key)?;
+    db_unlocked(db_key, &app_state);
     Ok(r)
 }
 
@@ -980,14 +1332,54 @@ pub(crate) async fn unlock_kdbx(
     db_key: &str,
     password: Option<&str>,
     key_file_name: Option<&str>,
+    app_state: State<'_, app_state::AppState>,
 ) -> Result<kp_service::KdbxLoaded> {
-    // We need to get the session encryption key from KeyChain(macOS)
-    // In case of Linux and Windows, the key is kept in memory and need to use Linux and Windows specific credential stores
-    // similiar to macOS KeyChain
+    // The locked db is read again with these credentials. The session encryption key is
+    // then stored again through 'key_secure' by the core
+    let r = db_lock::unlock(db_key, password, key_file_name)?;
+    db_unlocked(db_key, &app_state);
+    Ok(r)
+}
 
-    let r = kp_service::unlock_kdbx(db_key, password, key_file_name)?;
-    // Re-add this db's SSH keys now that it is unlocked.
-    ssh_agent::reload_keys_for_db(db_key);
+// Called after the db is opened or unlocked with these credentials
+#[command]
+pub(crate) async fn set_quick_unlock_pin(
+    db_key: &str,
+    password: Option<&str>,
+    key_file_name: Option<&str>,
+    pin: &str,
+) -> Result<()> {
+    Ok(quick_unlock::set_pin(db_key, password, key_file_name, pin)?)
+}
+
+// Called after the db is opened or unlocked with these credentials when the user enables the
+// biometric unlock for it
+#[command]
+pub(crate) async fn enable_biometric_unlock(
+    db_key: &str,
+    password: Option<&str>,
+    key_file_name: Option<&str>,
+) -> Result<()> {
+    Ok(quick_unlock::enable_biometric_unlock(
+        db_key,
+        password,
+        key_file_name,
+    )?)
+}
+
+#[command]
+pub(crate) async fn is_quick_unlock_pin_set(db_key: &str) -> Result<bool> {
+    Ok(quick_unlock::is_pin_set(db_key))
+}
+
+#[command]
+pub(crate) async fn unlock_kdbx_with_pin(
+    db_key: &str,
+    pin: &str,
+    app_state: State<'_, app_state::AppState>,
+) -> Result<kp_service::KdbxLoaded> {
+    let r = quick_unlock::unlock_with_pin(db_key, pin)?;
+    db_unlocked(db_key, &app_state);
     Ok(r)
 }
 
@@ -1396,7 +1788,7 @@ pub async fn rs_read_kdbx(
     app_state: State<'_, app_state::AppState>,
 ) -> Result<kp_service::KdbxLoaded> {
     let db_key_for_cache = db_file_name.clone();
-    let (kdbx_loaded, remote_mtime) = tokio::task::spawn_blocking(move || {
+    let (kdbx_loaded, remote_mtime, offline) = tokio::task::spawn_blocking(move || {
         crate::remote_storage::rs_read_kdbx(
             &db_file_name,
             password.as_deref(),
@@ -1406,6 +1798,8 @@ pub async fn rs_read_kdbx(
     .await
     .map_err(spawn_blocking_join_err)??;
     app_state.set_remote_mtime(&db_key_for_cache, remote_mtime);
+    crate::remote_storage::emit_sync_status(&db_key_for_cache, offline);
+    app_state.remote_poller.start_polling(&db_key_for_cache);
     if crate::remote_storage::is_kdbx_entry_backed(&db_key_for_cache) {
         app_state
             .preference
@@ -1424,18 +1818,21 @@ pub async fn rs_save_kdbx(
 ) -> Result<kp_service::KdbxSaved> {
     let recorded_mtime = app_state.remote_mtime(&db_key);
     let backup_file_name = app_state.get_backup_file(&db_key);
+    let backup_file_for_task = backup_file_name.clone();
     let db_key_for_cache = db_key.clone();
-    let (kdbx_saved, remote_mtime) = tokio::task::spawn_blocking(move || {
+    let (kdbx_saved, remote_mtime, queued) = tokio::task::spawn_blocking(move || {
         crate::remote_storage::rs_save_kdbx(
             &db_key,
             overwrite,
             recorded_mtime,
-            backup_file_name.as_deref(),
+            backup_file_for_task.as_deref(),
         )
     })
     .await
     .map_err(spawn_blocking_join_err)??;
     app_state.set_remote_mtime(&db_key_for_cache, remote_mtime);
+    backup_service::backup_written(&db_key_for_cache, backup_file_name.as_deref());
+    crate::remote_storage::emit_sync_status(&db_key_for_cache, queued);
     Ok(kdbx_saved)
 }
 
@@ -1445,13 +1842,15 @@ pub async fn rs_create_kdbx(
     app_state: State<'_, app_state::AppState>,
 ) -> Result<kp_service::KdbxLoaded> {
     let backup_file_name = app_state.get_backup_file(&new_db.database_file_name);
-    let (kdbx_loaded, db_key, remote_mtime) =
-        tokio::task::spawn_blocking(move || {
-            crate::remote_storage::rs_create_kdbx(new_db, backup_file_name.as_deref())
-        })
-        .await
-        .map_err(spawn_blocking_join_err)??;
+    let backup_file_for_task = backup_file_name.clone();
+    let (kdbx_loaded, db_key, remote_mtime) = tokio::task::spawn_blocking(move || {
+        crate::remote_storage::rs_create_kdbx(new_db, backup_file_for_task.as_deref())
+    })
+    .await
+    .map_err(spawn_blocking_join_err)??;
     app_state.set_remote_mtime(&db_key, remote_mtime);
+    backup_service::backup_written(&db_key, backup_file_name.as_deref());
+    app_state.remote_poller.start_polling(&db_key);
     if crate::remote_storage::is_kdbx_entry_backed(&db_key) {
         app_state
             .preference
@@ -1527,6 +1926,93 @@ pub async fn rs_merge_with_remote(
     Ok(merge_result)
 }
 
+// Pushes the saves queued in the offline copy once the server is reachable again.
+// Called by the UI only when the db has no unsaved changes. Any remote change made
+// meanwhile is merged first and the result has the merge details in that case
+#[tauri::command]
+pub async fn rs_sync_offline_changes(
+    db_key: String,
+    app_state: State<'_, app_state::AppState>,
+) -> Result<crate::remote_storage::OfflineSyncResult> {
+    let recorded_mtime = app_state.remote_mtime(&db_key);
+    let db_key_for_call = db_key.clone();
+    let (sync_result, remote_mtime) = tokio::task::spawn_blocking(move || {
+        crate::remote_storage::rs_sync_offline_changes(&db_key_for_call, recorded_mtime)
+    })
+    .await
+    .map_err(spawn_blocking_join_err)??;
+    app_state.set_remote_mtime(&db_key, remote_mtime);
+    crate::remote_storage::emit_sync_status(
+        &db_key,
+        sync_result.status == crate::remote_storage::OfflineSyncStatus::StillOffline,
+    );
+    Ok(sync_result)
+}
+
+// Lists the previous versions of a remote db kept on the server (see the history
+// copies setting), the latest first
+#[tauri::command]
+pub async fn rs_list_history_copies(
+    db_key: String,
+) -> Result<Vec<crate::remote_storage::history::RemoteHistoryCopy>> {
+    Ok(tokio::task::spawn_blocking(move || {
+        crate::remote_storage::history::rs_list_history_copies(&db_key)
+    })
+    .await
+    .map_err(spawn_blocking_join_err)??)
+}
+
+// Puts a history copy back as the remote db file. The opened db still has the replaced
+// version and the UI closes and opens it again. The background checks are stopped so that
+// the restored version is not merged into the opened db meanwhile. The recorded mtime is
+// left as it is and a save of the opened db is reported as a conflict
+#[tauri::command]
+pub async fn rs_restore_history_copy(
+    db_key: String,
+    slot: u8,
+    app_state: State<'_, app_state::AppState>,
+) -> Result<()> {
+    app_state.remote_poller.stop_polling(&db_key);
+    tokio::task::spawn_blocking(move || {
+        crate::remote_storage::history::rs_restore_history_copy(&db_key, slot)
+    })
+    .await
+    .map_err(spawn_blocking_join_err)??;
+    Ok(())
+}
+
+// S3 connections are entries of the custom entry type "S3 Connection" and are listed
+// and browsed through the S3 client in remote_storage::s3 (not in onekeepass-core)
+#[tauri::command]
+pub async fn rs_s3_list_connections(
+) -> Result<Vec<crate::remote_storage::s3::S3ConnectionEntrySummary>> {
+    Ok(crate::remote_storage::s3::list_connection_entries()?)
+}
+
+#[tauri::command]
+pub async fn rs_s3_connect_by_id_and_list_root(
+    connection_id: String,
+) -> Result<crate::remote_storage::s3::S3ConnectStatus> {
+    Ok(tokio::task::spawn_blocking(move || {
+        crate::remote_storage::s3::connect_by_id_and_list_root(&connection_id)
+    })
+    .await
+    .map_err(spawn_blocking_join_err)??)
+}
+
+#[tauri::command]
+pub async fn rs_s3_list_sub_dir(
+    connection_id: String,
+    parent_dir: String,
+    sub_dir: String,
+) -> Result<crate::remote_storage::s3::S3DirEntry> {
+    Ok(tokio::task::spawn_blocking(move || {
+        crate::remote_storage::s3::list_sub_dir(&connection_id, &parent_dir, &sub_dir)
+    })
+    .await
+    .map_err(spawn_blocking_join_err)??)
+}
+
 #[tauri::command]
 pub async fn rs_list_kdbx_source_connections(
     rs_storage_type: RemoteStorageType,
@@ -5,7 +5,25 @@ pub mod event_names {
     pub const BROWSER_CONNECTION_REQUEST_EVENT: &str = "BrowserConnectionRequestEvent";
     pub const PASSKEY_DATA_CHANGED_EVENT: &str = "PasskeyDataChangedEvent";
     pub const DB_FILE_CHANGED_EVENT: &str = "DbFileChangedEvent";
+    pub const DB_FILE_REMOVED_EVENT: &str = "DbFileRemovedEvent";
+    pub const DB_FILE_RENAMED_EVENT: &str = "DbFileRenamedEvent";
+    pub const DB_CONFLICT_COPY_DETECTED_EVENT: &str = "DbConflictCopyDetectedEvent";
+    pub const REMOTE_SYNC_STATUS_EVENT: &str = "RemoteSyncStatusEvent";
     pub const SSH_AGENT_SIGN_REQUEST_EVENT: &str = "SshAgentSignRequestEvent";
+    pub const PASSKEY_USER_VERIFICATION_REQUEST_EVENT: &str = "PasskeyUserVerificationRequestEvent";
+    pub const BACKUP_VERIFICATION_FAILED_EVENT: &str = "BackupVerificationFailedEvent";
+    pub const GIT_CREDENTIAL_REQUEST_EVENT: &str = "GitCredentialRequestEvent";
+    pub const GIT_CREDENTIAL_DATA_CHANGED_EVENT: &str = "GitCredentialDataChangedEvent";
+    pub const CLI_CLIENT_APPROVAL_REQUEST_EVENT: &str = "CliClientApprovalRequestEvent";
+    pub const SESSION_LOCKED_DBS_EVENT: &str = "SessionLockedDbsEvent";
+    pub const LAUNCH_REQUEST_EVENT: &str = "LaunchRequestEvent";
+    pub const QUICK_SEARCH_REQUEST_EVENT: &str = "QuickSearchRequestEvent";
+    #[cfg(target_os = "linux")]
+    pub const SECRET_SERVICE_ACCESS_REQUEST_EVENT: &str = "SecretServiceAccessRequestEvent";
+    #[cfg(target_os = "linux")]
+    pub const SECRET_SERVICE_UNLOCK_REQUEST_EVENT: &str = "SecretServiceUnlockRequestEvent";
+    #[cfg(target_os = "linux")]
+    pub const SECRET_SERVICE_DATA_CHANGED_EVENT: &str = "SecretServiceDataChangedEvent";
 }
 
 pub mod event_action_names {
@@ -1,24 +1,48 @@
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use log::{debug, error, info};
+use notify::event::{ModifyKind, RenameMode};
 use notify::{Config, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use serde::Serialize;
 use tauri::{Emitter, Manager};
 
 use onekeepass_core::db_service as kp_service;
+use onekeepass_core::error::{self, Result};
 
-use crate::constants::event_names::DB_FILE_CHANGED_EVENT;
+use crate::app_preference::ExternalChangePolicy;
+use crate::app_state::AppState;
+use crate::constants::event_names::{
+    DB_CONFLICT_COPY_DETECTED_EVENT, DB_FILE_CHANGED_EVENT, DB_FILE_REMOVED_EVENT,
+    DB_FILE_RENAMED_EVENT,
+};
 use crate::constants::window_labels::MAIN_WINDOW_LABEL;
+use crate::{backup_service, mas, remote_storage, ssh_agent};
 
 struct WatcherEntry {
     watcher: RecommendedWatcher,
     // True once the event has been sent to the frontend but not yet acknowledged.
     // Prevents duplicate events if the file is written multiple times quickly.
     notification_pending: bool,
+    // Set once the removal or rename of the db file has been reported. Cleared when the
+    // file appears again (e.g. our own save or the file moved back)
+    file_missing: bool,
+    // Set between a remove or rename event and the delayed check that the file is still missing
+    missing_check_pending: bool,
+    // The new path seen in a rename event while the missing check is pending
+    renamed_to: Option<PathBuf>,
+    // Conflict copies of this db already reported to the frontend
+    reported_conflict_copies: HashSet<PathBuf>,
 }
 
+// Sync tools and editors often replace the db file by a remove followed by a create.
+// The file is reported as removed only when it is still missing after this delay
+const MISSING_FILE_CHECK_DELAY: Duration = Duration::from_millis(750);
+
 pub(crate) struct DbFileWatcherState {
     watchers: Arc<Mutex<HashMap<String, WatcherEntry>>>,
 }
@@ -26,6 +50,41 @@ pub(crate) struct DbFileWatcherState {
 #[derive(Clone, Serialize)]
 pub(crate) struct DbFileChangedPayload {
     pub db_key: String,
+    // The UI prompts the user only when this is 'Prompt'. Otherwise it calls
+    // 'apply_external_change' and informs the user afterwards
+    pub policy: ExternalChangePolicy,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct DbFileMovedPayload {
+    pub db_key: String,
+    // Set when the db file was renamed within its directory. None when the file was
+    // removed or moved elsewhere
+    pub new_path: Option<String>,
+}
+
+#[derive(Clone, Serialize)]
+pub(crate) struct DbConflictCopyPayload {
+    pub db_key: String,
+    // Full path of the conflict copy created by a sync tool next to the db file
+    pub conflict_copy: String,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum ExternalChangeAction {
+    Reloaded,
+    Merged,
+    MergedAndSaved,
+}
+
+#[derive(Serialize)]
+pub(crate) struct ExternalChangeApplied {
+    pub action: ExternalChangeAction,
+    // Set when the db was reloaded
+    pub kdbx_loaded: Option<kp_service::KdbxLoaded>,
+    // Set when the disk version was merged
+    pub merge_result: Option<kp_service::MergeResult>,
 }
 
 impl DbFileWatcherState {
@@ -50,77 +109,7 @@ impl DbFileWatcherState {
         let watcher_result = RecommendedWatcher::new(
             move |res: notify::Result<notify::Event>| {
                 let Ok(event) = res else { return };
-
-                // Only react to data-modifying or file-creation events.
-                // Atomic-save patterns (write-to-temp + rename) may emit Create events.
-                let is_relevant = matches!(event.kind, EventKind::Modify(_) | EventKind::Create(_));
-                if !is_relevant {
-                    return;
-                }
-
-                // Confirm the event concerns our specific db file
-                let file_changed = event
-                    .paths
-                    .iter()
-                    .any(|p| p.to_string_lossy() == db_key_owned);
-                if !file_changed {
-                    return;
-                }
-
-                // Guard: skip if a notification is already pending for this db
-                {
-                    let mut map = watchers_clone.lock().unwrap();
-                    match map.get_mut(&db_key_owned) {
-                        Some(e) if e.notification_pending => {
-                            debug!(
-                                "Watcher: notification already pending for {} — skipping",
-                                &db_key_owned
-                            );
-                            return;
-                        }
-                        None => {
-                            // DB was closed (entry removed) while callback was in flight
-                            return;
-                        }
-                        _ => {}
-                    }
-                }
-
-                // Confirm it is truly an external change by comparing checksums.
-                // Our own saves update checksum_hash via write_kdbx_file(), so they return Ok here.
-                match kp_service::read_and_verify_db_file(&db_key_owned) {
-                    Ok(()) => {
-                        debug!(
-                            "Watcher: checksum matches for {} — our own save, ignoring",
-                            &db_key_owned
-                        );
-                    }
-                    Err(_) => {
-                        info!("External change confirmed for {}", &db_key_owned);
-
-                        // Set notification_pending before emitting to prevent races
-                        {
-                            let mut map = watchers_clone.lock().unwrap();
-                            if let Some(e) = map.get_mut(&db_key_owned) {
-                                e.notification_pending = true;
-                            }
-                        }
-
-                        // Emit event to the frontend via the global app handle
-                        if let Some(win) = crate::app_state::AppState::global_app_handle()
-                            .get_webview_window(MAIN_WINDOW_LABEL)
-                        {
-                            if let Err(e) = win.emit(
-                                DB_FILE_CHANGED_EVENT,
-                                DbFileChangedPayload {
-                                    db_key: db_key_owned.clone(),
-                                },
-                            ) {
-                                error!("Failed to emit DB_FILE_CHANGED_EVENT: {}", e);
-                            }
-                        }
-                    }
-                }
+                handle_event(&watchers_clone, &db_key_owned, &event);
             },
             Config::default(),
         );
@@ -143,6 +132,10 @@ impl DbFileWatcherState {
                             WatcherEntry {
                                 watcher,
                                 notification_pending: false,
+                                file_missing: false,
+                                missing_check_pending: false,
+                                renamed_to: None,
+                                reported_conflict_copies: HashSet::new(),
                             },
                         );
                     }
@@ -175,3 +168,395 @@ impl DbFileWatcherState {
         }
     }
 }
+
+type Watchers = Arc<Mutex<HashMap<String, WatcherEntry>>>;
+
+fn handle_event(watchers: &Watchers, db_key: &str, event: &notify::Event) {
+    let db_path = Path::new(db_key);
+    let concerns_db = event.paths.iter().any(|p| p == db_path);
+
+    match event.kind {
+        EventKind::Remove(_) if concerns_db => {
+            db_file_disappeared(watchers, db_key, None);
+        }
+        // Renamed within the watched directory and both paths are known
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both))
+            if event.paths.first().map(|p| p.as_path()) == Some(db_path) =>
+        {
+            db_file_disappeared(watchers, db_key, event.paths.get(1).cloned());
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) if concerns_db => {
+            db_file_disappeared(watchers, db_key, None);
+        }
+        // Some platforms (e.g. macOS) do not tell whether the path is the old or the new name
+        EventKind::Modify(ModifyKind::Name(RenameMode::Any))
+            if concerns_db && !db_path.exists() =>
+        {
+            db_file_disappeared(watchers, db_key, None);
+        }
+        EventKind::Modify(_) | EventKind::Create(_) => {
+            if let EventKind::Modify(ModifyKind::Name(RenameMode::To)) = event.kind {
+                // The second half of a rename reported as two separate events
+                record_rename_target(watchers, db_key, &event.paths);
+            }
+
+            for path in event
+                .paths
+                .iter()
+                .filter(|p| is_conflict_copy_of(db_path, p))
+            {
+                conflict_copy_detected(watchers, db_key, path);
+            }
+
+            // Atomic-save patterns (write-to-temp + rename) may emit Create events
+            if concerns_db {
+                db_file_changed(watchers, db_key);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn db_file_changed(watchers: &Watchers, db_key: &str) {
+    // The removal or rename of the file is handled by 'db_file_disappeared'
+    if !Path::new(db_key).exists() {
+        return;
+    }
+
+    // Guard: skip if a notification is already pending for this db
+    {
+        let mut map = watchers.lock().unwrap();
+        match map.get_mut(db_key) {
+            Some(e) => {
+                // The file is back at its path
+                e.file_missing = false;
+                if e.notification_pending {
+                    debug!(
+                        "Watcher: notification already pending for {} — skipping",
+                        db_key
+                    );
+                    return;
+                }
+            }
+            None => {
+                // DB was closed (entry removed) while callback was in flight
+                return;
+            }
+        }
+    }
+
+    // Confirm it is truly an external change by comparing checksums.
+    // Our own saves update checksum_hash via write_kdbx_file(), so they return Ok here.
+    match kp_service::read_and_verify_db_file(db_key) {
+        Ok(()) => {
+            debug!(
+                "Watcher: checksum matches for {} — our own save, ignoring",
+                db_key
+            );
+        }
+        Err(_) => {
+            info!("External change confirmed for {}", db_key);
+
+            // Set notification_pending before emitting to prevent races
+            {
+                let mut map = watchers.lock().unwrap();
+                if let Some(e) = map.get_mut(db_key) {
+                    e.notification_pending = true;
+                }
+            }
+
+            emit_to_main_window(
+                DB_FILE_CHANGED_EVENT,
+                DbFileChangedPayload {
+                    db_key: db_key.to_string(),
+                    policy: AppState::state_instance().external_change_policy(db_key),
+                },
+            );
+        }
+    }
+}
+
+fn db_file_disappeared(watchers: &Watchers, db_key: &str, renamed_to: Option<PathBuf>) {
+    {
+        let mut map = watchers.lock().unwrap();
+        let Some(e) = map.get_mut(db_key) else {
+            return;
+        };
+        if renamed_to.is_some() {
+            e.renamed_to = renamed_to;
+        }
+        if e.file_missing || e.missing_check_pending {
+            return;
+        }
+        e.missing_check_pending = true;
+    }
+
+    let watchers = Arc::clone(watchers);
+    let db_key = db_key.to_string();
+    thread::spawn(move || {
+        thread::sleep(MISSING_FILE_CHECK_DELAY);
+
+        let renamed_to = {
+            let mut map = watchers.lock().unwrap();
+            let Some(e) = map.get_mut(&db_key) else {
+                return;
+            };
+            e.missing_check_pending = false;
+            let renamed_to = e.renamed_to.take();
+            if Path::new(&db_key).exists() {
+                // Replaced by a remove + create. Any content change is reported by 'db_file_changed'
+                return;
+            }
+            e.file_missing = true;
+            renamed_to
+        };
+
+        let new_path = renamed_to.map(|p| p.to_string_lossy().to_string());
+        info!("Db file {} is missing. Renamed to {:?}", &db_key, &new_path);
+
+        let event_name = if new_path.is_some() {
+            DB_FILE_RENAMED_EVENT
+        } else {
+            DB_FILE_REMOVED_EVENT
+        };
+        emit_to_main_window(event_name, DbFileMovedPayload { db_key, new_path });
+    });
+}
+
+fn record_rename_target(watchers: &Watchers, db_key: &str, paths: &[PathBuf]) {
+    let mut map = watchers.lock().unwrap();
+    if let Some(e) = map.get_mut(db_key) {
+        if e.missing_check_pending {
+            if let Some(p) = paths.iter().find(|p| p.as_path() != Path::new(db_key)) {
+                e.renamed_to = Some(p.clone());
+            }
+        }
+    }
+}
+
+fn conflict_copy_detected(watchers: &Watchers, db_key: &str, conflict_copy: &Path) {
+    {
+        let mut map = watchers.lock().unwrap();
+        let Some(e) = map.get_mut(db_key) else {
+            return;
+        };
+        // A sync tool may write the same copy a few times
+        if !e
+            .reported_conflict_copies
+            .insert(conflict_copy.to_path_buf())
+        {
+            return;
+        }
+    }
+
+    info!("Conflict copy {:?} detected for {}", conflict_copy, db_key);
+
+    emit_to_main_window(
+        DB_CONFLICT_COPY_DETECTED_EVENT,
+        DbConflictCopyPayload {
+            db_key: db_key.to_string(),
+            conflict_copy: conflict_copy.to_string_lossy().to_string(),
+        },
+    );
+}
+
+pub(crate) fn emit_to_main_window<S: Serialize + Clone>(event_name: &str, payload: S) {
+    // Emit event to the frontend via the global app handle
+    if let Some(win) = AppState::global_app_handle().get_webview_window(MAIN_WINDOW_LABEL) {
+        if let Err(e) = win.emit(event_name, payload) {
+            error!("Failed to emit {}: {}", event_name, e);
+        }
+    }
+}
+
+// Checks whether 'candidate' is a copy of the db file created by a sync tool on conflicting
+// writes. The names used by Dropbox, Nextcloud/ownCloud and Syncthing are recognized e.g
+// 'MyDb (conflicted copy 2024-05-01).kdbx', 'MyDb (John's conflicted copy).kdbx',
+// 'MyDb_conflict-20240501-101010.kdbx' and 'MyDb.sync-conflict-20240501-101010-ABCDEFG.kdbx'
+fn is_conflict_copy_of(db_path: &Path, candidate: &Path) -> bool {
+    if candidate == db_path || candidate.parent() != db_path.parent() {
+        return false;
+    }
+
+    let same_extension = db_path.extension().map(|e| e.to_ascii_lowercase())
+        == candidate.extension().map(|e| e.to_ascii_lowercase());
+    if !same_extension {
+        return false;
+    }
+
+    let (Some(db_stem), Some(stem)) = (
+        db_path.file_stem().and_then(|s| s.to_str()),
+        candidate.file_stem().and_then(|s| s.to_str()),
+    ) else {
+        return false;
+    };
+
+    let Some(suffix) = stem.strip_prefix(db_stem) else {
+        return false;
+    };
+    let suffix = suffix.to_lowercase();
+
+    (suffix.starts_with(" (") && suffix.ends_with(')') && suffix.contains("conflict"))
+        || suffix.starts_with("_conflict-")
+        || suffix.starts_with(".sync-conflict-")
+}
+
+// Merges a conflict copy reported by DB_CONFLICT_COPY_DETECTED_EVENT into the opened db.
+// The copy is expected to use the same credentials as the opened db. The copy is left as
+// it is so that the user can remove it after saving the merged db
+pub(crate) fn merge_conflict_copy(
+    db_key: &str,
+    conflict_copy: &str,
+) -> Result<kp_service::MergeResult> {
+    if !is_conflict_copy_of(Path::new(db_key), Path::new(conflict_copy)) {
+        return Err(error::Error::DataError(
+            "The file is not a conflict copy of the database",
+        ));
+    }
+
+    let data = std::fs::read(conflict_copy)?;
+    let mut reader = Cursor::new(&data);
+    let merge_result = kp_service::merge_kdbx_with_reader(db_key, &mut reader)?;
+
+    info!("Merged conflict copy {} into {}", conflict_copy, db_key);
+
+    Ok(merge_result)
+}
+
+// Applies an external change of the local db file without asking the user as per the
+// db's policy. 'save_pending' is true when the opened db has unsaved changes and in
+// that case the disk version is always merged and the merged db is saved.
+// Any error is returned to the UI which then falls back to asking the user
+pub(crate) fn apply_external_change(
+    db_key: &str,
+    save_pending: bool,
+) -> Result<ExternalChangeApplied> {
+    let app_state = AppState::state_instance();
+    let remote = remote_storage::is_remote_db_key(db_key);
+    let policy = if remote {
+        remote_storage::poller::remote_change_policy(db_key)
+    } else {
+        app_state.external_change_policy(db_key)
+    };
+
+    if policy == ExternalChangePolicy::Prompt {
+        return Err(error::Error::DataError(
+            "External changes are not applied automatically for this database",
+        ));
+    }
+
+    if remote {
+        return apply_remote_change(db_key, save_pending);
+    }
+
+    let applied = if policy == ExternalChangePolicy::AutoReload && !save_pending {
+        let kdbx_loaded = kp_service::reload_kdbx(db_key)?;
+        ExternalChangeApplied {
+            action: ExternalChangeAction::Reloaded,
+            kdbx_loaded: Some(kdbx_loaded),
+            merge_result: None,
+        }
+    } else {
+        let merge_result = kp_service::merge_kdbx_with_disk_version(db_key)?;
+
+        let action = if save_pending {
+            let backup_file_name = app_state.get_backup_file(db_key);
+            {
+                let _db_file_access = mas::db_file_access(db_key);
+                kp_service::save_kdbx_with_backup(db_key, backup_file_name.as_deref(), false)?;
+            }
+            backup_service::backup_written(db_key, backup_file_name.as_deref());
+            ExternalChangeAction::MergedAndSaved
+        } else {
+            ExternalChangeAction::Merged
+        };
+
+        ExternalChangeApplied {
+            action,
+            kdbx_loaded: None,
+            merge_result: Some(merge_result),
+        }
+    };
+
+    // Both reload and merge may change the agent-enabled SSH Key entries
+    ssh_agent::reload_keys_for_db(db_key);
+    app_state.db_file_watcher.clear_notification_pending(db_key);
+
+    info!(
+        "External change of {} applied automatically: {:?}",
+        db_key, applied.action
+    );
+
+    Ok(applied)
+}
+
+// Remote dbs have no reload path and a remote change is merged only when there are no
+// local edits. With unsaved changes the user is asked as the merged db then needs a save
+// that overwrites the remote file. Blocks on the remote storage calls
+fn apply_remote_change(db_key: &str, save_pending: bool) -> Result<ExternalChangeApplied> {
+    if save_pending {
+        return Err(error::Error::DataError(
+            "Remote changes are merged automatically only when there are no unsaved changes",
+        ));
+    }
+
+    let (merge_result, remote_mtime) =
+        remote_storage::run_blocking(|| remote_storage::rs_merge_with_remote(db_key))?;
+    AppState::state_instance().set_remote_mtime(db_key, remote_mtime);
+    ssh_agent::reload_keys_for_db(db_key);
+
+    info!("Remote change of {} merged automatically", db_key);
+
+    Ok(ExternalChangeApplied {
+        action: ExternalChangeAction::Merged,
+        kdbx_loaded: None,
+        merge_result: Some(merge_result),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_conflict_copy_names() {
+        let db = Path::new("/data/sync/MyDb.kdbx");
+        let copy = |name: &str| Path::new("/data/sync").join(name);
+
+        assert!(is_conflict_copy_of(
+            db,
+            &copy("MyDb (conflicted copy 2024-05-01).kdbx")
+        ));
+        assert!(is_conflict_copy_of(
+            db,
+            &copy("MyDb (John's conflicted copy 2024-05-01).kdbx")
+        ));
+        assert!(is_conflict_copy_of(
+            db,
+            &copy("MyDb (conflict 2024-05-01 101010).KDBX")
+        ));
+        assert!(is_conflict_copy_of(
+            db,
+            &copy("MyDb_conflict-20240501-101010.kdbx")
+        ));
+        assert!(is_conflict_copy_of(
+            db,
+            &copy("MyDb.sync-conflict-20240501-101010-ABCDEFG.kdbx")
+        ));
+
+        assert!(!is_conflict_copy_of(db, &copy("MyDb.kdbx")));
+        assert!(!is_conflict_copy_of(db, &copy("MyDb (1).kdbx")));
+        assert!(!is_conflict_copy_of(
+            db,
+            &copy("MyDb (conflicted copy).kdbx.tmp")
+        ));
+        assert!(!is_conflict_copy_of(
+            db,
+            &copy("Other (conflicted copy).kdbx")
+        ));
+        assert!(!is_conflict_copy_of(
+            db,
+            Path::new("/data/other/MyDb (conflicted copy).kdbx")
+        ));
+    }
+}
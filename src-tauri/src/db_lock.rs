@@ -0,0 +1,187 @@
+// Locking of an opened db in the backend. A locked db is closed in onekeepass-core so that
+// its decrypted content and keys are dropped, including the session encryption key kept
+// through key_secure. Every backend path (browser_service, passkeys, ssh_agent, auto open
+// ...) then finds the db as not opened until it is unlocked.
+//
+// What is kept in memory for the unlock:
+//   - a local db without unsaved changes: nothing. It is read again from its file
+//   - a remote db or a db with unsaved changes: the db serialized as KDBX and so encrypted
+//     with its own composite key. A local db unlocked from this copy differs from its
+//     file and the usual external change check offers to merge with the file
+//
+// The unlock needs the credentials in both cases. The PIN and biometric unlocks get them
+// from quick_unlock.rs
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::{Mutex, OnceLock};
+
+use log::{debug, error, info};
+use serde::Serialize;
+use tauri::{Emitter, Manager};
+
+use onekeepass_core::db_service as kp_service;
+use onekeepass_core::error::Result;
+
+use crate::app_state::AppState;
+use crate::constants::event_names::SESSION_LOCKED_DBS_EVENT;
+use crate::constants::window_labels::MAIN_WINDOW_LABEL;
+use crate::{backup_service, key_secure, remote_storage, ssh_agent};
+
+struct LockedDb {
+    // None when the db is read again from its file
+    serialized: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Serialize)]
+struct LockedDbsPayload {
+    db_keys: Vec<String>,
+}
+
+fn locked_dbs() -> &'static Mutex<HashMap<String, LockedDb>> {
+    static LOCKED_DBS: OnceLock<Mutex<HashMap<String, LockedDb>>> = OnceLock::new();
+    LOCKED_DBS.get_or_init(Default::default)
+}
+
+pub(crate) fn locked_db_keys() -> Vec<String> {
+    locked_dbs().lock().unwrap().keys().cloned().collect()
+}
+
+pub(crate) fn is_locked(db_key: &str) -> bool {
+    locked_dbs().lock().unwrap().contains_key(db_key)
+}
+
+pub(crate) fn lock(db_key: &str, save_pending: bool) -> Result<()> {
+    if let Some(locked) = locked_dbs().lock().unwrap().get_mut(db_key) {
+        // A db locked on a session event is kept serialized as its unsaved changes are not
+        // known in the backend (see session_lock.rs). The UI then locks it again with
+        // 'save_pending' and a local db without unsaved changes is read from its file
+        if !save_pending && !remote_storage::is_remote_db_key(db_key) {
+            locked.serialized = None;
+        }
+        return Ok(());
+    }
+
+    let serialized = if save_pending || remote_storage::is_remote_db_key(db_key) {
+        let mut mem = Cursor::new(Vec::<u8>::new());
+        kp_service::save_kdbx_to_writer(&mut mem, db_key)?;
+        Some(mem.into_inner())
+    } else {
+        None
+    };
+
+    kp_service::close_kdbx(db_key)?;
+    // The core removes it on close. This makes sure it is not left behind
+    let _ = key_secure::delete_key(db_key);
+    // A backup opened in the backup browser is decrypted too
+    backup_service::close_backups_of(db_key);
+
+    locked_dbs()
+        .lock()
+        .unwrap()
+        .insert(db_key.to_string(), LockedDb { serialized });
+    debug!("Database {} is locked", db_key);
+    Ok(())
+}
+
+// Informs the UI about the dbs locked here without any UI action (session events, okp lock).
+// The UI marks them as locked and locks each one again with its save pending state
+pub(crate) fn notify_locked_in_backend(db_keys: Vec<String>) {
+    if let Some(win) = AppState::global_app_handle().get_webview_window(MAIN_WINDOW_LABEL) {
+        if let Err(e) = win.emit(SESSION_LOCKED_DBS_EVENT, LockedDbsPayload { db_keys }) {
+            error!("Failed to emit SESSION_LOCKED_DBS_EVENT: {}", e);
+        }
+    }
+}
+
+// Locks all opened dbs without any UI action. Used on a session event (see session_lock.rs)
+// and from the tray menu
+pub(crate) fn lock_all_in_backend() {
+    let app_state = AppState::state_instance();
+
+    // Denies the sign requests waiting for a confirmation and drops all keys. The agent is
+    // started again below without any key and gets a db's keys when that db is unlocked
+    ssh_agent::clear_all_keys();
+
+    // The opened backups are closed and not locked as they are opened again from their files
+    backup_service::close_all_backups();
+
+    let mut db_keys = vec![];
+    for db_key in backup_service::opened_db_keys().unwrap_or_default() {
+        app_state.remote_poller.stop_polling(&db_key);
+        match lock(&db_key, true) {
+            Ok(()) => db_keys.push(db_key),
+            Err(e) => error!("Locking the database {} failed: {}", db_key, e),
+        }
+    }
+    info!("Locked {} database(s) in the backend", db_keys.len());
+
+    app_state.start_ssh_agent_if_enabled();
+
+    notify_locked_in_backend(db_keys);
+}
+
+// The last part of a file path or of a remote db key
+fn file_name(db_key: &str) -> Option<&str> {
+    db_key.rsplit(['/', '\\']).next().filter(|s| !s.is_empty())
+}
+
+// Unlocks a db locked here. Any other db is unlocked by onekeepass-core as before, which
+// checks the credentials against the opened db
+pub(crate) fn unlock(
+    db_key: &str,
+    password: Option<&str>,
+    key_file_name: Option<&str>,
+) -> Result<kp_service::KdbxLoaded> {
+    let Some(locked) = locked_dbs().lock().unwrap().remove(db_key) else {
+        return kp_service::unlock_kdbx(db_key, password, key_file_name);
+    };
+
+    let r = match &locked.serialized {
+        Some(data) => kp_service::read_kdbx(
+            &mut Cursor::new(data),
+            db_key,
+            password,
+            key_file_name,
+            file_name(db_key),
+        ),
+        None => kp_service::load_kdbx(db_key, password, key_file_name),
+    };
+
+    match r {
+        Ok(kdbx_loaded) => {
+            debug!("Database {} is unlocked", db_key);
+            Ok(kdbx_loaded)
+        }
+        Err(e) => {
+            // Wrong credentials. The db stays locked
+            locked_dbs()
+                .lock()
+                .unwrap()
+                .insert(db_key.to_string(), locked);
+            Err(e)
+        }
+    }
+}
+
+// Called when a db is closed. Returns true when the db was locked and so is already closed
+// in onekeepass-core
+pub(crate) fn discard(db_key: &str) -> bool {
+    locked_dbs().lock().unwrap().remove(db_key).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_file_name() {
+        assert_eq!(file_name("/home/user/dbs/Test.kdbx"), Some("Test.kdbx"));
+        assert_eq!(file_name("C:\\Users\\user\\Test.kdbx"), Some("Test.kdbx"));
+        assert_eq!(
+            file_name("Sftp-3a0b7c4e-96a4-4a4b-8f2e-0c1d2e3f4a5b-/dbs/Test.kdbx"),
+            Some("Test.kdbx")
+        );
+        assert_eq!(file_name("/home/user/"), None);
+    }
+}
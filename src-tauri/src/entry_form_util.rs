@@ -0,0 +1,70 @@
+// The form data of the entries created and changed by the backend itself (Secret Service
+// items, git credentials). The entry type and the fields are taken from the form data made
+// by onekeepass-core so that these entries are the same as the ones made in the UI
+
+use uuid::Uuid;
+
+use onekeepass_core::db_service as kp_service;
+use onekeepass_core::error::{self, Result};
+
+// The name of the standard entry type as listed by core
+const LOGIN_ENTRY_TYPE_NAME: &str = "Login";
+
+// The form data of a new Login entry in the group 'group_uuid' or in the root group
+pub(crate) fn new_login_entry_form_data(
+    db_key: &str,
+    group_uuid: Option<&Uuid>,
+) -> Result<kp_service::EntryFormData> {
+    let entry_type_uuid = kp_service::entry_type_headers(db_key)?
+        .standard
+        .into_iter()
+        .find(|h| h.name == LOGIN_ENTRY_TYPE_NAME)
+        .map(|h| h.uuid)
+        .ok_or(error::Error::DataError("The Login entry type is not found"))?;
+    kp_service::new_entry_form_data_by_id(db_key, &entry_type_uuid, group_uuid)
+}
+
+// Returns false when the form data has no field 'key'
+pub(crate) fn set_field_value(
+    form_data: &mut kp_service::EntryFormData,
+    key: &str,
+    value: &str,
+) -> bool {
+    let mut found = false;
+    for kv in form_data.section_fields.values_mut().flatten() {
+        if kv.key == key {
+            kv.value = Some(value.to_string());
+            found = true;
+        }
+    }
+    found
+}
+
+// A custom text field for 'form_data'. It is a copy of one of the standard text fields core
+// made for the entry and so only the values set here differ
+pub(crate) fn custom_field(
+    form_data: &kp_service::EntryFormData,
+    key: &str,
+    value: &str,
+    protected: bool,
+) -> Result<kp_service::KeyValueData> {
+    let text_field = |name: &str| {
+        form_data
+            .section_fields
+            .values()
+            .flatten()
+            .find(|kv| kv.key == name)
+            .cloned()
+    };
+    let mut kv = text_field(kp_service::entry_keyvalue_key::URL)
+        .or_else(|| text_field(kp_service::entry_keyvalue_key::USER_NAME))
+        .ok_or(error::Error::DataError(
+            "The entry does not have a text field to add a field",
+        ))?;
+    kv.key = key.to_string();
+    kv.value = Some(value.to_string());
+    kv.protected = protected;
+    kv.required = false;
+    kv.standard_field = false;
+    Ok(kv)
+}
@@ -1,17 +1,22 @@
 use std::{
-    fs, io,
+    collections::HashSet,
+    fs,
+    hash::Hash,
+    io,
     path::{Path, PathBuf},
 };
 
-use chrono::{Local, NaiveDateTime};
+use chrono::{Datelike, Local, NaiveDateTime};
 use log::debug;
 use onekeepass_core::db_service as kp_service;
 
-fn normalized_backup_source_stem(file_stem: &str) -> String {
-    const BACKUP_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H %M %S";
-    const TIMESTAMP_LEN: usize = 19;
-    const HASH_LEN: usize = 8;
+use crate::app_preference::BackupRetention;
+
+const BACKUP_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H %M %S";
+const TIMESTAMP_LEN: usize = 19;
+const HASH_LEN: usize = 8;
 
+fn normalized_backup_source_stem(file_stem: &str) -> String {
     let mut current = file_stem.to_string();
     let mut stripped_timestamped_suffix = false;
 
@@ -117,6 +122,20 @@ pub(crate) fn generate_timestamped_backup_file_name(
         return None;
     }
 
+    let (fname_no_extension, source_hash) = timestamped_backup_source(db_file_name);
+    let ts = Local::now().format(BACKUP_TIMESTAMP_FORMAT).to_string();
+    let backup_file_name = format!("{fname_no_extension}-{source_hash}-{ts}.kdbx");
+
+    debug!("timestamped backup_file_name is {}", backup_file_name);
+    backup_dir_path
+        .join(backup_file_name)
+        .to_str()
+        .map(|s| s.to_string())
+}
+
+// Gets the stem and the source hash parts used in the timestamped backup file names
+// of the database file 'db_file_name'
+fn timestamped_backup_source(db_file_name: &str) -> (String, String) {
     let db_path = Path::new(db_file_name);
     let fname_no_extension = db_path.file_stem().map_or_else(
         || "DB_FILE_NAME".into(),
@@ -127,14 +146,178 @@ pub(crate) fn generate_timestamped_backup_file_name(
         "{:08x}",
         kp_service::service_util::string_to_simple_hash(db_file_name) & 0xffff_ffff
     );
-    let ts = Local::now().format("%Y-%m-%d %H %M %S").to_string();
-    let backup_file_name = format!("{fname_no_extension}-{source_hash}-{ts}.kdbx");
 
-    debug!("timestamped backup_file_name is {}", backup_file_name);
-    backup_dir_path
-        .join(backup_file_name)
-        .to_str()
-        .map(|s| s.to_string())
+    (fname_no_extension, source_hash)
+}
+
+// Splits a timestamped backup file name "<stem>-<source hash>-<timestamp>.kdbx" into
+// its stem, source hash and timestamp parts
+fn parse_timestamped_backup_file_name(file_name: &str) -> Option<(&str, &str, NaiveDateTime)> {
+    let name = file_name.strip_suffix(".kdbx")?;
+    if !name.is_char_boundary(name.len().checked_sub(TIMESTAMP_LEN)?) {
+        return None;
+    }
+    let (rest, ts) = name.split_at(name.len() - TIMESTAMP_LEN);
+    let timestamp = NaiveDateTime::parse_from_str(ts, BACKUP_TIMESTAMP_FORMAT).ok()?;
+
+    let rest = rest.strip_suffix('-')?;
+    let (stem, hash) = rest.rsplit_once('-')?;
+    if stem.is_empty() || hash.len() != HASH_LEN || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    Some((stem, hash, timestamp))
+}
+
+pub(crate) struct TimestampedBackup {
+    pub(crate) path: PathBuf,
+    pub(crate) timestamp: NaiveDateTime,
+    pub(crate) size: u64,
+}
+
+// Finds all timestamped backups in 'backup_dir' with the given stem and source hash.
+// The returned list is sorted newest first
+fn find_timestamped_backups(
+    backup_dir: &Path,
+    stem: &str,
+    source_hash: &str,
+) -> io::Result<Vec<TimestampedBackup>> {
+    let mut backups = vec![];
+    for entry in fs::read_dir(backup_dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+        match parse_timestamped_backup_file_name(file_name) {
+            Some((s, h, timestamp)) if s == stem && h == source_hash => {
+                let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                backups.push(TimestampedBackup {
+                    path: entry.path(),
+                    timestamp,
+                    size,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    backups.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    Ok(backups)
+}
+
+// Lists the timestamped backups of the database file 'db_file_name' found in
+// 'backup_dir' (newest first)
+pub(crate) fn list_timestamped_backups(
+    backup_dir: &Path,
+    db_file_name: &str,
+) -> io::Result<Vec<TimestampedBackup>> {
+    let (stem, source_hash) = timestamped_backup_source(db_file_name);
+    find_timestamped_backups(backup_dir, &stem, &source_hash)
+}
+
+// Marks the newest backup of each of the last 'count' periods as kept.
+// The backups are expected to be sorted newest first
+fn keep_generations<K: Eq + Hash>(
+    backups: &[TimestampedBackup],
+    count: u32,
+    period_of: impl Fn(&NaiveDateTime) -> K,
+    keep: &mut HashSet<usize>,
+) {
+    let mut seen_periods = HashSet::new();
+    for (i, b) in backups.iter().enumerate() {
+        if seen_periods.len() >= count as usize {
+            break;
+        }
+        if seen_periods.insert(period_of(&b.timestamp)) {
+            keep.insert(i);
+        }
+    }
+}
+
+// Returns the indexes of the backups to keep as per the retention policy.
+// The backups are expected to be sorted newest first
+fn backups_to_keep(backups: &[TimestampedBackup], retention: &BackupRetention) -> HashSet<usize> {
+    let mut keep = HashSet::new();
+    if backups.is_empty() {
+        return keep;
+    }
+
+    // The newest one is the backup just written and is never removed
+    keep.insert(0);
+    keep.extend(0..backups.len().min(retention.keep_last as usize));
+
+    keep_generations(
+        backups,
+        retention.keep_daily,
+        |ts| (ts.year(), ts.ordinal()),
+        &mut keep,
+    );
+    keep_generations(
+        backups,
+        retention.keep_weekly,
+        |ts| {
+            let w = ts.iso_week();
+            (w.year(), w.week())
+        },
+        &mut keep,
+    );
+    keep_generations(
+        backups,
+        retention.keep_monthly,
+        |ts| (ts.year(), ts.month()),
+        &mut keep,
+    );
+
+    if retention.max_total_size_mb > 0 {
+        let max_bytes = retention.max_total_size_mb.saturating_mul(1024 * 1024);
+        let mut total = 0u64;
+        let mut kept: Vec<usize> = keep.iter().copied().collect();
+        kept.sort();
+        // Older backups are dropped first once the limit is reached
+        for i in kept {
+            total = total.saturating_add(backups[i].size);
+            if total > max_bytes && i != 0 {
+                keep.remove(&i);
+            }
+        }
+    }
+
+    keep
+}
+
+// Removes the timestamped backups of the same database as 'backup_file_name' that are
+// not selected by the retention policy. Only the files in the backup dir that have the
+// same stem and source hash are considered so that the backups of other databases are
+// never touched. Returns the number of backup files removed
+pub(crate) fn prune_timestamped_backups(
+    backup_file_name: &str,
+    retention: &BackupRetention,
+) -> io::Result<usize> {
+    let backup_path = Path::new(backup_file_name);
+    let (Some(backup_dir), Some(name)) = (
+        backup_path.parent(),
+        backup_path.file_name().and_then(|n| n.to_str()),
+    ) else {
+        return Ok(0);
+    };
+    let Some((stem, source_hash, _)) = parse_timestamped_backup_file_name(name) else {
+        return Ok(0);
+    };
+
+    let backups = find_timestamped_backups(backup_dir, stem, source_hash)?;
+    let keep = backups_to_keep(&backups, retention);
+
+    let mut removed = 0;
+    for (i, b) in backups.iter().enumerate() {
+        if !keep.contains(&i) {
+            debug!("Pruning backup file {:?}", &b.path);
+            remove_file_if_exists(&b.path)?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
 }
 
 pub(crate) fn remove_dir_files<P: AsRef<Path>>(path: P) -> io::Result<()> {
@@ -154,9 +337,28 @@ pub(crate) fn remove_file_if_exists<P: AsRef<Path>>(path: P) -> io::Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use super::{generate_timestamped_backup_file_name, normalized_backup_source_stem};
+    use super::{
+        backups_to_keep, generate_timestamped_backup_file_name, normalized_backup_source_stem,
+        parse_timestamped_backup_file_name, TimestampedBackup,
+    };
+    use crate::app_preference::BackupRetention;
+    use chrono::NaiveDateTime;
     use std::path::PathBuf;
 
+    fn backup(ts: &str, size: u64) -> TimestampedBackup {
+        TimestampedBackup {
+            path: PathBuf::from(format!("/mybackups/Testcsv-2cba3048-{ts}.kdbx")),
+            timestamp: NaiveDateTime::parse_from_str(ts, "%Y-%m-%d %H %M %S").unwrap(),
+            size,
+        }
+    }
+
+    fn sorted_keep(backups: &[TimestampedBackup], retention: &BackupRetention) -> Vec<usize> {
+        let mut v: Vec<usize> = backups_to_keep(backups, retention).into_iter().collect();
+        v.sort();
+        v
+    }
+
     #[test]
     fn normal_name_stays_unchanged() {
         assert_eq!(normalized_backup_source_stem("Testcsv"), "Testcsv");
@@ -215,4 +417,105 @@ mod tests {
         assert!(!backup_file_name.starts_with("/mybackups/Testcsv-2cba3048-"));
         assert!(backup_file_name.ends_with(".kdbx"));
     }
+
+    #[test]
+    fn parses_timestamped_backup_file_name() {
+        let (stem, hash, ts) =
+            parse_timestamped_backup_file_name("My-Passwords-2cba3048-2026-04-17 11 15 52.kdbx")
+                .unwrap();
+        assert_eq!(stem, "My-Passwords");
+        assert_eq!(hash, "2cba3048");
+        assert_eq!(ts.to_string(), "2026-04-17 11:15:52");
+
+        assert!(
+            parse_timestamped_backup_file_name("MyPassword_10084644638414928086.kdbx").is_none()
+        );
+        assert!(
+            parse_timestamped_backup_file_name("Testcsv-2cba3048-2026-04-17 11 15 52.txt")
+                .is_none()
+        );
+        assert!(
+            parse_timestamped_backup_file_name("Testcsv-2cbz3048-2026-04-17 11 15 52.kdbx")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn keep_last_and_newest_always_kept() {
+        let backups = vec![
+            backup("2026-04-17 11 15 52", 10),
+            backup("2026-04-17 10 15 52", 10),
+            backup("2026-04-16 10 15 52", 10),
+        ];
+        let retention = BackupRetention {
+            enabled: true,
+            ..Default::default()
+        };
+        assert_eq!(sorted_keep(&backups, &retention), vec![0]);
+
+        let retention = BackupRetention {
+            enabled: true,
+            keep_last: 2,
+            ..Default::default()
+        };
+        assert_eq!(sorted_keep(&backups, &retention), vec![0, 1]);
+    }
+
+    #[test]
+    fn daily_weekly_monthly_generations() {
+        let backups = vec![
+            backup("2026-04-17 11 15 52", 10),
+            backup("2026-04-17 10 15 52", 10),
+            backup("2026-04-16 10 15 52", 10),
+            backup("2026-04-08 10 15 52", 10),
+            backup("2026-03-20 10 15 52", 10),
+            backup("2026-02-20 10 15 52", 10),
+        ];
+        let retention = BackupRetention {
+            enabled: true,
+            keep_daily: 2,
+            ..Default::default()
+        };
+        assert_eq!(sorted_keep(&backups, &retention), vec![0, 2]);
+
+        let retention = BackupRetention {
+            enabled: true,
+            keep_weekly: 2,
+            ..Default::default()
+        };
+        assert_eq!(sorted_keep(&backups, &retention), vec![0, 3]);
+
+        let retention = BackupRetention {
+            enabled: true,
+            keep_monthly: 3,
+            ..Default::default()
+        };
+        assert_eq!(sorted_keep(&backups, &retention), vec![0, 4, 5]);
+    }
+
+    #[test]
+    fn size_limit_drops_oldest_kept_backups() {
+        let mb = 1024 * 1024;
+        let backups = vec![
+            backup("2026-04-17 11 15 52", 2 * mb),
+            backup("2026-04-17 10 15 52", 2 * mb),
+            backup("2026-04-16 10 15 52", 2 * mb),
+        ];
+        let retention = BackupRetention {
+            enabled: true,
+            keep_last: 3,
+            max_total_size_mb: 5,
+            ..Default::default()
+        };
+        assert_eq!(sorted_keep(&backups, &retention), vec![0, 1]);
+
+        // The newest backup is kept even when it alone exceeds the limit
+        let retention = BackupRetention {
+            enabled: true,
+            keep_last: 3,
+            max_total_size_mb: 1,
+            ..Default::default()
+        };
+        assert_eq!(sorted_keep(&backups, &retention), vec![0]);
+    }
 }
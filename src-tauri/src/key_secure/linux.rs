@@ -0,0 +1,374 @@
+// The encryption key for the db keys is kept in the freedesktop Secret Service
+// (gnome-keyring, KWallet ...) reached over the session D-Bus.
+// See https://specifications.freedesktop.org/secret-service-spec/latest/
+//
+// The item goes only to the "session" collection, which gnome-keyring keeps in memory. The
+// key is kept in the local store (this process' memory) when the provider has no unlocked
+// session collection, no provider is running or any Secret Service call fails. The default
+// collection is not used as it is written to disk.
+//
+// The local store is used also while this app itself provides the Secret Service (see
+// secret_service/mod.rs).
+//
+// One connection and session is opened on the first use and kept for the later calls. The
+// connection and the local store have their own locks so that the app's own keys are stored
+// and read without holding the key store mutex during the D-Bus calls (see key_secure/mod.rs).
+//
+// The secret is transferred with the "plain" algorithm. The session bus is private to the
+// user and the value is only the key used to encrypt the db keys.
+//
+// Earlier versions also used the default collection. The items left there are of no use
+// once the app exits and are deleted at startup (see remove_stale_keys).
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::{Connection, Proxy};
+use dbus::Path;
+use log::{debug, info};
+use secstr::SecVec;
+
+use onekeepass_core::db_service as kp_service;
+
+const SECRETS_DEST: &str = "org.freedesktop.secrets";
+const SECRETS_PATH: &str = "/org/freedesktop/secrets";
+const SERVICE_IFACE: &str = "org.freedesktop.Secret.Service";
+const COLLECTION_IFACE: &str = "org.freedesktop.Secret.Collection";
+const ITEM_IFACE: &str = "org.freedesktop.Secret.Item";
+const SESSION_IFACE: &str = "org.freedesktop.Secret.Session";
+
+const APPLICATION_NAME: &str = "onekeepass";
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+// (session, parameters, value, content type) as defined in the spec
+type Secret = (Path<'static>, Vec<u8>, Vec<u8>, String);
+
+// The state is kept in the statics below and so every instance is the same store
+#[derive(Default)]
+pub struct KeyStoreServiceImpl;
+
+// The keys kept in this process' memory when the Secret Service is not used
+fn local_store() -> &'static Mutex<HashMap<String, SecVec<u8>>> {
+    static LOCAL_STORE: OnceLock<Mutex<HashMap<String, SecVec<u8>>>> = OnceLock::new();
+    LOCAL_STORE.get_or_init(Default::default)
+}
+
+// The open session. None till the first use and after a failed call
+fn secret_service() -> &'static Mutex<Option<SecretService>> {
+    static SECRET_SERVICE: OnceLock<Mutex<Option<SecretService>>> = OnceLock::new();
+    SECRET_SERVICE.get_or_init(Default::default)
+}
+
+// Calls 'f' with the open session. A session kept from an earlier call may have been closed
+// by a restarted provider and so a failed call is tried once more with a new session
+fn with_secret_service<T>(
+    f: impl Fn(&SecretService) -> Result<T, dbus::Error>,
+) -> Result<T, dbus::Error> {
+    let mut cached = secret_service().lock().unwrap();
+    // The provider of this app may have been started after the session was opened
+    if crate::secret_service::is_running() {
+        *cached = None;
+        return Err(dbus::Error::new_failed(
+            "The secret service is provided by this app",
+        ));
+    }
+    if let Some(service) = cached.as_ref() {
+        match f(service) {
+            Ok(v) => return Ok(v),
+            Err(e) => debug!(
+                "Secret service call failed ({}) and reopening the session",
+                e
+            ),
+        }
+    }
+    *cached = None;
+    let service = SecretService::open()?;
+    let result = f(&service);
+    if result.is_ok() {
+        *cached = Some(service);
+    }
+    result
+}
+
+pub(crate) fn store_key(db_key: &str, data: Vec<u8>) {
+    match with_secret_service(|s| s.store(db_key, &data)) {
+        Ok(()) => {
+            debug!("Encrypted key is stored in the secret service");
+            // Any earlier local copy is no more used
+            local_store().lock().unwrap().remove(db_key);
+        }
+        Err(e) => {
+            // As a fallback, we keep the enc key locally
+            info!(
+                "Secret service is not available ({}) and using local store",
+                e
+            );
+            local_store()
+                .lock()
+                .unwrap()
+                .insert(db_key.into(), SecVec::new(data));
+        }
+    }
+}
+
+pub(crate) fn get_key(db_key: &str) -> Option<Vec<u8>> {
+    if let Some(v) = local_store().lock().unwrap().get(db_key) {
+        return Some(Vec::from(v.unsecure()));
+    }
+
+    match with_secret_service(|s| s.get(db_key)) {
+        Ok(v) => v,
+        Err(e) => {
+            log::error!("Getting the key from the secret service failed: {}", e);
+            None
+        }
+    }
+}
+
+pub(crate) fn delete_key(db_key: &str) {
+    if local_store().lock().unwrap().remove(db_key).is_some() {
+        debug!("Key is deleted from the local store");
+        return;
+    }
+
+    if let Err(e) = with_secret_service(|s| s.delete(db_key)) {
+        info!("Deleting the key from the secret service failed: {}", e);
+    }
+}
+
+#[inline]
+fn formatted_key(db_key: &str) -> String {
+    format!(
+        "OKP-{}",
+        kp_service::service_util::string_to_simple_hash(db_key)
+    )
+}
+
+fn item_attributes(db_key: &str) -> HashMap<String, String> {
+    HashMap::from([
+        ("application".to_string(), APPLICATION_NAME.to_string()),
+        ("okp-key".to_string(), formatted_key(db_key)),
+    ])
+}
+
+// Deletes the items stored by an earlier run of this app. The keys are needed only while
+// the app runs and an item left in a collection kept on disk would stay there
+pub(crate) fn remove_stale_keys() {
+    let removed = SecretService::connect().and_then(|connection| {
+        let service = connection.with_proxy(SECRETS_DEST, SECRETS_PATH, CALL_TIMEOUT);
+        let attributes = HashMap::from([("application", APPLICATION_NAME)]);
+        // The items of a locked collection can not be deleted without a prompt
+        let (unlocked, _locked): (Vec<Path<'static>>, Vec<Path<'static>>) =
+            service.method_call(SERVICE_IFACE, "SearchItems", (attributes,))?;
+        for item in &unlocked {
+            let (_prompt,): (Path<'static>,) = connection
+                .with_proxy(SECRETS_DEST, item.clone(), CALL_TIMEOUT)
+                .method_call(ITEM_IFACE, "Delete", ())?;
+        }
+        Ok(unlocked.len())
+    });
+    match removed {
+        Ok(0) => {}
+        Ok(n) => info!("Removed {} stale key(s) from the secret service", n),
+        Err(e) => debug!("Stale keys are not removed from the secret service: {}", e),
+    }
+}
+
+// Called by core while it holds the key store mutex
+impl kp_service::KeyStoreService for KeyStoreServiceImpl {
+    fn store_key(&mut self, db_key: &str, data: Vec<u8>) -> kp_service::Result<()> {
+        store_key(db_key, data);
+        Ok(())
+    }
+
+    fn get_key(&self, db_key: &str) -> Option<Vec<u8>> {
+        get_key(db_key)
+    }
+
+    fn delete_key(&mut self, db_key: &str) -> kp_service::Result<()> {
+        delete_key(db_key);
+        Ok(())
+    }
+
+    fn copy_key(&mut self, source_db_key: &str, target_db_key: &str) -> kp_service::Result<()> {
+        let mut local = local_store().lock().unwrap();
+        if let Some(source_db_key) = local.get(source_db_key).cloned() {
+            local.insert(target_db_key.into(), source_db_key);
+            debug!("Keys are copied in local store...");
+            return Ok(());
+        }
+        drop(local);
+
+        let Some(data) = get_key(source_db_key) else {
+            return Err(onekeepass_core::error::Error::UnexpectedError(
+                "Copying key failed as the source key is not found".into(),
+            ));
+        };
+        store_key(target_db_key, data);
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------
+
+// An open Secret Service session. The session is closed when this is dropped
+struct SecretService {
+    connection: Connection,
+    session: Path<'static>,
+    collection: Path<'static>,
+}
+
+impl SecretService {
+    // Connects to the provider on the session bus
+    fn connect() -> Result<Connection, dbus::Error> {
+        // The provider of this app serves only the db groups and is not a key store
+        if crate::secret_service::is_running() {
+            return Err(dbus::Error::new_failed(
+                "The secret service is provided by this app",
+            ));
+        }
+        Connection::new_session()
+    }
+
+    fn open() -> Result<Self, dbus::Error> {
+        let connection = Self::connect()?;
+        let (session, collection) = {
+            let service = connection.with_proxy(SECRETS_DEST, SECRETS_PATH, CALL_TIMEOUT);
+            let (_, session): (Variant<Box<dyn RefArg>>, Path<'static>) =
+                service.method_call(SERVICE_IFACE, "OpenSession", ("plain", Variant("")))?;
+            (session, Self::unlocked_collection(&service)?)
+        };
+        Ok(Self {
+            connection,
+            session,
+            collection,
+        })
+    }
+
+    // The in-memory "session" collection. Providers without one are not used
+    fn unlocked_collection(service: &Proxy<'_, &Connection>) -> Result<Path<'static>, dbus::Error> {
+        let (collection,): (Path<'static>,) =
+            service.method_call(SERVICE_IFACE, "ReadAlias", ("session",))?;
+        if &*collection == "/" {
+            return Err(dbus::Error::new_failed(
+                "The secret service has no session collection",
+            ));
+        }
+        // An unlocked collection is returned as is. Otherwise the provider returns a
+        // prompt that is not shown here
+        let (unlocked, _prompt): (Vec<Path<'static>>, Path<'static>) =
+            service.method_call(SERVICE_IFACE, "Unlock", (vec![collection.clone()],))?;
+        if unlocked.contains(&collection) {
+            Ok(collection)
+        } else {
+            Err(dbus::Error::new_failed(
+                "The secret service session collection is locked",
+            ))
+        }
+    }
+
+    fn proxy<'a>(&'a self, path: &Path<'static>) -> Proxy<'a, &'a Connection> {
+        self.connection
+            .with_proxy(SECRETS_DEST, path.clone(), CALL_TIMEOUT)
+    }
+
+    fn store(&self, db_key: &str, data: &[u8]) -> Result<(), dbus::Error> {
+        let mut properties = PropMap::new();
+        properties.insert(
+            "org.freedesktop.Secret.Item.Label".into(),
+            Variant(Box::new(format!("OneKeePass {}", formatted_key(db_key))) as Box<dyn RefArg>),
+        );
+        properties.insert(
+            "org.freedesktop.Secret.Item.Attributes".into(),
+            Variant(Box::new(item_attributes(db_key)) as Box<dyn RefArg>),
+        );
+        let secret: Secret = (
+            self.session.clone(),
+            vec![],
+            data.to_vec(),
+            "application/octet-stream".into(),
+        );
+
+        // The last arg 'replace' updates any item with the same attributes
+        let (_item, prompt): (Path<'static>, Path<'static>) = self
+            .proxy(&self.collection)
+            .method_call(COLLECTION_IFACE, "CreateItem", (properties, secret, true))?;
+        if &*prompt != "/" {
+            return Err(dbus::Error::new_failed(
+                "Storing the key in the secret service needs a prompt",
+            ));
+        }
+        Ok(())
+    }
+
+    fn find_items(&self, db_key: &str) -> Result<Vec<Path<'static>>, dbus::Error> {
+        let (items,): (Vec<Path<'static>>,) = self.proxy(&self.collection).method_call(
+            COLLECTION_IFACE,
+            "SearchItems",
+            (item_attributes(db_key),),
+        )?;
+        Ok(items)
+    }
+
+    fn get(&self, db_key: &str) -> Result<Option<Vec<u8>>, dbus::Error> {
+        let Some(item) = self.find_items(db_key)?.into_iter().next() else {
+            return Ok(None);
+        };
+        let (secret,): (Secret,) =
+            self.proxy(&item)
+                .method_call(ITEM_IFACE, "GetSecret", (self.session.clone(),))?;
+        Ok(Some(secret.2))
+    }
+
+    fn delete(&self, db_key: &str) -> Result<(), dbus::Error> {
+        for item in self.find_items(db_key)? {
+            let (_prompt,): (Path<'static>,) =
+                self.proxy(&item).method_call(ITEM_IFACE, "Delete", ())?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SecretService {
+    fn drop(&mut self) {
+        let _ = self
+            .proxy(&self.session)
+            .method_call::<(), _, _, _>(SESSION_IFACE, "Close", ());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use onekeepass_core::db_service::KeyStoreService;
+
+    // Needs a secret service provider on the session bus. For example in a headless session:
+    //   dbus-run-session -- sh -c 'echo -n "" | gnome-keyring-daemon --unlock --components=secrets \
+    //     && cargo test key_secure -- --ignored'
+    #[test]
+    #[ignore]
+    fn verify_secret_service_key_store() {
+        let db_key = "/tmp/okp-key-secure-test.kdbx";
+        let copy_db_key = "/tmp/okp-key-secure-test-copy.kdbx";
+
+        let mut kss = KeyStoreServiceImpl;
+        kss.store_key(db_key, vec![1, 2, 3, 4]).unwrap();
+        // Stored in the secret service and not locally
+        assert!(local_store().lock().unwrap().is_empty());
+        assert_eq!(kss.get_key(db_key), Some(vec![1, 2, 3, 4]));
+
+        // Replaces the earlier item
+        kss.store_key(db_key, vec![5, 6]).unwrap();
+        assert_eq!(kss.get_key(db_key), Some(vec![5, 6]));
+
+        kss.copy_key(db_key, copy_db_key).unwrap();
+        assert_eq!(kss.get_key(copy_db_key), Some(vec![5, 6]));
+
+        kss.delete_key(db_key).unwrap();
+        kss.delete_key(copy_db_key).unwrap();
+        assert_eq!(kss.get_key(db_key), None);
+        assert_eq!(kss.get_key(copy_db_key), None);
+    }
+}
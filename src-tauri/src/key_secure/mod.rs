@@ -3,7 +3,11 @@
 #[path = "macos.rs"]
 mod imp;
 
-#[cfg(any(target_os = "windows", target_os = "linux"))]
+#[cfg(target_os = "linux")]
+#[path = "linux.rs"]
+mod imp;
+
+#[cfg(target_os = "windows")]
 //#[cfg(any(target_os = "windows", target_os = "linux", feature = "onekeepass-dev"))]
 #[path = "other.rs"]
 mod imp;
@@ -11,18 +15,72 @@ mod imp;
 use self::imp::KeyStoreServiceImpl;
 
 use log::debug;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use onekeepass_core::db_service::{self as kp_service, KeyStoreOperation, KeyStoreService};
 
-use onekeepass_core::db_service::KeyStoreOperation;
+static KEY_MAIN_STORE: OnceLock<Arc<Mutex<KeyStoreServiceImpl>>> = OnceLock::new();
 
 pub fn init_key_main_store() {
+    // Done before any key is stored so that only the items of an earlier run are removed
+    #[cfg(target_os = "linux")]
+    imp::remove_stale_keys();
     let kss = Arc::new(Mutex::new(KeyStoreServiceImpl::default()));
-    // In case, we need to hold any reference at this module, then we need to Arc::clone
-    // and use it
+    // A reference is kept here so that the app can keep its own keys (e.g. quick unlock)
+    // in the same store
+    let _ = KEY_MAIN_STORE.set(Arc::clone(&kss));
     KeyStoreOperation::init(kss);
     debug!("key_secure - key_main_store is initialized in init_key_main_store ");
 }
 
+fn key_main_store() -> kp_service::Result<&'static Arc<Mutex<KeyStoreServiceImpl>>> {
+    KEY_MAIN_STORE
+        .get()
+        .ok_or(onekeepass_core::error::Error::DataError(
+            "Key store is not initialized",
+        ))
+}
+
+// The 'key_name' should not be a db key as the core uses those.
+// On Linux, the key store mutex is not held during the D-Bus calls (see linux.rs)
+pub(crate) fn store_key(key_name: &str, data: Vec<u8>) -> kp_service::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        key_main_store()?;
+        imp::store_key(key_name, data);
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        key_main_store()?.lock().unwrap().store_key(key_name, data)
+    }
+}
+
+pub(crate) fn get_key(key_name: &str) -> Option<Vec<u8>> {
+    #[cfg(target_os = "linux")]
+    {
+        key_main_store().ok()?;
+        imp::get_key(key_name)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        key_main_store().ok()?.lock().unwrap().get_key(key_name)
+    }
+}
+
+pub(crate) fn delete_key(key_name: &str) -> kp_service::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        key_main_store()?;
+        imp::delete_key(key_name);
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        key_main_store()?.lock().unwrap().delete_key(key_name)
+    }
+}
+
 /*
 #[cfg(all(target_os = "macos", not(feature = "onekeepass-dev")))]
 mod macos;
@@ -19,7 +19,7 @@ impl KeyStoreService for KeyStoreServiceImpl {
     fn store_key(&mut self, db_key: &str, data: Vec<u8>) -> kp_service::Result<()> {
         // On successful loading of database, the keys are encrypted with Aes GCM cipher
         // and the encryption key for keys is stored in the KeyChain for macOS.
-        // For now in case of Windows and Linux, we keep it locally
+        // For now in case of Windows, we keep it locally (see linux.rs for Linux)
 
         debug!("store_key is called and data size {}", data.len());
         self.store.insert(db_key.into(), SecVec::new(data));
@@ -0,0 +1,240 @@
+// Databases and entries to open that are given from outside the app:
+//
+//  - .kdbx paths in the command line, with an optional '--keyfile <path>'
+//    (e.g 'onekeepass-frontend ~/vault.kdbx --keyfile ~/vault.keyx' or a double click on a file)
+//  - an 'onekeepass://open?db=<path>&entry=<uuid>' link. Without 'db', the entry is looked up in
+//    the active db
+//
+// Only one instance of the app runs. When the app is started again, the args of the new process
+// are forwarded to the running one by the single instance plugin and the new process exits.
+// On macOS, files and links are instead received in the 'Opened' run event.
+//
+// The requests are queued here and the UI is told to take them with 'pending_launch_requests'.
+// At the start up, the UI takes the queued requests once its event listeners are registered.
+// A db is never opened without the user entering the credentials. The open dialog is just
+// shown with the given paths
+
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, Url};
+use uuid::Uuid;
+
+use crate::constants::event_names::LAUNCH_REQUEST_EVENT;
+use crate::constants::window_labels::MAIN_WINDOW_LABEL;
+
+const URL_SCHEME: &str = "onekeepass";
+const KEY_FILE_ARG: &str = "--keyfile";
+
+#[derive(Serialize, Clone, Debug, Default, PartialEq)]
+pub(crate) struct LaunchRequest {
+    // The absolute path of the db file. None to use the active db
+    db_file_name: Option<String>,
+    key_file_name: Option<String>,
+    // The entry to show once the db is opened
+    entry_uuid: Option<String>,
+}
+
+fn pending_requests() -> &'static Mutex<Vec<LaunchRequest>> {
+    static PENDING_REQUESTS: OnceLock<Mutex<Vec<LaunchRequest>>> = OnceLock::new();
+    PENDING_REQUESTS.get_or_init(Default::default)
+}
+
+fn is_kdbx_file(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case("kdbx"))
+        .unwrap_or(false)
+}
+
+fn absolute_path(cwd: &Path, path: &str) -> PathBuf {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        cwd.join(path)
+    }
+}
+
+// Parses an 'onekeepass://open?db=..&entry=..' link
+fn parse_open_url(url: &Url) -> Option<LaunchRequest> {
+    if url.scheme() != URL_SCHEME || url.host_str() != Some("open") {
+        log::warn!("Not a supported link: {}", url);
+        return None;
+    }
+
+    let mut request = LaunchRequest::default();
+    for (name, value) in url.query_pairs() {
+        match name.as_ref() {
+            "db" if is_kdbx_file(Path::new(value.as_ref())) => {
+                request.db_file_name = Some(value.to_string())
+            }
+            "entry" if Uuid::parse_str(&value).is_ok() => {
+                request.entry_uuid = Some(value.to_string())
+            }
+            _ => log::warn!("Ignored the query parameter '{}' of the link", name),
+        }
+    }
+
+    (request.db_file_name.is_some() || request.entry_uuid.is_some()).then_some(request)
+}
+
+// Parses the args of a process. The first arg is the program. The key file given with
+// '--keyfile' is used for the db files in the same args
+fn parse_args(args: &[String], cwd: &Path) -> Vec<LaunchRequest> {
+    let mut db_files = vec![];
+    let mut key_file_name = None;
+    let mut requests = vec![];
+
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if arg == KEY_FILE_ARG {
+            key_file_name = iter.next().map(|k| absolute_path(cwd, k));
+        } else if let Some(k) = arg.strip_prefix("--keyfile=") {
+            key_file_name = Some(absolute_path(cwd, k));
+        } else if arg.starts_with(&format!("{}:", URL_SCHEME)) {
+            if let Some(r) = Url::parse(arg).ok().as_ref().and_then(parse_open_url) {
+                requests.push(r);
+            }
+        } else if is_kdbx_file(Path::new(arg)) {
+            db_files.push(absolute_path(cwd, arg));
+        } else {
+            log::debug!("Ignored the arg {}", arg);
+        }
+    }
+
+    let key_file_name = key_file_name.map(|k| k.to_string_lossy().to_string());
+    let mut file_requests: Vec<LaunchRequest> = db_files
+        .into_iter()
+        .map(|f| LaunchRequest {
+            db_file_name: Some(f.to_string_lossy().to_string()),
+            key_file_name: key_file_name.clone(),
+            entry_uuid: None,
+        })
+        .collect();
+    file_requests.extend(requests);
+    file_requests
+}
+
+fn show_main_window(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window(MAIN_WINDOW_LABEL) {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+}
+
+fn queue(app: &AppHandle, requests: Vec<LaunchRequest>) {
+    if requests.is_empty() {
+        return;
+    }
+    log::info!("Received {} launch request(s)", requests.len());
+    pending_requests().lock().unwrap().extend(requests);
+    // The UI may not be listening yet at the start up. It takes the queued requests then
+    let _ = app.emit(LAUNCH_REQUEST_EVENT, ());
+}
+
+// Called in the app setup with the args of this process
+pub(crate) fn init(app: &AppHandle) {
+    let args: Vec<String> = std::env::args().collect();
+    let cwd = std::env::current_dir().unwrap_or_default();
+    queue(app, parse_args(&args, &cwd));
+}
+
+// Called by the single instance plugin with the args of a new process
+pub(crate) fn on_second_instance(app: &AppHandle, args: Vec<String>, cwd: String) {
+    show_main_window(app);
+    queue(app, parse_args(&args, Path::new(&cwd)));
+}
+
+// Called on macOS with the files or links opened with the app
+#[cfg(target_os = "macos")]
+pub(crate) fn on_opened(app: &AppHandle, urls: Vec<Url>) {
+    let requests = urls
+        .iter()
+        .filter_map(|url| {
+            if url.scheme() == "file" {
+                url.to_file_path()
+                    .ok()
+                    .filter(|p| is_kdbx_file(p))
+                    .map(|p| LaunchRequest {
+                        db_file_name: Some(p.to_string_lossy().to_string()),
+                        ..Default::default()
+                    })
+            } else {
+                parse_open_url(url)
+            }
+        })
+        .collect();
+    show_main_window(app);
+    queue(app, requests);
+}
+
+// Called to open a recent db from the tray menu
+pub(crate) fn open_db(app: &AppHandle, db_file_name: &str) {
+    show_main_window(app);
+    queue(
+        app,
+        vec![LaunchRequest {
+            db_file_name: Some(db_file_name.to_string()),
+            ..Default::default()
+        }],
+    );
+}
+
+// Returns the queued requests and clears the queue
+pub(crate) fn take_pending_requests() -> Vec<LaunchRequest> {
+    std::mem::take(&mut *pending_requests().lock().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parse_file_args() {
+        let cwd = Path::new("/home/user");
+        let r = parse_args(
+            &args("okp vault.kdbx --keyfile keys/vault.keyx /tmp/Other.KDBX --verbose"),
+            cwd,
+        );
+        assert_eq!(r.len(), 2);
+        assert_eq!(
+            r[0],
+            LaunchRequest {
+                db_file_name: Some("/home/user/vault.kdbx".into()),
+                key_file_name: Some("/home/user/keys/vault.keyx".into()),
+                entry_uuid: None,
+            }
+        );
+        assert_eq!(r[1].db_file_name.as_deref(), Some("/tmp/Other.KDBX"));
+
+        assert!(parse_args(&args("okp"), cwd).is_empty());
+        assert!(parse_args(&args("okp notes.txt"), cwd).is_empty());
+    }
+
+    #[test]
+    fn parse_open_links() {
+        let cwd = Path::new("/");
+        let r = parse_args(
+            &args("okp onekeepass://open?db=%2Fhome%2Fuser%2Fvault.kdbx&entry=6691d1b7-13b7-4f7e-82bc-481629d9f6e3"),
+            cwd,
+        );
+        assert_eq!(
+            r,
+            vec![LaunchRequest {
+                db_file_name: Some("/home/user/vault.kdbx".into()),
+                key_file_name: None,
+                entry_uuid: Some("6691d1b7-13b7-4f7e-82bc-481629d9f6e3".into()),
+            }]
+        );
+
+        // No valid db or entry
+        assert!(parse_args(&args("okp onekeepass://open?entry=abc"), cwd).is_empty());
+        assert!(parse_args(&args("okp onekeepass://delete?db=/a.kdbx"), cwd).is_empty());
+    }
+}
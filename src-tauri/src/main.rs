@@ -9,22 +9,33 @@ mod app_state;
 mod auto_open;
 #[cfg(not(feature = "mas-build"))]
 mod auto_type;
+mod backup_service;
 mod biometric;
 mod browser_service;
+mod cli_service;
 #[cfg(target_os = "linux")]
 mod clipboard;
 mod commands;
 mod constants;
 mod db_file_watcher;
+mod db_lock;
+mod entry_form_util;
 mod file_util;
 mod key_secure;
+mod launch_request;
 mod mas;
 mod menu;
 mod pass_phrase;
+mod quick_unlock;
 mod remote_storage;
 mod sandbox;
+#[cfg(target_os = "linux")]
+mod secret_service;
+#[cfg(target_os = "linux")]
+mod session_lock;
 mod ssh_agent;
 mod translation;
+mod tray;
 #[cfg(not(feature = "mas-build"))]
 mod updater;
 // mod callback_service_provider;
@@ -73,6 +84,12 @@ fn main() {
     // See below
 
     let app = tauri::Builder::default()
+        // Should be the first plugin registered. A new process forwards its args to the
+        // running app and exits
+        .plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
+            launch_request::on_second_instance(app, args, cwd);
+        }))
+        .plugin(tauri_plugin_deep_link::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_clipboard_manager::init())
         .plugin(tauri_plugin_global_shortcut::Builder::new().build())
@@ -81,6 +98,17 @@ fn main() {
         .manage(app_state::AppState::new())
         .setup(|app| {
             app_state::init_app(app);
+            // The 'onekeepass' scheme is registered by the installers. An AppImage and a dev
+            // build need to register it at runtime
+            #[cfg(any(target_os = "linux", all(debug_assertions, windows)))]
+            {
+                use tauri_plugin_deep_link::DeepLinkExt;
+                if let Err(e) = app.deep_link().register_all() {
+                    log::error!("Registering the onekeepass url scheme failed: {}", e);
+                }
+            }
+            launch_request::init(app.app_handle());
+            tray::init(app.app_handle());
             Ok(menu::build_menus(app.app_handle())?)
         })
         // .on_window_event(|event| match event.event() {
@@ -93,7 +121,10 @@ fn main() {
         // })
         // .menu(menu::get_app_menu(menu_translation))
         .on_menu_event(|app_handle, menu_event| {
-            let _ = menu::handle_menu_events(app_handle, &menu_event);
+            // The tray menu events are handled in the backend
+            if !tray::handle_menu_event(app_handle, &menu_event) {
+                let _ = menu::handle_menu_events(app_handle, &menu_event);
+            }
         })
         .invoke_handler(tauri::generate_handler![
             // Sorted alphabetically
@@ -103,35 +134,44 @@ fn main() {
             commands::add_custom_icon_from_file,
             commands::add_custom_icon_from_url,
             commands::analyzed_password,
+            commands::apply_external_db_change,
             commands::authenticate_with_biometric,
             commands::auto_open_group_uuid,
             commands::browser_ext_pick_install_dir,
             commands::browser_ext_manifest_statuses,
             commands::browser_ext_use_user_permission,
+            commands::cancel_passkey_user_verification,
             #[cfg(not(feature = "mas-build"))]
             commands::check_for_updates,
             commands::clear_csv_data_cache,
             commands::clear_recent_files,
+            commands::cli_client_approval_result,
             #[cfg(target_os = "linux")]
             commands::clipboard_clear,
             #[cfg(target_os = "linux")]
             commands::clipboard_get_text,
             commands::clone_entry,
+            commands::close_db_backup,
             commands::close_kdbx,
             commands::collect_entry_group_tags,
             commands::combined_category_details,
+            commands::compare_db_with_backup,
             commands::create_kdbx,
             commands::create_new_db_with_imported_csv,
             commands::delete_custom_entry_type,
             commands::delete_history_entries,
             commands::delete_history_entry_by_index,
+            commands::delete_passkey,
             commands::empty_trash,
+            commands::enable_biometric_unlock,
             commands::entry_form_current_otp,
             commands::entry_form_current_otps,
             commands::entry_summary_data,
             commands::entry_type_headers,
             commands::export_as_xml,
             commands::export_main_content_as_xml,
+            commands::export_passkeys,
+            commands::external_change_policy,
             commands::form_otp_url,
             commands::generate_key_file,
             commands::generate_password_phrase,
@@ -139,17 +179,23 @@ fn main() {
             commands::get_db_settings,
             commands::get_entry_form_data_by_id,
             commands::get_group_by_id,
+            commands::git_credential_confirm_result,
             commands::groups_summary_data,
             commands::history_entries_summary,
             commands::history_entry_by_index,
             commands::import_csv_file,
+            commands::import_passkeys,
             commands::insert_entry_from_form_data,
             commands::insert_group,
             commands::insert_or_update_custom_entry_type,
             commands::init_timers,
             commands::is_path_exists,
+            commands::is_quick_unlock_pin_set,
             commands::kdbx_context_statuses,
+            commands::last_backup_verification,
             commands::list_custom_icons,
+            commands::list_db_backups,
+            commands::list_passkeys,
             commands::load_custom_svg_icons,
             commands::load_language_translations,
             commands::load_kdbx,
@@ -157,6 +203,7 @@ fn main() {
             commands::mark_group_as_category,
             commands::menu_action_requested,
             commands::merge_databases,
+            commands::merge_db_conflict_copy,
             commands::merge_kdbx_with_disk_version,
             // commands::menu_titles_change_requested,
             commands::clone_entry_to_other_db,
@@ -166,13 +213,18 @@ fn main() {
             commands::move_group,
             commands::move_group_to_other_db,
             commands::move_group_to_recycle_bin,
+            commands::move_passkey,
             commands::new_blank_group,
             commands::new_entry_form_data,
+            commands::open_db_backup,
             commands::open_all_auto_open_dbs,
             #[cfg(not(feature = "mas-build"))]
             commands::parse_auto_type_sequence,
+            commands::pending_launch_requests,
             #[cfg(not(feature = "mas-build"))]
             commands::platform_window_titles,
+            commands::quick_search,
+            commands::quick_search_field_value,
             commands::read_and_verify_db_file,
             commands::read_app_preference,
             commands::read_text_file,
@@ -181,18 +233,28 @@ fn main() {
             commands::remove_entry_permanently,
             commands::remove_group_permanently,
             commands::remove_recent_file,
+            commands::rename_passkey,
             commands::resolve_auto_open_properties,
+            commands::restore_db_backup,
+            commands::restore_entries_from_db_backup,
+            commands::revoke_cli_client,
             commands::rs_acknowledge_remote_change,
             commands::rs_check_remote_modified,
             commands::rs_connect_and_retrieve_root_dir,
             commands::rs_connect_by_id_and_retrieve_root_dir,
             commands::rs_create_kdbx,
             commands::rs_get_remote_storage_config,
+            commands::rs_list_history_copies,
             commands::rs_list_kdbx_source_connections,
             commands::rs_list_sub_dir,
             commands::rs_merge_with_remote,
             commands::rs_read_kdbx,
+            commands::rs_restore_history_copy,
+            commands::rs_s3_connect_by_id_and_list_root,
+            commands::rs_s3_list_connections,
+            commands::rs_s3_list_sub_dir,
             commands::rs_save_kdbx,
+            commands::rs_sync_offline_changes,
             commands::save_all_modified_dbs,
             commands::save_as_kdbx,
             commands::open_attachment_temp_file,
@@ -202,10 +264,18 @@ fn main() {
             commands::save_to_db_file,
             commands::score_password,
             commands::search_term,
+            #[cfg(target_os = "linux")]
+            commands::secret_service_confirm_result,
+            #[cfg(target_os = "linux")]
+            commands::secret_service_status,
             commands::set_active_db_key,
             commands::set_db_settings,
             commands::set_entry_custom_icon,
+            commands::set_external_change_policy,
             commands::set_group_custom_icon,
+            commands::set_quick_unlock_pin,
+            #[cfg(target_os = "linux")]
+            commands::set_secret_service_collection,
             // commands::send_sequence_to_winow,
             // commands::send_sequence_to_winow_sync,
             #[cfg(not(feature = "mas-build"))]
@@ -216,6 +286,10 @@ fn main() {
             commands::ssh_agent_sign_confirm_result,
             commands::start_ssh_agent,
             commands::stop_ssh_agent,
+            #[cfg(target_os = "linux")]
+            commands::start_secret_service,
+            #[cfg(target_os = "linux")]
+            commands::stop_secret_service,
             commands::start_polling_entry_otp_fields,
             commands::stop_polling_entry_otp_fields,
             commands::stop_polling_all_entries_otp_fields,
@@ -226,12 +300,14 @@ fn main() {
             // commands::tokio_runtime_start,
             commands::unlock_kdbx,
             commands::unlock_kdbx_on_biometric_authentication,
+            commands::unlock_kdbx_with_pin,
             commands::update_browser_ext_support_preference,
             commands::update_db_with_imported_csv,
             commands::update_entry_from_form_data,
             commands::update_group,
             commands::upload_entry_attachment,
             commands::update_preference,
+            commands::verify_passkey_user,
             // This is a test command that may be used to experiment any backend call during dev time
             // commands::test_call,
             // commands::test_simulate_verified_flag_preference,
@@ -250,6 +326,12 @@ fn main() {
             info!("Application is ready");
         }
 
+        // Files and onekeepass:// links opened with the app (see launch_request.rs)
+        #[cfg(target_os = "macos")]
+        tauri::RunEvent::Opened { urls } => {
+            launch_request::on_opened(app_handle, urls);
+        }
+
         tauri::RunEvent::WindowEvent { label, event, .. } => {
             match event {
                 tauri::WindowEvent::Focused(focused) => {
@@ -262,6 +344,13 @@ fn main() {
                     let _r = window.emit(MAIN_WINDOW_EVENT, wr);
                 }
                 tauri::WindowEvent::CloseRequested { api, .. } => {
+                    // The app keeps running in the tray and the window is only hidden
+                    if tray::keeps_running_on_close(app_handle) {
+                        info!("Window {} is hidden to the tray on close", label);
+                        api.prevent_close();
+                        tray::hide_main_window(app_handle);
+                        return;
+                    }
                     info!(
                         "Window event is CloseRequested and will not be closed for window {}",
                         label
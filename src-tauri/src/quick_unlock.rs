@@ -0,0 +1,364 @@
+// Quick unlock of a locked db with a short PIN where the biometric unlock is not
+// available (Linux, Windows).
+//
+// After a full unlock the user may set a PIN. The credentials of that unlock (password and
+// key file path) are checked against the opened db and are then encrypted (AES-256-GCM) with a key
+// derived from the PIN (Argon2id) and a random wrap key. The encrypted credentials are kept
+// only in memory. The wrap key is kept through key_secure, which may write it to disk (the
+// KeyChain on macOS). On Linux it is kept only in memory (see key_secure/linux.rs).
+//
+// The PIN is removed after MAX_PIN_ATTEMPTS wrong entries, when it expires
+// (QuickUnlockPreference) or when the db is closed. The full credentials are then required.
+//
+// The biometric unlock (macOS) uses the same way to keep the credentials of a full open or
+// unlock. This is done only when the user enables it for the db in the open/unlock dialog.
+// There the key is only the random key kept in the KeyChain. See db_lock.rs for why the
+// credentials are needed to unlock a locked db
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::Argon2;
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use onekeepass_core::db_service as kp_service;
+use onekeepass_core::error::{self, Result};
+
+use crate::app_state::AppState;
+use crate::{biometric, db_lock, key_secure};
+
+pub(crate) const MAX_PIN_ATTEMPTS: u8 = 3;
+// The invalid PIN messages in 'unlock_with_pin' are written for 3 attempts
+const _: () = assert!(MAX_PIN_ATTEMPTS == 3);
+const MIN_PIN_LENGTH: usize = 4;
+
+#[derive(Serialize, Deserialize)]
+struct Credentials {
+    password: Option<String>,
+    key_file_name: Option<String>,
+}
+
+// The serialized 'Credentials' encrypted with a key that is not kept here
+struct ProtectedCredentials {
+    nonce: Vec<u8>,
+    encrypted: Vec<u8>,
+}
+
+impl ProtectedCredentials {
+    fn new(
+        cipher: &Aes256Gcm,
+        password: Option<&str>,
+        key_file_name: Option<&str>,
+    ) -> Result<Self> {
+        let credentials = serde_json::to_vec(&Credentials {
+            password: password.map(|s| s.to_string()),
+            key_file_name: key_file_name.map(|s| s.to_string()),
+        })
+        .map_err(|e| error::Error::UnexpectedError(e.to_string()))?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let encrypted = cipher
+            .encrypt(&nonce, credentials.as_slice())
+            .map_err(|_| {
+                error::Error::UnexpectedError("Encrypting the credentials failed".into())
+            })?;
+        Ok(Self {
+            nonce: nonce.to_vec(),
+            encrypted,
+        })
+    }
+
+    // None when the key of the cipher is not the one used to encrypt
+    fn decrypt(&self, cipher: &Aes256Gcm) -> Option<Credentials> {
+        let data = cipher
+            .decrypt(Nonce::from_slice(&self.nonce), self.encrypted.as_slice())
+            .ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+}
+
+struct PinProtected {
+    salt: [u8; 16],
+    credentials: ProtectedCredentials,
+    attempts_left: u8,
+    expires_at: Instant,
+}
+
+fn pins() -> &'static Mutex<HashMap<String, PinProtected>> {
+    static PINS: OnceLock<Mutex<HashMap<String, PinProtected>>> = OnceLock::new();
+    PINS.get_or_init(Default::default)
+}
+
+fn biometric_protected() -> &'static Mutex<HashMap<String, ProtectedCredentials>> {
+    static BIOMETRIC_PROTECTED: OnceLock<Mutex<HashMap<String, ProtectedCredentials>>> =
+        OnceLock::new();
+    BIOMETRIC_PROTECTED.get_or_init(Default::default)
+}
+
+fn wrap_key_name(db_key: &str) -> String {
+    format!("quick-unlock-{}", db_key)
+}
+
+fn biometric_key_name(db_key: &str) -> String {
+    format!("biometric-unlock-{}", db_key)
+}
+
+fn random_key() -> Vec<u8> {
+    let mut key = vec![0u8; 32];
+    OsRng.fill_bytes(&mut key);
+    key
+}
+
+fn pin_expiry() -> Duration {
+    let minutes = AppState::state_instance()
+        .quick_unlock_preference()
+        .pin_expiry_minutes
+        .max(1);
+    Duration::from_secs(minutes as u64 * 60)
+}
+
+fn cipher(pin: &str, salt: &[u8], wrap_key: &[u8]) -> Result<Aes256Gcm> {
+    let mut pin_key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(pin.as_bytes(), salt, &mut pin_key)
+        .map_err(|e| error::Error::UnexpectedError(format!("PIN key derivation failed {}", e)))?;
+    let key = Sha256::new()
+        .chain_update(wrap_key)
+        .chain_update(pin_key)
+        .finalize();
+    Aes256Gcm::new_from_slice(&key).map_err(|e| error::Error::UnexpectedError(e.to_string()))
+}
+
+// The credentials are checked against the opened db before they are kept for a PIN or for the
+// biometric unlock. The core
+// takes only the password and the key file to open a db and so those are kept and not the
+// composite key derived from them
+fn verify_credentials(
+    db_key: &str,
+    password: Option<&str>,
+    key_file_name: Option<&str>,
+) -> Result<()> {
+    if db_lock::is_locked(db_key) || !kp_service::is_db_opened(db_key) {
+        return Err(error::Error::DataError(
+            "The database needs to be opened first",
+        ));
+    }
+    // Only compares the credentials with the ones of the opened db
+    kp_service::unlock_kdbx(db_key, password, key_file_name).map_err(|e| {
+        info!("Credentials to keep for {} are not valid: {}", db_key, e);
+        error::Error::DataError("The credentials do not match the opened database")
+    })?;
+    Ok(())
+}
+
+// Sets the PIN for an unlocked db with the credentials the db was opened or unlocked with.
+// Any earlier PIN of this db is replaced
+pub(crate) fn set_pin(
+    db_key: &str,
+    password: Option<&str>,
+    key_file_name: Option<&str>,
+    pin: &str,
+) -> Result<()> {
+    if pin.chars().count() < MIN_PIN_LENGTH {
+        return Err(error::Error::DataError(
+            "The PIN should have at least 4 characters",
+        ));
+    }
+
+    verify_credentials(db_key, password, key_file_name)?;
+
+    let wrap_key = random_key();
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let credentials =
+        ProtectedCredentials::new(&cipher(pin, &salt, &wrap_key)?, password, key_file_name)?;
+
+    key_secure::store_key(&wrap_key_name(db_key), wrap_key)?;
+    pins().lock().unwrap().insert(
+        db_key.to_string(),
+        PinProtected {
+            salt,
+            credentials,
+            attempts_left: MAX_PIN_ATTEMPTS,
+            expires_at: Instant::now() + pin_expiry(),
+        },
+    );
+    debug!("Quick unlock PIN is set for {}", db_key);
+    Ok(())
+}
+
+fn remove_locked(pins: &mut HashMap<String, PinProtected>, db_key: &str) {
+    if pins.remove(db_key).is_some() {
+        let _ = key_secure::delete_key(&wrap_key_name(db_key));
+        debug!("Quick unlock PIN is removed for {}", db_key);
+    }
+}
+
+// Called when the db is closed
+pub(crate) fn remove_for_db(db_key: &str) {
+    remove_locked(&mut pins().lock().unwrap(), db_key);
+    if biometric_protected()
+        .lock()
+        .unwrap()
+        .remove(db_key)
+        .is_some()
+    {
+        let _ = key_secure::delete_key(&biometric_key_name(db_key));
+    }
+}
+
+// Whether the db can be unlocked with a PIN now
+pub(crate) fn is_pin_set(db_key: &str) -> bool {
+    let mut pins = pins().lock().unwrap();
+    match pins.get(db_key) {
+        Some(p) if p.expires_at <= Instant::now() => {
+            remove_locked(&mut pins, db_key);
+            false
+        }
+        Some(_) => true,
+        None => false,
+    }
+}
+
+pub(crate) fn unlock_with_pin(db_key: &str, pin: &str) -> Result<kp_service::KdbxLoaded> {
+    let credentials = {
+        let mut pins = pins().lock().unwrap();
+        let Some(protected) = pins.get_mut(db_key) else {
+            return Err(error::Error::DataError(
+                "No quick unlock PIN is set for this database. Please use the database credentials",
+            ));
+        };
+        if protected.expires_at <= Instant::now() {
+            remove_locked(&mut pins, db_key);
+            return Err(error::Error::DataError(
+                "The quick unlock PIN has expired. Please use the database credentials",
+            ));
+        }
+        let Some(wrap_key) = key_secure::get_key(&wrap_key_name(db_key)) else {
+            remove_locked(&mut pins, db_key);
+            return Err(error::Error::DataError(
+                "The quick unlock key is not found. Please use the database credentials",
+            ));
+        };
+
+        match protected
+            .credentials
+            .decrypt(&cipher(pin, &protected.salt, &wrap_key)?)
+        {
+            Some(c) => {
+                protected.attempts_left = MAX_PIN_ATTEMPTS;
+                c
+            }
+            None => {
+                protected.attempts_left = protected.attempts_left.saturating_sub(1);
+                let attempts_left = protected.attempts_left;
+                info!("Invalid quick unlock PIN for {}", db_key);
+                if attempts_left == 0 {
+                    remove_locked(&mut pins, db_key);
+                    return Err(error::Error::DataError(
+                        "Too many invalid PIN entries. The quick unlock PIN is removed. Please use the database credentials",
+                    ));
+                }
+                return Err(error::Error::DataError(if attempts_left == 1 {
+                    "Invalid PIN. 1 attempt left"
+                } else {
+                    "Invalid PIN. 2 attempts left"
+                }));
+            }
+        }
+    };
+
+    db_lock::unlock(
+        db_key,
+        credentials.password.as_deref(),
+        credentials.key_file_name.as_deref(),
+    )
+}
+
+// Keeps the credentials of a full open or unlock for the later biometric unlock of this db.
+// Called only when the user enables it. Any earlier credentials of this db are replaced
+pub(crate) fn enable_biometric_unlock(
+    db_key: &str,
+    password: Option<&str>,
+    key_file_name: Option<&str>,
+) -> Result<()> {
+    if biometric::supported_biometric_type() == "None" {
+        return Err(error::Error::DataError(
+            "The biometric unlock is not available",
+        ));
+    }
+    verify_credentials(db_key, password, key_file_name)?;
+
+    let key = random_key();
+    let cipher = Aes256Gcm::new_from_slice(&key)
+        .map_err(|e| error::Error::UnexpectedError(e.to_string()))?;
+    let protected = ProtectedCredentials::new(&cipher, password, key_file_name)?;
+    key_secure::store_key(&biometric_key_name(db_key), key)?;
+    biometric_protected()
+        .lock()
+        .unwrap()
+        .insert(db_key.to_string(), protected);
+    debug!("Biometric unlock is enabled for {}", db_key);
+    Ok(())
+}
+
+// Unlocks a db locked by db_lock once the biometric authentication is successful.
+// An unlocked db is handled by onekeepass-core as before
+pub(crate) fn unlock_on_biometric(db_key: &str) -> Result<kp_service::KdbxLoaded> {
+    if !db_lock::is_locked(db_key) {
+        return kp_service::unlock_kdbx_on_biometric_authentication(db_key);
+    }
+
+    let credentials = {
+        let protected = biometric_protected().lock().unwrap();
+        protected
+            .get(db_key)
+            .zip(key_secure::get_key(&biometric_key_name(db_key)))
+            .and_then(|(p, key)| p.decrypt(&Aes256Gcm::new_from_slice(&key).ok()?))
+    }
+    .ok_or(error::Error::DataError(
+        "The database needs to be unlocked with its credentials",
+    ))?;
+
+    db_lock::unlock(
+        db_key,
+        credentials.password.as_deref(),
+        credentials.key_file_name.as_deref(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_pin_cipher() {
+        let wrap_key = [7u8; 32];
+        let salt = [3u8; 16];
+        let protected = ProtectedCredentials::new(
+            &cipher("1234", &salt, &wrap_key).unwrap(),
+            Some("secret"),
+            None,
+        )
+        .unwrap();
+
+        let credentials = protected
+            .decrypt(&cipher("1234", &salt, &wrap_key).unwrap())
+            .unwrap();
+        assert_eq!(credentials.password.as_deref(), Some("secret"));
+        assert_eq!(credentials.key_file_name, None);
+
+        // A wrong PIN or a different wrap key does not decrypt
+        assert!(protected
+            .decrypt(&cipher("1235", &salt, &wrap_key).unwrap())
+            .is_none());
+        assert!(protected
+            .decrypt(&cipher("1234", &salt, &[8u8; 32]).unwrap())
+            .is_none());
+    }
+}
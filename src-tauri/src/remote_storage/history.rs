@@ -0,0 +1,148 @@
+// Previous versions of a remote db kept on the server next to the db file as
+// "<file>.1" ... "<file>.N" (N is RemoteSyncPreference.history_copies). Before a save
+// overwrites the remote file, its current content is written to one of these copies.
+//
+// The remote storages can not rename or delete files, so the copies are not shifted
+// on each save. Instead the version being replaced goes to a slot not yet used or else
+// to the slot with the oldest copy. The copies are ordered by their modified time when
+// listed.
+
+use std::sync::Arc;
+
+use log::{debug, info, warn};
+use serde::Serialize;
+
+use onekeepass_core::error::{self, Result};
+
+use crate::app_state::AppState;
+
+use super::{write_to_remote, RemoteFile};
+
+pub(crate) const MAX_HISTORY_COPIES: u8 = 10;
+
+fn history_db_key(db_key: &str, slot: u8) -> String {
+    format!("{}.{}", db_key, slot)
+}
+
+fn history_copies_to_keep() -> u8 {
+    AppState::state_instance()
+        .remote_sync_preference()
+        .history_copies
+        .min(MAX_HISTORY_COPIES)
+}
+
+// Keeps the current content of the remote file as a history copy before it is
+// overwritten. Best-effort like the local backup: a failure is logged and the save
+// goes ahead
+pub(super) fn keep_history_copy(remote_file: &RemoteFile, db_key: &str) {
+    let copies = history_copies_to_keep();
+    if copies == 0 {
+        return;
+    }
+    match write_history_copy(remote_file, db_key, copies) {
+        Ok(Some(slot)) => debug!("Remote history copy {} of {} written", slot, db_key),
+        Ok(None) => {}
+        Err(e) => warn!(
+            "Writing the remote history copy of {} failed: {}",
+            db_key, e
+        ),
+    }
+}
+
+fn write_history_copy(remote_file: &RemoteFile, db_key: &str, copies: u8) -> Result<Option<u8>> {
+    // Nothing to keep for a new remote file
+    if !remote_file.exists() {
+        return Ok(None);
+    }
+    let (data, _) = remote_file.read()?;
+
+    let slot = next_slot(db_key, copies)?;
+    let copy = RemoteFile::from_db_key(&history_db_key(db_key, slot))?;
+    let data = Arc::new(data);
+    if copy.exists() {
+        copy.write(data)?;
+    } else {
+        copy.create(data)?;
+    }
+    Ok(Some(slot))
+}
+
+// The first unused slot or else the slot with the oldest copy
+fn next_slot(db_key: &str, copies: u8) -> Result<u8> {
+    let mut oldest: Option<(u8, i64)> = None;
+    for slot in 1..=copies {
+        let copy = RemoteFile::from_db_key(&history_db_key(db_key, slot))?;
+        if !copy.exists() {
+            return Ok(slot);
+        }
+        let modified = copy.modified_time().unwrap_or(i64::MIN);
+        if oldest.map_or(true, |(_, m)| modified < m) {
+            oldest = Some((slot, modified));
+        }
+    }
+    Ok(oldest.map(|(slot, _)| slot).unwrap_or(1))
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct RemoteHistoryCopy {
+    pub slot: u8,
+    pub file_name: String,
+    // Seconds since the epoch when reported by the server
+    pub modified: Option<i64>,
+}
+
+// Lists the history copies of a remote db, the latest first
+pub(crate) fn rs_list_history_copies(db_key: &str) -> Result<Vec<RemoteHistoryCopy>> {
+    let remote_file = RemoteFile::from_db_key(db_key)?;
+    remote_file.connect().map_err(|e| {
+        info!("Remote storage connection error: {}", e);
+        error::Error::NoRemoteStorageConnection
+    })?;
+
+    let mut list = vec![];
+    for slot in 1..=MAX_HISTORY_COPIES {
+        let copy = RemoteFile::from_db_key(&history_db_key(db_key, slot))?;
+        if !copy.exists() {
+            continue;
+        }
+        list.push(RemoteHistoryCopy {
+            slot,
+            file_name: copy.file_name().unwrap_or_default().to_string(),
+            modified: copy.modified_time(),
+        });
+    }
+    list.sort_by(|a, b| b.modified.cmp(&a.modified));
+    Ok(list)
+}
+
+// Puts a history copy back as the remote db file. The version it replaces is kept as a
+// history copy so that the restore can be undone. The opened db is not changed here and
+// the caller needs to close and open the db again to use the restored version. Returns the
+// remote mtime after the restore
+pub(crate) fn rs_restore_history_copy(db_key: &str, slot: u8) -> Result<Option<i64>> {
+    if slot == 0 || slot > MAX_HISTORY_COPIES {
+        return Err(error::Error::DataError("Invalid remote history copy"));
+    }
+
+    let remote_file = RemoteFile::from_db_key(db_key)?;
+    remote_file.connect().map_err(|e| {
+        info!("Remote storage connection error: {}", e);
+        error::Error::NoRemoteStorageConnection
+    })?;
+
+    let copy = RemoteFile::from_db_key(&history_db_key(db_key, slot))?;
+    if !copy.exists() {
+        return Err(error::Error::DataError(
+            "The remote history copy is not found",
+        ));
+    }
+    // Read before the current version is kept as a history copy as that may reuse this slot
+    let (data, _) = copy.read()?;
+
+    // Unlike on save, the restore does not go ahead without this copy
+    write_history_copy(&remote_file, db_key, history_copies_to_keep().max(slot))?;
+
+    let remote_mtime = write_to_remote(&remote_file, db_key, data)?;
+    info!("Remote history copy {} of {} restored", slot, db_key);
+    Ok(remote_mtime)
+}
@@ -1,27 +1,45 @@
 // Desktop orchestration over the shared remote-storage layer in
-// onekeepass-core. Thin compared to mobile: no on-disk backup, no offline
-// read-from-backup fallback, no history pruning, no iOS Autofill copy.
+// onekeepass-core. Thin compared to mobile: no iOS Autofill copy. Previous
+// versions of the remote file can be kept on the server (see history). S3
+// compatible storage is not in onekeepass-core and is handled in s3.rs;
+// RemoteFile below hides the difference from the db read/save functions.
+//
+// Offline mode: every successful read or write keeps a local copy of the
+// remote file (see offline_cache). When the server can not be connected, the
+// db is opened from that copy and saves are queued in it ("pending sync").
+// The queued changes are pushed by rs_sync_offline_changes once the server is
+// reachable again, merging any remote change made meanwhile.
 //
 // Conflict detection: when opening a remote db, the remote file's
 // modified-time is recorded in AppState keyed by db_key. On save, the
 // recorded value is compared to the current remote mtime; a mismatch
 // (when overwrite==false) returns DbFileContentChangeDetected and the UI
-// prompts the user to merge or force-overwrite.
+// prompts the user to merge or force-overwrite. For S3 the recorded value is
+// a token of the object's version id or ETag instead (see s3.rs).
 
 pub(crate) mod callback_service_provider;
+pub(crate) mod history;
+pub(crate) mod offline_cache;
+pub(crate) mod poller;
+pub(crate) mod s3;
+mod safe_write;
 
 // Cheap check for routing: true when db_key was minted by the remote-storage
-// open/create flow (prefixed "Sftp-" or "Webdav-"). The actual parse only
+// open/create flow (prefixed "Sftp-", "Webdav-" or "S3-"). The actual parse only
 // happens when we need to operate on the remote server. Callers use this to
-// keep local file paths off the SFTP/WebDAV code path and vice versa.
+// keep local file paths off the remote storage code path and vice versa.
 pub(crate) fn is_remote_db_key(db_key: &str) -> bool {
-    db_key.starts_with("Sftp-") || db_key.starts_with("Webdav-")
+    db_key.starts_with("Sftp-")
+        || db_key.starts_with("Webdav-")
+        || db_key.starts_with(s3::S3_DB_KEY_PREFIX)
 }
 
 use std::io::Cursor;
 use std::sync::Arc;
 
-use log::{debug, info};
+use log::{debug, error, info};
+use serde::Serialize;
+use tauri::{Emitter, Manager};
 use uuid::Uuid;
 
 use onekeepass_core::db_service::{
@@ -35,12 +53,127 @@ use onekeepass_core::remote_storage::storage_service::{
     RemoteStorageTypeConfig,
 };
 
+use crate::app_state::AppState;
+use crate::constants::event_names::REMOTE_SYNC_STATUS_EVENT;
+use crate::constants::window_labels::MAIN_WINDOW_LABEL;
+
+// Runs 'f' on a thread where blocking is allowed. The remote storage functions block
+// (the blocking_recv of onekeepass-core and the HTTP requests of WebDAV and S3) and that
+// panics on a tokio worker thread. Tauri commands use spawn_blocking instead. This is for
+// the sync callers that may themselves run in an async task (passkeys, Secret Service ...)
+pub(crate) fn run_blocking<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    if tokio::runtime::Handle::try_current().is_err() {
+        return f();
+    }
+    std::thread::scope(|s| {
+        s.spawn(f)
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    })
+}
+
+// Waits for the future of an HTTP request made by the remote storage functions
+fn block_on<F>(future: F) -> F::Output
+where
+    F: std::future::Future + Send,
+    F::Output: Send,
+{
+    run_blocking(|| tauri::async_runtime::block_on(future))
+}
+
+// The remote file of a db_key on any of the supported remote storages
+enum RemoteFile {
+    Core(RemoteStorageOperationType),
+    S3(s3::S3Object),
+}
+
+impl RemoteFile {
+    fn from_db_key(db_key: &str) -> Result<Self> {
+        if db_key.starts_with(s3::S3_DB_KEY_PREFIX) {
+            Ok(Self::S3(s3::S3Object::from_db_key(db_key)?))
+        } else {
+            Ok(Self::Core(rs_type_from_db_key(db_key)?))
+        }
+    }
+
+    fn file_name(&self) -> Option<&str> {
+        match self {
+            Self::Core(op) => op.file_name(),
+            Self::S3(obj) => Some(obj.file_name()),
+        }
+    }
+
+    fn connect(&self) -> Result<()> {
+        match self {
+            Self::Core(op) => op.connect_by_id().map(|_| ()),
+            Self::S3(obj) => obj.connect(),
+        }
+    }
+
+    // Returns the file content and its remote mtime
+    fn read(&self) -> Result<(Vec<u8>, Option<i64>)> {
+        match self {
+            Self::Core(op) => {
+                let r = op.read()?;
+                Ok((r.data, r.meta.modified.map(|t| t as i64)))
+            }
+            Self::S3(obj) => obj.read(),
+        }
+    }
+
+    // Returns the remote mtime after the write
+    fn write(&self, data: Arc<Vec<u8>>) -> Result<Option<i64>> {
+        match self {
+            Self::Core(op) => Ok(op.write_file(data)?.modified.map(|t| t as i64)),
+            Self::S3(obj) => obj.write(data),
+        }
+    }
+
+    fn create(&self, data: Arc<Vec<u8>>) -> Result<Option<i64>> {
+        match self {
+            Self::Core(op) => Ok(op.create_file(data)?.modified.map(|t| t as i64)),
+            Self::S3(obj) => obj.create(data),
+        }
+    }
+
+    fn exists(&self) -> bool {
+        match self {
+            Self::Core(op) => op.file_metadata().is_ok(),
+            Self::S3(obj) => obj.exists(),
+        }
+    }
+
+    // The current remote mtime. None when the server does not report one or the
+    // lookup failed
+    fn current_mtime(&self) -> Option<i64> {
+        match self {
+            Self::Core(op) => op
+                .file_metadata()
+                .ok()
+                .and_then(|m: RemoteFileMetadata| m.modified.map(|t| t as i64)),
+            Self::S3(obj) => obj.current_version().ok().flatten(),
+        }
+    }
+
+    // Seconds since the epoch of the last change of the file. Unlike 'current_mtime',
+    // this is the time even for S3
+    fn modified_time(&self) -> Option<i64> {
+        match self {
+            Self::Core(_) => self.current_mtime(),
+            Self::S3(obj) => obj.last_modified(),
+        }
+    }
+}
+
 // True when the remote connection referenced by db_key resolves to a
-// REMOTE_CONNECTION_SFTP / _WEBDAV entry in a currently-open kdbx db.
+// REMOTE_CONNECTION_SFTP / _WEBDAV (or "S3 Connection") entry in a currently-open kdbx db.
 // Ad-hoc form-based connections (no backing entry) return false. Used to gate
 // recent-files registration: only entry-backed remotes get listed, because
 // only those can be re-opened later by simply re-opening the parent kdbx.
 pub(crate) fn is_kdbx_entry_backed(db_key: &str) -> bool {
+    if let Some((connection_id, _)) = s3::parse_s3_db_key(db_key) {
+        return s3::is_entry_backed(&connection_id);
+    }
     let Ok(parsed) = parse_db_key(db_key) else {
         return false;
     };
@@ -67,6 +200,10 @@ pub(crate) fn is_kdbx_entry_backed(db_key: &str) -> bool {
 // needed them. A no-op for local db_keys. Removal is in-memory only and never
 // touches a persisted store.
 pub(crate) fn clear_cached_connection_config(db_key: &str) {
+    if let Some((connection_id, _)) = s3::parse_s3_db_key(db_key) {
+        s3::remove_config_in_memory(&connection_id);
+        return;
+    }
     let Ok(parsed) = parse_db_key(db_key) else {
         return;
     };
@@ -81,6 +218,30 @@ pub(crate) fn clear_cached_connection_config(db_key: &str) {
     ConnectionConfigs::remove_config_in_memory(remote_type, &connection_id);
 }
 
+#[derive(Clone, Serialize)]
+pub(crate) struct RemoteSyncStatusPayload {
+    pub db_key: String,
+    // True when the server could not be connected in the last open or save
+    pub offline: bool,
+    // True when there are saves queued in the offline copy
+    pub pending_sync: bool,
+}
+
+// Informs the UI about the offline state of a remote db after its open, save or sync.
+// The UI tells the user only when the state changes
+pub(crate) fn emit_sync_status(db_key: &str, offline: bool) {
+    let payload = RemoteSyncStatusPayload {
+        db_key: db_key.to_string(),
+        offline,
+        pending_sync: offline_cache::is_pending_sync(db_key),
+    };
+    if let Some(win) = AppState::global_app_handle().get_webview_window(MAIN_WINDOW_LABEL) {
+        if let Err(e) = win.emit(REMOTE_SYNC_STATUS_EVENT, payload) {
+            error!("Failed to emit REMOTE_SYNC_STATUS_EVENT: {}", e);
+        }
+    }
+}
+
 // All functions below are synchronous and internally call the
 // onekeepass-core macros that do `oneshot::Receiver::blocking_recv()`. That
 // primitive panics if called from a Tokio worker thread, so the Tauri
@@ -90,28 +251,50 @@ pub(crate) fn clear_cached_connection_config(db_key: &str) {
 // functions take or return raw mtime values; the AppState mtime cache is
 // updated by the Tauri command on either side of the spawn_blocking call.
 
+// Returns the loaded db, the remote mtime it is based on and true when it was loaded
+// from the offline copy. The offline copy is also used when it has queued saves even
+// though the server is reachable so that those changes are not lost; the caller then
+// pushes them with 'rs_sync_offline_changes'
 pub(crate) fn rs_read_kdbx(
     db_file_name: &str,
     password: Option<&str>,
     key_file_name: Option<&str>,
-) -> Result<(KdbxLoaded, Option<i64>)> {
-    let rs_operation_type = rs_type_from_db_key(db_file_name)?;
+) -> Result<(KdbxLoaded, Option<i64>, bool)> {
+    let remote_file = RemoteFile::from_db_key(db_file_name)?;
 
-    rs_operation_type.connect_by_id().map_err(|e| {
+    let file_name = remote_file.file_name().ok_or(error::Error::DataError(
+        "File name is not found in the rs operation type formed from the db key parsing",
+    ))?;
+
+    let connected = remote_file.connect().map_err(|e| {
         info!("Remote storage connection error: {}", e);
         error::Error::NoRemoteStorageConnection
-    })?;
+    });
+
+    if connected.is_err() || offline_cache::is_pending_sync(db_file_name) {
+        if let Some((data, meta)) = offline_cache::read_cached(db_file_name) {
+            info!(
+                "Opening the offline copy of {} (pending sync: {})",
+                db_file_name, meta.pending_sync
+            );
+            let mut reader = Cursor::new(&data);
+            let kdbx_loaded = db_service::read_kdbx(
+                &mut reader,
+                db_file_name,
+                password,
+                key_file_name,
+                Some(file_name),
+            )?;
+            return Ok((kdbx_loaded, meta.base_remote_mtime, connected.is_err()));
+        }
+    }
+    connected?;
 
     debug!("Remote server connected; reading file");
 
-    let r = rs_operation_type.read()?;
-    let remote_mtime = r.meta.modified.map(|t| t as i64);
-
-    let file_name = rs_operation_type.file_name().ok_or(error::Error::DataError(
-        "File name is not found in the rs operation type formed from the db key parsing",
-    ))?;
+    let (data, remote_mtime) = remote_file.read()?;
 
-    let mut reader = Cursor::new(&r.data);
+    let mut reader = Cursor::new(&data);
     let kdbx_loaded = db_service::read_kdbx(
         &mut reader,
         db_file_name,
@@ -120,37 +303,138 @@ pub(crate) fn rs_read_kdbx(
         Some(file_name),
     )?;
 
-    Ok((kdbx_loaded, remote_mtime))
+    // Cached only after the credentials are verified by the successful read above
+    offline_cache::store_synced(db_file_name, &data, remote_mtime);
+
+    Ok((kdbx_loaded, remote_mtime, false))
 }
 
+// Returns the saved db, the remote mtime after the save and true when the save was
+// queued in the offline copy as the server could not be connected. A queued save
+// returns the recorded mtime unchanged
 pub(crate) fn rs_save_kdbx(
     db_key: &str,
     overwrite: bool,
     recorded_mtime: Option<i64>,
     backup_file_name: Option<&str>,
-) -> Result<(KdbxSaved, Option<i64>)> {
-    let rs_operation_type = rs_type_from_db_key(db_key)?;
+) -> Result<(KdbxSaved, Option<i64>, bool)> {
+    let remote_file = RemoteFile::from_db_key(db_key)?;
 
-    rs_operation_type.connect_by_id().map_err(|e| {
+    if let Err(e) = remote_file.connect() {
         info!("Remote storage connection error: {}", e);
-        error::Error::NoRemoteStorageConnection
-    })?;
+        let (kdbx_saved, bytes) = serialize_kdbx(db_key)?;
+        write_local_backup(backup_file_name, &bytes);
+        offline_cache::store_pending(db_key, &bytes, recorded_mtime).map_err(|e| {
+            info!("Queuing the save of {} failed: {}", db_key, e);
+            error::Error::NoRemoteStorageConnection
+        })?;
+        info!("Server not reachable; the save of {} is queued", db_key);
+        return Ok((kdbx_saved, recorded_mtime, true));
+    }
 
-    if !overwrite && is_remote_file_modified(&rs_operation_type, recorded_mtime)? {
+    if !overwrite && is_remote_file_modified(&remote_file, recorded_mtime)? {
         return Err(error::Error::DbFileContentChangeDetected);
     }
 
+    let (kdbx_saved, bytes) = serialize_kdbx(db_key)?;
+    write_local_backup(backup_file_name, &bytes);
+
+    history::keep_history_copy(&remote_file, db_key);
+    let remote_mtime = write_to_remote(&remote_file, db_key, bytes)?;
+
+    Ok((kdbx_saved, remote_mtime, false))
+}
+
+fn serialize_kdbx(db_key: &str) -> Result<(KdbxSaved, Vec<u8>)> {
     let mut mem = Cursor::new(Vec::<u8>::new());
     let kdbx_saved = db_service::save_kdbx_to_writer(&mut mem, db_key)?;
+    Ok((kdbx_saved, mem.into_inner()))
+}
 
-    let bytes = mem.into_inner();
-    write_local_backup(backup_file_name, &bytes);
-
+// Pushes the bytes to the server (see safe_write) and refreshes the offline copy with them
+fn write_to_remote(remote_file: &RemoteFile, db_key: &str, bytes: Vec<u8>) -> Result<Option<i64>> {
     let data = Arc::new(bytes);
-    let meta = rs_operation_type.write_file(data)?;
-    let remote_mtime = meta.modified.map(|t| t as i64);
+    let remote_mtime = safe_write::write_verified(remote_file, db_key, data.clone())?;
+
+    offline_cache::store_synced(db_key, &data, remote_mtime);
+
+    Ok(remote_mtime)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum OfflineSyncStatus {
+    // There are no queued saves for this db
+    NothingPending,
+    // The server is still not reachable and the saves stay queued
+    StillOffline,
+    Synced,
+    // The remote file was changed meanwhile and it was merged before pushing
+    MergedAndSynced,
+}
+
+#[derive(Serialize)]
+pub(crate) struct OfflineSyncResult {
+    pub status: OfflineSyncStatus,
+    pub merge_result: Option<MergeResult>,
+}
+
+// Pushes the saves queued while offline. When the remote file changed since the offline
+// copy was taken, the remote version is first merged into the in-memory db (as
+// rs_merge_with_remote does) and the merged db is pushed. The caller should call this only
+// when the in-memory db has no unsaved changes as the whole in-memory db is pushed.
+// Returns the result and the remote mtime to record
+pub(crate) fn rs_sync_offline_changes(
+    db_key: &str,
+    recorded_mtime: Option<i64>,
+) -> Result<(OfflineSyncResult, Option<i64>)> {
+    let not_pushed = |status| {
+        Ok((
+            OfflineSyncResult {
+                status,
+                merge_result: None,
+            },
+            recorded_mtime,
+        ))
+    };
+
+    if !offline_cache::is_pending_sync(db_key) {
+        return not_pushed(OfflineSyncStatus::NothingPending);
+    }
+
+    let remote_file = RemoteFile::from_db_key(db_key)?;
+
+    if let Err(e) = remote_file.connect() {
+        debug!("Remote storage still not reachable for {}: {}", db_key, e);
+        return not_pushed(OfflineSyncStatus::StillOffline);
+    }
 
-    Ok((kdbx_saved, remote_mtime))
+    let merge_result = if is_remote_file_modified(&remote_file, recorded_mtime)? {
+        let (data, _) = remote_file.read()?;
+        let mut reader = Cursor::new(&data);
+        Some(db_service::merge_kdbx_with_reader(db_key, &mut reader)?)
+    } else {
+        None
+    };
+
+    let (_kdbx_saved, bytes) = serialize_kdbx(db_key)?;
+    history::keep_history_copy(&remote_file, db_key);
+    let remote_mtime = write_to_remote(&remote_file, db_key, bytes)?;
+
+    let status = if merge_result.is_some() {
+        OfflineSyncStatus::MergedAndSynced
+    } else {
+        OfflineSyncStatus::Synced
+    };
+    info!("Queued saves of {} pushed: {:?}", db_key, status);
+
+    Ok((
+        OfflineSyncResult {
+            status,
+            merge_result,
+        },
+        remote_mtime,
+    ))
 }
 
 pub(crate) fn rs_create_kdbx(
@@ -158,9 +442,9 @@ pub(crate) fn rs_create_kdbx(
     backup_file_name: Option<&str>,
 ) -> Result<(KdbxLoaded, String, Option<i64>)> {
     let db_key = new_db.database_file_name.clone();
-    let rs_operation_type = rs_type_from_db_key(&db_key)?;
+    let remote_file = RemoteFile::from_db_key(&db_key)?;
 
-    if rs_operation_type.file_metadata().is_ok() {
+    if remote_file.exists() {
         return Err(error::Error::DataError(
             "A file with this name already exists at this location. Choose a different name.",
         ));
@@ -173,8 +457,9 @@ pub(crate) fn rs_create_kdbx(
     write_local_backup(backup_file_name, &bytes);
 
     let data = Arc::new(bytes);
-    let meta = rs_operation_type.create_file(data)?;
-    let remote_mtime = meta.modified.map(|t| t as i64);
+    let remote_mtime = remote_file.create(data.clone())?;
+
+    offline_cache::store_synced(&db_key, &data, remote_mtime);
 
     Ok((kdbx_loaded, db_key, remote_mtime))
 }
@@ -183,7 +468,8 @@ pub(crate) fn rs_create_kdbx(
 // preferences-resolved backup file before pushing them to the remote.
 // Best-effort — a backup failure is logged but does not abort the save (the
 // user's primary target is the remote file). The parent directory is
-// created if it does not already exist.
+// created if it does not already exist. The written backup is verified by the
+// caller after the save succeeds (see backup_service::backup_written).
 fn write_local_backup(backup_file_name: Option<&str>, bytes: &[u8]) {
     let Some(backup_path) = backup_file_name else {
         return;
@@ -216,20 +502,12 @@ fn write_local_backup(backup_file_name: Option<&str>, bytes: &[u8]) {
 // (some servers don't report mtime, or this is the first save after open
 // with no prior record), treats it as unmodified to avoid spurious
 // conflicts blocking the user.
-fn is_remote_file_modified(
-    rs_operation_type: &RemoteStorageOperationType,
-    recorded: Option<i64>,
-) -> Result<bool> {
+fn is_remote_file_modified(remote_file: &RemoteFile, recorded: Option<i64>) -> Result<bool> {
     let Some(recorded) = recorded else {
         return Ok(false);
     };
 
-    let current: Option<i64> = rs_operation_type
-        .file_metadata()
-        .ok()
-        .and_then(|m: RemoteFileMetadata| m.modified.map(|t| t as i64));
-
-    let Some(current) = current else {
+    let Some(current) = remote_file.current_mtime() else {
         return Ok(false);
     };
 
@@ -241,19 +519,14 @@ fn is_remote_file_modified(
 // whether the remote file diverged from what we last saw. The recorded
 // value lives in AppState; the Tauri command does the compare.
 pub(crate) fn rs_current_remote_mtime(db_key: &str) -> Result<Option<i64>> {
-    let rs_operation_type = rs_type_from_db_key(db_key)?;
+    let remote_file = RemoteFile::from_db_key(db_key)?;
 
-    rs_operation_type.connect_by_id().map_err(|e| {
+    remote_file.connect().map_err(|e| {
         info!("Remote storage connection error: {}", e);
         error::Error::NoRemoteStorageConnection
     })?;
 
-    let current = rs_operation_type
-        .file_metadata()
-        .ok()
-        .and_then(|m: RemoteFileMetadata| m.modified.map(|t| t as i64));
-
-    Ok(current)
+    Ok(remote_file.current_mtime())
 }
 
 // Downloads the current remote bytes and merges them into the in-memory
@@ -261,19 +534,21 @@ pub(crate) fn rs_current_remote_mtime(db_key: &str) -> Result<Option<i64>> {
 // remote file's mtime at fetch time (so the caller can refresh
 // AppState.remote_mtime and avoid an immediate re-prompt).
 pub(crate) fn rs_merge_with_remote(db_key: &str) -> Result<(MergeResult, Option<i64>)> {
-    let rs_operation_type = rs_type_from_db_key(db_key)?;
+    let remote_file = RemoteFile::from_db_key(db_key)?;
 
-    rs_operation_type.connect_by_id().map_err(|e| {
+    remote_file.connect().map_err(|e| {
         info!("Remote storage connection error: {}", e);
         error::Error::NoRemoteStorageConnection
     })?;
 
-    let r = rs_operation_type.read()?;
-    let remote_mtime = r.meta.modified.map(|t| t as i64);
+    let (data, remote_mtime) = remote_file.read()?;
 
-    let mut reader = Cursor::new(&r.data);
+    let mut reader = Cursor::new(&data);
     let merge_result = db_service::merge_kdbx_with_reader(db_key, &mut reader)?;
 
+    // The merged db is saved by the user afterwards. Till then any queued saves stay
+    // queued and the offline copy is left as it is
+
     Ok((merge_result, remote_mtime))
 }
 
@@ -0,0 +1,157 @@
+// Local copies of remote (SFTP/WebDAV) databases used when the server can not
+// be reached. The cached file holds the kdbx bytes exactly as last read from or
+// written to the server, so it is encrypted the same way as the remote file. A
+// small json sidecar records the remote mtime the copy is based on and whether
+// the copy has saves that are not yet pushed to the server ("pending sync").
+//
+// Files are named after a hash of the db_key because the db_key contains the
+// connection id and the remote path (slashes etc).
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::app_paths;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct CacheMeta {
+    pub db_key: String,
+    // The mtime of the server version this copy is based on. Used as the recorded
+    // mtime for the conflict check when the pending changes are pushed
+    pub base_remote_mtime: Option<i64>,
+    // True when one or more saves were queued while the server was not reachable
+    pub pending_sync: bool,
+    // Seconds since the epoch of the last successful read from or write to the server
+    pub synced_at: Option<i64>,
+}
+
+fn cache_file_stem(db_key: &str) -> String {
+    hex::encode(&Sha256::digest(db_key.as_bytes())[..16])
+}
+
+fn cache_paths_in(dir: &Path, db_key: &str) -> (PathBuf, PathBuf) {
+    let stem = cache_file_stem(db_key);
+    (
+        dir.join(format!("{}.kdbx", &stem)),
+        dir.join(format!("{}.json", &stem)),
+    )
+}
+
+// Writes to a temp file first so that an interrupted write never leaves a partial
+// copy in place of the last good one
+fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+fn read_meta_in(dir: &Path, db_key: &str) -> Option<CacheMeta> {
+    let (_, meta_path) = cache_paths_in(dir, db_key);
+    let data = fs::read(meta_path).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+fn store_in(dir: &Path, db_key: &str, data: &[u8], meta: &CacheMeta) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let (kdbx_path, meta_path) = cache_paths_in(dir, db_key);
+    let meta_json = serde_json::to_vec(meta).map_err(io::Error::other)?;
+
+    write_atomic(&kdbx_path, data)?;
+    write_atomic(&meta_path, &meta_json)
+}
+
+fn read_in(dir: &Path, db_key: &str) -> Option<(Vec<u8>, CacheMeta)> {
+    let meta = read_meta_in(dir, db_key)?;
+    // Guards against a hash collision or a stale sidecar
+    if meta.db_key != db_key {
+        return None;
+    }
+    let (kdbx_path, _) = cache_paths_in(dir, db_key);
+    let data = fs::read(kdbx_path).ok()?;
+    Some((data, meta))
+}
+
+// Records the bytes just read from or written to the server. Clears any pending sync
+pub(crate) fn store_synced(db_key: &str, data: &[u8], remote_mtime: Option<i64>) {
+    let meta = CacheMeta {
+        db_key: db_key.to_string(),
+        base_remote_mtime: remote_mtime,
+        pending_sync: false,
+        synced_at: Some(chrono::Utc::now().timestamp()),
+    };
+    match store_in(&app_paths::app_remote_cache_dir(), db_key, data, &meta) {
+        Ok(()) => debug!("Offline copy of {} updated", db_key),
+        Err(e) => error!("Writing the offline copy of {} failed: {}", db_key, e),
+    }
+}
+
+// Queues a save made while the server is not reachable. The recorded mtime (the server
+// version the in-memory db is based on) is kept so that a remote change made meanwhile
+// is detected on sync
+pub(crate) fn store_pending(
+    db_key: &str,
+    data: &[u8],
+    recorded_mtime: Option<i64>,
+) -> io::Result<()> {
+    let meta = CacheMeta {
+        db_key: db_key.to_string(),
+        base_remote_mtime: recorded_mtime,
+        pending_sync: true,
+        synced_at: cache_meta(db_key).and_then(|m| m.synced_at),
+    };
+    store_in(&app_paths::app_remote_cache_dir(), db_key, data, &meta)
+}
+
+pub(crate) fn read_cached(db_key: &str) -> Option<(Vec<u8>, CacheMeta)> {
+    read_in(&app_paths::app_remote_cache_dir(), db_key)
+}
+
+pub(crate) fn cache_meta(db_key: &str) -> Option<CacheMeta> {
+    read_meta_in(&app_paths::app_remote_cache_dir(), db_key).filter(|m| m.db_key == db_key)
+}
+
+pub(crate) fn is_pending_sync(db_key: &str) -> bool {
+    cache_meta(db_key).is_some_and(|m| m.pending_sync)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_pending_copy_keeps_base_mtime() {
+        let dir = std::env::temp_dir().join(format!("okp-offline-cache-{}", std::process::id()));
+        let db_key = "Sftp-1234-/home/user/dbs/MyDb.kdbx";
+
+        let synced = CacheMeta {
+            db_key: db_key.to_string(),
+            base_remote_mtime: Some(100),
+            pending_sync: false,
+            synced_at: Some(1),
+        };
+        store_in(&dir, db_key, b"synced", &synced).unwrap();
+
+        let (data, meta) = read_in(&dir, db_key).unwrap();
+        assert_eq!(data, b"synced");
+        assert!(!meta.pending_sync);
+
+        let pending = CacheMeta {
+            pending_sync: true,
+            ..meta
+        };
+        store_in(&dir, db_key, b"queued", &pending).unwrap();
+
+        let (data, meta) = read_in(&dir, db_key).unwrap();
+        assert_eq!(data, b"queued");
+        assert!(meta.pending_sync);
+        assert_eq!(meta.base_remote_mtime, Some(100));
+
+        assert!(read_in(&dir, "Webdav-5678-/MyDb.kdbx").is_none());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,147 @@
+// Background checks of the opened remote (SFTP/WebDAV) dbs. One task per db polls
+// the remote file's mtime and emits the same DB_FILE_CHANGED_EVENT the local file
+// watcher emits so that the UI handles local and remote changes the same way.
+//
+// A failed check backs off (doubling the interval up to 16 times) and the UI is
+// told when the server becomes unreachable or reachable again through
+// REMOTE_SYNC_STATUS_EVENT. The UI pushes any saves queued while offline on the
+// latter (see rs_sync_offline_changes).
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::{debug, error, info};
+use tauri::async_runtime::JoinHandle;
+
+use crate::app_preference::ExternalChangePolicy;
+use crate::app_state::AppState;
+use crate::constants::event_names::DB_FILE_CHANGED_EVENT;
+use crate::db_file_watcher::{self, DbFileChangedPayload};
+
+use super::{emit_sync_status, is_remote_db_key, offline_cache, rs_current_remote_mtime};
+
+const MAX_BACKOFF_SHIFT: u32 = 4;
+
+// Some servers report mtime in seconds only and a check is a full connect. So the
+// interval set in the preference is not allowed to go below this
+const MIN_POLL_INTERVAL_SECS: u32 = 15;
+
+// How often the preference is checked again while the background checks are turned off
+const DISABLED_RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+pub(crate) struct RemotePollerState {
+    pollers: Mutex<HashMap<String, JoinHandle<()>>>,
+}
+
+impl RemotePollerState {
+    pub(crate) fn new() -> Self {
+        Self {
+            pollers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Starts the background checks for a remote db. A no-op for local db keys and
+    // when the checks are already running for the db
+    pub(crate) fn start_polling(&self, db_key: &str) {
+        if !is_remote_db_key(db_key) {
+            return;
+        }
+        let mut pollers = self.pollers.lock().unwrap();
+        if pollers.contains_key(db_key) {
+            return;
+        }
+        let handle = tauri::async_runtime::spawn(poll_remote_db(db_key.to_string()));
+        pollers.insert(db_key.to_string(), handle);
+        info!("Started background checks for {}", db_key);
+    }
+
+    // Called when the db is closed
+    pub(crate) fn stop_polling(&self, db_key: &str) {
+        if let Some(handle) = self.pollers.lock().unwrap().remove(db_key) {
+            handle.abort();
+            info!("Stopped background checks for {}", db_key);
+        }
+    }
+}
+
+// The policy sent to the UI with a remote change. Remote changes are merged without
+// asking as per the remote sync preference; otherwise the db's own policy is used
+pub(crate) fn remote_change_policy(db_key: &str) -> ExternalChangePolicy {
+    let app_state = AppState::state_instance();
+    if app_state.remote_sync_preference().auto_merge {
+        ExternalChangePolicy::AutoMerge
+    } else {
+        app_state.external_change_policy(db_key)
+    }
+}
+
+async fn poll_remote_db(db_key: String) {
+    let mut failures: u32 = 0;
+    let mut offline = false;
+    // The remote version the UI was last told about. The same change is reported
+    // only once even if the user chooses not to merge it now
+    let mut notified_mtime: Option<i64> = None;
+
+    loop {
+        let interval_secs = AppState::state_instance()
+            .remote_sync_preference()
+            .poll_interval_secs;
+        if interval_secs == 0 {
+            tokio::time::sleep(DISABLED_RECHECK_INTERVAL).await;
+            continue;
+        }
+
+        let interval = Duration::from_secs(interval_secs.max(MIN_POLL_INTERVAL_SECS) as u64);
+        tokio::time::sleep(interval * (1 << failures.min(MAX_BACKOFF_SHIFT))).await;
+
+        // The remote storage calls block and can not run on an async worker thread
+        let key = db_key.clone();
+        let current = match tokio::task::spawn_blocking(move || rs_current_remote_mtime(&key)).await
+        {
+            Ok(Ok(current)) => current,
+            Ok(Err(e)) => {
+                debug!("Background check of {} failed: {}", &db_key, e);
+                failures = failures.saturating_add(1);
+                if !offline {
+                    offline = true;
+                    emit_sync_status(&db_key, true);
+                }
+                continue;
+            }
+            Err(e) => {
+                error!("Background check worker join error: {}", e);
+                continue;
+            }
+        };
+
+        failures = 0;
+        if offline {
+            offline = false;
+            emit_sync_status(&db_key, false);
+        }
+
+        // The queued saves are pushed first and that push merges any remote change
+        if offline_cache::is_pending_sync(&db_key) {
+            continue;
+        }
+
+        let recorded = AppState::state_instance().remote_mtime(&db_key);
+        let (Some(current), Some(recorded)) = (current, recorded) else {
+            continue;
+        };
+        if current == recorded || notified_mtime == Some(current) {
+            continue;
+        }
+        notified_mtime = Some(current);
+
+        info!("Remote change detected for {}", &db_key);
+        db_file_watcher::emit_to_main_window(
+            DB_FILE_CHANGED_EVENT,
+            DbFileChangedPayload {
+                db_key: db_key.clone(),
+                policy: remote_change_policy(&db_key),
+            },
+        );
+    }
+}
@@ -0,0 +1,435 @@
+// Remote saves that do not leave a truncated db on the server when the connection drops
+// halfway. The new content is first uploaded to "<file>.okp-upload" and read back to
+// verify its size and checksum. Only then is the db file replaced:
+//
+//   WebDAV - the verified upload is moved over the db file (MOVE with Overwrite: T)
+//   SFTP   - onekeepass-core has no rename for SFTP and a session of its own is opened
+//            for the renames. The upload is renamed over the db file with the
+//            "posix-rename@openssh.com" extension when the server offers it. Otherwise, as
+//            a plain SFTP rename fails when the target exists, the db file is first renamed
+//            to "<file>.okp-previous", the upload is renamed to the db file and then the
+//            previous file is removed. When the second rename fails, the previous file is
+//            renamed back
+//   S3     - a PUT replaces the object only when the whole body is received and its
+//            checksum (x-amz-content-sha256) matches. So it is written directly
+//
+// A failed verification of the upload leaves the db file as it was.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use log::{debug, info};
+use reqwest::{Method, Url};
+use russh::client;
+use russh_sftp::client::error::Error as SftpError;
+use russh_sftp::client::RawSftpSession;
+use russh_sftp::protocol::{Packet, StatusCode};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use onekeepass_core::error::{self, Result};
+use onekeepass_core::remote_storage::storage_service::{
+    parse_db_key, ConnectionConfigs, RemoteStorageType,
+};
+
+use super::RemoteFile;
+
+const UPLOAD_SUFFIX: &str = ".okp-upload";
+const PREVIOUS_SUFFIX: &str = ".okp-previous";
+const POSIX_RENAME_EXTENSION: &str = "posix-rename@openssh.com";
+
+fn upload_db_key(db_key: &str) -> String {
+    format!("{}{}", db_key, UPLOAD_SUFFIX)
+}
+
+// Writes the db bytes to the remote file and returns the remote mtime after the write
+pub(super) fn write_verified(
+    remote_file: &RemoteFile,
+    db_key: &str,
+    data: Arc<Vec<u8>>,
+) -> Result<Option<i64>> {
+    if matches!(remote_file, RemoteFile::S3(_)) {
+        return remote_file.write(data);
+    }
+
+    let upload_key = upload_db_key(db_key);
+    let upload = RemoteFile::from_db_key(&upload_key)?;
+    if upload.exists() {
+        upload.write(data.clone())?;
+    } else {
+        upload.create(data.clone())?;
+    }
+    verify(&upload, &data).map_err(|e| {
+        info!("Verification of the upload for {} failed: {}", db_key, e);
+        error::Error::UnexpectedError(format!(
+            "The uploaded database could not be verified ({}). The remote database is not changed",
+            e
+        ))
+    })?;
+    debug!("Upload for {} verified", db_key);
+
+    if db_key.starts_with("Webdav-") {
+        webdav_move(&upload_key, db_key)?;
+    } else {
+        sftp_replace(&upload_key, db_key)?;
+    }
+    Ok(remote_file.current_mtime())
+}
+
+// Reads the remote file back and compares it with the bytes written
+fn verify(remote_file: &RemoteFile, expected: &[u8]) -> std::result::Result<(), String> {
+    let (data, _) = remote_file
+        .read()
+        .map_err(|e| format!("read back failed: {}", e))?;
+    check_same_content(expected, &data)
+}
+
+fn check_same_content(expected: &[u8], actual: &[u8]) -> std::result::Result<(), String> {
+    if actual.len() != expected.len() {
+        return Err(format!(
+            "size {} instead of {} bytes",
+            actual.len(),
+            expected.len()
+        ));
+    }
+    if Sha256::digest(actual) != Sha256::digest(expected) {
+        return Err("checksum mismatch".to_string());
+    }
+    Ok(())
+}
+
+// The remote path part of "<type>-<connection id>-<path>"
+fn db_key_path(db_key: &str) -> Option<&str> {
+    let (_, rest) = db_key.split_once('-')?;
+    // A hyphenated uuid is 36 chars and is followed by '-' and the path
+    rest.get(36..)?.strip_prefix('-')
+}
+
+fn webdav_url(root_url: &str, path: &str) -> Result<Url> {
+    let mut url =
+        Url::parse(root_url).map_err(|_| error::Error::DataError("Invalid WebDAV root url"))?;
+    url.path_segments_mut()
+        .map_err(|_| error::Error::DataError("Invalid WebDAV root url"))?
+        .pop_if_empty()
+        .extend(path.split('/').filter(|s| !s.is_empty()));
+    Ok(url)
+}
+
+// The connection config of the db key as json. Only a few fields are needed here. They
+// are read through serde so that this does not depend on how the config enum wraps
+// WebdavConnectionConfig or SftpConnectionConfig
+fn connection_config(db_key: &str, rs_type: RemoteStorageType) -> Result<serde_json::Value> {
+    let parsed = parse_db_key(db_key)?;
+    let connection_id = Uuid::parse_str(parsed.connection_id)
+        .map_err(|_| error::Error::DataError("Invalid connection_id (uuid)"))?;
+    let config = ConnectionConfigs::find_remote_storage_config(&connection_id, rs_type)
+        .ok_or(error::Error::NoRemoteStorageConnection)?;
+    let config =
+        serde_json::to_value(&config).map_err(|e| error::Error::UnexpectedError(e.to_string()))?;
+    // A config wrapped in an enum variant is serialized as {"<Variant>": {..}}
+    Ok(match config.as_object() {
+        Some(m) if m.len() == 1 && m.values().all(|v| v.is_object()) => {
+            m.values().next().cloned().unwrap_or_default()
+        }
+        _ => config,
+    })
+}
+
+// Moves the 'from' file over the 'to' file on the WebDAV server of the db keys
+fn webdav_move(from_db_key: &str, to_db_key: &str) -> Result<()> {
+    let config = connection_config(to_db_key, RemoteStorageType::Webdav)?;
+    let field = |name: &str| config.get(name).and_then(|v| v.as_str());
+
+    let root_url = field("root_url").ok_or(error::Error::DataError(
+        "The WebDAV root url is not found in the connection config",
+    ))?;
+    let (Some(from_path), Some(to_path)) = (db_key_path(from_db_key), db_key_path(to_db_key))
+    else {
+        return Err(error::Error::DataError("Invalid WebDAV remote db key"));
+    };
+    let from = webdav_url(root_url, from_path)?;
+    let to = webdav_url(root_url, to_path)?;
+
+    let client = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(60))
+        .danger_accept_invalid_certs(
+            config
+                .get("allow_untrusted_cert")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+        )
+        .build()
+        .map_err(|e| error::Error::UnexpectedError(e.to_string()))?;
+
+    let method = Method::from_bytes(b"MOVE").expect("MOVE is a valid method");
+    let mut request = client
+        .request(method, from)
+        .header("Destination", to.as_str())
+        .header("Overwrite", "T");
+    if let Some(user_name) = field("user_name") {
+        request = request.basic_auth(user_name, field("password"));
+    }
+
+    let status = super::block_on(async move { request.send().await })
+        .map_err(|e| {
+            info!("WebDAV MOVE failed: {}", e);
+            error::Error::NoRemoteStorageConnection
+        })?
+        .status();
+
+    if !status.is_success() {
+        return Err(error::Error::UnexpectedError(format!(
+            "Replacing the remote database with the verified upload failed with status {}",
+            status
+        )));
+    }
+    debug!("WebDAV upload moved over {}", to_path);
+    Ok(())
+}
+
+// The server keys of the hosts not in known_hosts as first seen by the rename sessions of
+// this app run, keyed by "host:port"
+fn accepted_host_keys() -> &'static Mutex<HashMap<String, russh::keys::PublicKey>> {
+    static ACCEPTED_HOST_KEYS: OnceLock<Mutex<HashMap<String, russh::keys::PublicKey>>> =
+        OnceLock::new();
+    ACCEPTED_HOST_KEYS.get_or_init(Default::default)
+}
+
+struct SftpClient {
+    host: String,
+    port: u16,
+}
+
+impl client::Handler for SftpClient {
+    type Error = russh::Error;
+
+    // The server key must match the one in the user's known_hosts. For a host not listed
+    // there, it must match the key seen by the earlier rename sessions to this host
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &russh::keys::PublicKey,
+    ) -> std::result::Result<bool, Self::Error> {
+        match russh::keys::check_known_hosts(&self.host, self.port, server_public_key) {
+            Ok(true) => return Ok(true),
+            Ok(false) => {}
+            Err(russh::keys::Error::KeyChanged { line }) => {
+                log::error!(
+                    "The SFTP server key of {} differs from the one in known_hosts (line {})",
+                    self.host,
+                    line
+                );
+                return Ok(false);
+            }
+            Err(e) => debug!("known_hosts is not checked: {}", e),
+        }
+
+        let mut accepted = accepted_host_keys().lock().unwrap();
+        let accepted_key = accepted
+            .entry(format!("{}:{}", self.host, self.port))
+            .or_insert_with(|| server_public_key.clone());
+        let same_key = accepted_key.key_data() == server_public_key.key_data();
+        if !same_key {
+            log::error!(
+                "The SFTP server key of {} differs from the one accepted earlier",
+                self.host
+            );
+        }
+        Ok(same_key)
+    }
+}
+
+fn sftp_error(e: impl std::fmt::Display) -> error::Error {
+    info!("SFTP rename session error: {}", e);
+    error::Error::NoRemoteStorageConnection
+}
+
+// Opens an SFTP session with the connection config of the db key. Also returns whether the
+// server offers the posix rename
+async fn sftp_session(config: &serde_json::Value) -> Result<(RawSftpSession, bool)> {
+    let field = |name: &str| config.get(name).and_then(|v| v.as_str());
+    let host = field("host").ok_or(error::Error::DataError(
+        "The SFTP host is not found in the connection config",
+    ))?;
+    let port = config.get("port").and_then(|v| v.as_u64()).unwrap_or(22) as u16;
+    let user_name = field("user_name").unwrap_or_default();
+
+    let ssh_config = Arc::new(client::Config {
+        inactivity_timeout: Some(Duration::from_secs(60)),
+        ..Default::default()
+    });
+    let handler = SftpClient {
+        host: host.to_string(),
+        port,
+    };
+    let mut session = client::connect(ssh_config, (host, port), handler)
+        .await
+        .map_err(|e| match e {
+            russh::Error::UnknownKey => error::Error::UnexpectedError(format!(
+                "The SFTP server key of {} is not the expected one. The remote database is not changed",
+                host
+            )),
+            e => sftp_error(e),
+        })?;
+    let auth_result = match field("private_key_full_file_name").filter(|s| !s.is_empty()) {
+        Some(key_file) => {
+            let key =
+                russh::keys::load_secret_key(key_file, field("password")).map_err(sftp_error)?;
+            let hash_alg = session
+                .best_supported_rsa_hash()
+                .await
+                .map_err(sftp_error)?
+                .flatten();
+            session
+                .authenticate_publickey(
+                    user_name,
+                    russh::keys::PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg),
+                )
+                .await
+        }
+        None => {
+            session
+                .authenticate_password(user_name, field("password").unwrap_or_default())
+                .await
+        }
+    }
+    .map_err(sftp_error)?;
+    if !auth_result.success() {
+        return Err(error::Error::UnexpectedError(
+            "SFTP authentication failed for the rename of the verified upload".to_string(),
+        ));
+    }
+
+    let channel = session.channel_open_session().await.map_err(sftp_error)?;
+    channel
+        .request_subsystem(true, "sftp")
+        .await
+        .map_err(sftp_error)?;
+    let sftp = RawSftpSession::new(channel.into_stream());
+    let version = sftp.init().await.map_err(sftp_error)?;
+    let posix_rename = version.extensions.contains_key(POSIX_RENAME_EXTENSION);
+    Ok((sftp, posix_rename))
+}
+
+fn is_no_such_file(e: &SftpError) -> bool {
+    matches!(e, SftpError::Status(status) if status.status_code == StatusCode::NoSuchFile)
+}
+
+async fn sftp_exists(sftp: &RawSftpSession, path: &str) -> std::result::Result<bool, SftpError> {
+    match sftp.stat(path).await {
+        Ok(_) => Ok(true),
+        Err(e) if is_no_such_file(&e) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Renames 'from' over 'to' in one step with the posix-rename@openssh.com extension. The
+// request data is the two paths as ssh strings
+async fn sftp_posix_rename(
+    sftp: &RawSftpSession,
+    from: &str,
+    to: &str,
+) -> std::result::Result<(), SftpError> {
+    let mut data = vec![];
+    for path in [from, to] {
+        data.extend_from_slice(&(path.len() as u32).to_be_bytes());
+        data.extend_from_slice(path.as_bytes());
+    }
+    match sftp.extended(POSIX_RENAME_EXTENSION, data).await? {
+        Packet::Status(status) if status.status_code == StatusCode::Ok => Ok(()),
+        Packet::Status(status) => Err(SftpError::Status(status)),
+        _ => Err(SftpError::UnexpectedPacket),
+    }
+}
+
+// Renames the verified upload to the db file on the SFTP server of the db keys
+fn sftp_replace(upload_db_key: &str, db_key: &str) -> Result<()> {
+    let config = connection_config(db_key, RemoteStorageType::Sftp)?;
+    let (Some(upload_path), Some(db_path)) = (db_key_path(upload_db_key), db_key_path(db_key))
+    else {
+        return Err(error::Error::DataError("Invalid SFTP remote db key"));
+    };
+    let previous_path = format!("{}{}", db_path, PREVIOUS_SUFFIX);
+
+    super::block_on(async move {
+        let (sftp, posix_rename) = sftp_session(&config).await?;
+        let rename_error = |e: SftpError| {
+            error::Error::UnexpectedError(format!(
+                "Replacing the remote database with the verified upload failed ({}). The remote database is not changed",
+                e
+            ))
+        };
+
+        let db_exists = sftp_exists(&sftp, db_path).await.map_err(rename_error)?;
+        // Left behind by an earlier save that failed after the first rename. When the db file
+        // is missing, it is the only copy of the db and is kept
+        if db_exists && sftp_exists(&sftp, &previous_path).await.unwrap_or(false) {
+            sftp.remove(previous_path.as_str())
+                .await
+                .map_err(rename_error)?;
+        }
+
+        if posix_rename {
+            sftp_posix_rename(&sftp, upload_path, db_path)
+                .await
+                .map_err(rename_error)?;
+            let _ = sftp.close_session();
+            debug!("SFTP upload renamed over {} with the posix rename", db_path);
+            return Ok(());
+        }
+
+        if db_exists {
+            sftp.rename(db_path, previous_path.as_str())
+                .await
+                .map_err(rename_error)?;
+        }
+
+        if let Err(e) = sftp.rename(upload_path, db_path).await {
+            info!("Renaming the SFTP upload over {} failed: {}", db_path, e);
+            if db_exists {
+                if let Err(restore_err) = sftp.rename(previous_path.as_str(), db_path).await {
+                    return Err(error::Error::UnexpectedError(format!(
+                        "Replacing the remote database failed ({}) and the previous database is kept on the server as {} ({})",
+                        e, previous_path, restore_err
+                    )));
+                }
+            }
+            return Err(rename_error(e));
+        }
+
+        if db_exists {
+            if let Err(e) = sftp.remove(previous_path.as_str()).await {
+                info!("Removing {} failed: {}", previous_path, e);
+            }
+        }
+        let _ = sftp.close_session();
+        debug!("SFTP upload renamed over {}", db_path);
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_content_check_and_urls() {
+        assert!(check_same_content(b"kdbx-bytes", b"kdbx-bytes").is_ok());
+        assert!(check_same_content(b"kdbx-bytes", b"kdbx-by").is_err());
+        assert!(check_same_content(b"kdbx-bytes", b"kdbx-bytez").is_err());
+
+        let db_key = "Webdav-3a0b7c4e-96a4-4a4b-8f2e-0c1d2e3f4a5b-/dbs/My Db.kdbx";
+        assert_eq!(db_key_path(db_key), Some("/dbs/My Db.kdbx"));
+        assert_eq!(
+            db_key_path(&upload_db_key(db_key)),
+            Some("/dbs/My Db.kdbx.okp-upload")
+        );
+
+        let url = webdav_url("https://host.example.com/dav/", "/dbs/My Db.kdbx").unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://host.example.com/dav/dbs/My%20Db.kdbx"
+        );
+    }
+}
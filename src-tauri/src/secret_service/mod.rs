@@ -0,0 +1,307 @@
+// Freedesktop Secret Service provider (Linux).
+// See https://specifications.freedesktop.org/secret-service-spec/latest/
+//
+// When enabled, this app owns `org.freedesktop.secrets` on the session bus so that apps
+// like NetworkManager, git-credential-libsecret or Chrome keep their secrets in a db. The
+// groups chosen in the group tree are served as collections (see objects.rs) and the D-Bus
+// interfaces are implemented in server.rs.
+//
+// - Every read, create, change or delete of an item is confirmed by the user in the UI
+//   like a "Require Confirmation" SSH key. A read allowed once is not asked again in the
+//   same client session
+// - A collection is locked while its db is locked. An Unlock call returns a prompt and the
+//   UI is asked to show the unlock screen of that db. The prompt is completed in
+//   `db_unlocked`
+// - Only the "plain" algorithm is supported for the secret transfer as the session bus is
+//   private to the user
+//
+// The provider is disabled by default and can not start when another provider (GNOME
+// Keyring, KWallet ...) already owns the name.
+
+mod objects;
+mod server;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use dbus::blocking::stdintf::org_freedesktop_dbus::RequestNameReply;
+use dbus::blocking::SyncConnection;
+use dbus::channel::MatchingReceiver;
+use dbus::message::MatchRule;
+use serde::Serialize;
+use tauri::Emitter;
+
+use crate::app_state::AppState;
+use crate::constants::event_names::{
+    SECRET_SERVICE_ACCESS_REQUEST_EVENT, SECRET_SERVICE_DATA_CHANGED_EVENT,
+    SECRET_SERVICE_UNLOCK_REQUEST_EVENT,
+};
+
+const BUS_NAME: &str = "org.freedesktop.secrets";
+
+// How long an access request waits for the user before it is denied
+const CONFIRM_TIMEOUT_SECS: u64 = 60;
+
+// How often the stop flag is checked by the bus thread
+const PROCESS_TIMEOUT: Duration = Duration::from_millis(500);
+
+// Snapshot of the provider state reported to the UI
+#[derive(Serialize, Clone, Debug, Default)]
+pub(crate) struct SecretServiceStatus {
+    pub running: bool,
+    pub collection_count: usize,
+    // Last start error, e.g. another provider owns the name
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct ServiceRuntime {
+    // `Some` while the name is owned
+    conn: Option<Arc<SyncConnection>>,
+    stop_flag: Option<Arc<AtomicBool>>,
+    thread: Option<JoinHandle<()>>,
+    last_error: Option<String>,
+}
+
+fn runtime() -> &'static Mutex<ServiceRuntime> {
+    static RUNTIME: OnceLock<Mutex<ServiceRuntime>> = OnceLock::new();
+    RUNTIME.get_or_init(Default::default)
+}
+
+// In-flight access requests keyed by a generated request id. The thread serving the call
+// waits on the receiver and the UI's answer command removes the sender
+fn pending_confirms() -> &'static Mutex<HashMap<String, mpsc::Sender<bool>>> {
+    static PENDING_CONFIRMS: OnceLock<Mutex<HashMap<String, mpsc::Sender<bool>>>> = OnceLock::new();
+    PENDING_CONFIRMS.get_or_init(Default::default)
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum AccessAction {
+    Read,
+    Create,
+    Change,
+    Delete,
+}
+
+// Payload sent to the UI to raise the allow/deny dialog
+#[derive(Serialize, Clone, Debug)]
+struct AccessRequestPayload {
+    request_id: String,
+    client: String,
+    action: AccessAction,
+    item_label: String,
+    collection_label: String,
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct DbKeyPayload {
+    db_key: String,
+}
+
+fn snapshot(rt: &ServiceRuntime) -> SecretServiceStatus {
+    let running = rt.conn.is_some();
+    SecretServiceStatus {
+        running,
+        collection_count: if running {
+            objects::collections().len()
+        } else {
+            0
+        },
+        error: rt.last_error.clone(),
+    }
+}
+
+fn connect() -> Result<Arc<SyncConnection>, String> {
+    let conn = SyncConnection::new_session().map_err(|e| e.to_string())?;
+    match conn
+        .request_name(BUS_NAME, false, false, true)
+        .map_err(|e| e.to_string())?
+    {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => Ok(Arc::new(conn)),
+        _ => Err(format!(
+            "{} is owned by another provider (e.g. GNOME Keyring or KWallet)",
+            BUS_NAME
+        )),
+    }
+}
+
+// Owns the bus name and serves the calls on a thread of its own. Idempotent
+pub(crate) fn start() -> SecretServiceStatus {
+    let mut rt = runtime().lock().unwrap();
+    if rt.conn.is_some() {
+        return snapshot(&rt);
+    }
+    rt.last_error = None;
+
+    let conn = match connect() {
+        Ok(c) => c,
+        Err(e) => {
+            log::error!("Secret Service failed to start: {}", e);
+            rt.last_error = Some(e);
+            return snapshot(&rt);
+        }
+    };
+
+    // The handler keeps only a weak reference as it is owned by the connection
+    let weak = Arc::downgrade(&conn);
+    conn.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |msg, c| {
+            server::handle_method_call(msg, c, &weak);
+            true
+        }),
+    );
+
+    let stop_flag = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let conn = conn.clone();
+        let stop_flag = stop_flag.clone();
+        thread::Builder::new()
+            .name("secret-service".into())
+            .spawn(move || {
+                while !stop_flag.load(Ordering::Relaxed) {
+                    if let Err(e) = conn.process(PROCESS_TIMEOUT) {
+                        log::error!("Secret Service bus connection failed: {}", e);
+                        break;
+                    }
+                }
+            })
+    };
+
+    match thread {
+        Ok(handle) => {
+            rt.conn = Some(conn);
+            rt.stop_flag = Some(stop_flag);
+            rt.thread = Some(handle);
+            log::info!("Secret Service started");
+        }
+        Err(e) => {
+            let _ = conn.release_name(BUS_NAME);
+            rt.last_error = Some(e.to_string());
+        }
+    }
+    snapshot(&rt)
+}
+
+// Releases the bus name. The open client sessions and prompts are dropped and any access
+// request waiting for the user is denied
+pub(crate) fn stop() -> SecretServiceStatus {
+    let mut rt = runtime().lock().unwrap();
+
+    if let Some(stop_flag) = rt.stop_flag.take() {
+        stop_flag.store(true, Ordering::Relaxed);
+    }
+    if let Some(handle) = rt.thread.take() {
+        let _ = handle.join();
+    }
+    if let Some(conn) = rt.conn.take() {
+        let _ = conn.release_name(BUS_NAME);
+        log::info!("Secret Service stopped");
+    }
+    server::reset();
+
+    let pending: Vec<_> = pending_confirms().lock().unwrap().drain().collect();
+    for (_, tx) in pending {
+        let _ = tx.send(false);
+    }
+
+    rt.last_error = None;
+    snapshot(&rt)
+}
+
+pub(crate) fn status() -> SecretServiceStatus {
+    snapshot(&runtime().lock().unwrap())
+}
+
+pub(crate) fn is_running() -> bool {
+    runtime().lock().unwrap().conn.is_some()
+}
+
+// Completes the unlock prompts waiting for this db. Called when a db is unlocked in the UI
+pub(crate) fn db_unlocked(db_key: &str) {
+    let conn = runtime().lock().unwrap().conn.clone();
+    if let Some(conn) = conn {
+        server::complete_unlock_prompts(&conn, db_key);
+    }
+}
+
+// Raises the allow/deny dialog and waits for the user's answer. Called on the thread
+// serving the call. Returns true only on an explicit allow
+pub(super) fn request_confirmation(
+    client: String,
+    action: AccessAction,
+    item_label: String,
+    collection_label: String,
+) -> bool {
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let (tx, rx) = mpsc::channel::<bool>();
+
+    pending_confirms()
+        .lock()
+        .unwrap()
+        .insert(request_id.clone(), tx);
+
+    let payload = AccessRequestPayload {
+        request_id: request_id.clone(),
+        client,
+        action,
+        item_label,
+        collection_label,
+    };
+
+    if let Err(e) = AppState::global_app_handle().emit(SECRET_SERVICE_ACCESS_REQUEST_EVENT, payload)
+    {
+        log::error!("Secret Service: failed to emit access request event: {}", e);
+        pending_confirms().lock().unwrap().remove(&request_id);
+        return false;
+    }
+
+    let outcome = rx.recv_timeout(Duration::from_secs(CONFIRM_TIMEOUT_SECS));
+    pending_confirms().lock().unwrap().remove(&request_id);
+
+    match outcome {
+        Ok(allow) => allow,
+        Err(_) => {
+            log::info!("Secret Service: access request timed out or was abandoned; denying");
+            false
+        }
+    }
+}
+
+// Delivers the user's allow/deny answer. Called from the `secret_service_confirm_result`
+// command
+pub(crate) fn submit_confirmation(request_id: &str, allow: bool) {
+    if let Some(tx) = pending_confirms().lock().unwrap().remove(request_id) {
+        let _ = tx.send(allow);
+    } else {
+        log::warn!(
+            "Secret Service: answer for unknown/expired request {}",
+            request_id
+        );
+    }
+}
+
+// Asks the UI to show the unlock screen of a locked db
+pub(super) fn emit_unlock_request(db_key: &str) {
+    let payload = DbKeyPayload {
+        db_key: db_key.to_string(),
+    };
+    if let Err(e) = AppState::global_app_handle().emit(SECRET_SERVICE_UNLOCK_REQUEST_EVENT, payload)
+    {
+        log::error!("Secret Service: failed to emit unlock request event: {}", e);
+    }
+}
+
+// Informs the UI that a client app changed the entries of this db
+pub(super) fn emit_data_changed(db_key: &str) {
+    let payload = DbKeyPayload {
+        db_key: db_key.to_string(),
+    };
+    if let Err(e) = AppState::global_app_handle().emit(SECRET_SERVICE_DATA_CHANGED_EVENT, payload) {
+        log::error!("Secret Service: failed to emit data changed event: {}", e);
+    }
+}
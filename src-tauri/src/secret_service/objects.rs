@@ -0,0 +1,376 @@
+// The Secret Service objects as seen in the opened dbs. A collection is a group chosen in
+// the group tree and its items are the entries directly in that group. Nothing is cached
+// here and every call reads the db through kp_service, so the UI and this service always see
+// the same data.
+//
+// The object paths use the simple (hyphenated less) uuids
+//   /org/freedesktop/secrets/collection/<group uuid>
+//   /org/freedesktop/secrets/collection/<group uuid>/<entry uuid>
+
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use onekeepass_core::db_service as kp_service;
+use onekeepass_core::error::{self, Result};
+
+use crate::app_preference::SecretServiceCollection;
+use crate::app_state::AppState;
+use crate::browser_service::passkey_db;
+use crate::{db_lock, entry_form_util};
+
+pub(super) const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+pub(super) const DEFAULT_ALIAS_PATH: &str = "/org/freedesktop/secrets/aliases/default";
+const COLLECTION_PREFIX: &str = "/org/freedesktop/secrets/collection/";
+const SESSION_PREFIX: &str = "/org/freedesktop/secrets/session/";
+const PROMPT_PREFIX: &str = "/org/freedesktop/secrets/prompt/";
+
+// The attributes given by the client apps are kept as the fields of this section
+const ATTRIBUTES_SECTION_NAME: &str = "Secret Service";
+
+const TITLE: &str = "Title";
+const USER_NAME: &str = "UserName";
+const PASSWORD: &str = "Password";
+const URL: &str = "URL";
+
+#[derive(Debug, PartialEq)]
+pub(super) enum ObjectPath {
+    Service,
+    DefaultAlias,
+    Collection(Uuid),
+    Item(Uuid, Uuid),
+    Session(u64),
+    Prompt(u64),
+}
+
+pub(super) fn parse_path(path: &str) -> Option<ObjectPath> {
+    if path == SERVICE_PATH {
+        return Some(ObjectPath::Service);
+    }
+    if path == DEFAULT_ALIAS_PATH {
+        return Some(ObjectPath::DefaultAlias);
+    }
+    if let Some(id) = path.strip_prefix(SESSION_PREFIX) {
+        return id.parse().ok().map(ObjectPath::Session);
+    }
+    if let Some(id) = path.strip_prefix(PROMPT_PREFIX) {
+        return id.parse().ok().map(ObjectPath::Prompt);
+    }
+
+    let rest = path.strip_prefix(COLLECTION_PREFIX)?;
+    match rest.split_once('/') {
+        None => Uuid::parse_str(rest).ok().map(ObjectPath::Collection),
+        Some((group, entry)) => Some(ObjectPath::Item(
+            Uuid::parse_str(group).ok()?,
+            Uuid::parse_str(entry).ok()?,
+        )),
+    }
+}
+
+pub(super) fn collection_path(group_uuid: &Uuid) -> String {
+    format!("{}{}", COLLECTION_PREFIX, group_uuid.simple())
+}
+
+pub(super) fn item_path(group_uuid: &Uuid, entry_uuid: &Uuid) -> String {
+    format!(
+        "{}{}/{}",
+        COLLECTION_PREFIX,
+        group_uuid.simple(),
+        entry_uuid.simple()
+    )
+}
+
+pub(super) fn session_path(id: u64) -> String {
+    format!("{}{}", SESSION_PREFIX, id)
+}
+
+pub(super) fn prompt_path(id: u64) -> String {
+    format!("{}{}", PROMPT_PREFIX, id)
+}
+
+// The chosen groups of the dbs that are opened or locked. The groups of a closed db are not
+// served till that db is opened again
+pub(super) fn collections() -> Vec<SecretServiceCollection> {
+    let opened = crate::backup_service::opened_db_keys().unwrap_or_default();
+    AppState::state_instance()
+        .secret_service_collections()
+        .into_iter()
+        .filter(|c| opened.contains(&c.db_key) || db_lock::is_locked(&c.db_key))
+        .collect()
+}
+
+pub(super) fn find_collection(group_uuid: &Uuid) -> Option<SecretServiceCollection> {
+    collections()
+        .into_iter()
+        .find(|c| &c.group_uuid == group_uuid)
+}
+
+// The first collection is used as the default one
+pub(super) fn default_collection() -> Option<SecretServiceCollection> {
+    collections().into_iter().next()
+}
+
+pub(super) fn is_locked(collection: &SecretServiceCollection) -> bool {
+    !kp_service::is_db_opened(&collection.db_key)
+}
+
+#[derive(Debug)]
+pub(super) struct Item {
+    pub(super) entry_uuid: Uuid,
+    pub(super) label: String,
+    pub(super) attributes: HashMap<String, String>,
+    pub(super) secret: String,
+    // Seconds since the epoch
+    pub(super) created: u64,
+}
+
+fn field_value(form_data: &kp_service::EntryFormData, key: &str) -> Option<String> {
+    form_data
+        .section_fields
+        .values()
+        .flatten()
+        .find(|kv| kv.key == key)
+        .and_then(|kv| kv.value.clone())
+        .filter(|v| !v.is_empty())
+}
+
+// The attributes set by the client apps. An entry created in this app has none and the
+// title, user name and url are used so that such an entry can still be searched
+fn item_attributes(form_data: &kp_service::EntryFormData) -> HashMap<String, String> {
+    let mut attributes: HashMap<String, String> = form_data
+        .section_fields
+        .get(ATTRIBUTES_SECTION_NAME)
+        .into_iter()
+        .flatten()
+        .filter_map(|kv| Some((kv.key.clone(), kv.value.clone()?)))
+        .collect();
+
+    attributes
+        .entry(TITLE.to_string())
+        .or_insert_with(|| form_data.title.clone());
+    for key in [USER_NAME, URL] {
+        if let Some(v) = field_value(form_data, key) {
+            attributes.entry(key.to_string()).or_insert(v);
+        }
+    }
+    attributes
+}
+
+fn item_from_form_data(form_data: &kp_service::EntryFormData) -> Item {
+    Item {
+        entry_uuid: form_data.uuid,
+        label: form_data.title.clone(),
+        attributes: item_attributes(form_data),
+        secret: field_value(form_data, PASSWORD).unwrap_or_default(),
+        created: form_data.creation_time.and_utc().timestamp().max(0) as u64,
+    }
+}
+
+// All items of an unlocked collection
+pub(super) fn items(collection: &SecretServiceCollection) -> Result<Vec<Item>> {
+    let entries = kp_service::entry_summary_data(
+        &collection.db_key,
+        kp_service::EntryCategory::Group(collection.group_uuid.to_string()),
+    )?;
+
+    let mut result = vec![];
+    for entry in entries {
+        let Ok(entry_uuid) = Uuid::parse_str(&entry.uuid) else {
+            continue;
+        };
+        let form_data = kp_service::get_entry_form_data_by_id(&collection.db_key, &entry_uuid)?;
+        if form_data.group_uuid == collection.group_uuid {
+            result.push(item_from_form_data(&form_data));
+        }
+    }
+    Ok(result)
+}
+
+fn item_form_data(
+    collection: &SecretServiceCollection,
+    entry_uuid: &Uuid,
+) -> Result<kp_service::EntryFormData> {
+    let form_data = kp_service::get_entry_form_data_by_id(&collection.db_key, entry_uuid)?;
+    // An entry moved to another group or to the recycle bin is no more an item here
+    if form_data.group_uuid != collection.group_uuid {
+        return Err(error::Error::DataError(
+            "The entry is not in the group of this collection",
+        ));
+    }
+    Ok(form_data)
+}
+
+pub(super) fn item(collection: &SecretServiceCollection, entry_uuid: &Uuid) -> Result<Item> {
+    item_form_data(collection, entry_uuid).map(|f| item_from_form_data(&f))
+}
+
+// True when the item has all attributes of the query
+pub(super) fn matches(
+    attributes: &HashMap<String, String>,
+    query: &HashMap<String, String>,
+) -> bool {
+    query
+        .iter()
+        .all(|(k, v)| attributes.get(k).is_some_and(|a| a == v))
+}
+
+fn set_attributes(
+    form_data: &mut kp_service::EntryFormData,
+    attributes: &HashMap<String, String>,
+) -> Result<()> {
+    let mut keys: Vec<_> = attributes.keys().collect();
+    keys.sort();
+    let mut fields = vec![];
+    for key in keys {
+        fields.push(entry_form_util::custom_field(
+            form_data,
+            key,
+            &attributes[key],
+            false,
+        )?);
+    }
+
+    if fields.is_empty() {
+        form_data.section_fields.remove(ATTRIBUTES_SECTION_NAME);
+        form_data
+            .section_names
+            .retain(|n| n != ATTRIBUTES_SECTION_NAME);
+    } else {
+        if !form_data
+            .section_names
+            .iter()
+            .any(|n| n == ATTRIBUTES_SECTION_NAME)
+        {
+            form_data
+                .section_names
+                .push(ATTRIBUTES_SECTION_NAME.to_string());
+        }
+        form_data
+            .section_fields
+            .insert(ATTRIBUTES_SECTION_NAME.to_string(), fields);
+    }
+    Ok(())
+}
+
+// The first item with exactly these attributes that is replaced by CreateItem
+pub(super) fn item_to_replace(
+    collection: &SecretServiceCollection,
+    attributes: &HashMap<String, String>,
+) -> Result<Option<Uuid>> {
+    Ok(items(collection)?
+        .into_iter()
+        .find(|i| matches(&i.attributes, attributes))
+        .map(|i| i.entry_uuid))
+}
+
+// Creates a login entry in the group of the collection or, when 'replace' is set, updates
+// the entry of an item. The db is saved as done for a passkey
+pub(super) fn create_item(
+    collection: &SecretServiceCollection,
+    label: &str,
+    attributes: &HashMap<String, String>,
+    secret: &str,
+    replace: Option<Uuid>,
+) -> Result<Uuid> {
+    let db_key = &collection.db_key;
+    let mut form_data = match replace {
+        Some(entry_uuid) => item_form_data(collection, &entry_uuid)?,
+        None => entry_form_util::new_login_entry_form_data(db_key, Some(&collection.group_uuid))?,
+    };
+
+    form_data.title = label.to_string();
+    if !entry_form_util::set_field_value(&mut form_data, PASSWORD, secret) {
+        return Err(error::Error::DataError(
+            "The entry does not have a password field",
+        ));
+    }
+    set_attributes(&mut form_data, attributes)?;
+
+    let entry_uuid = form_data.uuid;
+    if replace.is_some() {
+        kp_service::update_entry_from_form_data(db_key, form_data)?;
+    } else {
+        kp_service::insert_entry_from_form_data(db_key, form_data)?;
+    }
+    passkey_db::save_db_with_backup(db_key)?;
+    Ok(entry_uuid)
+}
+
+pub(super) fn set_secret(
+    collection: &SecretServiceCollection,
+    entry_uuid: &Uuid,
+    secret: &str,
+) -> Result<()> {
+    let mut form_data = item_form_data(collection, entry_uuid)?;
+    if !entry_form_util::set_field_value(&mut form_data, PASSWORD, secret) {
+        return Err(error::Error::DataError(
+            "The entry does not have a password field",
+        ));
+    }
+    kp_service::update_entry_from_form_data(&collection.db_key, form_data)?;
+    passkey_db::save_db_with_backup(&collection.db_key)
+}
+
+// The entry goes to the recycle bin as done for a delete in the UI
+pub(super) fn delete_item(collection: &SecretServiceCollection, entry_uuid: &Uuid) -> Result<()> {
+    let _ = item_form_data(collection, entry_uuid)?;
+    kp_service::move_entry_to_recycle_bin(&collection.db_key, *entry_uuid)?;
+    passkey_db::save_db_with_backup(&collection.db_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_parse_path() {
+        let group = Uuid::new_v4();
+        let entry = Uuid::new_v4();
+
+        assert_eq!(parse_path(SERVICE_PATH), Some(ObjectPath::Service));
+        assert_eq!(
+            parse_path(DEFAULT_ALIAS_PATH),
+            Some(ObjectPath::DefaultAlias)
+        );
+        assert_eq!(
+            parse_path(&collection_path(&group)),
+            Some(ObjectPath::Collection(group))
+        );
+        assert_eq!(
+            parse_path(&item_path(&group, &entry)),
+            Some(ObjectPath::Item(group, entry))
+        );
+        assert_eq!(parse_path(&session_path(3)), Some(ObjectPath::Session(3)));
+        assert_eq!(parse_path(&prompt_path(7)), Some(ObjectPath::Prompt(7)));
+
+        assert_eq!(parse_path("/"), None);
+        assert_eq!(
+            parse_path("/org/freedesktop/secrets/collection/login"),
+            None
+        );
+        assert_eq!(parse_path("/org/freedesktop/secrets/session/abc"), None);
+    }
+
+    #[test]
+    fn verify_matches() {
+        let attributes = HashMap::from([
+            ("xdg:schema".to_string(), "org.git.Password".to_string()),
+            ("server".to_string(), "github.com".to_string()),
+            ("user".to_string(), "octo".to_string()),
+        ]);
+
+        assert!(matches(&attributes, &HashMap::new()));
+        assert!(matches(
+            &attributes,
+            &HashMap::from([("server".to_string(), "github.com".to_string())])
+        ));
+        assert!(!matches(
+            &attributes,
+            &HashMap::from([("server".to_string(), "gitlab.com".to_string())])
+        ));
+        assert!(!matches(
+            &attributes,
+            &HashMap::from([("protocol".to_string(), "https".to_string())])
+        ));
+    }
+}
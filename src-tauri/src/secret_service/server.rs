@@ -0,0 +1,830 @@
+// The D-Bus side of the Secret Service: the method calls received on the owned name are
+// dispatched here to the Service, Collection, Item, Session and Prompt interfaces and to
+// org.freedesktop.DBus.Properties.
+//
+// A call that needs the user's confirmation is served on a thread of its own so that the
+// bus thread keeps serving other calls. Its reply is sent from that thread.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::sync::{Mutex, OnceLock, Weak};
+use std::thread;
+use std::time::Duration;
+
+use dbus::arg::{PropMap, RefArg, TypeMismatchError, Variant};
+use dbus::blocking::SyncConnection;
+use dbus::channel::Sender;
+use dbus::strings::ErrorName;
+use dbus::{Message, Path};
+use uuid::Uuid;
+
+use onekeepass_core::db_service as kp_service;
+
+use super::objects::{self, ObjectPath};
+use super::AccessAction;
+use crate::app_preference::SecretServiceCollection;
+
+const SERVICE_IFACE: &str = "org.freedesktop.Secret.Service";
+const COLLECTION_IFACE: &str = "org.freedesktop.Secret.Collection";
+const ITEM_IFACE: &str = "org.freedesktop.Secret.Item";
+const SESSION_IFACE: &str = "org.freedesktop.Secret.Session";
+const PROMPT_IFACE: &str = "org.freedesktop.Secret.Prompt";
+const PROPERTIES_IFACE: &str = "org.freedesktop.DBus.Properties";
+
+const DBUS_DEST: &str = "org.freedesktop.DBus";
+const DBUS_PATH: &str = "/org/freedesktop/DBus";
+const DBUS_IFACE: &str = "org.freedesktop.DBus";
+
+const LABEL_PROPERTY: &str = "org.freedesktop.Secret.Item.Label";
+const ATTRIBUTES_PROPERTY: &str = "org.freedesktop.Secret.Item.Attributes";
+
+const PLAIN_ALGORITHM: &str = "plain";
+const CONTENT_TYPE: &str = "text/plain; charset=utf8";
+const NO_OBJECT: &str = "/";
+
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+// (session, parameters, value, content type) as defined in the spec
+type Secret = (Path<'static>, Vec<u8>, Vec<u8>, String);
+
+struct DbusError {
+    name: &'static str,
+    text: String,
+}
+
+impl DbusError {
+    fn new(name: &'static str, text: impl Into<String>) -> Self {
+        Self {
+            name,
+            text: text.into(),
+        }
+    }
+
+    fn no_such_object() -> Self {
+        Self::new(
+            "org.freedesktop.Secret.Error.NoSuchObject",
+            "No such object",
+        )
+    }
+
+    fn is_locked() -> Self {
+        Self::new(
+            "org.freedesktop.Secret.Error.IsLocked",
+            "The database of this collection is locked",
+        )
+    }
+
+    fn no_session() -> Self {
+        Self::new("org.freedesktop.Secret.Error.NoSession", "No such session")
+    }
+
+    fn not_supported(text: &str) -> Self {
+        Self::new("org.freedesktop.DBus.Error.NotSupported", text)
+    }
+
+    fn access_denied() -> Self {
+        Self::new(
+            "org.freedesktop.DBus.Error.AccessDenied",
+            "The request was denied in OneKeePass",
+        )
+    }
+
+    fn invalid_args(text: impl Into<String>) -> Self {
+        Self::new("org.freedesktop.DBus.Error.InvalidArgs", text)
+    }
+
+    fn failed(text: impl Into<String>) -> Self {
+        Self::new("org.freedesktop.DBus.Error.Failed", text)
+    }
+
+    fn reply(&self, msg: &Message) -> Message {
+        let text = CString::new(self.text.replace('\0', "")).unwrap_or_default();
+        msg.error(&ErrorName::from(self.name), &text)
+    }
+}
+
+impl From<TypeMismatchError> for DbusError {
+    fn from(e: TypeMismatchError) -> Self {
+        Self::invalid_args(e.to_string())
+    }
+}
+
+impl From<onekeepass_core::error::Error> for DbusError {
+    fn from(e: onekeepass_core::error::Error) -> Self {
+        Self::failed(format!("{}", e))
+    }
+}
+
+// None when the reply is sent later from another thread
+type CallResult = Result<Option<Message>, DbusError>;
+
+struct ClientSession {
+    // Unique bus name of the client that opened the session
+    owner: String,
+    // Item paths whose reads the user allowed in this session
+    allowed: HashSet<String>,
+}
+
+struct UnlockPrompt {
+    objects: Vec<Path<'static>>,
+    // The locked dbs of these objects
+    db_keys: HashSet<String>,
+    // Completed only after the client called Prompt
+    prompted: bool,
+}
+
+#[derive(Default)]
+struct ServerState {
+    last_id: u64,
+    sessions: HashMap<u64, ClientSession>,
+    prompts: HashMap<u64, UnlockPrompt>,
+}
+
+impl ServerState {
+    fn next_id(&mut self) -> u64 {
+        self.last_id += 1;
+        self.last_id
+    }
+}
+
+fn state() -> &'static Mutex<ServerState> {
+    static STATE: OnceLock<Mutex<ServerState>> = OnceLock::new();
+    STATE.get_or_init(Default::default)
+}
+
+// Held while a confirmation dialog is shown so that the requests are asked one by one
+fn confirm_lock() -> &'static Mutex<()> {
+    static CONFIRM_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+    CONFIRM_LOCK.get_or_init(Default::default)
+}
+
+// Drops all sessions and prompts. Called when the service is stopped
+pub(super) fn reset() {
+    *state().lock().unwrap() = ServerState::default();
+}
+
+pub(super) fn handle_method_call(msg: Message, conn: &SyncConnection, weak: &Weak<SyncConnection>) {
+    let reply = match dispatch(&msg, conn, weak) {
+        Ok(Some(reply)) => reply,
+        Ok(None) => return,
+        Err(e) => e.reply(&msg),
+    };
+    let _ = conn.send(reply);
+}
+
+fn dispatch(msg: &Message, conn: &SyncConnection, weak: &Weak<SyncConnection>) -> CallResult {
+    let path = msg.path().map(|p| p.to_string()).unwrap_or_default();
+    let interface = msg.interface().map(|i| i.to_string()).unwrap_or_default();
+    let member = msg.member().map(|m| m.to_string()).unwrap_or_default();
+    let object = objects::parse_path(&path).ok_or_else(DbusError::no_such_object)?;
+
+    if interface == PROPERTIES_IFACE {
+        return properties_call(msg, &object, &member).map(Some);
+    }
+
+    match (&object, interface.as_str(), member.as_str()) {
+        (ObjectPath::Service, SERVICE_IFACE, "OpenSession") => open_session(msg),
+        (ObjectPath::Service, SERVICE_IFACE, "SearchItems") => search_items(msg),
+        (ObjectPath::Service, SERVICE_IFACE, "Unlock") => unlock(msg),
+        (ObjectPath::Service, SERVICE_IFACE, "Lock") => lock(msg),
+        (ObjectPath::Service, SERVICE_IFACE, "GetSecrets") => get_secrets(msg, weak),
+        (ObjectPath::Service, SERVICE_IFACE, "ReadAlias") => read_alias(msg),
+        (ObjectPath::Service, SERVICE_IFACE, "CreateCollection" | "SetAlias") => Err(
+            DbusError::not_supported("The collections are chosen in OneKeePass"),
+        ),
+        (ObjectPath::Collection(_) | ObjectPath::DefaultAlias, COLLECTION_IFACE, "CreateItem") => {
+            create_item(msg, &object, weak)
+        }
+        (ObjectPath::Collection(_) | ObjectPath::DefaultAlias, COLLECTION_IFACE, "SearchItems") => {
+            collection_search_items(msg, &object)
+        }
+        (ObjectPath::Collection(_) | ObjectPath::DefaultAlias, COLLECTION_IFACE, "Delete") => Err(
+            DbusError::not_supported("The collections are chosen in OneKeePass"),
+        ),
+        (ObjectPath::Item(..), ITEM_IFACE, "GetSecret") => get_secret(msg, &object, weak),
+        (ObjectPath::Item(..), ITEM_IFACE, "SetSecret") => set_secret(msg, &object, weak),
+        (ObjectPath::Item(..), ITEM_IFACE, "Delete") => delete_item(msg, &object, weak),
+        (ObjectPath::Session(id), SESSION_IFACE, "Close") => close_session(msg, *id),
+        (ObjectPath::Prompt(id), PROMPT_IFACE, "Prompt") => prompt(msg, conn, *id),
+        (ObjectPath::Prompt(id), PROMPT_IFACE, "Dismiss") => dismiss(msg, conn, *id),
+        _ => Err(DbusError::new(
+            "org.freedesktop.DBus.Error.UnknownMethod",
+            format!("Unknown method {}.{}", interface, member),
+        )),
+    }
+}
+
+// Serves the call on a thread of its own and sends its reply from there
+fn defer<F>(msg: &Message, weak: &Weak<SyncConnection>, f: F) -> CallResult
+where
+    F: FnOnce(&Message, &SyncConnection) -> Result<Message, DbusError> + Send + 'static,
+{
+    let msg = msg.duplicate().map_err(DbusError::failed)?;
+    let weak = weak.clone();
+    thread::spawn(move || {
+        let Some(conn) = weak.upgrade() else {
+            return;
+        };
+        let reply = f(&msg, &conn).unwrap_or_else(|e| e.reply(&msg));
+        let _ = conn.send(reply);
+    });
+    Ok(None)
+}
+
+fn sender(msg: &Message) -> String {
+    msg.sender().map(|s| s.to_string()).unwrap_or_default()
+}
+
+fn variant<T: RefArg + 'static>(value: T) -> Variant<Box<dyn RefArg>> {
+    Variant(Box::new(value))
+}
+
+// The client's process name shown in the confirmation dialog. The unique bus name is used
+// when the process is not known
+fn client_name(conn: &SyncConnection, msg: &Message) -> String {
+    let sender = sender(msg);
+    let proxy = conn.with_proxy(DBUS_DEST, DBUS_PATH, CALL_TIMEOUT);
+    let r: Result<(u32,), dbus::Error> =
+        proxy.method_call(DBUS_IFACE, "GetConnectionUnixProcessID", (sender.as_str(),));
+    r.ok()
+        .and_then(|(pid,)| std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or(sender)
+}
+
+// Asks the user. The caller holds confirm_lock
+fn ask(
+    conn: &SyncConnection,
+    msg: &Message,
+    action: AccessAction,
+    item_label: &str,
+    collection: &SecretServiceCollection,
+) -> bool {
+    // A request queued behind a dialog is denied once the service is stopped
+    if !super::is_running() {
+        return false;
+    }
+    super::request_confirmation(
+        client_name(conn, msg),
+        action,
+        item_label.to_string(),
+        collection.label.clone(),
+    )
+}
+
+fn confirm(
+    conn: &SyncConnection,
+    msg: &Message,
+    action: AccessAction,
+    item_label: &str,
+    collection: &SecretServiceCollection,
+) -> Result<(), DbusError> {
+    let _guard = confirm_lock().lock().unwrap();
+    if ask(conn, msg, action, item_label, collection) {
+        Ok(())
+    } else {
+        Err(DbusError::access_denied())
+    }
+}
+
+// A read allowed once is not asked again in the same session
+fn confirm_read(
+    conn: &SyncConnection,
+    msg: &Message,
+    session_id: u64,
+    item_path: &str,
+    item_label: &str,
+    collection: &SecretServiceCollection,
+) -> bool {
+    let _guard = confirm_lock().lock().unwrap();
+    let allowed = state()
+        .lock()
+        .unwrap()
+        .sessions
+        .get(&session_id)
+        .is_some_and(|s| s.allowed.contains(item_path));
+    if allowed {
+        return true;
+    }
+
+    let allow = ask(conn, msg, AccessAction::Read, item_label, collection);
+    if allow {
+        if let Some(session) = state().lock().unwrap().sessions.get_mut(&session_id) {
+            session.allowed.insert(item_path.to_string());
+        }
+    }
+    allow
+}
+
+// The id of a session opened by the sender of this call
+fn check_session(msg: &Message, session: &Path) -> Result<u64, DbusError> {
+    let Some(ObjectPath::Session(id)) = objects::parse_path(session) else {
+        return Err(DbusError::no_session());
+    };
+    match state().lock().unwrap().sessions.get(&id) {
+        Some(s) if s.owner == sender(msg) => Ok(id),
+        _ => Err(DbusError::no_session()),
+    }
+}
+
+fn collection_of(object: &ObjectPath) -> Result<SecretServiceCollection, DbusError> {
+    match object {
+        ObjectPath::Collection(group_uuid) | ObjectPath::Item(group_uuid, _) => {
+            objects::find_collection(group_uuid)
+        }
+        ObjectPath::DefaultAlias => objects::default_collection(),
+        _ => None,
+    }
+    .ok_or_else(DbusError::no_such_object)
+}
+
+fn unlocked_collection_of(object: &ObjectPath) -> Result<SecretServiceCollection, DbusError> {
+    let collection = collection_of(object)?;
+    if objects::is_locked(&collection) {
+        return Err(DbusError::is_locked());
+    }
+    Ok(collection)
+}
+
+fn entry_uuid_of(object: &ObjectPath) -> Result<Uuid, DbusError> {
+    match object {
+        ObjectPath::Item(_, entry_uuid) => Ok(*entry_uuid),
+        _ => Err(DbusError::no_such_object()),
+    }
+}
+
+// The key value pairs of an a{ss} read as a variant
+fn string_map(arg: &dyn RefArg) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+    if let Some(mut iter) = arg.as_iter() {
+        while let (Some(k), Some(v)) = (iter.next(), iter.next()) {
+            if let (Some(k), Some(v)) = (k.as_str(), v.as_str()) {
+                result.insert(k.to_string(), v.to_string());
+            }
+        }
+    }
+    result
+}
+
+fn secret_value(secret: &Secret) -> Result<String, DbusError> {
+    String::from_utf8(secret.2.clone())
+        .map_err(|_| DbusError::invalid_args("Only text secrets are supported"))
+}
+
+fn matching_item_paths(
+    collection: &SecretServiceCollection,
+    query: &HashMap<String, String>,
+) -> Result<Vec<Path<'static>>, DbusError> {
+    Ok(objects::items(collection)?
+        .into_iter()
+        .filter(|i| objects::matches(&i.attributes, query))
+        .map(|i| Path::from(objects::item_path(&collection.group_uuid, &i.entry_uuid)))
+        .collect())
+}
+
+//------------------------------------------------------------------------------------------
+// org.freedesktop.Secret.Service
+
+fn open_session(msg: &Message) -> CallResult {
+    let (algorithm, _input): (String, Variant<Box<dyn RefArg>>) = msg.read2()?;
+    if algorithm != PLAIN_ALGORITHM {
+        return Err(DbusError::not_supported(
+            "Only the plain algorithm is supported",
+        ));
+    }
+
+    let id = {
+        let mut st = state().lock().unwrap();
+        let id = st.next_id();
+        st.sessions.insert(
+            id,
+            ClientSession {
+                owner: sender(msg),
+                allowed: HashSet::new(),
+            },
+        );
+        id
+    };
+    Ok(Some(msg.method_return().append2(
+        Variant(""),
+        Path::from(objects::session_path(id)),
+    )))
+}
+
+// The items of a locked db can not be searched and so the locked list is always empty
+fn search_items(msg: &Message) -> CallResult {
+    let query: HashMap<String, String> = msg.read1()?;
+    let mut unlocked = vec![];
+    for collection in objects::collections() {
+        if !objects::is_locked(&collection) {
+            unlocked.extend(matching_item_paths(&collection, &query)?);
+        }
+    }
+    Ok(Some(
+        msg.method_return()
+            .append2(unlocked, Vec::<Path<'static>>::new()),
+    ))
+}
+
+// Returns a prompt when any object is in a locked db. That prompt asks the UI to unlock the
+// dbs and is completed when they are unlocked
+fn unlock(msg: &Message) -> CallResult {
+    let paths: Vec<Path<'static>> = msg.read1()?;
+
+    let mut unlocked = vec![];
+    let mut locked = vec![];
+    let mut db_keys = HashSet::new();
+    for path in paths {
+        let Some(collection) = objects::parse_path(&path).and_then(|o| collection_of(&o).ok())
+        else {
+            continue;
+        };
+        if objects::is_locked(&collection) {
+            db_keys.insert(collection.db_key);
+            locked.push(path);
+        } else {
+            unlocked.push(path);
+        }
+    }
+
+    let prompt = if locked.is_empty() {
+        Path::from(NO_OBJECT)
+    } else {
+        let mut st = state().lock().unwrap();
+        let id = st.next_id();
+        st.prompts.insert(
+            id,
+            UnlockPrompt {
+                objects: locked,
+                db_keys,
+                prompted: false,
+            },
+        );
+        Path::from(objects::prompt_path(id))
+    };
+    Ok(Some(msg.method_return().append2(unlocked, prompt)))
+}
+
+// The dbs are locked only in OneKeePass. Nothing is locked here
+fn lock(msg: &Message) -> CallResult {
+    let _paths: Vec<Path<'static>> = msg.read1()?;
+    Ok(Some(msg.method_return().append2(
+        Vec::<Path<'static>>::new(),
+        Path::from(NO_OBJECT),
+    )))
+}
+
+// The items that are locked or whose read is denied are left out
+fn get_secrets(msg: &Message, weak: &Weak<SyncConnection>) -> CallResult {
+    let (paths, session): (Vec<Path<'static>>, Path<'static>) = msg.read2()?;
+    let session_id = check_session(msg, &session)?;
+
+    defer(msg, weak, move |msg, conn| {
+        let mut secrets: HashMap<Path<'static>, Secret> = HashMap::new();
+        for path in paths {
+            let Some(object) = objects::parse_path(&path) else {
+                continue;
+            };
+            let (Ok(collection), Ok(entry_uuid)) =
+                (unlocked_collection_of(&object), entry_uuid_of(&object))
+            else {
+                continue;
+            };
+            let Ok(item) = objects::item(&collection, &entry_uuid) else {
+                continue;
+            };
+            if confirm_read(conn, msg, session_id, &path, &item.label, &collection) {
+                secrets.insert(
+                    path,
+                    (
+                        session.clone(),
+                        vec![],
+                        item.secret.into_bytes(),
+                        CONTENT_TYPE.to_string(),
+                    ),
+                );
+            }
+        }
+        Ok(msg.method_return().append1(secrets))
+    })
+}
+
+fn read_alias(msg: &Message) -> CallResult {
+    let name: String = msg.read1()?;
+    let path = match objects::default_collection() {
+        Some(c) if name == "default" => objects::collection_path(&c.group_uuid),
+        _ => NO_OBJECT.to_string(),
+    };
+    Ok(Some(msg.method_return().append1(Path::from(path))))
+}
+
+//------------------------------------------------------------------------------------------
+// org.freedesktop.Secret.Collection
+
+fn create_item(msg: &Message, object: &ObjectPath, weak: &Weak<SyncConnection>) -> CallResult {
+    let (properties, secret, replace): (PropMap, Secret, bool) = msg.read3()?;
+    check_session(msg, &secret.0)?;
+    let collection = unlocked_collection_of(object)?;
+
+    let label = properties
+        .get(LABEL_PROPERTY)
+        .and_then(|v| v.0.as_str())
+        .unwrap_or_default()
+        .to_string();
+    let attributes = properties
+        .get(ATTRIBUTES_PROPERTY)
+        .map(|v| string_map(&v.0))
+        .unwrap_or_default();
+    let value = secret_value(&secret)?;
+
+    defer(msg, weak, move |msg, conn| {
+        let existing = if replace {
+            objects::item_to_replace(&collection, &attributes)?
+        } else {
+            None
+        };
+        let action = match existing {
+            Some(_) => AccessAction::Change,
+            None => AccessAction::Create,
+        };
+        confirm(conn, msg, action, &label, &collection)?;
+
+        let entry_uuid = objects::create_item(&collection, &label, &attributes, &value, existing)?;
+        super::emit_data_changed(&collection.db_key);
+        Ok(msg.method_return().append2(
+            Path::from(objects::item_path(&collection.group_uuid, &entry_uuid)),
+            Path::from(NO_OBJECT),
+        ))
+    })
+}
+
+fn collection_search_items(msg: &Message, object: &ObjectPath) -> CallResult {
+    let query: HashMap<String, String> = msg.read1()?;
+    let collection = collection_of(object)?;
+    let found = if objects::is_locked(&collection) {
+        vec![]
+    } else {
+        matching_item_paths(&collection, &query)?
+    };
+    Ok(Some(msg.method_return().append1(found)))
+}
+
+//------------------------------------------------------------------------------------------
+// org.freedesktop.Secret.Item
+
+fn get_secret(msg: &Message, object: &ObjectPath, weak: &Weak<SyncConnection>) -> CallResult {
+    let session: Path<'static> = msg.read1()?;
+    let session_id = check_session(msg, &session)?;
+    let collection = unlocked_collection_of(object)?;
+    let entry_uuid = entry_uuid_of(object)?;
+
+    defer(msg, weak, move |msg, conn| {
+        let item = objects::item(&collection, &entry_uuid)?;
+        let item_path = objects::item_path(&collection.group_uuid, &entry_uuid);
+        if !confirm_read(conn, msg, session_id, &item_path, &item.label, &collection) {
+            return Err(DbusError::access_denied());
+        }
+        let secret: Secret = (
+            session,
+            vec![],
+            item.secret.into_bytes(),
+            CONTENT_TYPE.to_string(),
+        );
+        Ok(msg.method_return().append1(secret))
+    })
+}
+
+fn set_secret(msg: &Message, object: &ObjectPath, weak: &Weak<SyncConnection>) -> CallResult {
+    let secret: Secret = msg.read1()?;
+    check_session(msg, &secret.0)?;
+    let collection = unlocked_collection_of(object)?;
+    let entry_uuid = entry_uuid_of(object)?;
+    let value = secret_value(&secret)?;
+
+    defer(msg, weak, move |msg, conn| {
+        let item = objects::item(&collection, &entry_uuid)?;
+        confirm(conn, msg, AccessAction::Change, &item.label, &collection)?;
+        objects::set_secret(&collection, &entry_uuid, &value)?;
+        super::emit_data_changed(&collection.db_key);
+        Ok(msg.method_return())
+    })
+}
+
+fn delete_item(msg: &Message, object: &ObjectPath, weak: &Weak<SyncConnection>) -> CallResult {
+    let collection = unlocked_collection_of(object)?;
+    let entry_uuid = entry_uuid_of(object)?;
+
+    defer(msg, weak, move |msg, conn| {
+        let item = objects::item(&collection, &entry_uuid)?;
+        confirm(conn, msg, AccessAction::Delete, &item.label, &collection)?;
+        objects::delete_item(&collection, &entry_uuid)?;
+        super::emit_data_changed(&collection.db_key);
+        Ok(msg.method_return().append1(Path::from(NO_OBJECT)))
+    })
+}
+
+//------------------------------------------------------------------------------------------
+// org.freedesktop.Secret.Session and org.freedesktop.Secret.Prompt
+
+fn close_session(msg: &Message, id: u64) -> CallResult {
+    let mut st = state().lock().unwrap();
+    if st.sessions.get(&id).is_some_and(|s| s.owner == sender(msg)) {
+        st.sessions.remove(&id);
+    }
+    Ok(Some(msg.method_return()))
+}
+
+fn send_completed(conn: &SyncConnection, id: u64, dismissed: bool, objects: Vec<Path<'static>>) {
+    match Message::new_signal(objects::prompt_path(id), PROMPT_IFACE, "Completed") {
+        Ok(signal) => {
+            let _ = conn.send(signal.append2(dismissed, Variant(objects)));
+        }
+        Err(e) => log::error!(
+            "Secret Service: creating the Completed signal failed: {}",
+            e
+        ),
+    }
+}
+
+fn prompt(msg: &Message, conn: &SyncConnection, id: u64) -> CallResult {
+    let _window_id: String = msg.read1()?;
+
+    let (db_keys, objects) = {
+        let mut st = state().lock().unwrap();
+        let prompt = st
+            .prompts
+            .get_mut(&id)
+            .ok_or_else(DbusError::no_such_object)?;
+        prompt.prompted = true;
+        // A db may have been unlocked after the Unlock call
+        prompt.db_keys.retain(|k| !kp_service::is_db_opened(k));
+        if prompt.db_keys.is_empty() {
+            (vec![], st.prompts.remove(&id).map(|p| p.objects))
+        } else {
+            (prompt.db_keys.iter().cloned().collect::<Vec<_>>(), None)
+        }
+    };
+
+    let _ = conn.send(msg.method_return());
+    match objects {
+        Some(objects) => send_completed(conn, id, false, objects),
+        None => {
+            for db_key in db_keys {
+                super::emit_unlock_request(&db_key);
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn dismiss(msg: &Message, conn: &SyncConnection, id: u64) -> CallResult {
+    let removed = state().lock().unwrap().prompts.remove(&id);
+    let _ = conn.send(msg.method_return());
+    if removed.is_some() {
+        send_completed(conn, id, true, vec![]);
+    }
+    Ok(None)
+}
+
+// Completes the prompts that wait only for this db
+pub(super) fn complete_unlock_prompts(conn: &SyncConnection, db_key: &str) {
+    let completed: Vec<(u64, Vec<Path<'static>>)> = {
+        let mut st = state().lock().unwrap();
+        for prompt in st.prompts.values_mut() {
+            prompt.db_keys.remove(db_key);
+        }
+        let ids: Vec<u64> = st
+            .prompts
+            .iter()
+            .filter(|(_, p)| p.prompted && p.db_keys.is_empty())
+            .map(|(id, _)| *id)
+            .collect();
+        ids.into_iter()
+            .filter_map(|id| st.prompts.remove(&id).map(|p| (id, p.objects)))
+            .collect()
+    };
+
+    for (id, objects) in completed {
+        send_completed(conn, id, false, objects);
+    }
+}
+
+//------------------------------------------------------------------------------------------
+// org.freedesktop.DBus.Properties
+
+fn properties_call(msg: &Message, object: &ObjectPath, member: &str) -> Result<Message, DbusError> {
+    match member {
+        "Get" => {
+            let (_interface, name): (String, String) = msg.read2()?;
+            let value = properties(object)?
+                .remove(&name)
+                .ok_or_else(|| DbusError::invalid_args(format!("No such property {}", name)))?;
+            Ok(msg.method_return().append1(value))
+        }
+        "GetAll" => {
+            let _interface: String = msg.read1()?;
+            Ok(msg.method_return().append1(properties(object)?))
+        }
+        "Set" => Err(DbusError::not_supported(
+            "The properties are changed only in OneKeePass",
+        )),
+        _ => Err(DbusError::new(
+            "org.freedesktop.DBus.Error.UnknownMethod",
+            format!("Unknown method {}.{}", PROPERTIES_IFACE, member),
+        )),
+    }
+}
+
+fn properties(object: &ObjectPath) -> Result<PropMap, DbusError> {
+    let mut props = PropMap::new();
+    match object {
+        ObjectPath::Service => {
+            let collections: Vec<Path<'static>> = objects::collections()
+                .iter()
+                .map(|c| Path::from(objects::collection_path(&c.group_uuid)))
+                .collect();
+            props.insert("Collections".into(), variant(collections));
+        }
+        ObjectPath::Collection(_) | ObjectPath::DefaultAlias => {
+            let collection = collection_of(object)?;
+            let locked = objects::is_locked(&collection);
+            let items = if locked {
+                vec![]
+            } else {
+                matching_item_paths(&collection, &HashMap::new())?
+            };
+            props.insert("Items".into(), variant(items));
+            props.insert("Label".into(), variant(collection.label.clone()));
+            props.insert("Locked".into(), variant(locked));
+            // Not known for a group
+            props.insert("Created".into(), variant(0u64));
+            props.insert("Modified".into(), variant(0u64));
+        }
+        ObjectPath::Item(_, entry_uuid) => {
+            let collection = unlocked_collection_of(object)?;
+            let item = objects::item(&collection, entry_uuid)?;
+            props.insert("Locked".into(), variant(false));
+            props.insert("Attributes".into(), variant(item.attributes));
+            props.insert("Label".into(), variant(item.label));
+            props.insert("Created".into(), variant(item.created));
+            props.insert("Modified".into(), variant(item.created));
+        }
+        ObjectPath::Session(_) | ObjectPath::Prompt(_) => {}
+    }
+    Ok(props)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_item_properties_from_message() {
+        let attributes = HashMap::from([
+            ("xdg:schema".to_string(), "org.git.Password".to_string()),
+            ("server".to_string(), "github.com".to_string()),
+        ]);
+        let mut properties = PropMap::new();
+        properties.insert(LABEL_PROPERTY.into(), variant("Git".to_string()));
+        properties.insert(ATTRIBUTES_PROPERTY.into(), variant(attributes.clone()));
+
+        // The properties as received in a CreateItem call
+        let msg = Message::new_method_call(
+            "org.freedesktop.secrets",
+            objects::DEFAULT_ALIAS_PATH,
+            COLLECTION_IFACE,
+            "CreateItem",
+        )
+        .unwrap()
+        .append1(properties);
+        let received: PropMap = msg.read1().unwrap();
+
+        assert_eq!(
+            received.get(LABEL_PROPERTY).and_then(|v| v.0.as_str()),
+            Some("Git")
+        );
+        assert_eq!(
+            received
+                .get(ATTRIBUTES_PROPERTY)
+                .map(|v| string_map(&v.0))
+                .unwrap(),
+            attributes
+        );
+    }
+
+    #[test]
+    fn verify_secret_value() {
+        let session = Path::from(objects::session_path(1));
+        let secret: Secret = (
+            session.clone(),
+            vec![],
+            b"s3cret".to_vec(),
+            CONTENT_TYPE.into(),
+        );
+        assert_eq!(secret_value(&secret).ok(), Some("s3cret".to_string()));
+
+        let secret: Secret = (session, vec![], vec![0xff, 0xfe], CONTENT_TYPE.into());
+        assert!(secret_value(&secret).is_err());
+    }
+}
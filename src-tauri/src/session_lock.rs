@@ -0,0 +1,397 @@
+// Locks all opened dbs on the session events of a Linux desktop. The signals watched are
+//   - system bus: logind's PrepareForSleep (suspend, hibernate) and the Lock and the
+//     'Active' property change (user switch) of the logind session of this process
+//   - session bus: ActiveChanged of org.freedesktop.ScreenSaver (KDE, Xfce ...) and of
+//     org.gnome.ScreenSaver
+// Which of these events lock the dbs is set in SessionLockPreference. The preference is read
+// when an event is received.
+//
+// The dbs are locked through db_lock.rs as the UI may not run before the system sleeps.
+// Whether a db has unsaved changes is known only in the UI. So every db is kept serialized
+// here and the UI, on SESSION_LOCKED_DBS_EVENT, locks each db again with its save pending
+// state. A delay inhibitor lock is held so that logind waits for the lock before the sleep.
+//
+// To try it against a local bus, start the app with DBUS_SYSTEM_BUS_ADDRESS set to the
+// address of a 'dbus-daemon --session --print-address' and send the signals with dbus-send
+// to that bus
+
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use dbus::arg::{prop_cast, OwnedFd, PropMap};
+use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+use dbus::{Message, Path};
+use log::{debug, info};
+
+use crate::app_preference::SessionLockPreference;
+use crate::app_state::AppState;
+use crate::db_lock;
+
+const LOGIN1_DEST: &str = "org.freedesktop.login1";
+const LOGIN1_PATH: &str = "/org/freedesktop/login1";
+const LOGIN1_MANAGER_IFACE: &str = "org.freedesktop.login1.Manager";
+const LOGIN1_SESSION_IFACE: &str = "org.freedesktop.login1.Session";
+const PROPERTIES_IFACE: &str = "org.freedesktop.DBus.Properties";
+const FREEDESKTOP_SCREENSAVER_IFACE: &str = "org.freedesktop.ScreenSaver";
+const GNOME_SCREENSAVER_IFACE: &str = "org.gnome.ScreenSaver";
+
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SessionEvent {
+    Sleep,
+    // Back from a sleep. Nothing is locked on this
+    Resume,
+    SessionLock,
+    UserSwitch,
+    ScreenSaver,
+}
+
+impl SessionEvent {
+    fn locks_dbs(&self, preference: &SessionLockPreference) -> bool {
+        match self {
+            Self::Sleep => preference.lock_on_sleep,
+            Self::SessionLock | Self::UserSwitch => preference.lock_on_session_lock,
+            Self::ScreenSaver => preference.lock_on_screen_saver,
+            Self::Resume => false,
+        }
+    }
+}
+
+// Starts the watchers of both buses. A bus that can not be connected (e.g no logind in a
+// container) is only logged
+pub(crate) fn start() {
+    let _ = thread::Builder::new()
+        .name("session-lock-system-bus".into())
+        .spawn(|| {
+            if let Err(e) = watch_system_bus() {
+                info!("Session events on the system bus are not watched: {}", e);
+            }
+        });
+    let _ = thread::Builder::new()
+        .name("session-lock-session-bus".into())
+        .spawn(|| {
+            if let Err(e) = watch_session_bus() {
+                info!("Session events on the session bus are not watched: {}", e);
+            }
+        });
+}
+
+fn watch_system_bus() -> Result<(), dbus::Error> {
+    let conn = Connection::new_system()?;
+    let own_session = own_session(&conn);
+
+    let mut rules = vec![MatchRule::new_signal(
+        LOGIN1_MANAGER_IFACE,
+        "PrepareForSleep",
+    )];
+    match &own_session {
+        Some(session) => {
+            rules.push(
+                MatchRule::new_signal(LOGIN1_SESSION_IFACE, "Lock").with_path(session.clone()),
+            );
+            rules.push(
+                MatchRule::new_signal(PROPERTIES_IFACE, "PropertiesChanged")
+                    .with_path(session.clone()),
+            );
+        }
+        None => rules.push(MatchRule::new_signal(LOGIN1_SESSION_IFACE, "Lock")),
+    }
+
+    add_watches(&conn, rules, own_session, on_session_event)?;
+    take_sleep_inhibitor(&conn);
+    process(conn)
+}
+
+fn watch_session_bus() -> Result<(), dbus::Error> {
+    let conn = Connection::new_session()?;
+    let rules = vec![
+        MatchRule::new_signal(FREEDESKTOP_SCREENSAVER_IFACE, "ActiveChanged"),
+        MatchRule::new_signal(GNOME_SCREENSAVER_IFACE, "ActiveChanged"),
+    ];
+    add_watches(&conn, rules, None, on_session_event)?;
+    process(conn)
+}
+
+fn process(conn: Connection) -> Result<(), dbus::Error> {
+    loop {
+        conn.process(Duration::from_secs(300))?;
+    }
+}
+
+// The logind session of this process. Without it, the Lock of any session is taken and
+// the user switch is not watched
+fn own_session(conn: &Connection) -> Option<Path<'static>> {
+    let manager = conn.with_proxy(LOGIN1_DEST, LOGIN1_PATH, CALL_TIMEOUT);
+    let r: Result<(Path<'static>,), dbus::Error> = match std::env::var("XDG_SESSION_ID") {
+        Ok(id) => manager.method_call(LOGIN1_MANAGER_IFACE, "GetSession", (id,)),
+        Err(_) => manager.method_call(
+            LOGIN1_MANAGER_IFACE,
+            "GetSessionByPID",
+            (std::process::id(),),
+        ),
+    };
+    match r {
+        Ok((session,)) => {
+            debug!("Watching the logind session {}", session);
+            Some(session)
+        }
+        Err(e) => {
+            info!("The logind session is not found: {}", e);
+            None
+        }
+    }
+}
+
+fn add_watches<F>(
+    conn: &Connection,
+    rules: Vec<MatchRule<'static>>,
+    own_session: Option<Path<'static>>,
+    on_event: F,
+) -> Result<(), dbus::Error>
+where
+    F: Fn(&Connection, SessionEvent) + Clone + Send + 'static,
+{
+    for rule in rules {
+        let own_session = own_session.clone();
+        let on_event = on_event.clone();
+        conn.add_match(rule, move |_: (), conn, msg| {
+            if let Some(event) = session_event(msg, own_session.as_deref()) {
+                on_event(conn, event);
+            }
+            // Keeps the match
+            true
+        })?;
+    }
+    Ok(())
+}
+
+// The event of a received signal if any. The session signals of a session other than
+// 'own_session' are ignored
+fn session_event(msg: &Message, own_session: Option<&str>) -> Option<SessionEvent> {
+    let interface = msg.interface()?;
+    let member = msg.member()?;
+    let from_own_session = || match own_session {
+        Some(session) => msg.path().is_some_and(|p| &*p == session),
+        None => true,
+    };
+
+    match (&*interface, &*member) {
+        (LOGIN1_MANAGER_IFACE, "PrepareForSleep") => {
+            if msg.read1::<bool>().ok()? {
+                Some(SessionEvent::Sleep)
+            } else {
+                Some(SessionEvent::Resume)
+            }
+        }
+        (LOGIN1_SESSION_IFACE, "Lock") if from_own_session() => Some(SessionEvent::SessionLock),
+        (PROPERTIES_IFACE, "PropertiesChanged") if own_session.is_some() && from_own_session() => {
+            let (changed_iface, changed): (String, PropMap) = msg.read2().ok()?;
+            (changed_iface == LOGIN1_SESSION_IFACE
+                && prop_cast::<bool>(&changed, "Active") == Some(&false))
+            .then_some(SessionEvent::UserSwitch)
+        }
+        (FREEDESKTOP_SCREENSAVER_IFACE | GNOME_SCREENSAVER_IFACE, "ActiveChanged") => msg
+            .read1::<bool>()
+            .ok()?
+            .then_some(SessionEvent::ScreenSaver),
+        _ => None,
+    }
+}
+
+fn on_session_event(conn: &Connection, event: SessionEvent) {
+    match event {
+        SessionEvent::Resume => take_sleep_inhibitor(conn),
+        SessionEvent::Sleep => {
+            lock_all_dbs(event);
+            // Lets the system sleep now
+            *sleep_inhibitor().lock().unwrap() = None;
+        }
+        _ => lock_all_dbs(event),
+    }
+}
+
+fn sleep_inhibitor() -> &'static Mutex<Option<OwnedFd>> {
+    static SLEEP_INHIBITOR: OnceLock<Mutex<Option<OwnedFd>>> = OnceLock::new();
+    SLEEP_INHIBITOR.get_or_init(Default::default)
+}
+
+// logind delays the sleep till the returned fd is closed or its InhibitDelayMaxSec is over
+fn take_sleep_inhibitor(conn: &Connection) {
+    let manager = conn.with_proxy(LOGIN1_DEST, LOGIN1_PATH, CALL_TIMEOUT);
+    let r: Result<(OwnedFd,), dbus::Error> = manager.method_call(
+        LOGIN1_MANAGER_IFACE,
+        "Inhibit",
+        (
+            "sleep",
+            "OneKeePass",
+            "Locking the opened databases",
+            "delay",
+        ),
+    );
+    match r {
+        Ok((fd,)) => {
+            *sleep_inhibitor().lock().unwrap() = Some(fd);
+        }
+        Err(e) => info!("Sleep delay inhibitor is not available: {}", e),
+    }
+}
+
+fn lock_all_dbs(event: SessionEvent) {
+    let app_state = AppState::state_instance();
+    if !event.locks_dbs(&app_state.session_lock_preference()) {
+        debug!(
+            "Databases are not locked on {:?} as per the preference",
+            event
+        );
+        return;
+    }
+    info!("Locking all opened databases on {:?}", event);
+    db_lock::lock_all_in_backend();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbus::channel::Sender;
+    use std::sync::mpsc;
+
+    const OWN_SESSION: &str = "/org/freedesktop/login1/session/_32";
+    const OTHER_SESSION: &str = "/org/freedesktop/login1/session/_35";
+
+    fn signal(path: &str, interface: &str, member: &str) -> Message {
+        Message::new_signal(path, interface, member).unwrap()
+    }
+
+    fn active_changed(path: &str, active: bool) -> Message {
+        let mut changed = PropMap::new();
+        changed.insert(
+            "Active".into(),
+            dbus::arg::Variant(Box::new(active) as Box<dyn dbus::arg::RefArg>),
+        );
+        signal(path, PROPERTIES_IFACE, "PropertiesChanged").append3(
+            LOGIN1_SESSION_IFACE,
+            changed,
+            Vec::<String>::new(),
+        )
+    }
+
+    #[test]
+    fn verify_session_event() {
+        let own = Some(OWN_SESSION);
+
+        let msg = signal(LOGIN1_PATH, LOGIN1_MANAGER_IFACE, "PrepareForSleep").append1(true);
+        assert_eq!(session_event(&msg, own), Some(SessionEvent::Sleep));
+        let msg = signal(LOGIN1_PATH, LOGIN1_MANAGER_IFACE, "PrepareForSleep").append1(false);
+        assert_eq!(session_event(&msg, own), Some(SessionEvent::Resume));
+
+        let msg = signal(OWN_SESSION, LOGIN1_SESSION_IFACE, "Lock");
+        assert_eq!(session_event(&msg, own), Some(SessionEvent::SessionLock));
+        let msg = signal(OTHER_SESSION, LOGIN1_SESSION_IFACE, "Lock");
+        assert_eq!(session_event(&msg, own), None);
+        assert_eq!(session_event(&msg, None), Some(SessionEvent::SessionLock));
+        let msg = signal(OWN_SESSION, LOGIN1_SESSION_IFACE, "Unlock");
+        assert_eq!(session_event(&msg, own), None);
+
+        assert_eq!(
+            session_event(&active_changed(OWN_SESSION, false), own),
+            Some(SessionEvent::UserSwitch)
+        );
+        assert_eq!(session_event(&active_changed(OWN_SESSION, true), own), None);
+        assert_eq!(
+            session_event(&active_changed(OTHER_SESSION, false), own),
+            None
+        );
+        assert_eq!(
+            session_event(&active_changed(OWN_SESSION, false), None),
+            None
+        );
+
+        for (path, interface) in [
+            (
+                "/org/freedesktop/ScreenSaver",
+                FREEDESKTOP_SCREENSAVER_IFACE,
+            ),
+            ("/org/gnome/ScreenSaver", GNOME_SCREENSAVER_IFACE),
+        ] {
+            let msg = signal(path, interface, "ActiveChanged").append1(true);
+            assert_eq!(session_event(&msg, own), Some(SessionEvent::ScreenSaver));
+            let msg = signal(path, interface, "ActiveChanged").append1(false);
+            assert_eq!(session_event(&msg, own), None);
+        }
+    }
+
+    #[test]
+    fn verify_event_preference() {
+        let preference = SessionLockPreference {
+            lock_on_sleep: false,
+            lock_on_session_lock: true,
+            lock_on_screen_saver: false,
+        };
+        assert!(!SessionEvent::Sleep.locks_dbs(&preference));
+        assert!(SessionEvent::SessionLock.locks_dbs(&preference));
+        assert!(SessionEvent::UserSwitch.locks_dbs(&preference));
+        assert!(!SessionEvent::ScreenSaver.locks_dbs(&preference));
+        assert!(!SessionEvent::Resume.locks_dbs(&SessionLockPreference::default()));
+    }
+
+    // Watches and emits all the signals on a private session bus:
+    //   dbus-run-session -- cargo test session_lock -- --ignored
+    #[test]
+    #[ignore]
+    fn verify_signals_on_session_bus() {
+        let conn = Connection::new_session().unwrap();
+        let rules = vec![
+            MatchRule::new_signal(LOGIN1_MANAGER_IFACE, "PrepareForSleep"),
+            MatchRule::new_signal(LOGIN1_SESSION_IFACE, "Lock"),
+            MatchRule::new_signal(PROPERTIES_IFACE, "PropertiesChanged")
+                .with_path(Path::from(OWN_SESSION)),
+            MatchRule::new_signal(FREEDESKTOP_SCREENSAVER_IFACE, "ActiveChanged"),
+            MatchRule::new_signal(GNOME_SCREENSAVER_IFACE, "ActiveChanged"),
+        ];
+        let (tx, rx) = mpsc::channel();
+        add_watches(
+            &conn,
+            rules,
+            Some(Path::from(OWN_SESSION)),
+            move |_, event| {
+                let _ = tx.send(event);
+            },
+        )
+        .unwrap();
+        thread::spawn(move || process(conn));
+
+        let sender = Connection::new_session().unwrap();
+        for msg in [
+            signal(LOGIN1_PATH, LOGIN1_MANAGER_IFACE, "PrepareForSleep").append1(true),
+            signal(OTHER_SESSION, LOGIN1_SESSION_IFACE, "Lock"),
+            signal(OWN_SESSION, LOGIN1_SESSION_IFACE, "Lock"),
+            active_changed(OWN_SESSION, false),
+            signal(
+                "/org/gnome/ScreenSaver",
+                GNOME_SCREENSAVER_IFACE,
+                "ActiveChanged",
+            )
+            .append1(true),
+            signal(LOGIN1_PATH, LOGIN1_MANAGER_IFACE, "PrepareForSleep").append1(false),
+        ] {
+            sender.send(msg).unwrap();
+        }
+
+        let events: Vec<SessionEvent> = (0..5)
+            .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                SessionEvent::Sleep,
+                SessionEvent::SessionLock,
+                SessionEvent::UserSwitch,
+                SessionEvent::ScreenSaver,
+                SessionEvent::Resume,
+            ]
+        );
+    }
+}
@@ -0,0 +1,471 @@
+// The tray icon (a StatusNotifierItem/AppIndicator on Linux) and its menu:
+//
+//  - show or hide the main window and a quick search of the entries of all opened dbs
+//  - lock all opened dbs in the backend (see db_lock::lock_all_in_backend)
+//  - open a db from the recent files. The open dialog is shown through launch_request.rs
+//  - start or stop the SSH agent with its current status
+//  - quit, which goes through the UI so that unsaved changes are not lost
+//
+// A tray menu can not be updated when it is about to be shown on Linux. So the state shown in
+// the menu is checked periodically and the menu is built again when that state changes.
+// The icon is shown as per TrayPreference. When 'close_to_tray' is set, closing the main
+// window hides it and the app keeps running in the tray (see main.rs)
+
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use log::{debug, error, info};
+use serde::Serialize;
+use tauri::menu::{Menu, MenuBuilder, MenuEvent, MenuItemBuilder, SubmenuBuilder};
+use tauri::tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+use uuid::Uuid;
+
+use onekeepass_core::db_service as kp_service;
+use onekeepass_core::error::{self, Result};
+
+use crate::app_state::{self, AppState};
+use crate::constants::event_action_names::CLOSE_REQUESTED;
+use crate::constants::event_names::{MAIN_WINDOW_EVENT, QUICK_SEARCH_REQUEST_EVENT};
+use crate::constants::window_labels::MAIN_WINDOW_LABEL;
+use crate::menu::menu_ids::{LOCK_ALL_DATABASES, OPEN_RECENT, QUIT};
+use crate::translation::{self, SystemMenuTranslation};
+use crate::{backup_service, db_lock, launch_request, ssh_agent};
+
+const TRAY_ID: &str = "main-tray";
+
+// The state is checked this often to update the menu
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+// The ids of the tray menu items. These are different from the window menu ids in menu.rs as
+// both menus are handled in the same menu event handler
+pub mod tray_menu_ids {
+    pub const TRAY_SHOW_WINDOW: &str = "TrayShowWindow";
+    pub const TRAY_HIDE_WINDOW: &str = "TrayHideWindow";
+    pub const TRAY_QUICK_SEARCH: &str = "TrayQuickSearch";
+    pub const TRAY_LOCK_ALL: &str = "TrayLockAll";
+    pub const TRAY_OPEN_RECENT: &str = "TrayOpenRecent";
+    // Followed by the path of the db file
+    pub const TRAY_RECENT_PREFIX: &str = "TrayRecent:";
+    pub const TRAY_SSH_AGENT_STATUS: &str = "TraySshAgentStatus";
+    pub const TRAY_START_SSH_AGENT: &str = "TrayStartSshAgent";
+    pub const TRAY_STOP_SSH_AGENT: &str = "TrayStopSshAgent";
+    pub const TRAY_QUIT: &str = "TrayQuit";
+
+    // Used only as translation keys
+    pub const TRAY_SSH_AGENT_RUNNING: &str = "TraySshAgentRunning";
+    pub const TRAY_SSH_AGENT_STOPPED: &str = "TraySshAgentStopped";
+}
+use tray_menu_ids::*;
+
+// What the menu shows. The menu is built again only when this changes
+#[derive(Clone, Debug, Default, PartialEq)]
+struct MenuState {
+    window_visible: bool,
+    has_opened_dbs: bool,
+    recent_files: Vec<String>,
+    ssh_agent_running: bool,
+    ssh_key_count: usize,
+}
+
+fn last_menu_state() -> &'static Mutex<Option<MenuState>> {
+    static LAST_MENU_STATE: OnceLock<Mutex<Option<MenuState>>> = OnceLock::new();
+    LAST_MENU_STATE.get_or_init(Default::default)
+}
+
+// The opened dbs except a backup opened to compare with a db
+fn opened_db_keys() -> Vec<String> {
+    backup_service::opened_db_keys().unwrap_or_default()
+}
+
+fn current_menu_state<R: Runtime>(app: &AppHandle<R>) -> MenuState {
+    let agent = ssh_agent::status();
+    MenuState {
+        window_visible: app
+            .get_webview_window(MAIN_WINDOW_LABEL)
+            .and_then(|w| w.is_visible().ok())
+            .unwrap_or(false),
+        has_opened_dbs: !opened_db_keys().is_empty(),
+        recent_files: AppState::state_instance().recent_file_paths(),
+        ssh_agent_running: agent.running,
+        ssh_key_count: agent.key_count,
+    }
+}
+
+// The file name is shown for a recent db. The full path is in the menu item id
+fn recent_file_label(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
+
+fn build_tray_menu<R: Runtime>(
+    app: &AppHandle<R>,
+    tr: &SystemMenuTranslation,
+    state: &MenuState,
+) -> std::result::Result<Menu<R>, tauri::Error> {
+    let window_item = if state.window_visible {
+        MenuItemBuilder::new(tr.sub_menu(TRAY_HIDE_WINDOW, "Hide OneKeePass"))
+            .id(TRAY_HIDE_WINDOW)
+            .build(app)?
+    } else {
+        MenuItemBuilder::new(tr.sub_menu(TRAY_SHOW_WINDOW, "Show OneKeePass"))
+            .id(TRAY_SHOW_WINDOW)
+            .build(app)?
+    };
+
+    let quick_search = MenuItemBuilder::new(tr.sub_menu(TRAY_QUICK_SEARCH, "Quick Search..."))
+        .id(TRAY_QUICK_SEARCH)
+        .enabled(state.has_opened_dbs)
+        .build(app)?;
+
+    let lock_all = MenuItemBuilder::new(tr.sub_menu(LOCK_ALL_DATABASES, "Lock All Databases"))
+        .id(TRAY_LOCK_ALL)
+        .enabled(state.has_opened_dbs)
+        .build(app)?;
+
+    let mut recent_builder = SubmenuBuilder::with_id(
+        app,
+        TRAY_OPEN_RECENT,
+        tr.sub_menu(OPEN_RECENT, "Open Recent"),
+    )
+    .enabled(!state.recent_files.is_empty());
+    for path in &state.recent_files {
+        let item = MenuItemBuilder::new(recent_file_label(path))
+            .id(format!("{}{}", TRAY_RECENT_PREFIX, path))
+            .build(app)?;
+        recent_builder = recent_builder.item(&item);
+    }
+    let recent = recent_builder.build()?;
+
+    let ssh_status_label = if state.ssh_agent_running {
+        tr.sub_menu(
+            TRAY_SSH_AGENT_RUNNING,
+            "SSH Agent: Running with {{count}} keys",
+        )
+        .replace("{{count}}", &state.ssh_key_count.to_string())
+    } else {
+        tr.sub_menu(TRAY_SSH_AGENT_STOPPED, "SSH Agent: Stopped")
+    };
+    let ssh_status = MenuItemBuilder::new(ssh_status_label)
+        .id(TRAY_SSH_AGENT_STATUS)
+        .enabled(false)
+        .build(app)?;
+    let ssh_toggle = if state.ssh_agent_running {
+        MenuItemBuilder::new(tr.sub_menu(TRAY_STOP_SSH_AGENT, "Stop SSH Agent"))
+            .id(TRAY_STOP_SSH_AGENT)
+            .build(app)?
+    } else {
+        MenuItemBuilder::new(tr.sub_menu(TRAY_START_SSH_AGENT, "Start SSH Agent"))
+            .id(TRAY_START_SSH_AGENT)
+            .build(app)?
+    };
+
+    let quit = MenuItemBuilder::new(tr.sub_menu(QUIT, "Quit OneKeePass"))
+        .id(TRAY_QUIT)
+        .build(app)?;
+
+    MenuBuilder::new(app)
+        .item(&window_item)
+        .item(&quick_search)
+        .separator()
+        .item(&lock_all)
+        .item(&recent)
+        .separator()
+        .item(&ssh_status)
+        .item(&ssh_toggle)
+        .separator()
+        .item(&quit)
+        .build()
+}
+
+fn load_translation<R: Runtime>(app: &AppHandle<R>) -> SystemMenuTranslation {
+    let pref_str = app_state::read_preference_file();
+    let language = app_state::read_language_selection(&pref_str);
+    translation::load_system_menu_translations(&language, app)
+}
+
+// Builds the menu again when its state has changed since the last build
+fn refresh<R: Runtime>(app: &AppHandle<R>) {
+    let Some(tray) = app.tray_by_id(TRAY_ID) else {
+        return;
+    };
+    let state = current_menu_state(app);
+    if last_menu_state().lock().unwrap().as_ref() == Some(&state) {
+        return;
+    }
+
+    // The lock is not held here as the menu is set on the main thread, which may be waiting
+    // for it in a menu event
+    match build_tray_menu(app, &load_translation(app), &state) {
+        Ok(menu) => {
+            if let Err(e) = tray.set_menu(Some(menu)) {
+                error!("Setting the tray menu failed: {}", e);
+                return;
+            }
+            *last_menu_state().lock().unwrap() = Some(state);
+        }
+        Err(e) => error!("Building the tray menu failed: {}", e),
+    }
+}
+
+fn create<R: Runtime>(app: &AppHandle<R>) -> std::result::Result<(), tauri::Error> {
+    let state = current_menu_state(app);
+    let menu = build_tray_menu(app, &load_translation(app), &state)?;
+
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+        .tooltip("OneKeePass")
+        .menu(&menu)
+        .show_menu_on_left_click(false)
+        .on_tray_icon_event(|tray, event| {
+            // Not emitted on Linux where a click always shows the menu
+            if let TrayIconEvent::Click {
+                button: MouseButton::Left,
+                button_state: MouseButtonState::Up,
+                ..
+            } = event
+            {
+                toggle_main_window(tray.app_handle());
+            }
+        });
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    builder.build(app)?;
+
+    *last_menu_state().lock().unwrap() = Some(state);
+    info!("Tray icon is created");
+    Ok(())
+}
+
+// Called in the app setup. The refresh task runs for the app lifetime and does nothing while
+// the icon is not shown
+pub(crate) fn init(app: &AppHandle) {
+    if AppState::state_instance().tray_preference().show_icon {
+        if let Err(e) = create(app) {
+            error!("Creating the tray icon failed: {}", e);
+        }
+    }
+
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(REFRESH_INTERVAL).await;
+            refresh(&app);
+        }
+    });
+}
+
+// Called when the preference is updated
+pub(crate) fn set_shown(show_icon: bool) {
+    let app = AppState::global_app_handle();
+    let exists = app.tray_by_id(TRAY_ID).is_some();
+    if show_icon && !exists {
+        if let Err(e) = create(app) {
+            error!("Creating the tray icon failed: {}", e);
+        }
+    } else if !show_icon && exists {
+        let _ = app.remove_tray_by_id(TRAY_ID);
+        *last_menu_state().lock().unwrap() = None;
+        // A hidden window can not be shown again without the icon
+        show_main_window(app);
+        info!("Tray icon is removed");
+    }
+}
+
+// Whether the main window is to be hidden instead of closing the app
+pub(crate) fn keeps_running_on_close<R: Runtime>(app: &AppHandle<R>) -> bool {
+    let pref = AppState::state_instance().tray_preference();
+    pref.show_icon && pref.close_to_tray && app.tray_by_id(TRAY_ID).is_some()
+}
+
+pub(crate) fn hide_main_window<R: Runtime>(app: &AppHandle<R>) {
+    if let Some(window) = app.get_webview_window(MAIN_WINDOW_LABEL) {
+        let _ = window.hide();
+    }
+    refresh(app);
+}
+
+fn show_main_window<R: Runtime>(app: &AppHandle<R>) {
+    if let Some(window) = app.get_webview_window(MAIN_WINDOW_LABEL) {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+    refresh(app);
+}
+
+fn toggle_main_window<R: Runtime>(app: &AppHandle<R>) {
+    let visible = app
+        .get_webview_window(MAIN_WINDOW_LABEL)
+        .and_then(|w| w.is_visible().ok())
+        .unwrap_or(false);
+    if visible {
+        hide_main_window(app);
+    } else {
+        show_main_window(app);
+    }
+}
+
+fn emit_to_main_window<R: Runtime, S: Serialize + Clone>(
+    app: &AppHandle<R>,
+    event: &str,
+    payload: S,
+) {
+    if let Some(window) = app.get_webview_window(MAIN_WINDOW_LABEL) {
+        if let Err(e) = window.emit(event, payload) {
+            error!("Failed to emit {}: {}", event, e);
+        }
+    }
+}
+
+// Handles the events of the tray menu items. Returns false for any other menu event so that
+// it is handled by menu.rs
+pub(crate) fn handle_menu_event(app: &AppHandle, menu_event: &MenuEvent) -> bool {
+    let menu_id = menu_event.id().0.as_str();
+    if !menu_id.starts_with("Tray") {
+        return false;
+    }
+    debug!("Tray menu event {}", menu_id);
+
+    match menu_id {
+        TRAY_SHOW_WINDOW => show_main_window(app),
+        TRAY_HIDE_WINDOW => hide_main_window(app),
+        TRAY_QUICK_SEARCH => {
+            show_main_window(app);
+            emit_to_main_window(app, QUICK_SEARCH_REQUEST_EVENT, ());
+        }
+        TRAY_LOCK_ALL => {
+            db_lock::lock_all_in_backend();
+            refresh(app);
+        }
+        TRAY_START_SSH_AGENT | TRAY_STOP_SSH_AGENT => {
+            let start = menu_id == TRAY_START_SSH_AGENT;
+            // Persisted the same way as the start/stop commands of the settings
+            AppState::state_instance().set_ssh_agent_enabled(start);
+            let status = if start {
+                ssh_agent::start()
+            } else {
+                ssh_agent::stop()
+            };
+            if let Some(e) = status.error {
+                error!(
+                    "SSH agent {} failed: {}",
+                    if start { "start" } else { "stop" },
+                    e
+                );
+            }
+            refresh(app);
+        }
+        TRAY_QUIT => {
+            // Same as closing the window without the tray. The UI asks about any unsaved
+            // changes and then quits the app
+            show_main_window(app);
+            emit_to_main_window(
+                app,
+                MAIN_WINDOW_EVENT,
+                crate::WindowEventPayload::new(CLOSE_REQUESTED),
+            );
+        }
+        _ => {
+            if let Some(path) = menu_id.strip_prefix(TRAY_RECENT_PREFIX) {
+                launch_request::open_db(app, path);
+                refresh(app);
+            }
+        }
+    }
+    true
+}
+
+// ------------------------------------------------------------------------------------------
+// Quick search
+
+#[derive(Serialize, Debug)]
+pub(crate) struct QuickSearchEntry {
+    db_key: String,
+    db_name: String,
+    uuid: Uuid,
+    title: String,
+    username: Option<String>,
+    has_otp: bool,
+}
+
+// The field name of the TOTP of an entry
+const OTP_FIELD: &str = "otp";
+
+// Searches the entries of all opened dbs. Locked dbs are not searched
+pub(crate) fn quick_search(term: &str) -> Result<Vec<QuickSearchEntry>> {
+    let mut found = vec![];
+    if term.trim().is_empty() {
+        return Ok(found);
+    }
+
+    for db_key in opened_db_keys() {
+        let db_name = kp_service::browser_extension::get_db_name(&db_key)
+            .unwrap_or_else(|_| recent_file_label(&db_key));
+        for summary in kp_service::search_term(&db_key, term)?.entry_items {
+            let Ok(uuid) = Uuid::parse_str(&summary.uuid) else {
+                continue;
+            };
+            let fields = kp_service::entry_key_value_fields(&db_key, &uuid).unwrap_or_default();
+            found.push(QuickSearchEntry {
+                db_key: db_key.clone(),
+                db_name: db_name.clone(),
+                uuid,
+                title: summary.title,
+                username: fields
+                    .get(kp_service::entry_keyvalue_key::USER_NAME)
+                    .filter(|u| !u.is_empty())
+                    .cloned(),
+                has_otp: fields.contains_key(OTP_FIELD),
+            });
+        }
+    }
+    Ok(found)
+}
+
+// Returns the value of a quick search result to copy. 'field' is one of "username",
+// "password" or "totp". The current token is returned for "totp"
+pub(crate) fn quick_search_field_value(
+    db_key: &str,
+    entry_uuid: &Uuid,
+    field: &str,
+) -> Result<String> {
+    let key = match field {
+        "username" => kp_service::entry_keyvalue_key::USER_NAME,
+        "password" => kp_service::entry_keyvalue_key::PASSWORD,
+        "totp" => {
+            let data = kp_service::entry_form_current_otp(db_key, entry_uuid, OTP_FIELD)?;
+            return serde_json::to_value(data)
+                .ok()
+                .and_then(|v| v.get("token").and_then(|t| t.as_str()).map(String::from))
+                .ok_or_else(|| {
+                    error::Error::UnexpectedError("The entry has no TOTP token".into())
+                });
+        }
+        _ => {
+            return Err(error::Error::UnexpectedError(format!(
+                "Unsupported field '{}'",
+                field
+            )))
+        }
+    };
+
+    kp_service::entry_key_value_fields(db_key, entry_uuid)?
+        .remove(key)
+        .ok_or_else(|| error::Error::UnexpectedError(format!("The entry has no {}", field)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_recent_file_label() {
+        assert_eq!(recent_file_label("/home/user/dbs/Test.kdbx"), "Test.kdbx");
+        assert_eq!(recent_file_label("Test.kdbx"), "Test.kdbx");
+        assert_eq!(recent_file_label("/"), "/");
+    }
+}